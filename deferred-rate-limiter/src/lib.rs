@@ -59,6 +59,35 @@ where
         }
     }
 
+    /// seconds in each rate limit period
+    pub fn period(&self) -> f32 {
+        self.rrl.period
+    }
+
+    /// How many requests the key has made in the current period. This does not count as a request.
+    /// If redis is unavailable, this server's local count is used instead.
+    pub async fn peek(&self, key: K) -> u64 {
+        let redis_key = format!("{}:{}", self.prefix, key);
+
+        match self.rrl.peek_label(&redis_key).await {
+            Ok(count) => count,
+            Err(err) => {
+                // an open circuit breaker already logged why
+                if !self.rrl.circuit_breaker_is_open() {
+                    error!(
+                        "unable to peek rate limit. using local cache. err={:?}",
+                        err
+                    );
+                }
+
+                self.local_cache
+                    .get(&key)
+                    .map(|x| x.load(Ordering::Acquire))
+                    .unwrap_or_default()
+            }
+        }
+    }
+
     /// if setting max_per_period, be sure to keep the period the same for all requests to this label
     /// TODO: max_per_period being None means two things. some places it means unlimited, but here it means to use the default. make an enum
     pub async fn throttle(
@@ -121,8 +150,15 @@ where
                             // if we get a redis error, just let the user through.
                             // if users are sticky on a server, local caches will work well enough
                             // though now that we do this, we need to reset rate limits every minute! cache must have ttl!
-                            error!("unable to rate limit! creating empty cache. err={:?}", err);
-                            0
+                            if !rrl.circuit_breaker_is_open() {
+                                error!(
+                                    "unable to rate limit! starting a local count. err={:?}",
+                                    err
+                                );
+                            }
+
+                            // the local count includes this request so that it still limits while redis is down
+                            count
                         }
                     };
 
@@ -180,11 +216,13 @@ where
                             }
                             Err(err) => {
                                 // don't let redis errors block our users!
-                                error!(
-                                    "unable to query rate limits, but local cache is available. key={} err={:?}",
-                                    key,
-                                    err,
-                                );
+                                if !rrl.circuit_breaker_is_open() {
+                                    error!(
+                                        "unable to query rate limits, but local cache is available. key={} err={:?}",
+                                        key,
+                                        err,
+                                    );
+                                }
                                 // TODO: we need to start a timer that resets this count every minute
                                 DeferredRateLimitResult::Allowed
                             }
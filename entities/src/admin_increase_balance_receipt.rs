@@ -13,6 +13,8 @@ pub struct Model {
     pub admin_id: u64,
     pub deposit_to_user_id: u64,
     pub note: String,
+    #[sea_orm(unique)]
+    pub idempotency_key: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
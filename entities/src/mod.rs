@@ -20,4 +20,6 @@ pub mod secondary_user;
 pub mod serialization;
 pub mod stripe_increase_balance_receipt;
 pub mod user;
+pub mod user_address;
 pub mod user_tier;
+pub mod user_webhook;
@@ -16,4 +16,6 @@ pub use super::rpc_key::Entity as RpcKey;
 pub use super::secondary_user::Entity as SecondaryUser;
 pub use super::stripe_increase_balance_receipt::Entity as StripeIncreaseBalanceReceipt;
 pub use super::user::Entity as User;
+pub use super::user_address::Entity as UserAddress;
 pub use super::user_tier::Entity as UserTier;
+pub use super::user_webhook::Entity as UserWebhook;
@@ -22,6 +22,12 @@ pub struct Model {
     #[sea_orm(column_type = "Text", nullable)]
     pub call_data: Option<String>,
     pub chain_id: u64,
+    /// decoded from `revert_data` or the rpc's error message
+    #[sea_orm(column_type = "Text", nullable)]
+    pub reason: Option<String>,
+    /// hex of the revert's return data
+    #[sea_orm(column_type = "Text", nullable)]
+    pub revert_data: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -26,6 +26,7 @@ pub struct Model {
     pub sum_credits_used: Decimal,
     #[sea_orm(column_type = "Decimal(Some((20, 10)))")]
     pub sum_incl_free_credits_used: Decimal,
+    pub tag: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -29,6 +29,16 @@ pub struct Model {
     pub allowed_user_agents: Option<String>,
     #[sea_orm(column_type = "Double")]
     pub log_revert_chance: f64,
+    pub secret_key_hashed: bool,
+    pub quota_requests: Option<u64>,
+    pub quota_window: String,
+    /// only shown once, when it is created
+    #[serde(skip_serializing)]
+    pub hmac_secret: Option<String>,
+    pub scope: Option<String>,
+    pub chain_id: Option<u64>,
+    pub last_used_at: Option<DateTimeUtc>,
+    pub webhook_reverts: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -18,6 +18,8 @@ pub struct Model {
     pub description: Option<String>,
     pub email: Option<String>,
     pub user_tier_id: u64,
+    pub tos_version: Option<String>,
+    pub tos_accepted_at: Option<DateTimeUtc>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -44,6 +46,8 @@ pub enum Relation {
     SecondaryUser,
     #[sea_orm(has_many = "super::stripe_increase_balance_receipt::Entity")]
     StripeIncreaseBalanceReceipt,
+    #[sea_orm(has_many = "super::user_address::Entity")]
+    UserAddress,
     #[sea_orm(
         belongs_to = "super::user_tier::Entity",
         from = "Column::UserTierId",
@@ -52,6 +56,8 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     UserTier,
+    #[sea_orm(has_one = "super::user_webhook::Entity")]
+    UserWebhook,
 }
 
 impl Related<super::admin::Entity> for Entity {
@@ -120,10 +126,22 @@ impl Related<super::stripe_increase_balance_receipt::Entity> for Entity {
     }
 }
 
+impl Related<super::user_address::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UserAddress.def()
+    }
+}
+
 impl Related<super::user_tier::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserTier.def()
     }
 }
 
+impl Related<super::user_webhook::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UserWebhook.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -12,6 +12,10 @@ pub struct Model {
     pub max_requests_per_period: Option<u64>,
     pub max_concurrent_requests: Option<u32>,
     pub downgrade_tier_id: Option<u64>,
+    pub cache_max_age_seconds: Option<u64>,
+    pub gas_estimate_percent: Option<u16>,
+    pub max_request_bytes: Option<u32>,
+    pub max_response_bytes: Option<u32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
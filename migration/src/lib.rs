@@ -37,6 +37,21 @@ mod m20230705_214013_type_fixes;
 mod m20230707_211936_premium_tier_changes;
 mod m20230708_151756_rpc_accounting_free_usage_credits;
 mod m20230708_152131_referral_track_one_time_bonus_bonus;
+mod m20230710_181532_user_tos_version;
+mod m20230711_093012_user_address;
+mod m20230712_140512_hash_rpc_keys;
+mod m20230713_144446_rpc_accounting_tag;
+mod m20230714_102203_rpc_key_quota;
+mod m20230716_090512_rpc_key_hmac_secret;
+mod m20230717_113024_rpc_key_scope;
+mod m20230718_082211_rpc_key_last_used;
+mod m20230719_101530_user_webhook;
+mod m20230720_143012_user_tier_cache_max_age;
+mod m20230721_091547_user_tier_gas_estimate_percent;
+mod m20230722_103214_user_tier_max_bytes;
+mod m20230723_084512_admin_increase_balance_idempotency_key;
+mod m20230724_120341_rpc_key_webhook_reverts;
+mod m20230725_093218_revert_log_reason;
 
 pub struct Migrator;
 
@@ -81,6 +96,21 @@ impl MigratorTrait for Migrator {
             Box::new(m20230707_211936_premium_tier_changes::Migration),
             Box::new(m20230708_151756_rpc_accounting_free_usage_credits::Migration),
             Box::new(m20230708_152131_referral_track_one_time_bonus_bonus::Migration),
+            Box::new(m20230710_181532_user_tos_version::Migration),
+            Box::new(m20230711_093012_user_address::Migration),
+            Box::new(m20230712_140512_hash_rpc_keys::Migration),
+            Box::new(m20230713_144446_rpc_accounting_tag::Migration),
+            Box::new(m20230714_102203_rpc_key_quota::Migration),
+            Box::new(m20230716_090512_rpc_key_hmac_secret::Migration),
+            Box::new(m20230717_113024_rpc_key_scope::Migration),
+            Box::new(m20230718_082211_rpc_key_last_used::Migration),
+            Box::new(m20230719_101530_user_webhook::Migration),
+            Box::new(m20230720_143012_user_tier_cache_max_age::Migration),
+            Box::new(m20230721_091547_user_tier_gas_estimate_percent::Migration),
+            Box::new(m20230722_103214_user_tier_max_bytes::Migration),
+            Box::new(m20230723_084512_admin_increase_balance_idempotency_key::Migration),
+            Box::new(m20230724_120341_rpc_key_webhook_reverts::Migration),
+            Box::new(m20230725_093218_revert_log_reason::Migration),
         ]
     }
 }
@@ -0,0 +1,42 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // existing keys are still raw. the `hash_rpc_keys` subcommand hashes them
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcKey::Table)
+                    .add_column(
+                        ColumnDef::new(RpcKey::SecretKeyHashed)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcKey::Table)
+                    .drop_column(RpcKey::SecretKeyHashed)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum RpcKey {
+    Table,
+    SecretKeyHashed,
+}
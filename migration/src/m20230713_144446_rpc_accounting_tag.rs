@@ -0,0 +1,98 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // cannot be NULL because unique indexes allow duplicates on NULL. untagged requests use ""
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcAccountingV2::Table)
+                    .add_column(
+                        ColumnDef::new(RpcAccountingV2::Tag)
+                            .string_len(64)
+                            .not_null()
+                            .default(""),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // the unique index from stats_v2 was created without a name, so mysql named it after its first column
+        manager
+            .drop_index(
+                Index::drop()
+                    .table(RpcAccountingV2::Table)
+                    .name("rpc_key_id")
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .table(RpcAccountingV2::Table)
+                    .name("rpc_accounting_v2_unique")
+                    .col(RpcAccountingV2::RpcKeyId)
+                    .col(RpcAccountingV2::ChainId)
+                    .col(RpcAccountingV2::PeriodDatetime)
+                    .col(RpcAccountingV2::ArchiveNeeded)
+                    .col(RpcAccountingV2::ErrorResponse)
+                    .col(RpcAccountingV2::Tag)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .table(RpcAccountingV2::Table)
+                    .name("rpc_accounting_v2_unique")
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcAccountingV2::Table)
+                    .drop_column(RpcAccountingV2::Tag)
+                    .to_owned(),
+            )
+            .await?;
+
+        // tagged rows need to be merged before this will succeed
+        manager
+            .create_index(
+                Index::create()
+                    .table(RpcAccountingV2::Table)
+                    .name("rpc_key_id")
+                    .col(RpcAccountingV2::RpcKeyId)
+                    .col(RpcAccountingV2::ChainId)
+                    .col(RpcAccountingV2::PeriodDatetime)
+                    .col(RpcAccountingV2::ArchiveNeeded)
+                    .col(RpcAccountingV2::ErrorResponse)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum RpcAccountingV2 {
+    Table,
+    RpcKeyId,
+    ChainId,
+    PeriodDatetime,
+    ArchiveNeeded,
+    ErrorResponse,
+    Tag,
+}
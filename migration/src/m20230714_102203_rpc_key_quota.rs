@@ -0,0 +1,45 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL quota_requests means the key has no quota. quota_window is "day" or "month"
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcKey::Table)
+                    .add_column(ColumnDef::new(RpcKey::QuotaRequests).big_unsigned().null())
+                    .add_column(
+                        ColumnDef::new(RpcKey::QuotaWindow)
+                            .string_len(8)
+                            .not_null()
+                            .default("month"),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcKey::Table)
+                    .drop_column(RpcKey::QuotaRequests)
+                    .drop_column(RpcKey::QuotaWindow)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum RpcKey {
+    Table,
+    QuotaRequests,
+    QuotaWindow,
+}
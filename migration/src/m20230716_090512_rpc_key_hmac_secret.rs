@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL hmac_secret means requests with the key do not need to be signed.
+        // unlike the secret key, this cannot be hashed. the proxy needs it to check signatures
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcKey::Table)
+                    .add_column(ColumnDef::new(RpcKey::HmacSecret).string_len(64).null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcKey::Table)
+                    .drop_column(RpcKey::HmacSecret)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum RpcKey {
+    Table,
+    HmacSecret,
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL scope means any method. NULL chain_id means any chain
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcKey::Table)
+                    .add_column(ColumnDef::new(RpcKey::Scope).string_len(32).null())
+                    .add_column(ColumnDef::new(RpcKey::ChainId).big_unsigned().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RpcKey::Table)
+                    .drop_column(RpcKey::Scope)
+                    .drop_column(RpcKey::ChainId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum RpcKey {
+    Table,
+    Scope,
+    ChainId,
+}
@@ -0,0 +1,72 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // one webhook per user. the secret signs every delivery
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserWebhook::Table)
+                    .col(
+                        ColumnDef::new(UserWebhook::Id)
+                            .big_unsigned()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(UserWebhook::UserId)
+                            .big_unsigned()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(UserWebhook::Url).string_len(2048).not_null())
+                    .col(
+                        ColumnDef::new(UserWebhook::Secret)
+                            .string_len(64)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UserWebhook::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-user_webhook-user_id")
+                            .from(UserWebhook::Table, UserWebhook::UserId)
+                            .to(User::Table, User::Id),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserWebhook::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum User {
+    Table,
+    Id,
+}
+
+#[derive(Iden)]
+enum UserWebhook {
+    Table,
+    Id,
+    UserId,
+    Url,
+    Secret,
+    CreatedAt,
+}
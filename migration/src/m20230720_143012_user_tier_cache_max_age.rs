@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL uses the app's response_cache_max_age_seconds. 0 always skips the cache
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserTier::Table)
+                    .add_column(
+                        ColumnDef::new(UserTier::CacheMaxAgeSeconds)
+                            .big_unsigned()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserTier::Table)
+                    .drop_column(UserTier::CacheMaxAgeSeconds)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum UserTier {
+    Table,
+    CacheMaxAgeSeconds,
+}
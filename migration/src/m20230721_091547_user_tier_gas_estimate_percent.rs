@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL returns gas estimates as the backends gave them. 120 returns 1.2x
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserTier::Table)
+                    .add_column(
+                        ColumnDef::new(UserTier::GasEstimatePercent)
+                            .small_unsigned()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserTier::Table)
+                    .drop_column(UserTier::GasEstimatePercent)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum UserTier {
+    Table,
+    GasEstimatePercent,
+}
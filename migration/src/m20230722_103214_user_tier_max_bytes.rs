@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL only has the app's limits
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserTier::Table)
+                    .add_column(ColumnDef::new(UserTier::MaxRequestBytes).unsigned().null())
+                    .add_column(ColumnDef::new(UserTier::MaxResponseBytes).unsigned().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserTier::Table)
+                    .drop_column(UserTier::MaxRequestBytes)
+                    .drop_column(UserTier::MaxResponseBytes)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum UserTier {
+    Table,
+    MaxRequestBytes,
+    MaxResponseBytes,
+}
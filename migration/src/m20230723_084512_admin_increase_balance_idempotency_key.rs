@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // NULL for requests without an `Idempotency-Key` header. unique indexes allow any number of NULLs
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AdminIncreaseBalanceReceipt::Table)
+                    .add_column(
+                        ColumnDef::new(AdminIncreaseBalanceReceipt::IdempotencyKey)
+                            .string_len(255)
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // two retries racing each other can't both credit the user
+        manager
+            .create_index(
+                Index::create()
+                    .table(AdminIncreaseBalanceReceipt::Table)
+                    .name("admin_increase_balance_receipt_idempotency_key")
+                    .col(AdminIncreaseBalanceReceipt::IdempotencyKey)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .table(AdminIncreaseBalanceReceipt::Table)
+                    .name("admin_increase_balance_receipt_idempotency_key")
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AdminIncreaseBalanceReceipt::Table)
+                    .drop_column(AdminIncreaseBalanceReceipt::IdempotencyKey)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum AdminIncreaseBalanceReceipt {
+    Table,
+    IdempotencyKey,
+}
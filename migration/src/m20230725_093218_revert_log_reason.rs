@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // older reverts were saved without either
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RevertLog::Table)
+                    .add_column(ColumnDef::new(RevertLog::Reason).text().null())
+                    .add_column(ColumnDef::new(RevertLog::RevertData).text().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RevertLog::Table)
+                    .drop_column(RevertLog::Reason)
+                    .drop_column(RevertLog::RevertData)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum RevertLog {
+    Table,
+    Reason,
+    RevertData,
+}
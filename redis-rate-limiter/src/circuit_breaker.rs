@@ -0,0 +1,166 @@
+//! Stop waiting on a redis that is slow or down.
+//!
+//! Every command gets a timeout. After enough failures in a row the breaker opens and commands fail immediately
+//! until it has been open long enough to try redis again.
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::time::{timeout, Duration};
+use tracing::{info, warn};
+
+/// How many times a breaker opened
+pub static REDIS_CIRCUIT_BREAKER_TRIPS: AtomicU64 = AtomicU64::new(0);
+/// How many commands were not sent to redis because a breaker was open
+pub static REDIS_CIRCUIT_BREAKER_SKIPS: AtomicU64 = AtomicU64::new(0);
+
+/// Cheap to clone. Clones share their state
+#[derive(Clone, Debug, Default)]
+pub struct RedisCircuitBreaker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// None waits forever
+    command_timeout: Option<Duration>,
+    /// 0 never opens the breaker
+    failure_threshold: u32,
+    open_duration: Duration,
+    consecutive_failures: AtomicU32,
+    /// unix epoch milliseconds
+    open_until_ms: AtomicU64,
+}
+
+impl RedisCircuitBreaker {
+    pub fn new(
+        command_timeout: Option<Duration>,
+        failure_threshold: u32,
+        open_duration: Duration,
+    ) -> Self {
+        let inner = Inner {
+            command_timeout,
+            failure_threshold,
+            open_duration,
+            ..Default::default()
+        };
+
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    fn now_ms() -> u64 {
+        chrono::Utc::now().timestamp_millis() as u64
+    }
+
+    /// Once `open_duration` has passed, commands are sent again. One more failure opens it again
+    pub fn is_open(&self) -> bool {
+        Self::now_ms() < self.inner.open_until_ms.load(Ordering::Acquire)
+    }
+
+    /// Run a redis command with the timeout. Fails immediately if the breaker is open
+    pub async fn call<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: Future<Output = anyhow::Result<T>>,
+    {
+        if self.is_open() {
+            REDIS_CIRCUIT_BREAKER_SKIPS.fetch_add(1, Ordering::Relaxed);
+            return Err(anyhow::anyhow!("redis circuit breaker is open"));
+        }
+
+        let x = match self.inner.command_timeout {
+            Some(command_timeout) => match timeout(command_timeout, f).await {
+                Ok(x) => x,
+                Err(_) => Err(anyhow::anyhow!(
+                    "redis command timed out after {:?}",
+                    command_timeout
+                )),
+            },
+            None => f.await,
+        };
+
+        if x.is_ok() {
+            self.record_success();
+        } else {
+            self.record_failure();
+        }
+
+        x
+    }
+
+    fn record_success(&self) {
+        let failures = self.inner.consecutive_failures.swap(0, Ordering::AcqRel);
+
+        if self.inner.failure_threshold > 0 && failures >= self.inner.failure_threshold {
+            info!("redis is healthy again. closing the circuit breaker");
+        }
+    }
+
+    fn record_failure(&self) {
+        let failures = self
+            .inner
+            .consecutive_failures
+            .fetch_add(1, Ordering::AcqRel)
+            + 1;
+
+        if self.inner.failure_threshold == 0 || failures < self.inner.failure_threshold {
+            return;
+        }
+
+        let now = Self::now_ms();
+
+        let open_until = now + self.inner.open_duration.as_millis() as u64;
+
+        let previous = self
+            .inner
+            .open_until_ms
+            .fetch_max(open_until, Ordering::AcqRel);
+
+        // only count the failure that opened it. commands that were already in flight fail too
+        if previous < now {
+            REDIS_CIRCUIT_BREAKER_TRIPS.fetch_add(1, Ordering::Relaxed);
+
+            warn!(
+                failures,
+                open_ms = self.inner.open_duration.as_millis() as u64,
+                "redis is unhealthy. opening the circuit breaker"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RedisCircuitBreaker;
+    use tokio::time::Duration;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let x = RedisCircuitBreaker::new(None, 2, Duration::from_secs(60));
+
+        x.record_failure();
+        assert!(!x.is_open());
+
+        // a success resets the count
+        x.record_success();
+        x.record_failure();
+        assert!(!x.is_open());
+
+        x.record_failure();
+        assert!(x.is_open());
+
+        // clones share the state
+        assert!(x.clone().is_open());
+    }
+
+    #[test]
+    fn test_circuit_breaker_disabled() {
+        let x = RedisCircuitBreaker::default();
+
+        for _ in 0..10 {
+            x.record_failure();
+        }
+
+        assert!(!x.is_open());
+    }
+}
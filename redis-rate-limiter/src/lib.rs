@@ -1,8 +1,14 @@
 //#![warn(missing_docs)]
+mod circuit_breaker;
+
 use anyhow::Context;
 use std::ops::Add;
 use tokio::time::{Duration, Instant};
 
+pub use circuit_breaker::{
+    RedisCircuitBreaker, REDIS_CIRCUIT_BREAKER_SKIPS, REDIS_CIRCUIT_BREAKER_TRIPS,
+};
+
 pub use deadpool_redis::redis;
 pub use deadpool_redis::{
     Config as RedisConfig, Connection as RedisConnection, Manager as RedisManager,
@@ -17,6 +23,7 @@ pub struct RedisRateLimiter {
     /// seconds
     pub period: f32,
     pool: RedisPool,
+    breaker: RedisCircuitBreaker,
 }
 
 pub enum RedisRateLimitResult {
@@ -42,9 +49,21 @@ impl RedisRateLimiter {
             key_prefix,
             max_requests_per_period,
             period,
+            breaker: RedisCircuitBreaker::default(),
         }
     }
 
+    /// Share a circuit breaker with the other limiters that use the same redis
+    pub fn with_circuit_breaker(mut self, breaker: RedisCircuitBreaker) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// True if redis has been failing and is not being sent commands right now
+    pub fn circuit_breaker_is_open(&self) -> bool {
+        self.breaker.is_open()
+    }
+
     pub fn now_as_secs(&self) -> f32 {
         // TODO: if system time doesn't match redis, this won't work great
         (chrono::Utc::now().timestamp_millis() as f32) / 1_000.0
@@ -82,29 +101,34 @@ impl RedisRateLimiter {
         // TODO: include max per period in the throttle key?
         let throttle_key = format!("{}:{}:{}", self.key_prefix, label, period_id);
 
-        let mut conn = self
-            .pool
-            .get()
-            .await
-            .context("get redis connection for rate limits")?;
-
-        // TODO: at high concurency, this gives "connection reset by peer" errors. at least they are off the hot path
-        // TODO: only set expire if this is a new key
-
-        // TODO: automatic retry
-        let x: Vec<_> = redis::pipe()
-            .atomic()
-            // we could get the key first, but that means an extra redis call for every check. this seems better
-            .incr(&throttle_key, count)
-            // set expiration each time we set the key. ignore the result
-            .expire(&throttle_key, 1 + self.period as usize)
-            // TODO: NX will make it only set the expiration the first time. works in redis, but not elasticache
-            // .arg("NX")
-            .ignore()
-            // do the query
-            .query_async(&mut *conn)
-            .await
-            .context("cannot increment rate limit or set expiration")?;
+        let x: Vec<u64> = self
+            .breaker
+            .call(async {
+                let mut conn = self
+                    .pool
+                    .get()
+                    .await
+                    .context("get redis connection for rate limits")?;
+
+                // TODO: at high concurency, this gives "connection reset by peer" errors. at least they are off the hot path
+                // TODO: only set expire if this is a new key
+
+                // TODO: automatic retry
+                redis::pipe()
+                    .atomic()
+                    // we could get the key first, but that means an extra redis call for every check. this seems better
+                    .incr(&throttle_key, count)
+                    // set expiration each time we set the key. ignore the result
+                    .expire(&throttle_key, 1 + self.period as usize)
+                    // TODO: NX will make it only set the expiration the first time. works in redis, but not elasticache
+                    // .arg("NX")
+                    .ignore()
+                    // do the query
+                    .query_async(&mut *conn)
+                    .await
+                    .context("cannot increment rate limit or set expiration")
+            })
+            .await?;
 
         let new_count: u64 = *x.first().expect("check redis");
 
@@ -118,6 +142,34 @@ impl RedisRateLimiter {
         }
     }
 
+    /// The count for a label in the current period. Unlike `throttle_label`, this does not increment it.
+    pub async fn peek_label(&self, label: &str) -> anyhow::Result<u64> {
+        let now = self.now_as_secs();
+
+        let period_id = self.period_id(now);
+
+        let throttle_key = format!("{}:{}:{}", self.key_prefix, label, period_id);
+
+        let count: Option<u64> = self
+            .breaker
+            .call(async {
+                let mut conn = self
+                    .pool
+                    .get()
+                    .await
+                    .context("get redis connection for rate limits")?;
+
+                redis::cmd("GET")
+                    .arg(&throttle_key)
+                    .query_async(&mut *conn)
+                    .await
+                    .context("cannot get rate limit count")
+            })
+            .await?;
+
+        Ok(count.unwrap_or_default())
+    }
+
     #[inline]
     pub async fn throttle(&self) -> anyhow::Result<RedisRateLimitResult> {
         self.throttle_label("", None, 1).await
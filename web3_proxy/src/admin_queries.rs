@@ -52,14 +52,14 @@ pub async fn query_admin_modify_usertier<'a>(
     // TODO: Make a single query, where you retrieve the user, and directly from it the secondary user (otherwise we do two jumpy, which is unnecessary)
     // get the user id first. if it is 0, we should use a cache on the app
     let caller_id =
-        get_user_id_from_params(&mut redis_conn, db_conn, db_replica, bearer, params).await?;
+        get_user_id_from_params(app, &mut redis_conn, db_conn, db_replica, bearer, params).await?;
 
     trace!(%caller_id, "query_admin_modify_usertier");
 
     // Check if the caller is an admin (i.e. if he is in an admin table)
     let _admin = admin::Entity::find()
         .filter(admin::Column::UserId.eq(caller_id))
-        .one(db_conn)
+        .one(db_replica.as_ref())
         .await?
         .ok_or(Web3ProxyError::AccessDenied("not an admin".into()))?;
 
@@ -68,7 +68,7 @@ pub async fn query_admin_modify_usertier<'a>(
     // Fetch the admin, and the user
     let user: user::Model = user::Entity::find()
         .filter(user::Column::Address.eq(user_address.as_bytes()))
-        .one(db_conn)
+        .one(db_replica.as_ref())
         .await?
         .ok_or(Web3ProxyError::BadRequest(
             "No user with this id found".into(),
@@ -82,7 +82,7 @@ pub async fn query_admin_modify_usertier<'a>(
     // Now we can modify the user's tier
     let new_user_tier: user_tier::Model = user_tier::Entity::find()
         .filter(user_tier::Column::Title.eq(user_tier_title.clone()))
-        .one(db_conn)
+        .one(db_replica.as_ref())
         .await?
         .ok_or(Web3ProxyError::BadRequest(
             "User Tier name was not found".into(),
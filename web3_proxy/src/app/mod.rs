@@ -1,45 +1,81 @@
 mod ws;
 
-use crate::block_number::CacheMode;
-use crate::caches::{RegisteredUserRateLimitKey, RpcSecretKeyCache, UserBalanceCache};
+use crate::block_number::{
+    final_tx_block, get_logs_range, CacheMode, FINAL_TX_METHODS, TRACE_CACHE_METHODS,
+};
+use crate::caches::{
+    RegisteredUserRateLimitKey, RpcSecretKeyCache, UserBalanceCache, UserTierCache,
+};
 use crate::config::{AppConfig, TopConfig};
-use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
+use crate::errors::{
+    set_no_servers_retry_after, Web3ProxyError, Web3ProxyErrorCode, Web3ProxyErrorContext,
+    Web3ProxyResult,
+};
 use crate::frontend::authorization::{
-    Authorization, RequestMetadata, RequestOrMethod, ResponseOrBytes,
+    set_rpc_key_hash_secret, Authorization, AuthorizationType, RequestMetadata, RequestOrMethod,
+    ResponseOrBytes,
 };
+use crate::frontend::gas_estimate::pad_gas_estimate;
+use crate::frontend::key_last_used::KeyLastUsed;
 use crate::frontend::rpc_proxy_ws::ProxyMode;
+use crate::frontend::ws_connections::WsConnections;
+use crate::gas_oracle::{fee_history_params, is_method_not_found, suggested_priority_fee};
 use crate::jsonrpc::{
     JsonRpcErrorData, JsonRpcForwardedResponse, JsonRpcForwardedResponseEnum, JsonRpcId,
     JsonRpcParams, JsonRpcRequest, JsonRpcRequestEnum, JsonRpcResultData,
 };
-use crate::relational_db::{get_db, get_migrated_db, DatabaseConnection, DatabaseReplica};
+use crate::method_route::{
+    deprecation_warning, is_cached, method_route, rejects_wallet_method, MethodRoute,
+};
+use crate::redact::set_params_logging;
+use crate::relational_db::{
+    get_db, get_migrated_db, DatabaseConnection, DatabaseReplica, DbPoolOptions,
+};
+use crate::request_recorder::{
+    is_recordable, RecordedRequest, RequestRecorder, RequestRecorderSender,
+};
 use crate::response_cache::{
-    JsonRpcQueryCacheKey, JsonRpcResponseCache, JsonRpcResponseEnum, JsonRpcResponseWeigher,
+    CachedResponse, JsonRpcQueryCacheKey, JsonRpcResponseCache, JsonRpcResponseEnum,
+    JsonRpcResponseWeigher, RESPONSE_CACHE_LOOKUPS, RESPONSE_CACHE_MISSES,
 };
-use crate::rpcs::blockchain::Web3ProxyBlock;
+use crate::rpcs::audit::{should_audit, RESPONSE_AUDITS, RESPONSE_AUDIT_MISMATCHES};
+use crate::rpcs::blockchain::{HeadChange, Web3ProxyBlock, CHAIN_REORGS, DEEP_CHAIN_REORGS};
 use crate::rpcs::consensus::RankedRpcs;
-use crate::rpcs::many::Web3Rpcs;
+use crate::rpcs::many::{Web3Rpcs, NO_SERVERS_SYNCED, NO_SERVERS_SYNCED_ALERTS};
 use crate::rpcs::one::Web3Rpc;
 use crate::rpcs::provider::{connect_http, EthersHttpProvider};
+use crate::rpcs::request::MALFORMED_RESPONSES;
+use crate::rpcs::revert_log_buffer::{RevertLogBuffer, REVERT_LOGS_DROPPED};
 use crate::rpcs::transactions::TxStatus;
+use crate::stats::slo::SloMetrics;
+use crate::stats::tier_metrics::TierMetrics;
 use crate::stats::{AppStat, FlushedStats, StatBuffer};
+use crate::webhooks::{
+    send_webhook, RevertWebhooks, WebhookDelivery, WebhookEvent, WebhookNotification,
+    WebhookSender, REVERT_WEBHOOKS_SKIPPED, WEBHOOKS_DROPPED,
+};
 use anyhow::Context;
+use arc_swap::ArcSwap;
 use axum::http::StatusCode;
 use chrono::Utc;
 use deferred_rate_limiter::DeferredRateLimiter;
-use entities::user;
+use entities::{pending_login, user};
 use ethers::core::utils::keccak256;
 use ethers::prelude::{Address, Bytes, Transaction, TxHash, H256, U64};
-use ethers::types::U256;
+use ethers::types::{FeeHistory, U256};
 use ethers::utils::rlp::{Decodable, Rlp};
 use futures::future::join_all;
 use futures::stream::{FuturesUnordered, StreamExt};
 use hashbrown::{HashMap, HashSet};
 use migration::sea_orm::{DatabaseTransaction, EntityTrait, PaginatorTrait, TransactionTrait};
 use moka::future::{Cache, CacheBuilder};
+use nanorand::Rng;
 use once_cell::sync::OnceCell;
 use redis_rate_limiter::redis::AsyncCommands;
-use redis_rate_limiter::{redis, DeadpoolRuntime, RedisConfig, RedisPool, RedisRateLimiter};
+use redis_rate_limiter::{
+    redis, DeadpoolRuntime, RedisCircuitBreaker, RedisConfig, RedisPool, RedisRateLimiter,
+    REDIS_CIRCUIT_BREAKER_SKIPS, REDIS_CIRCUIT_BREAKER_TRIPS,
+};
 use serde::Serialize;
 use serde_json::json;
 use serde_json::value::RawValue;
@@ -47,13 +83,14 @@ use std::fmt;
 use std::net::IpAddr;
 use std::num::NonZeroU64;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
 use std::sync::{atomic, Arc};
 use std::time::Duration;
-use tokio::sync::{broadcast, mpsc, oneshot, watch, Semaphore};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinHandle;
 use tokio::time::timeout;
-use tracing::{error, info, trace, warn, Level};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn, Level};
 
 // TODO: make this customizable?
 // TODO: include GIT_REF in here. i had trouble getting https://docs.rs/vergen/latest/vergen/ to work with a workspace. also .git is in .dockerignore
@@ -67,6 +104,39 @@ pub static APP_USER_AGENT: &str = concat!(
 /// aggregate across 1 week
 pub const BILLING_PERIOD_SECONDS: i64 = 60 * 60 * 24 * 7;
 
+/// State reads that dapps poll at "latest". Their responses are only kept until the next head block.
+const LATEST_STATE_METHODS: [&str; 5] = [
+    "eth_call",
+    "eth_getBalance",
+    "eth_getCode",
+    "eth_getStorageAt",
+    "eth_getTransactionCount",
+];
+
+/// Who an `eth_blockNumber` high-water mark belongs to. Keyed requests share a mark across ips.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BlockNumberSession {
+    RpcKey(NonZeroU64),
+    Ip(IpAddr),
+}
+
+impl BlockNumberSession {
+    /// Internal requests don't have a session
+    fn new(authorization: &Authorization) -> Option<Self> {
+        if matches!(
+            authorization.authorization_type,
+            AuthorizationType::Internal
+        ) {
+            return None;
+        }
+
+        match authorization.checks.rpc_secret_key_id {
+            Some(rpc_secret_key_id) => Some(Self::RpcKey(rpc_secret_key_id)),
+            None => Some(Self::Ip(authorization.ip)),
+        }
+    }
+}
+
 /// Convenience type
 pub type Web3ProxyJoinHandle<T> = JoinHandle<Web3ProxyResult<T>>;
 
@@ -77,16 +147,44 @@ pub struct Web3ProxyApp {
     pub balanced_rpcs: Arc<Web3Rpcs>,
     /// Send 4337 Abstraction Bundler requests to one of these servers
     pub bundler_4337_rpcs: Option<Arc<Web3Rpcs>>,
-    /// application config
-    /// TODO: this will need a large refactor to handle reloads while running. maybe use a watch::Receiver?
-    pub config: AppConfig,
+    /// the highest `eth_blockNumber` returned to each session. None if `monotonic_block_number_seconds` is not set
+    /// The marks are atomics so that concurrent requests can only raise them
+    pub block_number_high_water: Option<Cache<BlockNumberSession, Arc<AtomicU64>>>,
+    /// application config. `apply_top_config` swaps in new values for the options that are safe to change while running
+    pub config: ArcSwap<AppConfig>,
     pub http_client: Option<reqwest::Client>,
     /// track JSONRPC responses
     pub jsonrpc_response_cache: JsonRpcResponseCache,
+    /// responses for `LATEST_STATE_METHODS` at the head block. cleared on every new head block
+    pub latest_response_cache: JsonRpcResponseCache,
+    /// responses for `TRACE_CACHE_METHODS` at confirmed blocks
+    pub trace_response_cache: JsonRpcResponseCache,
+    /// the last successful response for each request. only read when the backends are down. None unless `stale_if_error_seconds` is set
+    pub stale_response_cache: Option<JsonRpcResponseCache>,
+    /// responses that came from `stale_response_cache`
+    pub stale_responses_served: AtomicU64,
+    /// cancel this to stop the backend rpc connections and the revert log buffer
+    pub shutdown_token: CancellationToken,
+    /// while true, the proxy routes return 503. set from config and toggled by admins without a restart
+    pub maintenance_mode: AtomicBool,
     /// rpc clients that subscribe to newHeads use this channel
     /// don't drop this or the sender will stop working
     /// TODO: broadcast channel instead?
     pub watch_consensus_head_receiver: watch::Receiver<Option<Web3ProxyBlock>>,
+    /// expired logins and pending_logins deleted by the bulk cleanups. if this is high something weird might be happening
+    pub expired_logins_cleared: AtomicU64,
+    /// logins that were rejected because none of the signature checks passed
+    pub failed_login_verifications: AtomicU64,
+    /// login messages that were slowed down because their ip was over `login_tarpit_per_minute`
+    pub login_messages_tarpitted: AtomicU64,
+    /// pending_login rows saved. compare with the row count to see if the table is growing
+    pub pending_logins_created: AtomicU64,
+    /// open websockets. admins use this to find and disconnect abusive clients
+    pub ws_connections: Arc<WsConnections>,
+    /// websockets that were closed on connect because their ip was at `ws_max_connections_per_ip`
+    pub ws_connections_per_ip_rejected: AtomicU64,
+    /// websocket messages that were rejected for being larger than `ws_max_message_bytes`
+    pub ws_oversized_messages: AtomicU64,
     /// rpc clients that subscribe to pendingTransactions use this channel
     /// This is the Sender so that new channels can subscribe to it
     pending_tx_sender: broadcast::Sender<TxStatus>,
@@ -101,9 +199,19 @@ pub struct Web3ProxyApp {
     /// rate limit authenticated users
     pub frontend_registered_user_rate_limiter:
         Option<DeferredRateLimiter<RegisteredUserRateLimitKey>>,
+    /// rate limit ips that use keys. checked as well as `frontend_registered_user_rate_limiter`
+    pub frontend_keyed_ip_rate_limiter: Option<DeferredRateLimiter<IpAddr>>,
+    /// rate limit each rpc key across all of the ips that use it. checked as well as `frontend_registered_user_rate_limiter`
+    pub frontend_rpc_key_rate_limiter: Option<DeferredRateLimiter<NonZeroU64>>,
+    /// signatures that were already accepted. a signed request cannot be replayed while its timestamp is still valid
+    pub hmac_signatures_seen: Cache<[u8; 32], ()>,
     /// concurrent/parallel request limits for anonymous users
     pub ip_semaphores: Cache<IpAddr, Arc<Semaphore>>,
     pub kafka_producer: Option<rdkafka::producer::FutureProducer>,
+    /// keys that were used since `rpc_key.last_used_at` was last saved
+    pub key_last_used: Arc<KeyLastUsed>,
+    /// login messages requested by each ip in the last minute
+    pub login_messages_per_ip: Cache<IpAddr, Arc<AtomicU64>>,
     /// rate limit the login endpoint
     /// we do this because each pending login is a row in the database
     pub login_rate_limiter: Option<RedisRateLimiter>,
@@ -115,17 +223,32 @@ pub struct Web3ProxyApp {
     pub private_rpcs: Option<Arc<Web3Rpcs>>,
     pub prometheus_port: Arc<AtomicU16>,
     /// cache authenticated users so that we don't have to query the database on the hot path
-    // TODO: should the key be our RpcSecretKey class instead of Ulid?
     pub rpc_secret_key_cache: RpcSecretKeyCache,
     /// cache user balances so we don't have to check downgrade logic every single time
     pub user_balance_cache: UserBalanceCache,
+    /// every user tier. refreshed in the background
+    pub user_tier_cache: Arc<UserTierCache>,
     /// concurrent/parallel RPC request limits for authenticated users
     pub user_semaphores: Cache<(NonZeroU64, IpAddr), Arc<Semaphore>>,
     /// volatile cache used for rate limits
     /// TODO: i think i might just delete this entirely. instead use local-only concurrency limits.
     pub vredis_pool: Option<RedisPool>,
+    /// shared by every rate limiter that uses `vredis_pool`
+    pub vredis_breaker: RedisCircuitBreaker,
+    /// limits how many stats queries use the database at once. separate from the proxy's limits
+    pub stats_semaphore: Arc<Semaphore>,
     /// channel for sending stats in a background task
     pub stat_sender: Option<mpsc::UnboundedSender<AppStat>>,
+    /// request counts grouped by user tier
+    pub tier_metrics: Arc<TierMetrics>,
+    /// rolling success rate and latency for `GET /slo`
+    pub slo_metrics: Arc<SloMetrics>,
+    /// a sample of requests for load tests. None unless `record_requests_file` is set
+    pub request_recorder_sender: Option<RequestRecorderSender>,
+    /// account events for users' webhooks. None if there is no database
+    pub webhook_sender: Option<WebhookSender>,
+    /// revert events for keys with `webhook_reverts`. None if `webhook_revert_seconds` is 0
+    pub revert_webhooks: Option<Arc<RevertWebhooks>>,
 
     /// Optional time series database for making pretty graphs that load quickly
     influxdb_client: Option<influxdb2::Client>,
@@ -186,6 +309,10 @@ impl Web3ProxyApp {
         let stat_buffer_shutdown_receiver = shutdown_sender.subscribe();
         let mut background_shutdown_receiver = shutdown_sender.subscribe();
 
+        // the backend rpc tasks and the revert log buffer stop when this is cancelled
+        // proxyd cancels it after the frontend and stat buffer have finished
+        let shutdown_token = CancellationToken::new();
+
         // safety checks on the config
         // while i would prefer this to be in a "apply_top_config" function, that is a larger refactor
         // TODO: maybe don't spawn with a config at all. have all config updates come through an apply_top_config call
@@ -196,6 +323,13 @@ impl Web3ProxyApp {
             );
         }
 
+        set_rpc_key_hash_secret(
+            top_config.app.rpc_key_hash_secret.as_deref(),
+            top_config.app.db_url.is_some(),
+        )?;
+        set_params_logging(&top_config.app.params_logging);
+        set_no_servers_retry_after(top_config.app.no_servers_retry_after_seconds);
+
         if !top_config.extra.is_empty() {
             warn!(
                 extra=?top_config.extra.keys(),
@@ -233,9 +367,21 @@ impl Web3ProxyApp {
                 .db_max_connections
                 .unwrap_or(db_min_connections * 2);
 
-            db_conn = Some(
-                get_migrated_db(db_url.clone(), db_min_connections, db_max_connections).await?,
-            );
+            let db_pool_options = DbPoolOptions {
+                min_connections: db_min_connections,
+                max_connections: db_max_connections,
+                acquire_timeout: top_config
+                    .app
+                    .db_acquire_timeout_seconds
+                    .map(Duration::from_secs)
+                    .unwrap_or(DbPoolOptions::default().acquire_timeout),
+                idle_timeout: top_config
+                    .app
+                    .db_idle_timeout_seconds
+                    .map(Duration::from_secs),
+            };
+
+            db_conn = Some(get_migrated_db(db_url.clone(), db_pool_options).await?);
 
             db_replica = if let Some(db_replica_url) = top_config.app.db_replica_url.clone() {
                 if db_replica_url == db_url {
@@ -254,8 +400,11 @@ impl Web3ProxyApp {
 
                     let db_replica = get_db(
                         db_replica_url,
-                        db_replica_min_connections,
-                        db_replica_max_connections,
+                        DbPoolOptions {
+                            min_connections: db_replica_min_connections,
+                            max_connections: db_replica_max_connections,
+                            ..db_pool_options
+                        },
                     )
                     .await?;
 
@@ -311,6 +460,7 @@ impl Web3ProxyApp {
                     .unwrap_or(num_workers * 2);
 
                 // TODO: what are reasonable timeouts?
+                // rate limits don't wait on a full pool. their circuit breaker times out the wait for a connection too
                 let redis_pool = RedisConfig::from_url(redis_url)
                     .builder()?
                     .max_size(redis_max_connections)
@@ -379,6 +529,39 @@ impl Web3ProxyApp {
             .build()
             .into();
 
+        // make a http shared client
+        // TODO: can we configure the connection pool? should we?
+        // TODO: timeouts from config. defaults are hopefully good
+        let http_client = Some(
+            reqwest::ClientBuilder::new()
+                .connect_timeout(Duration::from_secs(5))
+                .timeout(Duration::from_secs(5 * 60))
+                .user_agent(APP_USER_AGENT)
+                .build()?,
+        );
+
+        // account events are sent to users' webhooks in the background. delivery retries can take a while
+        let webhook_sender = if let Some((webhook_sender, webhook_handle)) =
+            WebhookDelivery::try_spawn(db_conn.clone(), shutdown_token.child_token())
+        {
+            important_background_handles.push(webhook_handle);
+
+            Some(webhook_sender)
+        } else {
+            None
+        };
+
+        // reverts are found deep inside request handling, so keyed requests carry this on their Authorization
+        let revert_webhooks = webhook_sender
+            .clone()
+            .filter(|_| top_config.app.webhook_revert_seconds > 0)
+            .map(|webhook_sender| {
+                Arc::new(RevertWebhooks::new(
+                    webhook_sender,
+                    Duration::from_secs(top_config.app.webhook_revert_seconds),
+                ))
+            });
+
         // create a channel for receiving stats
         // we do this in a channel so we don't slow down our response to the users
         // stats can be saved in mysql, influxdb, both, or none
@@ -395,6 +578,8 @@ impl Web3ProxyApp {
             1,
             flush_stat_buffer_sender.clone(),
             flush_stat_buffer_receiver,
+            webhook_sender.clone(),
+            top_config.app.webhook_low_balance,
         )? {
             // since the database entries are used for accounting, we want to be sure everything is saved before exiting
             important_background_handles.push(spawned_stat_buffer.background_handle);
@@ -405,23 +590,72 @@ impl Web3ProxyApp {
             None
         };
 
-        // make a http shared client
-        // TODO: can we configure the connection pool? should we?
-        // TODO: timeouts from config. defaults are hopefully good
-        let http_client = Some(
-            reqwest::ClientBuilder::new()
-                .connect_timeout(Duration::from_secs(5))
-                .timeout(Duration::from_secs(5 * 60))
-                .user_agent(APP_USER_AGENT)
-                .build()?,
-        );
+        // revert logs are batched so a contract that reverts a lot can't hammer the database
+        if let Some(revert_log_handle) = RevertLogBuffer::try_spawn(
+            top_config.app.revert_log_batch_size,
+            db_conn.clone(),
+            top_config.app.revert_log_flush_seconds,
+            shutdown_token.child_token(),
+        ) {
+            important_background_handles.push(revert_log_handle);
+        }
+
+        // recording is off unless a file is set
+        let request_recorder_sender = if let Some((sender, handle)) = RequestRecorder::try_spawn(
+            top_config.app.record_requests_file.clone(),
+            top_config.app.record_requests_max,
+            shutdown_token.child_token(),
+        )
+        .await?
+        {
+            important_background_handles.push(handle);
+
+            Some(sender)
+        } else {
+            None
+        };
+
+        // saving every use would be a database write per request
+        let key_last_used = Arc::new(KeyLastUsed::default());
+
+        if let Some(key_last_used_handle) = key_last_used.try_spawn(
+            db_conn.clone(),
+            top_config.app.key_last_used_flush_seconds,
+            shutdown_token.child_token(),
+        ) {
+            important_background_handles.push(key_last_used_handle);
+        }
+
+        // tiers rarely change, so every authorization check reads them from memory
+        let user_tier_cache = Arc::new(UserTierCache::default());
+
+        if let Some(user_tier_handle) = user_tier_cache
+            .try_spawn(
+                db_conn.clone(),
+                top_config.app.user_tier_refresh_seconds,
+                shutdown_token.child_token(),
+            )
+            .await
+        {
+            important_background_handles.push(user_tier_handle);
+        }
 
         // create rate limiters
         // these are optional. they require redis
         let mut frontend_ip_rate_limiter = None;
         let mut frontend_registered_user_rate_limiter = None;
+        let mut frontend_keyed_ip_rate_limiter = None;
+        let mut frontend_rpc_key_rate_limiter = None;
         let mut login_rate_limiter = None;
 
+        // every rate limiter shares one breaker, including the backend rpcs' hard limits.
+        // when redis is unhealthy, it is unhealthy for all of them
+        let vredis_breaker = RedisCircuitBreaker::new(
+            top_config.app.volatile_redis_timeout(),
+            top_config.app.volatile_redis_breaker_failures,
+            Duration::from_secs(top_config.app.volatile_redis_breaker_seconds),
+        );
+
         if let Some(ref redis_pool) = vredis_pool {
             if let Some(public_requests_per_period) = top_config.app.public_requests_per_period {
                 // chain id is included in the app name so that rpc rate limits are per-chain
@@ -431,7 +665,8 @@ impl Web3ProxyApp {
                     public_requests_per_period,
                     60.0,
                     redis_pool.clone(),
-                );
+                )
+                .with_circuit_breaker(vredis_breaker.clone());
 
                 // these two rate limiters can share the base limiter
                 // these are deferred rate limiters because we don't want redis network requests on the hot path
@@ -442,14 +677,46 @@ impl Web3ProxyApp {
                     Some(DeferredRateLimiter::new(20_000, "key", rpc_rrl, None).await);
             }
 
+            if let Some(keyed_ip_requests_per_period) = top_config.app.keyed_ip_requests_per_period
+            {
+                let keyed_ip_rrl = RedisRateLimiter::new(
+                    &format!("web3_proxy:{}", top_config.app.chain_id),
+                    "frontend_keyed_ip",
+                    keyed_ip_requests_per_period,
+                    60.0,
+                    redis_pool.clone(),
+                )
+                .with_circuit_breaker(vredis_breaker.clone());
+
+                frontend_keyed_ip_rate_limiter =
+                    Some(DeferredRateLimiter::new(20_000, "keyed_ip", keyed_ip_rrl, None).await);
+            }
+
+            if let Some(rpc_key_requests_per_period) = top_config.app.rpc_key_requests_per_period {
+                let rpc_key_rrl = RedisRateLimiter::new(
+                    &format!("web3_proxy:{}", top_config.app.chain_id),
+                    "frontend_rpc_key",
+                    rpc_key_requests_per_period,
+                    60.0,
+                    redis_pool.clone(),
+                )
+                .with_circuit_breaker(vredis_breaker.clone());
+
+                frontend_rpc_key_rate_limiter =
+                    Some(DeferredRateLimiter::new(20_000, "rpc_key", rpc_key_rrl, None).await);
+            }
+
             // login rate limiter
-            login_rate_limiter = Some(RedisRateLimiter::new(
-                "web3_proxy",
-                "login",
-                top_config.app.login_rate_limit_per_period,
-                60.0,
-                redis_pool.clone(),
-            ));
+            login_rate_limiter = Some(
+                RedisRateLimiter::new(
+                    "web3_proxy",
+                    "login",
+                    top_config.app.login_rate_limit_per_period,
+                    60.0,
+                    redis_pool.clone(),
+                )
+                .with_circuit_breaker(vredis_breaker.clone()),
+            );
         }
 
         let (watch_consensus_head_sender, watch_consensus_head_receiver) = watch::channel(None);
@@ -473,6 +740,18 @@ impl Web3ProxyApp {
             .time_to_live(Duration::from_secs(300))
             .build();
 
+        // timestamps are valid on either side of now, so a signature can be valid for twice the max age
+        let hmac_signatures_seen = CacheBuilder::new(100_000)
+            .name("hmac_signatures_seen")
+            .time_to_live(Duration::from_secs(top_config.app.hmac_max_age_seconds * 2))
+            .build();
+
+        // the window starts with an ip's first request. it is not a sliding window, but it doesn't need to be
+        let login_messages_per_ip = CacheBuilder::new(100_000)
+            .name("login_messages_per_ip")
+            .time_to_live(Duration::from_secs(60))
+            .build();
+
         // responses can be very different in sizes, so this is a cache with a max capacity and a weigher
         // TODO: we should emit stats to calculate a more accurate expected cache size
         // TODO: do we actually want a TTL on this?
@@ -484,9 +763,52 @@ impl Web3ProxyApp {
             CacheBuilder::new(top_config.app.response_cache_max_bytes)
                 .name("jsonrpc_response_cache")
                 .time_to_idle(Duration::from_secs(3600))
-                .weigher(move |k, v| jsonrpc_weigher.weigh(k, v))
+                .support_invalidation_closures()
+                .weigher(move |k, v: &CachedResponse| jsonrpc_weigher.weigh(k, &v.response))
+                .build();
+
+        // these entries are only valid for one block, so this cache can be much smaller
+        let latest_response_cache: JsonRpcResponseCache =
+            CacheBuilder::new(top_config.app.response_cache_max_bytes / 10)
+                .name("latest_response_cache")
+                .support_invalidation_closures()
+                .weigher(move |k, v: &CachedResponse| jsonrpc_weigher.weigh(k, &v.response))
+                .build();
+
+        // keyed without a block so that any earlier answer can be used while backends are down
+        let stale_response_cache = if top_config.app.stale_if_error_seconds > 0 {
+            Some(
+                CacheBuilder::new(top_config.app.response_cache_max_bytes / 10)
+                    .name("stale_response_cache")
+                    .time_to_live(Duration::from_secs(top_config.app.stale_if_error_seconds))
+                    .support_invalidation_closures()
+                    .weigher(move |k, v: &CachedResponse| jsonrpc_weigher.weigh(k, &v.response))
+                    .build(),
+            )
+        } else {
+            None
+        };
+
+        // traces can be huge. allow single items up to 10% of this cache
+        let trace_weigher =
+            JsonRpcResponseWeigher((top_config.app.trace_cache_max_bytes / 10) as u32);
+
+        let trace_response_cache: JsonRpcResponseCache =
+            CacheBuilder::new(top_config.app.trace_cache_max_bytes)
+                .name("trace_response_cache")
+                .time_to_live(Duration::from_secs(top_config.app.trace_cache_ttl_seconds))
+                .support_invalidation_closures()
+                .weigher(move |k, v: &CachedResponse| trace_weigher.weigh(k, &v.response))
                 .build();
 
+        // sessions are only remembered briefly. a client that goes quiet for longer than this is fine seeing the consensus head
+        let block_number_high_water = top_config.app.monotonic_block_number_seconds.map(|ttl| {
+            CacheBuilder::new(10_000)
+                .name("block_number_high_water")
+                .time_to_idle(Duration::from_secs(ttl))
+                .build()
+        });
+
         // TODO: how should we handle hitting this max?
         let max_users = 20_000;
 
@@ -506,6 +828,7 @@ impl Web3ProxyApp {
             "balanced rpcs".to_string(),
             pending_transactions.clone(),
             Some(pending_tx_sender.clone()),
+            shutdown_token.child_token(),
             Some(watch_consensus_head_sender),
         )
         .await
@@ -533,6 +856,7 @@ impl Web3ProxyApp {
                 pending_transactions.clone(),
                 // TODO: subscribe to pending transactions on the private rpcs? they seem to have low rate limits, but they should have
                 None,
+                shutdown_token.child_token(),
                 // subscribing to new heads here won't work well. if they are fast, they might be ahead of balanced_rpcs
                 // they also often have low rate limits
                 // however, they are well connected to miners/validators. so maybe using them as a safety check would be good
@@ -564,6 +888,7 @@ impl Web3ProxyApp {
                 "eip4337 rpcs".to_string(),
                 pending_transactions.clone(),
                 None,
+                shutdown_token.child_token(),
                 None,
             )
             .await
@@ -580,13 +905,19 @@ impl Web3ProxyApp {
 
         let app = Self {
             balanced_rpcs,
+            block_number_high_water,
             bundler_4337_rpcs,
-            config: top_config.app.clone(),
+            config: ArcSwap::from_pointee(top_config.app.clone()),
             db_conn,
             db_replica,
+            expired_logins_cleared: AtomicU64::new(0),
+            failed_login_verifications: AtomicU64::new(0),
             frontend_port: frontend_port.clone(),
             frontend_ip_rate_limiter,
             frontend_registered_user_rate_limiter,
+            frontend_keyed_ip_rate_limiter,
+            frontend_rpc_key_rate_limiter,
+            hmac_signatures_seen,
             hostname,
             http_client,
             influxdb_client,
@@ -594,21 +925,51 @@ impl Web3ProxyApp {
             ip_semaphores,
             jsonrpc_response_cache,
             kafka_producer,
+            key_last_used,
+            latest_response_cache,
+            login_messages_per_ip,
+            login_messages_tarpitted: AtomicU64::new(0),
             login_rate_limiter,
+            maintenance_mode: AtomicBool::new(top_config.app.maintenance_mode),
+            pending_logins_created: AtomicU64::new(0),
             pending_transactions,
             pending_tx_sender,
             private_rpcs,
             prometheus_port: prometheus_port.clone(),
+            request_recorder_sender,
+            revert_webhooks,
             rpc_secret_key_cache,
+            shutdown_token,
+            slo_metrics: Default::default(),
             stat_sender,
+            stats_semaphore: Arc::new(Semaphore::new(top_config.app.stats_max_concurrent_queries)),
+            tier_metrics: Default::default(),
+            trace_response_cache,
             user_balance_cache,
             user_semaphores,
+            user_tier_cache,
             vredis_pool,
+            vredis_breaker,
             watch_consensus_head_receiver,
+            webhook_sender,
+            ws_connections: Default::default(),
+            ws_connections_per_ip_rejected: AtomicU64::new(0),
+            stale_response_cache,
+            stale_responses_served: AtomicU64::new(0),
+            ws_oversized_messages: AtomicU64::new(0),
         };
 
         let app = Arc::new(app);
 
+        // clear the latest cache whenever the head block changes. then warm the caches for the new block
+        {
+            let app = app.clone();
+
+            let handle = tokio::spawn(async move { app.new_head_cache_loop().await });
+
+            app_handles.push(handle);
+        }
+
         // watch for config changes
         // TODO: initial config reload should be from this channel. not from the call to spawn
 
@@ -655,10 +1016,25 @@ impl Web3ProxyApp {
         })
     }
 
+    /// Wait for the backend rpc tasks to exit. Only call this after cancelling `shutdown_token`!
+    pub async fn wait_for_rpcs_to_exit(&self) {
+        for rpcs in [
+            Some(&self.balanced_rpcs),
+            self.private_rpcs.as_ref(),
+            self.bundler_4337_rpcs.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            rpcs.wait_for_shutdown().await;
+        }
+    }
+
     pub async fn apply_top_config(&self, new_top_config: TopConfig) -> Web3ProxyResult<()> {
-        // TODO: also update self.config from new_top_config.app
         info!("applying new config");
 
+        self.apply_app_config(new_top_config.app);
+
         // connect to the backends
         self.balanced_rpcs
             .apply_server_configs(self, new_top_config.balanced_rpcs)
@@ -694,6 +1070,28 @@ impl Web3ProxyApp {
         Ok(())
     }
 
+    /// Options that are only read on startup keep their current values. Changes to them are logged and otherwise ignored
+    fn apply_app_config(&self, mut new_app_config: AppConfig) {
+        let current = self.config.load_full();
+
+        let ignored = current.keep_restart_only(&mut new_app_config);
+
+        if !ignored.is_empty() {
+            warn!(?ignored, "some config changes need a restart to apply");
+        }
+
+        if new_app_config == *current {
+            return;
+        }
+
+        set_params_logging(&new_app_config.params_logging);
+        set_no_servers_retry_after(new_app_config.no_servers_retry_after_seconds);
+
+        self.config.store(Arc::new(new_app_config));
+
+        info!("app config reloaded");
+    }
+
     pub fn head_block_receiver(&self) -> watch::Receiver<Option<Web3ProxyBlock>> {
         self.watch_consensus_head_receiver.clone()
     }
@@ -723,6 +1121,7 @@ impl Web3ProxyApp {
                 format!("http://127.0.0.1:{}", frontend_port)
                     .parse()
                     .unwrap(),
+                None,
                 self.http_client.clone(),
                 Duration::from_secs(10),
             )
@@ -753,6 +1152,19 @@ impl Web3ProxyApp {
             UserCount(-1)
         };
 
+        // -1 if there is no database
+        let pending_logins: i64 = if let Ok(db) = self.db_conn() {
+            match pending_login::Entity::find().count(db).await {
+                Ok(x) => x as i64,
+                Err(err) => {
+                    warn!(?err, "unable to count pending logins");
+                    -1
+                }
+            }
+        } else {
+            -1
+        };
+
         #[derive(Default, Serialize)]
         struct RecentCounts {
             one_week: i64,
@@ -789,10 +1201,10 @@ impl Web3ProxyApp {
                 let one_hour_ago = Utc::now().timestamp() - ONE_HOUR;
                 let one_minute_ago = Utc::now().timestamp() - ONE_MINUTE;
 
-                let recent_users_by_id = format!("recent_users:id:{}", self.config.chain_id);
-                let recent_users_by_ip = format!("recent_users:ip:{}", self.config.chain_id);
+                let recent_users_by_id = format!("recent_users:id:{}", self.config.load().chain_id);
+                let recent_users_by_ip = format!("recent_users:ip:{}", self.config.load().chain_id);
                 let recent_transactions =
-                    format!("eth_sendRawTransaction:{}", self.config.chain_id);
+                    format!("eth_sendRawTransaction:{}", self.config.load().chain_id);
 
                 match redis::pipe()
                     .atomic()
@@ -879,22 +1291,214 @@ impl Web3ProxyApp {
 
         #[derive(Serialize)]
         struct CombinedMetrics {
+            chain_reorgs: u64,
+            deep_chain_reorgs: u64,
+            expired_logins_cleared: u64,
+            failed_login_verifications: u64,
+            login_messages_tarpitted: u64,
+            malformed_responses: u64,
+            no_servers_synced: u64,
+            no_servers_synced_alerts: u64,
+            pending_logins: i64,
+            pending_logins_created: u64,
             recent_ip_counts: RecentCounts,
             recent_user_id_counts: RecentCounts,
             recent_tx_counts: RecentCounts,
+            redis_circuit_breaker_skips: u64,
+            redis_circuit_breaker_trips: u64,
+            response_audit_mismatches: u64,
+            response_audits: u64,
+            response_cache_lookups: u64,
+            response_cache_misses: u64,
+            revert_logs_dropped: u64,
+            revert_webhooks_skipped: u64,
+            stale_responses_served: u64,
             user_count: UserCount,
+            webhooks_dropped: u64,
+            ws_connections_per_ip_rejected: u64,
+            ws_oversized_messages: u64,
         }
 
         let metrics = CombinedMetrics {
+            chain_reorgs: CHAIN_REORGS.load(Ordering::Relaxed),
+            deep_chain_reorgs: DEEP_CHAIN_REORGS.load(Ordering::Relaxed),
+            expired_logins_cleared: self.expired_logins_cleared.load(Ordering::Relaxed),
+            failed_login_verifications: self.failed_login_verifications.load(Ordering::Relaxed),
+            login_messages_tarpitted: self.login_messages_tarpitted.load(Ordering::Relaxed),
+            malformed_responses: MALFORMED_RESPONSES.load(Ordering::Relaxed),
+            no_servers_synced: NO_SERVERS_SYNCED.load(Ordering::Relaxed),
+            no_servers_synced_alerts: NO_SERVERS_SYNCED_ALERTS.load(Ordering::Relaxed),
+            pending_logins,
+            pending_logins_created: self.pending_logins_created.load(Ordering::Relaxed),
             recent_ip_counts,
             recent_user_id_counts,
             recent_tx_counts,
+            redis_circuit_breaker_skips: REDIS_CIRCUIT_BREAKER_SKIPS.load(Ordering::Relaxed),
+            redis_circuit_breaker_trips: REDIS_CIRCUIT_BREAKER_TRIPS.load(Ordering::Relaxed),
+            response_audit_mismatches: RESPONSE_AUDIT_MISMATCHES.load(Ordering::Relaxed),
+            response_audits: RESPONSE_AUDITS.load(Ordering::Relaxed),
+            response_cache_lookups: RESPONSE_CACHE_LOOKUPS.load(Ordering::Relaxed),
+            response_cache_misses: RESPONSE_CACHE_MISSES.load(Ordering::Relaxed),
+            revert_logs_dropped: REVERT_LOGS_DROPPED.load(Ordering::Relaxed),
+            revert_webhooks_skipped: REVERT_WEBHOOKS_SKIPPED.load(Ordering::Relaxed),
+            stale_responses_served: self.stale_responses_served.load(Ordering::Relaxed),
             user_count,
+            webhooks_dropped: WEBHOOKS_DROPPED.load(Ordering::Relaxed),
+            ws_connections_per_ip_rejected: self
+                .ws_connections_per_ip_rejected
+                .load(Ordering::Relaxed),
+            ws_oversized_messages: self.ws_oversized_messages.load(Ordering::Relaxed),
         };
 
         // TODO: i don't like this library. it doesn't include HELP or TYPE lines and so our prometheus server fails to parse it
-        serde_prometheus::to_string(&metrics, Some("web3_proxy"), globals)
-            .expect("prometheus metrics should always serialize")
+        let mut serialized = serde_prometheus::to_string(&metrics, Some("web3_proxy"), globals)
+            .expect("prometheus metrics should always serialize");
+
+        // serde_prometheus can't label by tier, so these are written by hand
+        serialized.push_str(&self.tier_metrics.prometheus_text());
+
+        // same for the per-rpc gauges. private rpcs don't track a consensus head so only the balanced rpcs are included
+        serialized.push_str(&self.balanced_rpcs.head_prometheus_text());
+
+        serialized
+    }
+
+    /// Clear `latest_response_cache` on every new head block and then send `cache_warm_requests`.
+    /// Warming happens in the background so that a slow backend doesn't delay clearing the cache for the next block.
+    /// If the new head did not build on the last one, responses for the orphaned blocks are cleared too.
+    async fn new_head_cache_loop(self: Arc<Self>) -> Web3ProxyResult<()> {
+        let mut head_block_receiver = self.watch_consensus_head_receiver.clone();
+
+        let mut last_head_block = head_block_receiver.borrow_and_update().clone();
+
+        loop {
+            head_block_receiver
+                .changed()
+                .await
+                .web3_context("failed awaiting head block change")?;
+
+            let head_block = head_block_receiver.borrow_and_update().clone();
+
+            self.latest_response_cache.invalidate_all();
+
+            if let (Some(old), Some(new)) = (last_head_block.as_ref(), head_block.as_ref()) {
+                // the watch channel only keeps the latest head, so this might be several blocks past the last one
+                match self.balanced_rpcs.head_change(old, new) {
+                    HeadChange::Extended => {}
+                    HeadChange::Reorg { start, depth } => {
+                        self.check_reorg_depth(old, new, start, depth);
+
+                        self.invalidate_reorged_responses(start);
+                    }
+                    // without the common ancestor, clearing from the lower head is the best we can do
+                    HeadChange::Unknown { start } => self.invalidate_reorged_responses(start),
+                }
+            }
+
+            last_head_block = head_block;
+
+            if self.config.load().cache_warm_requests.is_empty() {
+                continue;
+            }
+
+            let app = self.clone();
+
+            tokio::spawn(async move { app.warm_response_caches().await });
+        }
+    }
+
+    /// Send a sample of requests to the recorder. The params are copied before caching can change them
+    fn record_request(&self, config: &AppConfig, request: &JsonRpcRequest) {
+        let sender = match self.request_recorder_sender.as_ref() {
+            Some(x) => x,
+            None => return,
+        };
+
+        // out of u16::MAX, the same as `response_audit_chance`
+        let chance = config.record_requests_chance;
+
+        if chance == 0
+            || nanorand::tls_rng().generate_range(0u16..u16::MAX) >= chance
+            || !is_recordable(&request.method)
+        {
+            return;
+        }
+
+        let _ = sender.send(RecordedRequest {
+            method: request.method.clone(),
+            params: request.params.clone(),
+        });
+    }
+
+    /// Blocks with `finality_confirmations` are not supposed to reorg. If one does, responses that were cached as final might be wrong
+    /// `depth` is how many of the old head's blocks were orphaned, found by walking back to the common ancestor
+    fn check_reorg_depth(
+        &self,
+        old: &Web3ProxyBlock,
+        new: &Web3ProxyBlock,
+        reorg_block: U64,
+        depth: u64,
+    ) {
+        let finality_confirmations = self.config.load().finality_confirmations();
+
+        if depth > finality_confirmations {
+            DEEP_CHAIN_REORGS.fetch_add(1, Ordering::Relaxed);
+
+            error!(
+                depth,
+                finality_confirmations,
+                %reorg_block,
+                %old,
+                %new,
+                "reorg deeper than finality_confirmations"
+            );
+        }
+    }
+
+    /// Every response cache with its name. The stale cache is only here if `stale_if_error_seconds` is on
+    pub fn response_caches(&self) -> Vec<(&'static str, &JsonRpcResponseCache)> {
+        let mut x = vec![
+            ("jsonrpc_response_cache", &self.jsonrpc_response_cache),
+            ("latest_response_cache", &self.latest_response_cache),
+            ("trace_response_cache", &self.trace_response_cache),
+        ];
+
+        if let Some(stale_response_cache) = self.stale_response_cache.as_ref() {
+            x.push(("stale_response_cache", stale_response_cache));
+        }
+
+        x
+    }
+
+    /// Responses keyed by block hash are still correct after a reorg, so only responses that depend on a block number are cleared
+    fn invalidate_reorged_responses(&self, reorg_block: U64) {
+        for cache in [&self.jsonrpc_response_cache, &self.trace_response_cache] {
+            if let Err(err) =
+                cache.invalidate_entries_if(move |_, v: &CachedResponse| v.is_reorged(&reorg_block))
+            {
+                error!(?err, "failed clearing reorged responses");
+            }
+        }
+    }
+
+    /// The responses are thrown away. Sending the requests is enough to cache them
+    async fn warm_response_caches(self: &Arc<Self>) {
+        let config = self.config.load_full();
+
+        let warmed = join_all(config.cache_warm_requests.iter().map(|x| async move {
+            let response = self
+                .internal_request::<_, Box<RawValue>>(&x.method, x.params.clone())
+                .await;
+
+            (x, response)
+        }))
+        .await;
+
+        for (x, response) in warmed {
+            if let Err(err) = response {
+                debug!(?err, method = %x.method, "failed warming the response cache");
+            }
+        }
     }
 
     /// make an internal request with stats and caching
@@ -920,7 +1524,11 @@ impl Web3ProxyApp {
         // TODO: proper ids
         let request = JsonRpcRequest::new(JsonRpcId::Number(1), method.to_string(), json!(params))?;
 
-        let (_, response, _) = self.proxy_request(request, authorization, None).await;
+        let config = self.config.load_full();
+
+        let (_, response, _) = self
+            .proxy_request(request, authorization, None, &config)
+            .await;
 
         if let Some(result) = response.result {
             let result = serde_json::from_str(result.get())?;
@@ -935,17 +1543,24 @@ impl Web3ProxyApp {
     }
 
     /// send the request or batch of requests to the approriate RPCs
+    /// `config` is the snapshot taken when the request started. a reload in the middle of the request doesn't change it
     pub async fn proxy_web3_rpc(
         self: &Arc<Self>,
+        config: &AppConfig,
         authorization: Arc<Authorization>,
         request: JsonRpcRequestEnum,
     ) -> Web3ProxyResult<(StatusCode, JsonRpcForwardedResponseEnum, Vec<Arc<Web3Rpc>>)> {
         // trace!(?request, "proxy_web3_rpc");
 
-        let response = match request {
+        let result_pointer = authorization
+            .result_pointer
+            .clone()
+            .filter(|_| config.result_pointer_header);
+
+        let mut response = match request {
             JsonRpcRequestEnum::Single(request) => {
                 let (status_code, response, rpcs) = self
-                    .proxy_request(request, authorization.clone(), None)
+                    .proxy_request(request, authorization.clone(), None, config)
                     .await;
 
                 (
@@ -956,7 +1571,7 @@ impl Web3ProxyApp {
             }
             JsonRpcRequestEnum::Batch(requests) => {
                 let (responses, rpcs) = self
-                    .proxy_web3_rpc_requests(&authorization, requests)
+                    .proxy_web3_rpc_requests(config, &authorization, requests)
                     .await?;
 
                 // TODO: real status code. if an error happens, i don't think we are following the spec here
@@ -968,21 +1583,30 @@ impl Web3ProxyApp {
             }
         };
 
+        // this is after the stats and caches so that they have the whole result
+        if let Some(result_pointer) = result_pointer {
+            response.1.project_results(&result_pointer)?;
+        }
+
         Ok(response)
     }
 
     /// cut up the request and send to potentually different servers
+    /// Client ids are never sent upstream. Each element gets a new id from the backend's provider and
+    /// `proxy_request` puts the client's id back on the response, so batches that share ids can't be mixed up.
     /// TODO: make sure this isn't a problem
     async fn proxy_web3_rpc_requests(
         self: &Arc<Self>,
+        config: &AppConfig,
         authorization: &Arc<Authorization>,
-        requests: Vec<JsonRpcRequest>,
+        mut requests: Vec<JsonRpcRequest>,
     ) -> Web3ProxyResult<(Vec<JsonRpcForwardedResponse>, Vec<Arc<Web3Rpc>>)> {
         // TODO: we should probably change ethers-rs to support this directly. they pushed this off to v2 though
         let num_requests = requests.len();
 
         if num_requests == 0 {
-            return Ok((vec![], vec![]));
+            // the spec says an empty batch gets a single "invalid request" error
+            return Err(Web3ProxyError::BadRequest("empty batch".into()));
         }
 
         // get the head block now so that any requests that need it all use the same block
@@ -993,17 +1617,37 @@ impl Web3ProxyApp {
             .ok_or(Web3ProxyError::NoServersSynced)?
             .clone();
 
+        // every request in the batch counts against the limits. otherwise batching would get around them
+        // requests after the ones that fit in the limits get the error instead of being sent. only sent requests use quota
+        let (num_allowed, limited) = self.rate_limit_batch(authorization, num_requests).await;
+
+        let rejected = requests.split_off(num_allowed);
+
         // TODO: use streams and buffers so we don't overwhelm our server
-        let responses = join_all(
+        let mut responses = join_all(
             requests
                 .into_iter()
                 .map(|request| {
-                    self.proxy_request(request, authorization.clone(), Some(&head_block))
+                    self.proxy_request(request, authorization.clone(), Some(&head_block), config)
                 })
                 .collect::<Vec<_>>(),
         )
         .await;
 
+        if let Some(limited) = limited {
+            trace!(num_allowed, num_requests, ?limited, "batch is over a limit");
+
+            let (status_code, response_data) = limited.as_response_parts();
+
+            responses.extend(rejected.into_iter().map(|request| {
+                (
+                    status_code,
+                    JsonRpcForwardedResponse::from_response_data(response_data.clone(), request.id),
+                    vec![],
+                )
+            }));
+        }
+
         let mut collected: Vec<JsonRpcForwardedResponse> = Vec::with_capacity(num_requests);
         let mut collected_rpc_names: HashSet<String> = HashSet::new();
         let mut collected_rpcs: Vec<Arc<Web3Rpc>> = vec![];
@@ -1027,6 +1671,17 @@ impl Web3ProxyApp {
         Ok((collected, collected_rpcs))
     }
 
+    /// Send an event to the user's webhook (if they have one) in the background
+    pub fn notify_webhook(&self, user_id: u64, event: WebhookEvent) {
+        if user_id == 0 {
+            return;
+        }
+
+        if let Some(webhook_sender) = self.webhook_sender.as_ref() {
+            send_webhook(webhook_sender, WebhookNotification::new(user_id, event));
+        }
+    }
+
     #[inline]
     pub fn db_conn(&self) -> Web3ProxyResult<&DatabaseConnection> {
         self.db_conn.as_ref().ok_or(Web3ProxyError::NoDatabase)
@@ -1042,11 +1697,21 @@ impl Web3ProxyApp {
         }
     }
 
+    /// Use this for reads that don't need to see a write from the same request. Writes and transactions use `db_conn`.
+    /// If no `db_replica_url` is configured, this is the primary database.
     #[inline]
     pub fn db_replica(&self) -> Web3ProxyResult<&DatabaseReplica> {
         self.db_replica.as_ref().ok_or(Web3ProxyError::NoDatabase)
     }
 
+    /// Hold this while running a stats query. Fails immediately instead of queueing when too many are already running
+    pub fn stats_permit(&self) -> Web3ProxyResult<OwnedSemaphorePermit> {
+        self.stats_semaphore
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Web3ProxyError::StatsBusy)
+    }
+
     pub async fn redis_conn(&self) -> Web3ProxyResult<redis_rate_limiter::RedisConnection> {
         match self.vredis_pool.as_ref() {
             None => Err(Web3ProxyError::NoDatabase),
@@ -1063,11 +1728,26 @@ impl Web3ProxyApp {
     /// if no protected rpcs are configured, then some public rpcs are used instead
     async fn try_send_protected<P: JsonRpcParams>(
         self: &Arc<Self>,
+        config: &AppConfig,
         method: &str,
         params: &P,
         request_metadata: &Arc<RequestMetadata>,
     ) -> Web3ProxyResult<Box<RawValue>> {
-        if let Some(protected_rpcs) = self.private_rpcs.as_ref() {
+        let below_min_balance = match request_metadata.authorization.as_ref() {
+            Some(authorization) => {
+                self.private_tx_below_min_balance(config, authorization)
+                    .await
+            }
+            None => false,
+        };
+
+        if below_min_balance {
+            // the relays would drop this transaction. the public mempool is better than nothing
+            warn!(
+                rpc_key_id = ?request_metadata.authorization.as_ref().and_then(|x| x.checks.rpc_secret_key_id),
+                "balance is below private_tx_min_balance. sending transaction to the public rpcs"
+            );
+        } else if let Some(protected_rpcs) = self.private_rpcs.as_ref() {
             if !protected_rpcs.is_empty() {
                 let protected_response = protected_rpcs
                     .try_send_all_synced_connections(
@@ -1113,55 +1793,354 @@ impl Web3ProxyApp {
             .await
     }
 
-    /// proxy request with up to 3 tries.
-    async fn proxy_request(
-        self: &Arc<Self>,
-        mut request: JsonRpcRequest,
-        authorization: Arc<Authorization>,
-        head_block: Option<&Web3ProxyBlock>,
-    ) -> (StatusCode, JsonRpcForwardedResponse, Vec<Arc<Web3Rpc>>) {
-        let request_metadata = RequestMetadata::new(
-            self,
-            authorization,
-            RequestOrMethod::Request(&request),
-            head_block,
-        )
-        .await;
-
-        let response_id = request.id;
+    /// True if the key wants private transactions but its balance is below `private_tx_min_balance`.
+    /// Those transactions are sent to the public rpcs instead.
+    pub async fn private_tx_below_min_balance(
+        &self,
+        config: &AppConfig,
+        authorization: &Authorization,
+    ) -> bool {
+        if !authorization.checks.private_txs {
+            return false;
+        }
 
-        // TODO: trace/kafka log request.params before we send them to _proxy_request_with_caching which might modify them
+        let min_balance = match config.private_tx_min_balance {
+            Some(x) => x,
+            None => return false,
+        };
 
-        let (code, response_data) = match self
-            ._proxy_request_with_caching(
-                &request.method,
-                &mut request.params,
-                head_block,
-                Some(2),
-                &request_metadata,
-            )
-            .await
-        {
-            Ok(response_data) => {
-                request_metadata
-                    .error_response
-                    .store(false, Ordering::Release);
+        // without private rpcs, everything goes to the public rpcs anyways
+        if self.private_rpcs.as_ref().map_or(true, |x| x.is_empty()) {
+            return false;
+        }
 
-                (StatusCode::OK, response_data)
-            }
-            Err(err) => {
-                request_metadata
-                    .error_response
-                    .store(true, Ordering::Release);
+        authorization.checks.latest_balance.read().await.remaining() < min_balance
+    }
 
-                err.as_response_parts()
-            }
+    /// Never return a lower `eth_blockNumber` to a session than it has already seen.
+    /// If the consensus head is behind the session's high-water mark, ask a backend that has caught up.
+    async fn monotonic_block_number(
+        &self,
+        authorization: &Authorization,
+        head_block_num: U64,
+        request_metadata: &Arc<RequestMetadata>,
+    ) -> U64 {
+        let (cache, session) = match (
+            self.block_number_high_water.as_ref(),
+            BlockNumberSession::new(authorization),
+        ) {
+            (Some(cache), Some(session)) => (cache, session),
+            _ => return head_block_num,
         };
 
-        let response = JsonRpcForwardedResponse::from_response_data(response_data, response_id);
+        let mark = cache
+            .get_with(session, async { Arc::new(AtomicU64::new(0)) })
+            .await;
 
-        // TODO: this serializes twice :/
-        request_metadata.add_response(ResponseOrBytes::Response(&response));
+        let block_num = match U64::from(mark.load(Ordering::Acquire)) {
+            high_water if high_water > head_block_num => {
+                match self
+                    .balanced_rpcs
+                    .try_proxy_connection::<_, U64>(
+                        "eth_blockNumber",
+                        &[(); 0],
+                        Some(request_metadata),
+                        Some(2),
+                        Some(Duration::from_secs(1)),
+                        Some(&high_water),
+                        None,
+                    )
+                    .await
+                {
+                    Ok(block_num) => block_num.max(high_water),
+                    Err(err) => {
+                        // no backend has caught up yet. repeating the mark is better than going backwards
+                        trace!(?err, %high_water, %head_block_num, "no rpc at the high-water mark");
+                        high_water
+                    }
+                }
+            }
+            _ => head_block_num,
+        };
+
+        // another request might have raised the mark while this one was waiting on a backend. never go below it
+        let previous = mark.fetch_max(block_num.as_u64(), Ordering::AcqRel);
+
+        block_num.max(U64::from(previous))
+    }
+
+    /// The chunk size to split an `eth_getLogs` request into. None if the range is small enough to send as is.
+    /// Errors if the range would need more than `get_logs_max_chunks` chunks.
+    fn get_logs_chunk_blocks(
+        &self,
+        config: &AppConfig,
+        params: &serde_json::Value,
+        from_block_num: Option<&U64>,
+        to_block_num: Option<&U64>,
+    ) -> Web3ProxyResult<Option<u64>> {
+        let chunk_blocks = match config.get_logs_chunk_blocks {
+            Some(x) if x > 0 => x,
+            _ => return Ok(None),
+        };
+
+        // without a fromBlock, the backend uses "latest" and the range is tiny
+        if params
+            .get(0)
+            .and_then(|x| x.get("fromBlock"))
+            .map_or(true, |x| x.is_null())
+        {
+            return Ok(None);
+        }
+
+        let (from_block_num, to_block_num) = match (from_block_num, to_block_num) {
+            (Some(from), Some(to)) if to > from => (from.as_u64(), to.as_u64()),
+            _ => return Ok(None),
+        };
+
+        let num_blocks = to_block_num - from_block_num + 1;
+
+        if num_blocks <= chunk_blocks {
+            return Ok(None);
+        }
+
+        let max_blocks = chunk_blocks.saturating_mul(config.get_logs_max_chunks);
+
+        if num_blocks > max_blocks {
+            return Err(Web3ProxyError::BadRequest(
+                format!(
+                    "eth_getLogs range of {} blocks is larger than the maximum of {} blocks",
+                    num_blocks, max_blocks
+                )
+                .into(),
+            ));
+        }
+
+        Ok(Some(chunk_blocks))
+    }
+
+    /// Send a copy of the request to a different rpc in the background and compare the responses.
+    /// The rpcs that already tried this request are skipped
+    fn spawn_response_audit(
+        &self,
+        method: &str,
+        params: &serde_json::Value,
+        response: Arc<RawValue>,
+        request_metadata: &RequestMetadata,
+        min_block_needed: Option<U64>,
+        max_block_needed: Option<U64>,
+    ) {
+        let balanced_rpcs = self.balanced_rpcs.clone();
+        let method = method.to_string();
+        let params = params.clone();
+        let skip_rpcs = request_metadata.backend_requests.lock().clone();
+
+        tokio::spawn(async move {
+            balanced_rpcs
+                .audit_response(
+                    &method,
+                    &params,
+                    &response,
+                    skip_rpcs,
+                    min_block_needed.as_ref(),
+                    max_block_needed.as_ref(),
+                )
+                .await
+        });
+    }
+
+    /// How much longer this request can spend on backend rpcs.
+    /// The `X-Timeout` header and `public_method_limits` can lower `request_timeout_seconds`, but not raise it.
+    fn request_timeout_budget(
+        &self,
+        config: &AppConfig,
+        request_metadata: &RequestMetadata,
+    ) -> Web3ProxyResult<Duration> {
+        let max = Duration::from_secs(config.request_timeout_seconds);
+
+        let mut timeout = request_metadata
+            .authorization
+            .as_ref()
+            .and_then(|x| x.timeout)
+            .map_or(max, |x| x.min(max));
+
+        if request_metadata
+            .authorization
+            .as_ref()
+            .map_or(false, |x| x.is_public())
+        {
+            if let Some(timeout_ms) = config
+                .public_method_limits
+                .get(request_metadata.method.as_ref())
+                .and_then(|x| x.timeout_ms)
+            {
+                timeout = timeout.min(Duration::from_millis(timeout_ms));
+            }
+        }
+
+        let remaining = timeout.saturating_sub(request_metadata.start_instant.elapsed());
+
+        if remaining.is_zero() {
+            return Err(Web3ProxyError::Timeout(None));
+        }
+
+        Ok(remaining)
+    }
+
+    /// Send an `eth_getLogs` request as several smaller ranges and join the logs back together in order.
+    /// At most `get_logs_chunk_concurrency` chunks are in flight at once.
+    #[allow(clippy::too_many_arguments)]
+    async fn proxy_get_logs_chunks(
+        &self,
+        config: &AppConfig,
+        params: &serde_json::Value,
+        from_block_num: U64,
+        to_block_num: U64,
+        chunk_blocks: u64,
+        request_metadata: &Arc<RequestMetadata>,
+        max_tries: Option<usize>,
+        max_wait: Duration,
+    ) -> Web3ProxyResult<Arc<RawValue>> {
+        let chunk_concurrency = config.get_logs_chunk_concurrency.max(1);
+
+        let mut chunks = vec![];
+
+        let mut chunk_start = from_block_num;
+        while chunk_start <= to_block_num {
+            let chunk_end = (chunk_start + chunk_blocks - 1).min(to_block_num);
+
+            let mut chunk_params = params.clone();
+
+            // get_logs_chunk_blocks already checked that this is an object with a fromBlock
+            if let Some(filter) = chunk_params.get_mut(0).and_then(|x| x.as_object_mut()) {
+                filter.insert("fromBlock".to_string(), json!(chunk_start));
+                filter.insert("toBlock".to_string(), json!(chunk_end));
+            }
+
+            chunks.push((chunk_params, chunk_start, chunk_end));
+
+            chunk_start = chunk_end + 1;
+        }
+
+        trace!(num_chunks = chunks.len(), "splitting eth_getLogs");
+
+        let responses: Vec<Web3ProxyResult<Vec<Box<RawValue>>>> =
+            futures::stream::iter(chunks.iter())
+                .map(|(chunk_params, chunk_start, chunk_end)| {
+                    self.balanced_rpcs
+                        .try_proxy_connection::<_, Vec<Box<RawValue>>>(
+                            "eth_getLogs",
+                            chunk_params,
+                            Some(request_metadata),
+                            max_tries,
+                            Some(max_wait),
+                            Some(chunk_start),
+                            Some(chunk_end),
+                        )
+                })
+                .buffered(chunk_concurrency)
+                .collect()
+                .await;
+
+        let mut logs = vec![];
+        for response in responses {
+            logs.extend(response?);
+        }
+
+        let logs = serde_json::value::to_raw_value(&logs)?;
+
+        Ok(logs.into())
+    }
+
+    /// proxy request with up to 3 tries.
+    async fn proxy_request(
+        self: &Arc<Self>,
+        mut request: JsonRpcRequest,
+        authorization: Arc<Authorization>,
+        head_block: Option<&Web3ProxyBlock>,
+        config: &AppConfig,
+    ) -> (StatusCode, JsonRpcForwardedResponse, Vec<Arc<Web3Rpc>>) {
+        // checked before renaming so that clients hear about the name they used
+        let deprecation = deprecation_warning(&request.method, config);
+
+        // rename before the metadata is created so that stats and compute units use the standard method
+        if let Some(method) = config.method_aliases.get(&request.method) {
+            trace!(alias=%request.method, %method, "renaming method");
+            request.method = method.clone();
+        }
+
+        // check the envelope before the metadata is created so that it sees the normalized request
+        let validated = request.validate(config.strict_jsonrpc_version);
+
+        let request_metadata = RequestMetadata::new(
+            self,
+            authorization,
+            RequestOrMethod::Request(&request),
+            head_block,
+        )
+        .await;
+
+        // the id stays here. the backend's provider picks its own id for the upstream request
+        let response_id = request.id;
+
+        if validated.is_ok() {
+            self.record_request(config, &request);
+        }
+
+        // TODO: trace/kafka log request.params before we send them to _proxy_request_with_caching which might modify them
+
+        // the key is made before _proxy_request_with_caching pins the params to a block
+        let stale_key = self.stale_response_key(config, &request.method, &request.params);
+
+        let response_data = match validated {
+            Ok(()) => {
+                self._proxy_request_with_caching(
+                    config,
+                    &request.method,
+                    &mut request.params,
+                    head_block,
+                    Some(2),
+                    &request_metadata,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+
+        let (response_data, stale_seconds) = self
+            .stale_if_error(&request.method, stale_key, response_data)
+            .await;
+
+        let (code, response_data) = match response_data {
+            Ok(response_data) => {
+                request_metadata
+                    .error_response
+                    .store(false, Ordering::Release);
+
+                (StatusCode::OK, response_data)
+            }
+            Err(err) => {
+                request_metadata
+                    .error_response
+                    .store(true, Ordering::Release);
+
+                err.as_response_parts()
+            }
+        };
+
+        let mut response = JsonRpcForwardedResponse::from_response_data(response_data, response_id);
+
+        response.stale_seconds = stale_seconds;
+
+        if let Some(deprecation) = deprecation {
+            if config.deprecation_notes {
+                response.warning = Some(deprecation.clone());
+            }
+
+            response.deprecation = Some(deprecation);
+        }
+
+        // TODO: this serializes twice :/
+        request_metadata.add_response(ResponseOrBytes::Response(&response));
 
         let rpcs = request_metadata.backend_rpcs_used();
 
@@ -1171,122 +2150,214 @@ impl Web3ProxyApp {
         (code, response, rpcs)
     }
 
+    /// None if `stale_if_error_seconds` is off or the method's responses are never cached
+    fn stale_response_key(
+        &self,
+        config: &AppConfig,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Option<u64> {
+        self.stale_response_cache.as_ref()?;
+
+        if !is_cached(method, config) {
+            return None;
+        }
+
+        let normalize = config.normalize_cache_params;
+
+        Some(JsonRpcQueryCacheKey::new(None, None, method, params, false, normalize).hash())
+    }
+
+    /// Save successful responses. If the backends are down, answer with a saved response instead of the error.
+    /// The age of the saved response is returned with it
+    async fn stale_if_error(
+        &self,
+        method: &str,
+        stale_key: Option<u64>,
+        response_data: Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>>,
+    ) -> (
+        Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>>,
+        Option<u64>,
+    ) {
+        let (cache, stale_key) = match (self.stale_response_cache.as_ref(), stale_key) {
+            (Some(cache), Some(stale_key)) => (cache, stale_key),
+            _ => return (response_data, None),
+        };
+
+        match response_data {
+            Ok(x @ JsonRpcResponseEnum::Result { .. }) => {
+                cache
+                    .insert(stale_key, CachedResponse::new(method, x.clone(), None))
+                    .await;
+
+                (Ok(x), None)
+            }
+            Err(err) if err.is_backend_outage() => match cache.get(&stale_key) {
+                Some(x) => {
+                    self.stale_responses_served.fetch_add(1, Ordering::Relaxed);
+
+                    let age = x.cached_at.elapsed();
+
+                    debug!(?age, ?err, "serving a stale response");
+
+                    (Ok(x.response), Some(age.as_secs()))
+                }
+                None => (Err(err), None),
+            },
+            x => (x, None),
+        }
+    }
+
+    /// Checks that the key (or lack of one) is allowed to call this method.
+    /// Subscriptions skip `proxy_request`, so the websocket frontend calls this before `eth_subscribe`
+    pub fn method_access_error(
+        config: &AppConfig,
+        authorization: &Authorization,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Option<JsonRpcErrorData> {
+        if authorization.is_public() {
+            if let Some(public_allowed_methods) = config.public_allowed_methods.as_ref() {
+                if !public_allowed_methods.contains(method) {
+                    return Some(JsonRpcErrorData {
+                        message: format!("the method {} requires an api key", method).into(),
+                        code: Web3ProxyErrorCode::AccessDenied.jsonrpc_code(),
+                        data: None,
+                    });
+                }
+            }
+        }
+
+        let scope = authorization.checks.scope?;
+
+        if !scope.allows_method(method) {
+            return Some(JsonRpcErrorData {
+                message: format!("the method {} is not allowed for {} keys", method, scope).into(),
+                code: Web3ProxyErrorCode::AccessDenied.jsonrpc_code(),
+                data: None,
+            });
+        }
+
+        if method == "eth_subscribe" {
+            if let Some(subscribe_to) = params.get(0).and_then(|x| x.as_str()) {
+                if !scope.allows_subscription(subscribe_to) {
+                    return Some(JsonRpcErrorData {
+                        message: format!(
+                            "{} subscriptions are not allowed for {} keys",
+                            subscribe_to, scope
+                        )
+                        .into(),
+                        code: Web3ProxyErrorCode::AccessDenied.jsonrpc_code(),
+                        data: None,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
     /// main logic for proxy_cached_request but in a dedicated function so the try operator is easy to use
     /// TODO: how can we make this generic?
     async fn _proxy_request_with_caching(
         self: &Arc<Self>,
+        config: &AppConfig,
         method: &str,
         params: &mut serde_json::Value,
         head_block: Option<&Web3ProxyBlock>,
         max_tries: Option<usize>,
         request_metadata: &Arc<RequestMetadata>,
     ) -> Web3ProxyResult<JsonRpcResponseEnum<Arc<RawValue>>> {
-        // TODO: don't clone into a new string?
-        let request_method = method.to_string();
-
         let authorization = request_metadata.authorization.clone().unwrap_or_default();
 
-        // TODO: serve net_version without querying the backend
-        // TODO: don't force RawValue
-        let response_data: JsonRpcResponseEnum<Arc<RawValue>> = match request_method.as_ref() {
-            // lots of commands are blocked
-            method @ ("db_getHex"
-            | "db_getString"
-            | "db_putHex"
-            | "db_putString"
-            | "debug_accountRange"
-            | "debug_backtraceAt"
-            | "debug_blockProfile"
-            | "debug_bundler_clearState"
-            | "debug_bundler_dumpMempool"
-            | "debug_bundler_sendBundleNow"
-            | "debug_chaindbCompact"
-            | "debug_chaindbProperty"
-            | "debug_cpuProfile"
-            | "debug_freeOSMemory"
-            | "debug_freezeClient"
-            | "debug_gcStats"
-            | "debug_goTrace"
-            | "debug_memStats"
-            | "debug_mutexProfile"
-            | "debug_setBlockProfileRate"
-            | "debug_setGCPercent"
-            | "debug_setHead"
-            | "debug_setMutexProfileFraction"
-            | "debug_standardTraceBadBlockToFile"
-            | "debug_standardTraceBlockToFile"
-            | "debug_startCPUProfile"
-            | "debug_startGoTrace"
-            | "debug_stopCPUProfile"
-            | "debug_stopGoTrace"
-            | "debug_writeBlockProfile"
-            | "debug_writeMemProfile"
-            | "debug_writeMutexProfile"
-            | "erigon_cacheCheck"
-            | "eth_compileLLL"
-            | "eth_compileSerpent"
-            | "eth_compileSolidity"
-            | "eth_getCompilers"
-            | "eth_sendTransaction"
-            | "eth_sign"
-            | "eth_signTransaction"
-            | "eth_submitHashrate"
-            | "eth_submitWork"
-            | "les_addBalance"
-            | "les_setClientParams"
-            | "les_setDefaultParams"
-            | "miner_setEtherbase"
-            | "miner_setExtra"
-            | "miner_setGasLimit"
-            | "miner_setGasPrice"
-            | "miner_start"
-            | "miner_stop"
-            | "personal_ecRecover"
-            | "personal_importRawKey"
-            | "personal_listAccounts"
-            | "personal_lockAccount"
-            | "personal_newAccount"
-            | "personal_sendTransaction"
-            | "personal_sign"
-            | "personal_unlockAccount"
-            | "shh_addToGroup"
-            | "shh_getFilterChanges"
-            | "shh_getMessages"
-            | "shh_hasIdentity"
-            | "shh_newFilter"
-            | "shh_newGroup"
-            | "shh_newIdentity"
-            | "shh_post"
-            | "shh_uninstallFilter"
-            | "shh_version") => {
-                // i don't think we will ever support these methods. maybe do Forbidden?
-                // TODO: what error code?
-                JsonRpcErrorData::from(format!(
-                    "the method {} does not exist/is not available",
-                    method
-                )).into()
+        if config.denied_methods.contains(method) {
+            return Ok(JsonRpcErrorData {
+                message: format!("the method {} does not exist/is not available", method).into(),
+                code: Web3ProxyErrorCode::MethodNotFound.jsonrpc_code(),
+                data: None,
             }
-            // TODO: implement these commands
-            method @ ("eth_getFilterChanges"
-            | "eth_getFilterLogs"
-            | "eth_newBlockFilter"
-            | "eth_newFilter"
-            | "eth_newPendingTransactionFilter"
-            | "eth_pollSubscriptions"
-            | "eth_uninstallFilter") => {
-                // TODO: unsupported command stat. use the count to prioritize new features
-                // TODO: what error code?
-                JsonRpcErrorData::from(format!(
-                    "the method {} is not yet implemented. contact us if you need this",
+            .into());
+        }
+
+        // the proxy has no keys to sign with. backends might have keys of their own, so these are never forwarded
+        if rejects_wallet_method(method, config.wallet_methods) {
+            return Ok(JsonRpcErrorData {
+                message: format!(
+                    "the method {} is not supported by the proxy. sign locally and send with eth_sendRawTransaction",
                     method
-                ))
-                .into()
+                )
+                .into(),
+                code: Web3ProxyErrorCode::MethodNotFound.jsonrpc_code(),
+                data: None,
             }
-            method @ ("eth_sendUserOperation"
-            | "eth_estimateUserOperationGas"
-            | "eth_getUserOperationByHash"
-            | "eth_getUserOperationReceipt"
-            | "eth_supportedEntryPoints") => match self.bundler_4337_rpcs.as_ref() {
+            .into());
+        }
+
+        let public_method_limit = if authorization.is_public() {
+            config.public_method_limits.get(method).copied()
+        } else {
+            None
+        };
+
+        if let Some(err) = Self::method_access_error(config, &authorization, method, params) {
+            return Ok(err.into());
+        }
+
+        // TODO: serve net_version without querying the backend
+        // TODO: don't force RawValue
+        // `method_route` decides where each method goes. the arms here only say how
+        let response_data: JsonRpcResponseEnum<Arc<RawValue>> = match method_route(method) {
+            // lots of commands are blocked
+            MethodRoute::Unsupported => match method {
+                // TODO: implement these commands
+                "eth_getFilterChanges"
+                | "eth_getFilterLogs"
+                | "eth_newBlockFilter"
+                | "eth_newFilter"
+                | "eth_newPendingTransactionFilter"
+                | "eth_pollSubscriptions"
+                | "eth_uninstallFilter" => {
+                    // TODO: unsupported command stat. use the count to prioritize new features
+                    // TODO: what error code?
+                    JsonRpcErrorData::from(format!(
+                        "the method {} is not yet implemented. contact us if you need this",
+                        method
+                    ))
+                    .into()
+                }
+
+                "eth_subscribe" => JsonRpcErrorData {
+                    message: "notifications not supported. eth_subscribe is only available over a websocket".into(),
+                    code: Web3ProxyErrorCode::MethodNotFound.jsonrpc_code(),
+                    data: None,
+                }
+                .into(),
+                "eth_unsubscribe" => JsonRpcErrorData {
+                    message: "notifications not supported. eth_unsubscribe is only available over a websocket".into(),
+                    code: Web3ProxyErrorCode::MethodNotFound.jsonrpc_code(),
+                    data: None,
+                }.into(),
+
+                "test" => JsonRpcErrorData {
+                    message: "The method test does not exist/is not available.".into(),
+                    code: Web3ProxyErrorCode::MethodNotFound.jsonrpc_code(),
+                    data: None,
+                }.into(),
+
+                method if method.starts_with("admin_") => {
+                    // TODO: emit a stat? will probably just be noise
+                    return Err(Web3ProxyError::AccessDenied("admin methods are not allowed".into()));
+                }
+                method => {
+                    // i don't think we will ever support these methods. maybe do Forbidden?
+                    // TODO: what error code?
+                    JsonRpcErrorData::from(format!(
+                        "the method {} does not exist/is not available",
+                        method
+                    )).into()
+                }
+            },
+            MethodRoute::Bundler4337 => match self.bundler_4337_rpcs.as_ref() {
                 Some(bundler_4337_rpcs) => {
                     // TODO: timeout
                     let x = bundler_4337_rpcs
@@ -1309,107 +2380,111 @@ impl Web3ProxyApp {
                     return Err(Web3ProxyError::NoServersSynced);
                 }
             },
-            "eth_accounts" => JsonRpcResponseEnum::from(serde_json::Value::Array(vec![])),
-            "eth_blockNumber" => {
-                match head_block.cloned().or(self.balanced_rpcs.head_block()) {
-                    Some(head_block) => JsonRpcResponseEnum::from(json!(head_block.number())),
-                    None => {
-                        return Err(Web3ProxyError::NoServersSynced);
-                    }
-                }
-            }
-            "eth_chainId" => JsonRpcResponseEnum::from(json!(U64::from(self.config.chain_id))),
-            // TODO: eth_callBundle (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_callbundle)
-            // TODO: eth_cancelPrivateTransaction (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_cancelprivatetransaction, but maybe just reject)
-            // TODO: eth_sendPrivateTransaction (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_sendprivatetransaction)
-            "eth_coinbase" => {
-                // no need for serving coinbase
-                JsonRpcResponseEnum::from(json!(Address::zero()))
-            }
-            "eth_estimateGas" => {
-                // TODO: timeout
-                let mut gas_estimate = self
-                    .balanced_rpcs
-                    .try_proxy_connection::<_, U256>(
-                        method,
-                        params,
-                        Some(request_metadata),
-                        max_tries,
-                        Some(Duration::from_secs(30)),
-                        None,
-                        None,
-                    )
-                    .await?;
-
-                let gas_increase = if let Some(gas_increase_percent) =
-                    self.config.gas_increase_percent
-                {
-                    let gas_increase = gas_estimate * gas_increase_percent / U256::from(100);
+            MethodRoute::Local => match method {
+                "eth_accounts" => JsonRpcResponseEnum::from(serde_json::Value::Array(vec![])),
+                "eth_blockNumber" => {
+                    let head_block_num = match head_block.cloned().or(self.balanced_rpcs.head_block()) {
+                        Some(head_block) => *head_block.number(),
+                        None => {
+                            return Err(Web3ProxyError::NoServersSynced);
+                        }
+                    };
 
-                    let min_gas_increase = self.config.gas_increase_min.unwrap_or_default();
+                    let block_num = self
+                        .monotonic_block_number(&authorization, head_block_num, request_metadata)
+                        .await;
 
-                    gas_increase.max(min_gas_increase)
-                } else {
-                    self.config.gas_increase_min.unwrap_or_default()
-                };
+                    JsonRpcResponseEnum::from(json!(block_num))
+                }
+                "eth_chainId" => JsonRpcResponseEnum::from(json!(U64::from(config.chain_id))),
+                // TODO: eth_callBundle (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_callbundle)
+                // TODO: eth_cancelPrivateTransaction (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_cancelprivatetransaction, but maybe just reject)
+                // TODO: eth_sendPrivateTransaction (https://docs.flashbots.net/flashbots-auction/searchers/advanced/rpc-endpoint#eth_sendprivatetransaction)
+                "eth_coinbase" => {
+                    // no need for serving coinbase
+                    JsonRpcResponseEnum::from(json!(Address::zero()))
+                }
 
-                gas_estimate += gas_increase;
+                // TODO: eth_gasPrice that does awesome magic to predict the future
+                "eth_hashrate" => JsonRpcResponseEnum::from(json!(U64::zero())),
 
-                // TODO: from_serializable?
-                JsonRpcResponseEnum::from(json!(gas_estimate))
-            }
-            "eth_getTransactionReceipt" | "eth_getTransactionByHash" => {
-                // try to get the transaction without specifying a min_block_height
-                // TODO: timeout
+                "eth_mining" => JsonRpcResponseEnum::from(serde_json::Value::Bool(false)),
 
-                let mut response_data = self
-                    .balanced_rpcs
-                    .try_proxy_connection::<_, Box<RawValue>>(
-                        method,
-                        params,
-                        Some(request_metadata),
-                        max_tries,
-                        Some(Duration::from_secs(30)),
-                        None,
-                        None,
-                    )
-                    .await;
+                "eth_syncing" => {
+                    // no stats on this. its cheap
+                    // TODO: return a real response if all backends are syncing or if no servers in sync
+                    // TODO: const
+                    JsonRpcResponseEnum::from(serde_json::Value::Bool(false))
+                }
 
-                // if we got "null", it is probably because the tx is old. retry on nodes with old block data
-                let try_archive = if let Ok(value) = &response_data {
-                    value.get() == "null"
-                } else {
-                    true
-                };
+                "net_listening" => {
+                    // TODO: only true if there are some backends on balanced_rpcs?
+                    // TODO: const
+                    JsonRpcResponseEnum::from(serde_json::Value::Bool(true))
+                }
+                "net_peerCount" => 
+                    JsonRpcResponseEnum::from(json!(U64::from(self.balanced_rpcs.num_synced_rpcs())))
+                ,
+                "web3_clientVersion" => 
+                    JsonRpcResponseEnum::from(serde_json::Value::String(APP_USER_AGENT.to_string()))
+                ,
+                "web3_sha3" => {
+                    // returns Keccak-256 (not the standardized SHA3-256) of the given data.
+                    // TODO: timeout
+                    match &params {
+                        serde_json::Value::Array(params) => {
+                            // TODO: make a struct and use serde conversion to clean this up
+                            if params.len() != 1
+                                || !params.get(0).map(|x| x.is_string()).unwrap_or(false)
+                            {
+                                // TODO: what error code?
+                                // TODO: use Web3ProxyError::BadRequest
+                                JsonRpcErrorData {
+                                    message: "Invalid request".into(),
+                                    code: Web3ProxyErrorCode::InvalidRequest.jsonrpc_code(),
+                                    data: None
+                                }.into()
+                            } else {
+                                // TODO: BadRequest instead of web3_context
+                                let param = Bytes::from_str(
+                                    params[0]
+                                        .as_str()
+                                        .ok_or(Web3ProxyError::ParseBytesError(None))
+                                        .web3_context("parsing params 0 into str then bytes")?,
+                                )
+                                .map_err(|x| {
+                                    trace!("bad request: {:?}", x);
+                                    Web3ProxyError::BadRequest(
+                                        "param 0 could not be read as H256".into(),
+                                    )
+                                })?;
 
-                if try_archive {
-                    request_metadata
-                        .archive_request
-                        .store(true, atomic::Ordering::Release);
+                                let hash = H256::from(keccak256(param));
 
-                    response_data = self
-                        .balanced_rpcs
-                        .try_proxy_connection::<_, Box<RawValue>>(
-                            method,
-                            params,
-                            Some(request_metadata),
-                            max_tries,
-                            Some(Duration::from_secs(30)),
-                            // TODO: should this be block 0 instead?
-                            Some(&U64::one()),
-                            None,
-                        )
-                        .await;
+                                JsonRpcResponseEnum::from(json!(hash))
+                            }
+                        }
+                        _ => {
+                            // TODO: this needs the correct error code in the response
+                            // TODO: Web3ProxyError::BadRequest instead?
+                            JsonRpcErrorData {
+                                message: "invalid request".into(),
+                                code: Web3ProxyErrorCode::InvalidRequest.jsonrpc_code(),
+                                data: None,
+                            }.into()
+                        }
+                    }
                 }
 
-                response_data.try_into()?
-            }
-            // TODO: eth_gasPrice that does awesome magic to predict the future
-            "eth_hashrate" => JsonRpcResponseEnum::from(json!(U64::zero())),
-            "eth_mining" => JsonRpcResponseEnum::from(serde_json::Value::Bool(false)),
+                method => {
+                    return Err(Web3ProxyError::NotImplemented(
+                        format!("{} is routed to the proxy but has no handler", method).into(),
+                    ));
+                }
+            },
             // TODO: eth_sendBundle (flashbots/eden command)
             // broadcast transactions to all private rpcs at once
-            "eth_sendRawTransaction" => {
+            MethodRoute::Private => {
                 // TODO: decode the transaction
 
                 // TODO: error if the chain_id is incorrect
@@ -1418,6 +2493,7 @@ impl Web3ProxyApp {
                     Duration::from_secs(30),
                     self
                         .try_send_protected(
+                            config,
                             method,
                             params,
                             request_metadata,
@@ -1461,7 +2537,7 @@ impl Web3ProxyApp {
                         let rlp = Rlp::new(params.as_ref());
 
                         if let Ok(tx) = Transaction::decode(&rlp) {
-                            // TODO: decode earlier and confirm that tx.chain_id (if set) matches self.config.chain_id
+                            // TODO: decode earlier and confirm that tx.chain_id (if set) matches config.chain_id
                             let tx_hash = json!(tx.hash());
 
                             trace!("tx_hash: {:#?}", tx_hash);
@@ -1473,12 +2549,13 @@ impl Web3ProxyApp {
 
                 // emit transaction count stats
                 // TODO: use this cache to avoid sending duplicate transactions?
-                if let Some(ref salt) = self.config.public_recent_ips_salt {
+                if let Some(ref salt) = config.public_recent_ips_salt {
                     if let JsonRpcResponseEnum::Result { value, .. } = &response {
                         let now = Utc::now().timestamp();
                         let app = self.clone();
 
                         let salted_tx_hash = format!("{}:{}", salt, value.get());
+                        let chain_id = config.chain_id;
 
                         let f = async move {
                             match app.redis_conn().await {
@@ -1487,7 +2564,7 @@ impl Web3ProxyApp {
                                         Bytes::from(keccak256(salted_tx_hash.as_bytes()));
 
                                     let recent_tx_hash_key =
-                                        format!("eth_sendRawTransaction:{}", app.config.chain_id);
+                                        format!("eth_sendRawTransaction:{}", chain_id);
 
                                     redis_conn
                                         .zadd(recent_tx_hash_key, hashed_tx_hash.to_string(), now)
@@ -1511,192 +2588,338 @@ impl Web3ProxyApp {
 
                 response
             }
-            "eth_syncing" => {
-                // no stats on this. its cheap
-                // TODO: return a real response if all backends are syncing or if no servers in sync
-                // TODO: const
-                JsonRpcResponseEnum::from(serde_json::Value::Bool(false))
-            }
-            "eth_subscribe" => JsonRpcErrorData {
-                message: "notifications not supported. eth_subscribe is only available over a websocket".into(),
-                code: -32601,
-                data: None,
-            }
-            .into(),
-            "eth_unsubscribe" => JsonRpcErrorData {
-                message: "notifications not supported. eth_unsubscribe is only available over a websocket".into(),
-                code: -32601,
-                data: None,
-            }.into(),
-            "net_listening" => {
-                // TODO: only true if there are some backends on balanced_rpcs?
-                // TODO: const
-                JsonRpcResponseEnum::from(serde_json::Value::Bool(true))
-            }
-            "net_peerCount" => 
-                JsonRpcResponseEnum::from(json!(U64::from(self.balanced_rpcs.num_synced_rpcs())))
-            ,
-            "web3_clientVersion" => 
-                JsonRpcResponseEnum::from(serde_json::Value::String(APP_USER_AGENT.to_string()))
-            ,
-            "web3_sha3" => {
-                // returns Keccak-256 (not the standardized SHA3-256) of the given data.
-                // TODO: timeout
-                match &params {
-                    serde_json::Value::Array(params) => {
-                        // TODO: make a struct and use serde conversion to clean this up
-                        if params.len() != 1
-                            || !params.get(0).map(|x| x.is_string()).unwrap_or(false)
-                        {
-                            // TODO: what error code?
-                            // TODO: use Web3ProxyError::BadRequest
-                            JsonRpcErrorData {
-                                message: "Invalid request".into(),
-                                code: -32600,
-                                data: None
-                            }.into()
-                        } else {
-                            // TODO: BadRequest instead of web3_context
-                            let param = Bytes::from_str(
-                                params[0]
-                                    .as_str()
-                                    .ok_or(Web3ProxyError::ParseBytesError(None))
-                                    .web3_context("parsing params 0 into str then bytes")?,
-                            )
-                            .map_err(|x| {
-                                trace!("bad request: {:?}", x);
-                                Web3ProxyError::BadRequest(
-                                    "param 0 could not be read as H256".into(),
-                                )
-                            })?;
+            MethodRoute::Balanced => match method {
+                "eth_estimateGas" => {
+                    // TODO: timeout
+                    let mut gas_estimate = self
+                        .balanced_rpcs
+                        .try_proxy_connection::<_, U256>(
+                            method,
+                            params,
+                            Some(request_metadata),
+                            max_tries,
+                            Some(Duration::from_secs(30)),
+                            None,
+                            None,
+                        )
+                        .await?;
 
-                            let hash = H256::from(keccak256(param));
+                    let gas_increase = if let Some(gas_increase_percent) =
+                        config.gas_increase_percent
+                    {
+                        let gas_increase = gas_estimate * gas_increase_percent / U256::from(100);
 
-                            JsonRpcResponseEnum::from(json!(hash))
-                        }
+                        let min_gas_increase = config.gas_increase_min.unwrap_or_default();
+
+                        gas_increase.max(min_gas_increase)
+                    } else {
+                        config.gas_increase_min.unwrap_or_default()
+                    };
+
+                    gas_estimate += gas_increase;
+
+                    // some clients ask for extra padding so that they don't have to add it themselves
+                    if let Some(gas_estimate_percent) = authorization.gas_estimate_percent() {
+                        gas_estimate = pad_gas_estimate(gas_estimate, gas_estimate_percent);
                     }
-                    _ => {
-                        // TODO: this needs the correct error code in the response
-                        // TODO: Web3ProxyError::BadRequest instead?
-                        JsonRpcErrorData {
-                            message: "invalid request".into(),
-                            code: StatusCode::BAD_REQUEST.as_u16().into(),
-                            data: None,
-                        }.into()
+
+                    // TODO: from_serializable?
+                    JsonRpcResponseEnum::from(json!(gas_estimate))
+                }
+                "eth_getTransactionReceipt" | "eth_getTransactionByHash" => {
+                    // try to get the transaction without specifying a min_block_height
+                    // TODO: timeout
+
+                    let mut response_data = self
+                        .balanced_rpcs
+                        .try_proxy_connection::<_, Box<RawValue>>(
+                            method,
+                            params,
+                            Some(request_metadata),
+                            max_tries,
+                            Some(Duration::from_secs(30)),
+                            None,
+                            None,
+                        )
+                        .await;
+
+                    // if we got "null", it is probably because the tx is old. retry on nodes with old block data
+                    let try_archive = if let Ok(value) = &response_data {
+                        value.get() == "null"
+                    } else {
+                        true
+                    };
+
+                    if try_archive {
+                        request_metadata
+                            .archive_request
+                            .store(true, atomic::Ordering::Release);
+
+                        response_data = self
+                            .balanced_rpcs
+                            .try_proxy_connection::<_, Box<RawValue>>(
+                                method,
+                                params,
+                                Some(request_metadata),
+                                max_tries,
+                                Some(Duration::from_secs(30)),
+                                // TODO: should this be block 0 instead?
+                                Some(&U64::one()),
+                                None,
+                            )
+                            .await;
                     }
+
+                    response_data.try_into()?
                 }
-            }
-            "test" => JsonRpcErrorData {
-                message: "The method test does not exist/is not available.".into(),
-                code: -32601,
-                data: None,
-            }.into(),
-            // anything else gets sent to backend rpcs and cached
-            method => {
-                if method.starts_with("admin_") {
-                    // TODO: emit a stat? will probably just be noise
-                    return Err(Web3ProxyError::AccessDenied("admin methods are not allowed".into()));
+
+                "eth_maxPriorityFeePerGas" if config.max_priority_fee_fallback => {
+                    let response_data: JsonRpcResponseEnum<Arc<RawValue>> = self
+                        .balanced_rpcs
+                        .try_proxy_connection::<_, Box<RawValue>>(
+                            method,
+                            params,
+                            Some(request_metadata),
+                            max_tries,
+                            Some(Duration::from_secs(30)),
+                            None,
+                            None,
+                        )
+                        .await
+                        .try_into()?;
+
+                    match response_data {
+                        JsonRpcResponseEnum::RpcError { ref error_data, .. }
+                            if is_method_not_found(error_data) =>
+                        {
+                            // the backend does not have this method. build the answer from recent rewards instead
+                            let fee_history = self
+                                .balanced_rpcs
+                                .try_proxy_connection::<_, FeeHistory>(
+                                    "eth_feeHistory",
+                                    &fee_history_params(),
+                                    Some(request_metadata),
+                                    max_tries,
+                                    Some(Duration::from_secs(30)),
+                                    None,
+                                    None,
+                                )
+                                .await?;
+
+                            JsonRpcResponseEnum::from(json!(suggested_priority_fee(&fee_history)))
+                        }
+                        x => x,
+                    }
                 }
 
-                // TODO: if no servers synced, wait for them to be synced? probably better to error and let haproxy retry another server
-                let head_block: Web3ProxyBlock = head_block
-                    .cloned()
-                    .or_else(|| self.balanced_rpcs.head_block())
-                    .ok_or(Web3ProxyError::NoServersSynced)?;
-
-                // we do this check before checking caches because it might modify the request params
-                // TODO: add a stat for archive vs full since they should probably cost different
-                // TODO: this cache key can be rather large. is that okay?
-                let cache_key: Option<JsonRpcQueryCacheKey> = match CacheMode::new(
-                    &authorization,
-                    method,
-                    params,
-                    &head_block,
-                    &self.balanced_rpcs,
-                )
-                .await
-                {
-                    CacheMode::CacheSuccessForever => Some(JsonRpcQueryCacheKey::new(
-                        None,
-                        None,
-                        method,
-                        params,
-                        false,
-                    )),
-                    CacheMode::CacheNever => None,
-                    CacheMode::Cache {
-                        block,
-                        cache_errors,
-                    } => {
-                        let block_depth = (head_block.number().saturating_sub(*block.num())).as_u64();
-
-                        if block_depth > self.config.archive_depth {
-                            trace!(%block_depth, archive_depth=%self.config.archive_depth);
-
-                            request_metadata
-                                .archive_request
-                                .store(true, atomic::Ordering::Release);
+                // anything else gets sent to backend rpcs and cached
+                method => {
+                    // trace requests only go to backends that serve them
+                    if !self.balanced_rpcs.serves_method(method) {
+                        return Err(Web3ProxyError::NotImplemented(
+                            format!("{} is not served by any backend", method).into(),
+                        ));
+                    }
+
+                    // the third param of eth_call is a state override set. not every backend supports it
+                    if method == "eth_call" && params.get(2).map_or(false, |x| !x.is_null()) {
+                        if !self.balanced_rpcs.supports_state_overrides() {
+                            return Err(Web3ProxyError::NotImplemented(
+                                "eth_call state overrides".into(),
+                            ));
                         }
 
-                        Some(JsonRpcQueryCacheKey::new(
-                            Some(block),
+                        request_metadata
+                            .state_override_request
+                            .store(true, atomic::Ordering::Release);
+                    }
+
+                    // TODO: if no servers synced, wait for them to be synced? probably better to error and let haproxy retry another server
+                    let head_block: Web3ProxyBlock = head_block
+                        .cloned()
+                        .or_else(|| self.balanced_rpcs.head_block())
+                        .ok_or(Web3ProxyError::NoServersSynced)?;
+
+                    let trace_cache_confirmations = (config.trace_cache_max_bytes > 0).then_some(config.trace_cache_confirmations);
+                    let finality_confirmations = config.finality_confirmations();
+                    let normalize_cache_params = config.normalize_cache_params;
+
+                    // we do this check before checking caches because it might modify the request params
+                    // TODO: add a stat for archive vs full since they should probably cost different
+                    // TODO: this cache key can be rather large. is that okay?
+                    let cache_key: Option<JsonRpcQueryCacheKey> = match CacheMode::new(
+                        &authorization,
+                        method,
+                        params,
+                        &head_block,
+                        &self.balanced_rpcs,
+                        trace_cache_confirmations,
+                        finality_confirmations,
+                    )
+                    .await
+                    {
+                        CacheMode::CacheSuccessForever => Some(JsonRpcQueryCacheKey::new(
+                            None,
                             None,
                             method,
                             params,
+                            false,
+                            normalize_cache_params,
+                        )),
+                        CacheMode::CacheNever => None,
+                        CacheMode::Cache {
+                            block,
                             cache_errors,
-                        ))
-                    }
-                    CacheMode::CacheRange {
-                        from_block,
-                        to_block,
-                        cache_errors,
-                    } => {
-                        let block_depth = (head_block.number().saturating_sub(*from_block.num())).as_u64();
-
-                        if block_depth > self.config.archive_depth {
-                            trace!(%block_depth, archive_depth=%self.config.archive_depth);
-
-                            request_metadata
-                                .archive_request
-                                .store(true, atomic::Ordering::Release);
+                        } => {
+                            let block_depth = (head_block.number().saturating_sub(*block.num())).as_u64();
+
+                            if block_depth > config.archive_depth {
+                                trace!(%block_depth, archive_depth=%config.archive_depth);
+
+                                request_metadata
+                                    .archive_request
+                                    .store(true, atomic::Ordering::Release);
+                            }
+
+                            Some(JsonRpcQueryCacheKey::new(
+                                Some(block),
+                                None,
+                                method,
+                                params,
+                                cache_errors,
+                                normalize_cache_params,
+                            ))
                         }
+                        CacheMode::CacheRange {
+                            from_block,
+                            to_block,
+                            cache_errors,
+                        } => {
+                            let block_depth = (head_block.number().saturating_sub(*from_block.num())).as_u64();
 
-                        Some(JsonRpcQueryCacheKey::new(
-                            Some(from_block),
-                            Some(to_block),
-                            method,
+                            if block_depth > config.archive_depth {
+                                trace!(%block_depth, archive_depth=%config.archive_depth);
+
+                                request_metadata
+                                    .archive_request
+                                    .store(true, atomic::Ordering::Release);
+                            }
+
+                            Some(JsonRpcQueryCacheKey::new(
+                                Some(from_block),
+                                Some(to_block),
+                                method,
+                                params,
+                                cache_errors,
+                                normalize_cache_params,
+                            ))
+                        }
+                    };
+
+                    // retries stop once this runs out so that we don't keep working on a request the client gave up on
+                    // TODO: different timeouts for different user tiers
+                    let backend_request_timetout = self.request_timeout_budget(config, request_metadata)?;
+
+                    // large eth_getLogs ranges are split up. check the size before spending time on the cache
+                    let get_logs_chunk_blocks = match (method, cache_key.as_ref()) {
+                        ("eth_getLogs", Some(cache_key)) => self.get_logs_chunk_blocks(
+                            config,
                             params,
-                            cache_errors,
-                        ))
-                    }
-                };
+                            cache_key.from_block_num(),
+                            cache_key.to_block_num(),
+                        )?,
+                        ("eth_getLogs", None) => {
+                            // uncached ranges are sent as they are, but ranges over the chunk cap are still rejected
+                            let (from_block_num, to_block_num) = get_logs_range(params, head_block.number()).unzip();
 
-                // TODO: different timeouts for different user tiers. get the duration out of the request_metadata
-                let backend_request_timetout = Duration::from_secs(240);
+                            self.get_logs_chunk_blocks(config, params, from_block_num.as_ref(), to_block_num.as_ref())?;
 
-                if let Some(cache_key) = cache_key {
-                    let from_block_num = cache_key.from_block_num().copied();
-                    let to_block_num = cache_key.to_block_num().copied();
-                    let cache_jsonrpc_errors = cache_key.cache_errors();
+                            None
+                        }
+                        _ => None,
+                    };
 
-                    // TODO: try to fetch out of s3
+                    if let Some(cache_key) = cache_key {
+                        let from_block_num = cache_key.from_block_num().copied();
+                        let to_block_num = cache_key.to_block_num().copied();
+                        let cache_jsonrpc_errors = cache_key.cache_errors();
 
-                    self
-                        .jsonrpc_response_cache
-                        .try_get_with::<_, Web3ProxyError>(cache_key.hash(), async {
-                            let response_data = timeout(
-                                backend_request_timetout + Duration::from_millis(100),
-                                self.balanced_rpcs
-                                    .try_proxy_connection::<_, Arc<RawValue>>(
-                                        method,
+                        // a reorg at or below this block clears the cached response
+                        let newest_block_num = to_block_num.or(from_block_num);
+
+                        // state reads at the head block are stale as soon as the next block arrives
+                        let response_cache = if to_block_num.is_none()
+                            && from_block_num.as_ref() == Some(head_block.number())
+                            && LATEST_STATE_METHODS.contains(&method)
+                        {
+                            &self.latest_response_cache
+                        } else if TRACE_CACHE_METHODS.contains(&method) {
+                            &self.trace_response_cache
+                        } else {
+                            &self.jsonrpc_response_cache
+                        };
+
+                        // TODO: try to fetch out of s3
+
+                        let cache_policy = authorization.checks.cache_policy;
+
+                        // the normal key changes with every head block. once the transaction's block is final, it is also saved under a key that doesn't
+                        let final_tx_key = FINAL_TX_METHODS
+                            .contains(&method)
+                            .then(|| {
+                                JsonRpcQueryCacheKey::new(None, None, method, params, false, normalize_cache_params)
+                                    .hash()
+                            });
+
+                        let params = &*params;
+
+                        let fetch = move || async move {
+                            RESPONSE_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+
+                            let response_data = if let (Some(chunk_blocks), Some(from_block_num), Some(to_block_num)) = (get_logs_chunk_blocks, from_block_num, to_block_num) {
+                                timeout(
+                                    backend_request_timetout + Duration::from_millis(100),
+                                    self.proxy_get_logs_chunks(
+                                        config,
                                         params,
-                                        Some(request_metadata),
+                                        from_block_num,
+                                        to_block_num,
+                                        chunk_blocks,
+                                        request_metadata,
                                         max_tries,
-                                        Some(backend_request_timetout),
-                                        from_block_num.as_ref(),
-                                        to_block_num.as_ref(),
+                                        backend_request_timetout,
                                     ))
-                                .await?;
+                                    .await?
+                            } else {
+                                let response_data = timeout(
+                                    backend_request_timetout + Duration::from_millis(100),
+                                    self.balanced_rpcs
+                                        .try_proxy_connection::<_, Arc<RawValue>>(
+                                            method,
+                                            params,
+                                            Some(request_metadata),
+                                            max_tries,
+                                            Some(backend_request_timetout),
+                                            from_block_num.as_ref(),
+                                            to_block_num.as_ref(),
+                                        ))
+                                    .await?;
+
+                                if let Ok(response) = &response_data {
+                                    if should_audit(config.response_audit_chance) {
+                                        self.spawn_response_audit(
+                                            method,
+                                            params,
+                                            response.clone(),
+                                            request_metadata,
+                                            from_block_num,
+                                            to_block_num,
+                                        );
+                                    }
+                                }
+
+                                response_data
+                            };
 
                             if !cache_jsonrpc_errors && let Err(err) = response_data {
                                 // if we are not supposed to cache jsonrpc errors,
@@ -1709,27 +2932,108 @@ impl Web3ProxyApp {
                                 // TODO: response data should maybe be Arc<JsonRpcResponseEnum<Box<RawValue>>>, but that's more work
                                 Ok(response_data)
                             }
-                        }).await?
-                } else {
-                    let x = timeout(
-                        backend_request_timetout + Duration::from_millis(100),
-                        self.balanced_rpcs
-                        .try_proxy_connection::<_, Arc<RawValue>>(
-                            method,
-                            params,
-                            Some(request_metadata),
-                            max_tries,
-                            Some(backend_request_timetout),
-                            None,
-                            None,
+                        };
+
+                        RESPONSE_CACHE_LOOKUPS.fetch_add(1, Ordering::Relaxed);
+
+                        // `no-cache` and `max-age` apply to final transactions too
+                        let final_tx = final_tx_key
+                            .and_then(|x| self.jsonrpc_response_cache.get(&x))
+                            .filter(|x| cache_policy.accepts(x));
+
+                        let used_final_tx = final_tx.is_some();
+
+                        let response_data = if let Some(final_tx) = final_tx {
+                            final_tx.response
+                        } else {
+                            let cache_key = cache_key.hash();
+
+                            match response_cache.get(&cache_key) {
+                                // a response that is too old for this request is skipped. the fresh one replaces it for everyone else too.
+                                // the old one is only replaced once the fresh one arrives. if the backend fails, other users still get it
+                                Some(cached) if !cache_policy.accepts(&cached) => {
+                                    let fresh = CachedResponse::new(method, fetch().await?, newest_block_num);
+
+                                    response_cache.insert(cache_key, fresh.clone()).await;
+
+                                    fresh.response
+                                }
+                                // everyone that asks for the same thing at once shares one backend request
+                                _ => response_cache
+                                    .try_get_with::<_, Web3ProxyError>(cache_key, async {
+                                        fetch()
+                                            .await
+                                            .map(|x| CachedResponse::new(method, x, newest_block_num))
+                                    })
+                                    .await?
+                                    .response,
+                            }
+                        };
+
+                        // like the normal key, a fresh response replaces the saved one
+                        if let Some(final_tx_key) = final_tx_key {
+                            if !used_final_tx {
+                                if let Some(tx_block_num) = final_tx_block(&response_data, head_block.number(), finality_confirmations) {
+                                    // a reorg deeper than finality_confirmations still clears this
+                                    self.jsonrpc_response_cache
+                                        .insert(final_tx_key, CachedResponse::new(method, response_data.clone(), Some(tx_block_num)))
+                                        .await;
+                                }
+                            }
+                        }
+
+                        response_data
+                    } else {
+                        let x = timeout(
+                            backend_request_timetout + Duration::from_millis(100),
+                            self.balanced_rpcs
+                            .try_proxy_connection::<_, Arc<RawValue>>(
+                                method,
+                                params,
+                                Some(request_metadata),
+                                max_tries,
+                                Some(backend_request_timetout),
+                                None,
+                                None,
+                            )
                         )
+                        .await??;
+
+                        x.into()
+                    }
+                }
+            },
+        };
+
+        if let Some(max_response_bytes) = public_method_limit.and_then(|x| x.max_response_bytes) {
+            if response_data.num_bytes() > max_response_bytes {
+                return Ok(JsonRpcErrorData {
+                    message: format!(
+                        "the response to {} is larger than {} bytes. larger responses require an api key",
+                        method, max_response_bytes
                     )
-                    .await??;
+                    .into(),
+                    code: Web3ProxyErrorCode::AccessDenied.jsonrpc_code(),
+                    data: None,
+                }
+                .into());
+            }
+        }
 
-                    x.into()
+        if let Some(max_response_bytes) = authorization.checks.max_response_bytes {
+            if response_data.num_bytes() > max_response_bytes {
+                return Ok(JsonRpcErrorData {
+                    message: format!(
+                        "the response to {} is larger than {} bytes. larger responses require a higher tier",
+                        method, max_response_bytes
+                    )
+                    .into(),
+                    code: Web3ProxyErrorCode::AccessDenied.jsonrpc_code(),
+                    data: None,
                 }
+                .into());
             }
-        };
+        }
 
         Ok(response_data)
     }
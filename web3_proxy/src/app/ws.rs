@@ -363,7 +363,7 @@ impl Web3ProxyApp {
                 if let Some(rate_limiter) = &self.frontend_ip_rate_limiter {
                     match rate_limiter
                         .throttle(
-                            authorization.ip,
+                            self.ip_limit_key(&authorization.ip),
                             authorization.checks.max_requests_per_period,
                             1,
                         )
@@ -4,6 +4,7 @@ use ethers::types::U256;
 use pagerduty_rs::eventsv2async::EventsV2 as PagerdutyAsyncEventsV2;
 use pagerduty_rs::eventsv2sync::EventsV2 as PagerdutySyncEventsV2;
 use sentry::types::Dsn;
+use std::time::Duration;
 use std::{
     borrow::Cow,
     fs, panic,
@@ -18,7 +19,8 @@ use web3_proxy::sub_commands;
 use web3_proxy::{
     app::APP_USER_AGENT,
     config::TopConfig,
-    relational_db::{get_db, get_migrated_db},
+    frontend::authorization::set_rpc_key_hash_secret,
+    relational_db::{get_db, get_migrated_db, DbPoolOptions},
 };
 
 #[cfg(feature = "mimalloc")]
@@ -29,7 +31,7 @@ use mimalloc::MiMalloc;
 static GLOBAL: MiMalloc = MiMalloc;
 
 #[cfg(feature = "deadlock_detection")]
-use {parking_lot::deadlock, std::thread, tokio::time::Duration};
+use {parking_lot::deadlock, std::thread};
 
 #[derive(Debug, FromArgs)]
 /// Command line interface for admins to interact with web3_proxy
@@ -46,6 +48,26 @@ pub struct Web3ProxyCli {
     #[argh(option)]
     pub db_url: Option<String>,
 
+    /// minimum size of the database connection pool. Defaults to db_min_connections in the config or 1
+    #[argh(option)]
+    pub db_min_connections: Option<u32>,
+
+    /// maximum size of the database connection pool. Defaults to db_max_connections in the config or 4
+    #[argh(option)]
+    pub db_max_connections: Option<u32>,
+
+    /// seconds to wait for a free database connection. Defaults to db_acquire_timeout_seconds in the config or 5
+    #[argh(option)]
+    pub db_acquire_timeout_seconds: Option<u64>,
+
+    /// seconds before an idle database connection is closed. Defaults to db_idle_timeout_seconds in the config
+    #[argh(option)]
+    pub db_idle_timeout_seconds: Option<u64>,
+
+    /// if no config, what secret rpc keys are hashed with. Required whenever there is a database
+    #[argh(option)]
+    pub rpc_key_hash_secret: Option<String>,
+
     /// if no config, what sentry url should the client should connect to
     #[argh(option)]
     pub sentry_url: Option<Dsn>,
@@ -64,17 +86,24 @@ enum SubCommand {
     ChangeUserTierByAddress(sub_commands::ChangeUserTierByAddressSubCommand),
     ChangeUserTierByKey(sub_commands::ChangeUserTierByKeySubCommand),
     CheckConfig(sub_commands::CheckConfigSubCommand),
+    CheckReferrals(sub_commands::CheckReferralsSubCommand),
     CountUsers(sub_commands::CountUsersSubCommand),
     CreateKey(sub_commands::CreateKeySubCommand),
     CreateUser(sub_commands::CreateUserSubCommand),
     DropMigrationLock(sub_commands::DropMigrationLockSubCommand),
+    DryRun(sub_commands::DryRunSubCommand),
+    ExportStats(sub_commands::ExportStatsSubCommand),
+    HashRpcKeys(sub_commands::HashRpcKeysSubCommand),
     MigrateStatsToV2(sub_commands::MigrateStatsToV2SubCommand),
     Pagerduty(sub_commands::PagerdutySubCommand),
     PopularityContest(sub_commands::PopularityContestSubCommand),
     Proxyd(sub_commands::ProxydSubCommand),
+    ReplayRecording(sub_commands::ReplayRecordingSubCommand),
+    ReplayRequest(sub_commands::ReplayRequestSubCommand),
     RpcAccounting(sub_commands::RpcAccountingSubCommand),
     SearchKafka(sub_commands::SearchKafkaSubCommand),
     Sentryd(sub_commands::SentrydSubCommand),
+    StaleKeys(sub_commands::StaleKeysSubCommand),
     TransferKey(sub_commands::TransferKeySubCommand),
     UserExport(sub_commands::UserExportSubCommand),
     UserImport(sub_commands::UserImportSubCommand),
@@ -183,10 +212,27 @@ fn main() -> anyhow::Result<()> {
             cli_config.db_url = top_config.app.db_url.clone();
         }
 
+        cli_config.db_min_connections = cli_config
+            .db_min_connections
+            .or(top_config.app.db_min_connections);
+        cli_config.db_max_connections = cli_config
+            .db_max_connections
+            .or(top_config.app.db_max_connections);
+        cli_config.db_acquire_timeout_seconds = cli_config
+            .db_acquire_timeout_seconds
+            .or(top_config.app.db_acquire_timeout_seconds);
+        cli_config.db_idle_timeout_seconds = cli_config
+            .db_idle_timeout_seconds
+            .or(top_config.app.db_idle_timeout_seconds);
+
         if let Some(sentry_url) = top_config.app.sentry_url.clone() {
             cli_config.sentry_url = Some(sentry_url);
         }
 
+        if cli_config.rpc_key_hash_secret.is_none() {
+            cli_config.rpc_key_hash_secret = top_config.app.rpc_key_hash_secret.clone();
+        }
+
         if top_config.app.chain_id == 137 {
             // TODO: these numbers are arbitrary. i think the maticnetwork/erigon fork has a bug
             if top_config.app.gas_increase_min.is_none() {
@@ -203,6 +249,33 @@ fn main() -> anyhow::Result<()> {
         (None, None)
     };
 
+    // subcommands that create or find rpc keys need this. set it before anything can touch the database
+    set_rpc_key_hash_secret(
+        cli_config.rpc_key_hash_secret.as_deref(),
+        cli_config.db_url.is_some(),
+    )?;
+
+    let db_pool_options = {
+        let default = DbPoolOptions::default();
+
+        let min_connections = cli_config
+            .db_min_connections
+            .unwrap_or(default.min_connections);
+
+        DbPoolOptions {
+            min_connections,
+            max_connections: cli_config
+                .db_max_connections
+                .unwrap_or(default.max_connections)
+                .max(min_connections),
+            acquire_timeout: cli_config
+                .db_acquire_timeout_seconds
+                .map(Duration::from_secs)
+                .unwrap_or(default.acquire_timeout),
+            idle_timeout: cli_config.db_idle_timeout_seconds.map(Duration::from_secs),
+        }
+    };
+
     let sentry_env = std::env::var("SENTRY_ENV")
         .map(Cow::from)
         .unwrap_or("production".into());
@@ -307,7 +380,7 @@ fn main() -> anyhow::Result<()> {
                     "'--config' (with a db) or '--db-url' is required to run change_admin_status",
                 );
 
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -316,7 +389,7 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run change_user_addres");
 
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -325,7 +398,7 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run change_user_tier");
 
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -334,7 +407,7 @@ fn main() -> anyhow::Result<()> {
                     "'--config' (with a db) or '--db-url' is required to run change_user_tier_by_address",
                 );
 
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -343,17 +416,27 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run change_user_tier_by_key");
 
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
             SubCommand::CheckConfig(x) => x.main().await,
+            SubCommand::DryRun(x) => x.main().await,
+            SubCommand::CheckReferrals(x) => {
+                let db_url = cli_config.db_url.expect(
+                    "'--config' (with a db) or '--db-url' is required to run check_referrals",
+                );
+
+                let db_conn = get_db(db_url, db_pool_options).await?;
+
+                x.main(&db_conn).await
+            }
             SubCommand::CreateKey(x) => {
                 let db_url = cli_config
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run create a key");
 
-                let db_conn = get_migrated_db(db_url, 1, 1).await?;
+                let db_conn = get_migrated_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -362,7 +445,16 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run create_user");
 
-                let db_conn = get_migrated_db(db_url, 1, 1).await?;
+                let db_conn = get_migrated_db(db_url, db_pool_options).await?;
+
+                x.main(&db_conn).await
+            }
+            SubCommand::StaleKeys(x) => {
+                let db_url = cli_config
+                    .db_url
+                    .expect("'--config' (with a db) or '--db-url' is required to run stale_keys");
+
+                let db_conn = get_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -371,7 +463,7 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run count_users");
 
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -382,13 +474,37 @@ fn main() -> anyhow::Result<()> {
 
                 x.main(top_config, top_config_path, num_workers).await
             }
+            SubCommand::ReplayRecording(x) => x.main().await,
+            SubCommand::ReplayRequest(x) => {
+                let top_config = top_config.expect("--config is required to run replay_request");
+
+                x.main(top_config, num_workers).await
+            }
             SubCommand::DropMigrationLock(x) => {
                 let db_url = cli_config
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run drop_migration_lock");
 
                 // very intentionally, do NOT run migrations here. that would wait forever if the migration lock is abandoned
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
+
+                x.main(&db_conn).await
+            }
+            SubCommand::ExportStats(x) => {
+                let db_url = cli_config
+                    .db_url
+                    .expect("'--config' (with a db) or '--db-url' is required to run export_stats");
+
+                let db_conn = get_db(db_url, db_pool_options).await?;
+
+                x.main(&db_conn).await
+            }
+            SubCommand::HashRpcKeys(x) => {
+                let db_url = cli_config
+                    .db_url
+                    .expect("'--config' (with a db) or '--db-url' is required to run hash_rpc_keys");
+
+                let db_conn = get_migrated_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -402,7 +518,7 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run the migration from stats-mysql to stats-influx");
 
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
                 x.main(top_config, &db_conn).await
             }
             SubCommand::Pagerduty(x) => {
@@ -426,7 +542,7 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run rpc_accounting");
 
-                let db_conn = get_migrated_db(db_url, 1, 1).await?;
+                let db_conn = get_migrated_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -434,7 +550,7 @@ fn main() -> anyhow::Result<()> {
                 let db_url = cli_config
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run transfer_key");
-                let db_conn = get_db(db_url, 1, 1).await?;
+                let db_conn = get_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -443,7 +559,7 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run user_export");
 
-                let db_conn = get_migrated_db(db_url, 1, 1).await?;
+                let db_conn = get_migrated_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -452,7 +568,7 @@ fn main() -> anyhow::Result<()> {
                     .db_url
                     .expect("'--config' (with a db) or '--db-url' is required to run user_import");
 
-                let db_conn = get_migrated_db(db_url, 1, 1).await?;
+                let db_conn = get_migrated_db(db_url, db_pool_options).await?;
 
                 x.main(&db_conn).await
             }
@@ -1,6 +1,8 @@
 //! Helper functions for turning ether's BlockNumber into numbers and updating incoming queries to match.
 use crate::{
     errors::{Web3ProxyError, Web3ProxyResult},
+    redact::redact_params,
+    response_cache::JsonRpcResponseEnum,
     rpcs::blockchain::Web3ProxyBlock,
 };
 use anyhow::Context;
@@ -9,7 +11,9 @@ use ethers::{
     prelude::{BlockNumber, U64},
     types::H256,
 };
+use serde::Deserialize;
 use serde_json::json;
+use serde_json::value::RawValue;
 use std::sync::Arc;
 use tracing::{error, trace, warn};
 
@@ -174,6 +178,97 @@ pub async fn clean_block_number(
     }
 }
 
+/// The index of the BlockNumber in the params of methods that take one.
+/// The BlockNumber is usually the last element.
+/// None for methods that don't take a BlockNumber or that need special handling (like eth_getLogs).
+/// TODO: double check these. i think some of the getBlock stuff will never need archive
+pub fn block_param_index(method: &str) -> Option<usize> {
+    match method {
+        "eth_call" => Some(1),
+        "eth_estimateGas" => Some(1),
+        "eth_getBalance" => Some(1),
+        "eth_getBlockReceipts" => Some(0),
+        "eth_getBlockTransactionCountByNumber" => Some(0),
+        "eth_getCode" => Some(1),
+        "eth_getStorageAt" => Some(2),
+        "eth_getTransactionByBlockNumberAndIndex" => Some(0),
+        "eth_getTransactionCount" => Some(1),
+        "eth_getUncleByBlockNumberAndIndex" => Some(0),
+        "eth_getUncleCountByBlockNumber" => Some(0),
+        _ => None,
+    }
+}
+
+/// Traces of these methods never change once their block is confirmed.
+/// They are large, so they get their own cache.
+pub const TRACE_CACHE_METHODS: [&str; 4] = [
+    "debug_traceBlockByHash",
+    "debug_traceBlockByNumber",
+    "trace_block",
+    "trace_filter",
+];
+
+/// Transactions and receipts say which block they are in. Once that block is final, they never change.
+pub const FINAL_TX_METHODS: [&str; 2] = ["eth_getTransactionByHash", "eth_getTransactionReceipt"];
+
+/// true if the block is at least `confirmations` behind the head. blocks this old are not expected to reorg
+fn is_confirmed(head_block_num: &U64, block_num: &U64, confirmations: u64) -> bool {
+    head_block_num.saturating_sub(*block_num) >= U64::from(confirmations)
+}
+
+/// The block of a transaction or receipt if that block is final. None for pending transactions, nulls, and errors
+pub fn final_tx_block(
+    response: &JsonRpcResponseEnum<Arc<RawValue>>,
+    head_block_num: &U64,
+    finality_confirmations: u64,
+) -> Option<U64> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct TxBlock {
+        block_number: Option<U64>,
+    }
+
+    let value = match response {
+        JsonRpcResponseEnum::Result { value, .. } => value,
+        JsonRpcResponseEnum::RpcError { .. } => return None,
+    };
+
+    let block_num = serde_json::from_str::<TxBlock>(value.get())
+        .ok()?
+        .block_number?;
+
+    is_confirmed(head_block_num, &block_num, finality_confirmations).then_some(block_num)
+}
+
+/// The block range of an `eth_getLogs` filter with tags like "latest" resolved against the head.
+/// None without a fromBlock (the backend uses "latest") or if a block can't be parsed.
+pub fn get_logs_range(params: &serde_json::Value, head_block_num: &U64) -> Option<(U64, U64)> {
+    let filter = params.get(0)?;
+
+    let from_block: BlockNumber = serde_json::from_value(filter.get("fromBlock")?.clone()).ok()?;
+
+    let to_block = match filter.get("toBlock") {
+        None | Some(serde_json::Value::Null) => BlockNumber::Latest,
+        Some(x) => serde_json::from_value(x.clone()).ok()?,
+    };
+
+    Some((
+        BlockNumber_to_U64(from_block, head_block_num).0,
+        BlockNumber_to_U64(to_block, head_block_num).0,
+    ))
+}
+
+/// the block number of a block param. None for tags like "latest" that move with the head
+fn fixed_block_num(x: Option<&serde_json::Value>) -> Web3ProxyResult<Option<U64>> {
+    let x = x.ok_or_else(|| Web3ProxyError::BadRequest("invalid format. no block".into()))?;
+
+    match serde_json::from_value::<BlockNumber>(x.clone())? {
+        BlockNumber::Earliest => Ok(Some(U64::zero())),
+        BlockNumber::Number(x) => Ok(Some(x)),
+        _ => Ok(None),
+    }
+}
+
 /// TODO: change this to also return the hash needed?
 pub enum CacheMode {
     CacheSuccessForever,
@@ -192,14 +287,28 @@ pub enum CacheMode {
 }
 
 impl CacheMode {
+    /// `trace_confirmations` is how deep a block must be before its traces are cached. None never caches traces.
+    /// `finality_confirmations` is how deep a block must be before responses about it are kept across new head blocks.
     pub async fn new(
         authorization: &Arc<Authorization>,
         method: &str,
         params: &mut serde_json::Value,
         head_block: &Web3ProxyBlock,
         rpcs: &Web3Rpcs,
+        trace_confirmations: Option<u64>,
+        finality_confirmations: u64,
     ) -> Self {
-        match Self::try_new(authorization, method, params, head_block, rpcs).await {
+        match Self::try_new(
+            authorization,
+            method,
+            params,
+            head_block,
+            rpcs,
+            trace_confirmations,
+            finality_confirmations,
+        )
+        .await
+        {
             Ok(x) => x,
             Err(err) => {
                 warn!(?err, "unable to determine cache mode from params");
@@ -214,7 +323,23 @@ impl CacheMode {
         params: &mut serde_json::Value,
         head_block: &Web3ProxyBlock,
         rpcs: &Web3Rpcs,
+        trace_confirmations: Option<u64>,
+        finality_confirmations: u64,
     ) -> Web3ProxyResult<Self> {
+        if let Some(trace_confirmations) = trace_confirmations {
+            if TRACE_CACHE_METHODS.contains(&method) {
+                return Self::try_new_trace(
+                    authorization,
+                    method,
+                    params,
+                    head_block,
+                    rpcs,
+                    trace_confirmations,
+                )
+                .await;
+            }
+        }
+
         // some requests have potentially very large responses
         // TODO: only skip caching if the response actually is large
         if method.starts_with("trace_") || method == "debug_traceTransaction" {
@@ -230,12 +355,7 @@ impl CacheMode {
         }
 
         // get the index for the BlockNumber
-        // The BlockNumber is usually the last element.
-        // TODO: double check these. i think some of the getBlock stuff will never need archive
         let block_param_id = match method {
-            "eth_call" => 1,
-            "eth_estimateGas" => 1,
-            "eth_getBalance" => 1,
             "eth_getBlockByHash" => {
                 // TODO: double check that any node can serve this
                 // TODO: can a block change? like what if it gets orphaned?
@@ -243,19 +363,27 @@ impl CacheMode {
             }
             "eth_getBlockByNumber" => {
                 // TODO: double check that any node can serve this
-                // TODO: CacheSuccessForever if the block is old enough
+                // a final block is keyed by its own hash so that new head blocks don't clear it
+                if let Some(block_num) = fixed_block_num(params.get(0))? {
+                    if is_confirmed(head_block.number(), &block_num, finality_confirmations) {
+                        let (block_hash, _) = rpcs.block_hash(authorization, &block_num).await?;
+
+                        return Ok(CacheMode::Cache {
+                            block: BlockNumAndHash(block_num, block_hash),
+                            cache_errors: true,
+                        });
+                    }
+                }
+
                 return Ok(CacheMode::Cache {
                     block: head_block.into(),
                     cache_errors: true,
                 });
             }
-            "eth_getBlockReceipts" => 0,
             "eth_getBlockTransactionCountByHash" => {
                 // TODO: double check that any node can serve this
                 return Ok(CacheMode::CacheSuccessForever);
             }
-            "eth_getBlockTransactionCountByNumber" => 0,
-            "eth_getCode" => 1,
             "eth_getLogs" => {
                 // TODO: think about this more
                 // TODO: jsonrpc has a specific code for this
@@ -318,7 +446,6 @@ impl CacheMode {
                     });
                 }
             }
-            "eth_getStorageAt" => 2,
             "eth_getTransactionByHash" => {
                 // TODO: not sure how best to look these up
                 // try full nodes first. retry will use archive
@@ -332,8 +459,6 @@ impl CacheMode {
                 // try full nodes first. retry will use archive
                 return Ok(CacheMode::CacheSuccessForever);
             }
-            "eth_getTransactionByBlockNumberAndIndex" => 0,
-            "eth_getTransactionCount" => 1,
             "eth_getTransactionReceipt" => {
                 // TODO: not sure how best to look these up
                 // try full nodes first. retry will use archive
@@ -348,22 +473,23 @@ impl CacheMode {
                 // TODO: what happens if this block is uncled later?
                 return Ok(CacheMode::CacheSuccessForever);
             }
-            "eth_getUncleByBlockNumberAndIndex" => 0,
             "eth_getUncleCountByBlockHash" => {
                 // TODO: check a Cache of recent hashes
                 // try full nodes first. retry will use archive
                 // TODO: what happens if this block is uncled later?
                 return Ok(CacheMode::CacheSuccessForever);
             }
-            "eth_getUncleCountByBlockNumber" => 0,
-            _ => {
-                // some other command that doesn't take block numbers as an argument
-                // since we are caching with the head block, it should be safe to cache_errors
-                return Ok(CacheMode::Cache {
-                    block: head_block.into(),
-                    cache_errors: true,
-                });
-            }
+            method => match block_param_index(method) {
+                Some(x) => x,
+                None => {
+                    // some other command that doesn't take block numbers as an argument
+                    // since we are caching with the head block, it should be safe to cache_errors
+                    return Ok(CacheMode::Cache {
+                        block: head_block.into(),
+                        cache_errors: true,
+                    });
+                }
+            },
         };
 
         match clean_block_number(authorization, params, block_param_id, head_block, rpcs).await {
@@ -372,14 +498,14 @@ impl CacheMode {
                 cache_errors: true,
             }),
             Err(Web3ProxyError::NoBlocksKnown) => {
-                warn!(%method, ?params, "no servers available to get block from params");
+                warn!(%method, params=?redact_params(method, params), "no servers available to get block from params");
                 Ok(CacheMode::Cache {
                     block: head_block.into(),
                     cache_errors: true,
                 })
             }
             Err(err) => {
-                error!(%method, ?params, ?err, "could not get block from params");
+                error!(%method, params=?redact_params(method, params), ?err, "could not get block from params");
                 Ok(CacheMode::Cache {
                     block: head_block.into(),
                     cache_errors: true,
@@ -387,4 +513,151 @@ impl CacheMode {
             }
         }
     }
+
+    /// Traces are only cached once their block is confirmed. They are keyed by block hash so a reorg can't serve the wrong trace.
+    /// Backend errors are not cached because a node that can't trace is not the same as a block that can't be traced.
+    async fn try_new_trace(
+        authorization: &Arc<Authorization>,
+        method: &str,
+        params: &serde_json::Value,
+        head_block: &Web3ProxyBlock,
+        rpcs: &Web3Rpcs,
+        trace_confirmations: u64,
+    ) -> Web3ProxyResult<Self> {
+        match method {
+            "debug_traceBlockByHash" => {
+                let block_hash = params.get(0).ok_or_else(|| {
+                    Web3ProxyError::BadRequest("invalid format. no params".into())
+                })?;
+
+                let block_hash: H256 = serde_json::from_value(block_hash.clone())?;
+
+                let block = rpcs
+                    .block(authorization, &block_hash, None, Some(3), None)
+                    .await?;
+
+                if !is_confirmed(head_block.number(), block.number(), trace_confirmations) {
+                    return Ok(Self::CacheNever);
+                }
+
+                Ok(Self::Cache {
+                    block: (&block).into(),
+                    cache_errors: false,
+                })
+            }
+            "debug_traceBlockByNumber" | "trace_block" => {
+                let block_num = match fixed_block_num(params.get(0))? {
+                    Some(x) => x,
+                    None => return Ok(Self::CacheNever),
+                };
+
+                if !is_confirmed(head_block.number(), &block_num, trace_confirmations) {
+                    return Ok(Self::CacheNever);
+                }
+
+                let (block_hash, _) = rpcs.block_hash(authorization, &block_num).await?;
+
+                Ok(Self::Cache {
+                    block: BlockNumAndHash(block_num, block_hash),
+                    cache_errors: false,
+                })
+            }
+            "trace_filter" => {
+                let obj = params.get(0).and_then(|x| x.as_object()).ok_or_else(|| {
+                    Web3ProxyError::BadRequest("invalid format. params not object".into())
+                })?;
+
+                // a missing fromBlock or toBlock means "latest"
+                let from_block_num = match fixed_block_num(obj.get("fromBlock")) {
+                    Ok(Some(x)) => x,
+                    _ => return Ok(Self::CacheNever),
+                };
+                let to_block_num = match fixed_block_num(obj.get("toBlock")) {
+                    Ok(Some(x)) => x,
+                    _ => return Ok(Self::CacheNever),
+                };
+
+                if !is_confirmed(head_block.number(), &to_block_num, trace_confirmations) {
+                    return Ok(Self::CacheNever);
+                }
+
+                let (from_block_hash, _) = rpcs.block_hash(authorization, &from_block_num).await?;
+                let (to_block_hash, _) = rpcs.block_hash(authorization, &to_block_num).await?;
+
+                Ok(Self::CacheRange {
+                    from_block: BlockNumAndHash(from_block_num, from_block_hash),
+                    to_block: BlockNumAndHash(to_block_num, to_block_hash),
+                    cache_errors: false,
+                })
+            }
+            _ => Ok(Self::CacheNever),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{final_tx_block, fixed_block_num, get_logs_range};
+    use crate::response_cache::JsonRpcResponseEnum;
+    use ethers::types::U64;
+    use serde_json::json;
+
+    #[test]
+    fn test_fixed_block_num() {
+        assert_eq!(
+            fixed_block_num(Some(&json!("0x10"))).unwrap(),
+            Some(U64::from(16))
+        );
+        assert_eq!(
+            fixed_block_num(Some(&json!("earliest"))).unwrap(),
+            Some(U64::zero())
+        );
+        assert_eq!(fixed_block_num(Some(&json!("latest"))).unwrap(), None);
+        assert_eq!(fixed_block_num(Some(&json!("safe"))).unwrap(), None);
+
+        assert!(fixed_block_num(None).is_err());
+        assert!(fixed_block_num(Some(&json!({"foo": "bar"}))).is_err());
+    }
+
+    #[test]
+    fn test_final_tx_block() {
+        let head = U64::from(100);
+
+        let receipt: JsonRpcResponseEnum<_> =
+            json!({"blockNumber": "0x50", "status": "0x1"}).into();
+
+        assert_eq!(final_tx_block(&receipt, &head, 12), Some(U64::from(80)));
+        assert_eq!(final_tx_block(&receipt, &head, 21), None);
+
+        let pending: JsonRpcResponseEnum<_> = json!({"blockNumber": null}).into();
+
+        assert_eq!(final_tx_block(&pending, &head, 0), None);
+
+        let not_found: JsonRpcResponseEnum<_> = serde_json::Value::Null.into();
+
+        assert_eq!(final_tx_block(&not_found, &head, 0), None);
+    }
+
+    #[test]
+    fn test_get_logs_range() {
+        let head = U64::from(100);
+
+        assert_eq!(
+            get_logs_range(&json!([{"fromBlock": "0x10", "toBlock": "0x20"}]), &head),
+            Some((U64::from(16), U64::from(32)))
+        );
+        assert_eq!(
+            get_logs_range(&json!([{"fromBlock": "earliest"}]), &head),
+            Some((U64::zero(), head))
+        );
+        assert_eq!(
+            get_logs_range(&json!([{"fromBlock": "0x10", "toBlock": "latest"}]), &head),
+            Some((U64::from(16), head))
+        );
+
+        // the backend uses "latest" for both
+        assert_eq!(get_logs_range(&json!([{"toBlock": "0x20"}]), &head), None);
+        assert_eq!(get_logs_range(&json!([{"blockHash": "0x01"}]), &head), None);
+        assert_eq!(get_logs_range(&json!([{"fromBlock": "bad"}]), &head), None);
+    }
 }
@@ -0,0 +1,326 @@
+//! Rewrite params before they are hashed into a cache key so that requests that mean the same thing share a response.
+//!
+//! Only the cache key uses these params. The request sent to the backends is not changed.
+//! Each method lists what is at each position in its params. Unknown methods and positions are hashed as they are.
+use crate::block_number::BlockNumAndHash;
+use serde_json::{json, Value};
+
+/// What a param holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ParamKind {
+    /// a 20 byte address. checksummed and lowercase addresses are the same
+    Address,
+    /// a hex number. leading zeros and uppercase digits don't change it
+    Quantity,
+    /// a block number, tag, or hash. the block the cache key is for replaces it if they match
+    Block,
+    /// the transaction object of `eth_call` and `eth_estimateGas`
+    Call,
+    /// the filter object of `eth_getLogs`
+    Filter,
+    /// anything else. data and hashes are left alone
+    Other,
+}
+
+use ParamKind::*;
+
+fn param_kinds(method: &str) -> Option<&'static [ParamKind]> {
+    let x: &[ParamKind] = match method {
+        "eth_call" => &[Call, Block],
+        "eth_estimateGas" => &[Call, Block],
+        "eth_feeHistory" => &[Quantity, Block, Other],
+        "eth_getBalance" => &[Address, Block],
+        "eth_getBlockByNumber" => &[Block, Other],
+        "eth_getBlockReceipts" => &[Block],
+        "eth_getBlockTransactionCountByNumber" => &[Block],
+        "eth_getCode" => &[Address, Block],
+        "eth_getLogs" => &[Filter],
+        "eth_getProof" => &[Address, Other, Block],
+        "eth_getStorageAt" => &[Address, Quantity, Block],
+        "eth_getTransactionByBlockHashAndIndex" => &[Other, Quantity],
+        "eth_getTransactionByBlockNumberAndIndex" => &[Block, Quantity],
+        "eth_getTransactionCount" => &[Address, Block],
+        "eth_getUncleByBlockHashAndIndex" => &[Other, Quantity],
+        "eth_getUncleByBlockNumberAndIndex" => &[Block, Quantity],
+        "eth_getUncleCountByBlockNumber" => &[Block],
+        _ => return None,
+    };
+
+    Some(x)
+}
+
+/// Keys in the `eth_call` transaction object
+const CALL_ADDRESS_KEYS: [&str; 2] = ["from", "to"];
+const CALL_QUANTITY_KEYS: [&str; 6] = [
+    "gas",
+    "gasPrice",
+    "maxFeePerGas",
+    "maxPriorityFeePerGas",
+    "nonce",
+    "value",
+];
+
+/// None if the method has no normalizer or nothing changed.
+/// `block` is the block the response is cached for. A block param that is "latest", its number, or its hash is replaced with its hash.
+pub fn normalize_cache_params(
+    method: &str,
+    params: &Value,
+    block: Option<&BlockNumAndHash>,
+) -> Option<Value> {
+    let kinds = param_kinds(method)?;
+
+    let params = params.as_array()?;
+
+    let mut changed = false;
+
+    let normalized = params
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let kind = kinds.get(i).copied().unwrap_or(Other);
+
+            match normalize_param(kind, x, block) {
+                Some(x) => {
+                    changed = true;
+                    x
+                }
+                None => x.clone(),
+            }
+        })
+        .collect();
+
+    changed.then_some(Value::Array(normalized))
+}
+
+/// None if the param is already normal
+fn normalize_param(kind: ParamKind, x: &Value, block: Option<&BlockNumAndHash>) -> Option<Value> {
+    match kind {
+        Address => normalize_address(x.as_str()?).map(Value::String),
+        Quantity => normalize_quantity(x.as_str()?).map(Value::String),
+        Block => normalize_block(x.as_str()?, block),
+        Call => {
+            let obj = x.as_object()?;
+
+            let mut obj = obj.clone();
+            let mut changed = false;
+
+            for (k, v) in obj.iter_mut() {
+                let new = if CALL_ADDRESS_KEYS.contains(&k.as_str()) {
+                    v.as_str().and_then(normalize_address)
+                } else if CALL_QUANTITY_KEYS.contains(&k.as_str()) {
+                    v.as_str().and_then(normalize_quantity)
+                } else {
+                    None
+                };
+
+                if let Some(new) = new {
+                    *v = Value::String(new);
+                    changed = true;
+                }
+            }
+
+            changed.then_some(Value::Object(obj))
+        }
+        Filter => {
+            let obj = x.as_object()?;
+
+            let mut obj = obj.clone();
+            let mut changed = false;
+
+            for (k, v) in obj.iter_mut() {
+                let new = match k.as_str() {
+                    "address" => match v {
+                        Value::String(x) => normalize_address(x).map(Value::String),
+                        Value::Array(x) => {
+                            let addresses: Vec<_> = x
+                                .iter()
+                                .map(|x| {
+                                    x.as_str()
+                                        .and_then(normalize_address)
+                                        .map_or_else(|| x.clone(), Value::String)
+                                })
+                                .collect();
+
+                            (&addresses != x).then_some(Value::Array(addresses))
+                        }
+                        _ => None,
+                    },
+                    // the range is already in the key as block hashes. only the way the numbers are written is fixed here
+                    "fromBlock" | "toBlock" => {
+                        v.as_str().and_then(normalize_quantity).map(Value::String)
+                    }
+                    _ => None,
+                };
+
+                if let Some(new) = new {
+                    *v = new;
+                    changed = true;
+                }
+            }
+
+            changed.then_some(Value::Object(obj))
+        }
+        Other => None,
+    }
+}
+
+/// "0xABCDEF..." -> "0xabcdef...". None if not an address or already lowercase
+fn normalize_address(x: &str) -> Option<String> {
+    let hex = x.strip_prefix("0x").or_else(|| x.strip_prefix("0X"))?;
+
+    if hex.len() != 40 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let normalized = format!("0x{}", hex.to_ascii_lowercase());
+
+    (normalized != x).then_some(normalized)
+}
+
+/// "0x00A" -> "0xa". None if not a hex number or already normal
+fn normalize_quantity(x: &str) -> Option<String> {
+    let hex = x.strip_prefix("0x").or_else(|| x.strip_prefix("0X"))?;
+
+    if hex.is_empty() || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let trimmed = hex.trim_start_matches('0');
+
+    let normalized = if trimmed.is_empty() {
+        "0x0".to_string()
+    } else {
+        format!("0x{}", trimmed.to_ascii_lowercase())
+    };
+
+    (normalized != x).then_some(normalized)
+}
+
+fn normalize_block(x: &str, block: Option<&BlockNumAndHash>) -> Option<Value> {
+    if let Some(block) = block {
+        let hash = format!("{:?}", block.hash());
+
+        let is_block = if x == "latest" {
+            // "latest" is only ever cached with the head block
+            true
+        } else if x.len() == 66 {
+            x.eq_ignore_ascii_case(&hash)
+        } else {
+            let num = normalize_quantity(x).unwrap_or_else(|| x.to_string());
+
+            num == format!("0x{:x}", block.num())
+        };
+
+        if is_block {
+            return (x != hash).then(|| json!(hash));
+        }
+    }
+
+    // other hashes are not numbers. trimming their zeros would only make them look like one
+    if x.len() == 66 {
+        return None;
+    }
+
+    normalize_quantity(x).map(Value::String)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_cache_params;
+    use crate::block_number::BlockNumAndHash;
+    use ethers::types::{H256, U64};
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize_addresses_and_quantities() {
+        let params = json!(["0xDe0B295669a9FD93d5F28D9Ec85E40f4cb697BAe", "0x00A", "0x1"]);
+
+        assert_eq!(
+            normalize_cache_params("eth_getStorageAt", &params, None),
+            Some(json!([
+                "0xde0b295669a9fd93d5f28d9ec85e40f4cb697bae",
+                "0xa",
+                "0x1"
+            ]))
+        );
+
+        // already normal
+        assert_eq!(
+            normalize_cache_params(
+                "eth_getBalance",
+                &json!(["0xde0b295669a9fd93d5f28d9ec85e40f4cb697bae", "0x1"]),
+                None
+            ),
+            None
+        );
+
+        // unknown methods are left alone
+        assert_eq!(normalize_cache_params("eth_foo", &params, None), None);
+    }
+
+    #[test]
+    fn test_normalize_call() {
+        let params = json!([{
+            "to": "0xDe0B295669a9FD93d5F28D9Ec85E40f4cb697BAe",
+            "data": "0xABCD",
+            "value": "0x00",
+        }]);
+
+        assert_eq!(
+            normalize_cache_params("eth_call", &params, None),
+            Some(json!([{
+                "to": "0xde0b295669a9fd93d5f28d9ec85e40f4cb697bae",
+                "data": "0xABCD",
+                "value": "0x0",
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_normalize_block() {
+        let block = BlockNumAndHash(U64::from(16), H256::repeat_byte(1));
+        let hash = format!("{:?}", H256::repeat_byte(1));
+
+        let address = "0xde0b295669a9fd93d5f28d9ec85e40f4cb697bae";
+
+        let upper = hash.to_uppercase();
+
+        for x in ["latest", "0x10", "0x0010", upper.as_str(), hash.as_str()] {
+            assert_eq!(
+                normalize_cache_params("eth_getBalance", &json!([address, x]), Some(&block))
+                    .unwrap_or_else(|| json!([address, x])),
+                json!([address, hash]),
+                "{}",
+                x
+            );
+        }
+
+        // other blocks only have their number cleaned up
+        assert_eq!(
+            normalize_cache_params("eth_getBalance", &json!([address, "0x0f"]), Some(&block)),
+            Some(json!([address, "0xf"]))
+        );
+        assert_eq!(
+            normalize_cache_params("eth_getBalance", &json!([address, "pending"]), Some(&block)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_normalize_filter() {
+        let params = json!([{
+            "address": ["0xDe0B295669a9FD93d5F28D9Ec85E40f4cb697BAe"],
+            "fromBlock": "0x010",
+            "topics": ["0xABCD"],
+        }]);
+
+        assert_eq!(
+            normalize_cache_params("eth_getLogs", &params, None),
+            Some(json!([{
+                "address": ["0xde0b295669a9fd93d5f28d9ec85e40f4cb697bae"],
+                "fromBlock": "0x10",
+                "topics": ["0xABCD"],
+            }]))
+        );
+    }
+}
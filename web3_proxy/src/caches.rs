@@ -1,19 +1,25 @@
+use crate::app::Web3ProxyJoinHandle;
 use crate::balance::Balance;
 use crate::errors::Web3ProxyResult;
 use crate::frontend::authorization::{AuthorizationChecks, RpcSecretKey};
+use arc_swap::ArcSwap;
 use derive_more::From;
-use entities::rpc_key;
+use entities::{rpc_key, user_tier};
+use hashbrown::HashMap;
 use migration::sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use moka::future::{Cache, ConcurrentCacheExt};
 use std::fmt;
 use std::net::IpAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock as AsyncRwLock;
-use tracing::trace;
+use tokio::time::{interval_at, Duration, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, trace, warn};
+use uuid::Uuid;
 
 /// Cache data from the database about rpc keys
-/// TODO: try Ulid/u128 instead of RpcSecretKey in case my hash method is broken
-pub type RpcSecretKeyCache = Cache<RpcSecretKey, AuthorizationChecks>;
+/// Keyed by the hashed secret key. See `RpcSecretKey::hashed`
+pub type RpcSecretKeyCache = Cache<Uuid, AuthorizationChecks>;
 
 #[derive(Clone, Copy, Hash, Eq, PartialEq)]
 pub struct RegisteredUserRateLimitKey(pub u64, pub IpAddr);
@@ -75,11 +81,124 @@ impl UserBalanceCache {
 
         for rpc_key_entity in rpc_keys {
             let rpc_key_id = rpc_key_entity.id;
-            let secret_key = rpc_key_entity.secret_key.into();
+            let hashed_secret_key = if rpc_key_entity.secret_key_hashed {
+                rpc_key_entity.secret_key
+            } else {
+                RpcSecretKey::from(rpc_key_entity.secret_key).hashed()?
+            };
 
-            trace!(%user_id, %rpc_key_id, ?secret_key, "invalidating");
+            trace!(%user_id, %rpc_key_id, "invalidating");
 
-            rpc_secret_key_cache.invalidate(&secret_key).await;
+            rpc_secret_key_cache.invalidate(&hashed_secret_key).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Every user tier, loaded at startup and reloaded from the database in the background so that authorization checks don't need a query.
+/// Tiers that are not loaded yet (or were just created) are read from the database once and then kept.
+#[derive(Default)]
+pub struct UserTierCache {
+    tiers: ArcSwap<HashMap<u64, user_tier::Model>>,
+}
+
+impl UserTierCache {
+    pub async fn get_or_load(
+        &self,
+        db_conn: &DatabaseConnection,
+        user_tier_id: u64,
+    ) -> Web3ProxyResult<Option<user_tier::Model>> {
+        if let Some(x) = self.tiers.load().get(&user_tier_id) {
+            return Ok(Some(x.clone()));
+        }
+
+        let x = user_tier::Entity::find_by_id(user_tier_id)
+            .one(db_conn)
+            .await?;
+
+        if let Some(x) = &x {
+            self.tiers.rcu(|tiers| {
+                let mut tiers = HashMap::clone(tiers);
+                tiers.insert(user_tier_id, x.clone());
+                tiers
+            });
+        }
+
+        Ok(x)
+    }
+
+    async fn refresh(&self, db_conn: &DatabaseConnection) -> Web3ProxyResult<()> {
+        let tiers: HashMap<_, _> = user_tier::Entity::find()
+            .all(db_conn)
+            .await?
+            .into_iter()
+            .map(|x| (x.id, x))
+            .collect();
+
+        trace!(tiers = tiers.len(), "refreshed user tiers");
+
+        self.tiers.store(Arc::new(tiers));
+
+        Ok(())
+    }
+
+    /// Load every tier now and then reload them every `refresh_seconds`.
+    /// Returns None if there is no database or refreshing is disabled.
+    pub async fn try_spawn(
+        self: &Arc<Self>,
+        db_conn: Option<DatabaseConnection>,
+        refresh_seconds: u64,
+        shutdown_token: CancellationToken,
+    ) -> Option<Web3ProxyJoinHandle<()>> {
+        let db_conn = db_conn?;
+
+        // tiers that fail to load here are loaded one at a time when they are first needed
+        if let Err(err) = self.refresh(&db_conn).await {
+            warn!(?err, "failed loading user tiers");
+        }
+
+        if refresh_seconds == 0 {
+            return None;
+        }
+
+        let x = self.clone();
+
+        let handle = tokio::spawn(async move {
+            x.refresh_loop(
+                db_conn,
+                Duration::from_secs(refresh_seconds),
+                shutdown_token,
+            )
+            .await
+        });
+
+        Some(handle)
+    }
+
+    async fn refresh_loop(
+        &self,
+        db_conn: DatabaseConnection,
+        refresh_interval: Duration,
+        shutdown_token: CancellationToken,
+    ) -> Web3ProxyResult<()> {
+        // the tiers were just loaded by try_spawn. skip the immediate first tick
+        let mut refresh_interval = interval_at(Instant::now() + refresh_interval, refresh_interval);
+        refresh_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = refresh_interval.tick() => {
+                    // keep the old tiers if this fails. they are better than querying on every request
+                    if let Err(err) = self.refresh(&db_conn).await {
+                        warn!(?err, "failed refreshing user tiers");
+                    }
+                }
+                _ = shutdown_token.cancelled() => {
+                    info!("user tier refresher shutting down");
+                    break;
+                }
+            }
         }
 
         Ok(())
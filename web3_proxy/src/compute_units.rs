@@ -24,13 +24,23 @@ pub struct ComputeUnit(Decimal);
 
 impl ComputeUnit {
     /// costs can vary widely depending on method and chain
+    /// `configured_cu` is from `MethodCosts::get` and takes priority over the built in costs
     #[instrument(level = "trace")]
-    pub fn new(method: &str, chain_id: u64, response_bytes: u64) -> Self {
+    pub fn new(
+        method: &str,
+        chain_id: u64,
+        response_bytes: u64,
+        configured_cu: Option<u64>,
+    ) -> Self {
         // TODO: this works, but this is fragile. think of a better way to check the method is a subscription
         if method.ends_with(')') {
             return Self::subscription_response(response_bytes);
         }
 
+        if let Some(cu) = configured_cu {
+            return Self(cu.into());
+        }
+
         let cu = if method.starts_with("ots_") {
             // TODO: refine the different methods
             1000
@@ -152,6 +162,10 @@ impl ComputeUnit {
         Self(2.into())
     }
 
+    pub fn value(&self) -> Decimal {
+        self.0
+    }
+
     /// Compute cost per request
     /// All methods cost the same
     /// The number of bytes are based on input, and output bytes
@@ -192,3 +206,31 @@ impl ComputeUnit {
         cost
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ComputeUnit;
+    use crate::config::MethodCosts;
+    use serde_json::json;
+
+    #[test]
+    fn test_method_costs() {
+        let method_costs: MethodCosts = serde_json::from_value(json!({
+            "default": {"eth_getLogs": 100},
+            "chains": {"42161": {"eth_getLogs": 300}},
+        }))
+        .unwrap();
+
+        let cu = |chain_id, method| {
+            ComputeUnit::new(method, chain_id, 0, method_costs.get(chain_id, method))
+                .value()
+                .to_string()
+        };
+
+        assert_eq!(cu(42161, "eth_getLogs"), "300");
+        assert_eq!(cu(1, "eth_getLogs"), "100");
+
+        // methods that are not listed keep the built in cost
+        assert_eq!(cu(42161, "eth_call"), "26");
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::frontend::authorization::Authorization;
 use crate::jsonrpc::{JsonRpcErrorData, JsonRpcForwardedResponse};
+use crate::quota::QuotaWindow;
 use crate::response_cache::JsonRpcResponseEnum;
 use crate::rpcs::provider::EthersHttpProvider;
 use axum::extract::ws::Message;
@@ -11,10 +12,11 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::Utc;
 use derive_more::{Display, Error, From};
 use ethers::prelude::ContractError;
 use ethers::types::{H256, U64};
-use http::header::InvalidHeaderValue;
+use http::header::{HeaderValue, InvalidHeaderValue, RETRY_AFTER};
 use http::uri::InvalidUri;
 use ipnet::AddrParseError;
 use migration::sea_orm::DbErr;
@@ -25,11 +27,26 @@ use rust_decimal::Error as DecimalError;
 use serde::Serialize;
 use serde_json::value::RawValue;
 use siwe::VerificationError;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::{borrow::Cow, net::IpAddr};
 use tokio::{sync::AcquireError, task::JoinError, time::Instant};
 use tracing::{debug, error, trace, warn};
 
+/// Which limit a 429 came from. "key" or "ip"
+pub const RATE_LIMIT_HEADER: &str = "X-W3P-RATE-LIMIT";
+
+/// The `Retry-After` sent when no backend rpcs are synced. Set from `no_servers_retry_after_seconds`
+static NO_SERVERS_RETRY_AFTER: AtomicU64 = AtomicU64::new(1);
+
+pub fn set_no_servers_retry_after(seconds: u64) {
+    NO_SERVERS_RETRY_AFTER.store(seconds, Ordering::Relaxed);
+}
+
+pub fn no_servers_retry_after() -> u64 {
+    NO_SERVERS_RETRY_AFTER.load(Ordering::Relaxed)
+}
+
 pub type Web3ProxyResult<T> = Result<T, Web3ProxyError>;
 // TODO: take "IntoResponse" instead of Response?
 pub type Web3ProxyResponse = Web3ProxyResult<Response>;
@@ -60,6 +77,15 @@ pub enum Web3ProxyError {
     Contract(ContractError<EthersHttpProvider>),
     Database(DbErr),
     Decimal(DecimalError),
+    /// every way of verifying a login signature failed. eip4361, eip191, then personal_sign
+    #[display(fmt = "{}, {}, {}", _0, _1, _2)]
+    #[error(ignore)]
+    #[from(ignore)]
+    EipVerificationFailed(
+        Box<Web3ProxyError>,
+        Box<Web3ProxyError>,
+        Box<Web3ProxyError>,
+    ),
     EthersHttpClient(ethers::prelude::HttpClientError),
     EthersProvider(ethers::prelude::ProviderError),
     EthersWsClient(ethers::prelude::WsClientError),
@@ -78,10 +104,20 @@ pub enum Web3ProxyError {
     },
     InvalidHeaderValue(InvalidHeaderValue),
     InvalidEip,
+    #[display(fmt = "{}={}", name, value)]
+    #[error(ignore)]
+    #[from(ignore)]
+    InvalidQueryParam {
+        name: &'static str,
+        value: String,
+    },
     InvalidInviteCode,
     Io(std::io::Error),
     UnknownReferralCode,
     InvalidReferer,
+    #[error(ignore)]
+    #[from(ignore)]
+    InvalidRequestSignature(Cow<'static, str>),
     InvalidSignatureLength,
     InvalidUserTier,
     InvalidUserAgent,
@@ -91,6 +127,10 @@ pub enum Web3ProxyError {
     #[from(ignore)]
     IpNotAllowed(IpAddr),
     JoinError(JoinError),
+    /// the proxy routes are turned off. the number is the seconds for `Retry-After`
+    #[from(ignore)]
+    #[error(ignore)]
+    MaintenanceMode(u64),
     #[display(fmt = "{:?}", _0)]
     #[error(ignore)]
     JsonRpcErrorData(JsonRpcErrorData),
@@ -129,8 +169,17 @@ pub enum Web3ProxyError {
     ParseBytesError(Option<ethers::types::ParseBytesError>),
     ParseMsgError(siwe::ParseError),
     ParseAddressError,
+    /// a key used all of its requests for this window. the number is the unix timestamp when the window resets
+    #[display(fmt = "{}, {}", _0, _1)]
+    #[error(ignore)]
+    #[from(ignore)]
+    QuotaExceeded(QuotaWindow, i64),
     #[display(fmt = "{:?}, {:?}", _0, _1)]
     RateLimited(Authorization, Option<Instant>),
+    /// the key was under its limit, but the ip was over `keyed_ip_requests_per_period`
+    #[display(fmt = "{:?}, {:?}", _0, _1)]
+    #[from(ignore)]
+    KeyedIpRateLimited(Authorization, Option<Instant>),
     Redis(RedisError),
     RedisDeadpool(RedisPoolError),
     RefererRequired,
@@ -141,6 +190,8 @@ pub enum Web3ProxyError {
     SemaphoreAcquireError(AcquireError),
     SerdeJson(serde_json::Error),
     SiweVerification(VerificationError),
+    /// too many stats queries are already running
+    StatsBusy,
     /// simple way to return an error message to the user and an anyhow to our logs
     #[display(fmt = "{}, {}, {:?}", _0, _1, _2)]
     StatusCode(StatusCode, Cow<'static, str>, Option<anyhow::Error>),
@@ -172,44 +223,124 @@ pub enum Web3ProxyError {
     WithContext(Option<Box<Web3ProxyError>>, Cow<'static, str>),
 }
 
+/// Stable error codes for the errors that we create. Clients can match on these.
+///
+/// Codes from the JSON-RPC spec are used when they fit. The rest are in the spec's -32000 to -32099 range for server errors.
+/// Never change or reuse a value! Errors from backend rpcs are passed through with their own codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Web3ProxyErrorCode {
+    /// The request could not be parsed
+    ParseError,
+    /// The request is not valid
+    InvalidRequest,
+    /// The method does not exist or is not available
+    MethodNotFound,
+    /// The method exists but the params are not valid
+    InvalidParams,
+    /// Something went wrong on our side
+    Internal,
+    /// The requested block is not known. This matches what geth returns
+    UnknownBlock,
+    /// The requested resource does not exist
+    NotFound,
+    /// No backend rpcs are able to serve the request right now. Shares -32000 with `UnknownBlock`. The http status tells them apart
+    Unavailable,
+    /// The method is not implemented yet
+    NotImplemented,
+    /// Too many requests
+    RateLimited,
+    /// The request took too long
+    Timeout,
+    /// A backend rpc gave a response that we could not use
+    BadResponse,
+    /// Missing or invalid credentials
+    Unauthorized,
+    /// Valid credentials, but not allowed to do this
+    AccessDenied,
+    /// The user needs to pay for this
+    PaymentRequired,
+}
+
+impl Web3ProxyErrorCode {
+    /// The `code` in the JSON-RPC error
+    pub fn jsonrpc_code(&self) -> i64 {
+        match self {
+            Self::ParseError => -32700,
+            Self::InvalidRequest => -32600,
+            Self::MethodNotFound => -32601,
+            Self::InvalidParams => -32602,
+            Self::Internal => -32603,
+            Self::UnknownBlock => -32000,
+            Self::NotFound => -32001,
+            Self::Unavailable => -32000,
+            Self::NotImplemented => -32004,
+            Self::RateLimited => -32005,
+            Self::Timeout => -32010,
+            Self::BadResponse => -32011,
+            Self::Unauthorized => -32020,
+            Self::AccessDenied => -32021,
+            Self::PaymentRequired => -32022,
+        }
+    }
+
+    /// The HTTP status of the response
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ParseError | Self::InvalidRequest | Self::InvalidParams => {
+                StatusCode::BAD_REQUEST
+            }
+            // json-rpc clients expect these to be a successful http response with an error in the body
+            Self::MethodNotFound | Self::UnknownBlock => StatusCode::OK,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Unavailable | Self::BadResponse => StatusCode::BAD_GATEWAY,
+            Self::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            Self::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            Self::Timeout => StatusCode::REQUEST_TIMEOUT,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::AccessDenied => StatusCode::FORBIDDEN,
+            Self::PaymentRequired => StatusCode::PAYMENT_REQUIRED,
+        }
+    }
+
+    /// The closest code for an error that was created with a specific status
+    pub fn from_status_code(status_code: StatusCode) -> Self {
+        match status_code {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::PAYMENT_REQUIRED => Self::PaymentRequired,
+            StatusCode::FORBIDDEN => Self::AccessDenied,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => Self::Timeout,
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            StatusCode::NOT_IMPLEMENTED => Self::NotImplemented,
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => Self::Unavailable,
+            x if x.is_server_error() => Self::Internal,
+            _ => Self::InvalidRequest,
+        }
+    }
+}
+
 impl Web3ProxyError {
     pub fn as_response_parts<R: Serialize>(&self) -> (StatusCode, JsonRpcResponseEnum<R>) {
         // TODO: include a unique request id in the data
-        let (code, err): (StatusCode, JsonRpcErrorData) = match self {
+        // errors from backends keep their own codes. everything else gets a code and status from `Web3ProxyErrorCode`
+        let (code, message): (Web3ProxyErrorCode, Cow<'static, str>) = match self {
             Self::Abi(err) => {
                 warn!(?err, "abi error");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: err.to_string().into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Internal, err.to_string().into())
             }
             Self::AccessDenied(msg) => {
                 // TODO: attach something to this trace. probably don't include much in the message though. don't want to leak creds by accident
                 trace!(%msg, "access denied");
                 (
-                    StatusCode::FORBIDDEN,
-                    JsonRpcErrorData {
-                        message: format!("FORBIDDEN: {}", msg).into(),
-                        code: StatusCode::FORBIDDEN.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::AccessDenied,
+                    format!("FORBIDDEN: {}", msg).into(),
                 )
             }
             Self::Anyhow(err) => {
                 warn!(?err, "anyhow");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        // TODO: is it safe to expose all of our anyhow strings?
-                        message: "INTERNAL SERVER ERROR".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                // TODO: is it safe to expose all of our anyhow strings?
+                (Web3ProxyErrorCode::Internal, "INTERNAL SERVER ERROR".into())
             }
             Self::Arc(err) => {
                 // recurse
@@ -218,445 +349,319 @@ impl Web3ProxyError {
             Self::BadRequest(err) => {
                 trace!(?err, "BAD_REQUEST");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: format!("bad request: {}", err).into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidRequest,
+                    format!("bad request: {}", err).into(),
                 )
             }
             Self::BadResponse(err) => {
                 // TODO: think about this one more. ankr gives us this because ethers fails to parse responses without an id
                 debug!(?err, "BAD_RESPONSE: {}", err);
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: format!("bad response: {}", err).into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::BadResponse,
+                    format!("bad response: {}", err).into(),
                 )
             }
             Self::BadRouting => {
                 error!("BadRouting");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "bad routing".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Internal, "bad routing".into())
             }
             Self::Contract(err) => {
                 warn!(?err, "Contract Error: {}", err);
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: format!("contract error: {}", err).into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::BadResponse,
+                    format!("contract error: {}", err).into(),
                 )
             }
             Self::Database(err) => {
                 error!(?err, "database err: {}", err);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "database error!".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Internal, "database error!".into())
             }
             Self::Decimal(err) => {
                 debug!(?err, "Decimal Error: {}", err);
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: format!("decimal error: {}", err).into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidParams,
+                    format!("decimal error: {}", err).into(),
+                )
+            }
+            Self::EipVerificationFailed(err_4361, err_191, err_personal_sign) => {
+                trace!(
+                    ?err_4361,
+                    ?err_191,
+                    ?err_personal_sign,
+                    "EipVerificationFailed"
+                );
+                (
+                    Web3ProxyErrorCode::Unauthorized,
+                    format!(
+                        "signature verification failed. eip4361: {}. eip191: {}. personal_sign: {}",
+                        err_4361, err_191, err_personal_sign
+                    )
+                    .into(),
                 )
             }
             Self::EthersHttpClient(err) => {
                 if let Ok(err) = JsonRpcErrorData::try_from(err) {
                     trace!(?err, "EthersHttpClient jsonrpc error");
-                    (StatusCode::OK, err)
-                } else {
-                    warn!(?err, "EthersHttpClient");
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        JsonRpcErrorData {
-                            message: "ethers http client error".into(),
-                            code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                            data: None,
-                        },
-                    )
+                    return (StatusCode::OK, JsonRpcResponseEnum::from(err));
                 }
+
+                warn!(?err, "EthersHttpClient");
+                (
+                    Web3ProxyErrorCode::BadResponse,
+                    "ethers http client error".into(),
+                )
             }
             Self::EthersProvider(err) => {
                 if let Ok(err) = JsonRpcErrorData::try_from(err) {
                     trace!(?err, "EthersProvider jsonrpc error");
-                    (StatusCode::OK, err)
-                } else {
-                    warn!(?err, "EthersProvider");
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        JsonRpcErrorData {
-                            message: "ethers provider error".into(),
-                            code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                            data: None,
-                        },
-                    )
+                    return (StatusCode::OK, JsonRpcResponseEnum::from(err));
                 }
+
+                warn!(?err, "EthersProvider");
+                (
+                    Web3ProxyErrorCode::BadResponse,
+                    "ethers provider error".into(),
+                )
             }
             Self::EthersWsClient(err) => {
                 if let Ok(err) = JsonRpcErrorData::try_from(err) {
                     trace!(?err, "EthersWsClient jsonrpc error");
-                    (StatusCode::OK, err)
-                } else {
-                    warn!(?err, "EthersWsClient");
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        JsonRpcErrorData {
-                            message: "ethers ws client error".into(),
-                            code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                            data: None,
-                        },
-                    )
+                    return (StatusCode::OK, JsonRpcResponseEnum::from(err));
                 }
+
+                warn!(?err, "EthersWsClient");
+                (
+                    Web3ProxyErrorCode::BadResponse,
+                    "ethers ws client error".into(),
+                )
             }
-            // Self::JsonRpcForwardedError(x) => (StatusCode::OK, x),
             Self::GasEstimateNotU256 => {
                 trace!("GasEstimateNotU256");
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "gas estimate result is not an U256".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::BadResponse,
+                    "gas estimate result is not an U256".into(),
                 )
             }
             Self::HdrRecord(err) => {
                 warn!(?err, "HdrRecord");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: err.to_string().into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Internal, err.to_string().into())
             }
             Self::Headers(err) => {
                 trace!(?err, "HeadersError");
-                (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: err.to_string().into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::InvalidRequest, err.to_string().into())
             }
             Self::HeaderToString(err) => {
                 trace!(?err, "HeaderToString");
-                (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: err.to_string().into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::InvalidRequest, err.to_string().into())
             }
             Self::HttpUri(err) => {
                 trace!(?err, "HttpUri");
-                (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: err.to_string().into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::InvalidRequest, err.to_string().into())
             }
             Self::Hyper(err) => {
                 warn!(?err, "hyper");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        // TODO: is it safe to expose these error strings?
-                        message: err.to_string().into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                // TODO: is it safe to expose these error strings?
+                (Web3ProxyErrorCode::Internal, err.to_string().into())
             }
             Self::InfluxDb2Request(err) => {
                 // TODO: attach a request id to the message and to this error so that if people report problems, we can dig in sentry to find out more
                 error!(?err, "influxdb2");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "influxdb2 error!".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Internal, "influxdb2 error!".into())
             }
             Self::InvalidBlockBounds { min, max } => {
                 trace!(%min, %max, "InvalidBlockBounds");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: format!(
-                            "Invalid blocks bounds requested. min ({}) > max ({})",
-                            min, max
-                        )
-                        .into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidParams,
+                    format!(
+                        "Invalid blocks bounds requested. min ({}) > max ({})",
+                        min, max
+                    )
+                    .into(),
                 )
             }
             Self::IpAddrParse(err) => {
                 debug!(?err, "IpAddrParse");
-                (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: err.to_string().into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::InvalidRequest, err.to_string().into())
             }
             Self::IpNotAllowed(ip) => {
                 trace!(?ip, "IpNotAllowed");
                 (
-                    StatusCode::FORBIDDEN,
-                    JsonRpcErrorData {
-                        message: format!("IP ({}) is not allowed!", ip).into(),
-                        code: StatusCode::FORBIDDEN.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::AccessDenied,
+                    format!("IP ({}) is not allowed!", ip).into(),
                 )
             }
             Self::InvalidHeaderValue(err) => {
                 trace!(?err, "InvalidHeaderValue");
-                (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: err.to_string().into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::InvalidRequest, err.to_string().into())
             }
             Self::InvalidEip => {
                 trace!("InvalidEip");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "invalid message eip given".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidParams,
+                    "invalid message eip given".into(),
+                )
+            }
+            Self::InvalidQueryParam { name, value } => {
+                trace!(%name, %value, "InvalidQueryParam");
+                (
+                    Web3ProxyErrorCode::InvalidParams,
+                    format!("invalid value for query param {}: {}", name, value).into(),
                 )
             }
             Self::InvalidInviteCode => {
                 trace!("InvalidInviteCode");
                 (
-                    StatusCode::UNAUTHORIZED,
-                    JsonRpcErrorData {
-                        message: "invalid invite code".into(),
-                        code: StatusCode::UNAUTHORIZED.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Unauthorized,
+                    "invalid invite code".into(),
                 )
             }
             Self::Io(err) => {
                 warn!(?err, "std io");
+                // TODO: is it safe to expose our io error strings?
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        // TODO: is it safe to expose our io error strings?
-                        message: format!("std io error: {}", err).into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Internal,
+                    format!("std io error: {}", err).into(),
                 )
             }
             Self::InvalidReferer => {
                 trace!("InvalidReferer");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "invalid referer!".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidRequest,
+                    "invalid referer!".into(),
+                )
+            }
+            Self::InvalidRequestSignature(msg) => {
+                trace!(%msg, "InvalidRequestSignature");
+                (
+                    Web3ProxyErrorCode::Unauthorized,
+                    format!("invalid request signature: {}", msg).into(),
                 )
             }
             Self::InvalidSignatureLength => {
                 trace!("InvalidSignatureLength");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "invalid signature length".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidParams,
+                    "invalid signature length".into(),
                 )
             }
             Self::InvalidUserAgent => {
                 trace!("InvalidUserAgent");
                 (
-                    StatusCode::FORBIDDEN,
-                    JsonRpcErrorData {
-                        message: "invalid user agent!".into(),
-                        code: StatusCode::FORBIDDEN.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::AccessDenied,
+                    "invalid user agent!".into(),
                 )
             }
-            Self::InvalidUserKey => {
-                trace!("InvalidUserKey");
+            // malformed, inactive, and unknown keys all get the exact same response so that keys cannot be enumerated
+            Self::InvalidUserKey | Self::UnknownKey => {
+                trace!("invalid or unknown key");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "UserKey was not a ULID or UUID".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::AccessDenied,
+                    "FORBIDDEN: invalid api key".into(),
                 )
             }
             Self::InvalidUserTier => {
                 warn!("InvalidUserTier");
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "UserTier is not valid!".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Internal,
+                    "UserTier is not valid!".into(),
                 )
             }
             Self::JoinError(err) => {
                 let code = if err.is_cancelled() {
                     trace!(?err, "JoinError. likely shutting down");
-                    StatusCode::BAD_GATEWAY
+                    Web3ProxyErrorCode::Unavailable
                 } else {
                     warn!(?err, "JoinError");
-                    StatusCode::INTERNAL_SERVER_ERROR
+                    Web3ProxyErrorCode::Internal
                 };
 
-                (
-                    code,
-                    JsonRpcErrorData {
-                        // TODO: different messages of cancelled or not?
-                        message: "Unable to complete request".into(),
-                        code: code.as_u16().into(),
-                        data: None,
-                    },
-                )
+                // TODO: different messages of cancelled or not?
+                (code, "Unable to complete request".into())
             }
             Self::JsonRpcErrorData(jsonrpc_error_data) => {
                 // TODO: do this without clone? the Arc needed it though
-                (StatusCode::OK, jsonrpc_error_data.clone())
+                return (
+                    StatusCode::OK,
+                    JsonRpcResponseEnum::from(jsonrpc_error_data.clone()),
+                );
+            }
+            Self::MaintenanceMode(retry_after) => {
+                trace!(retry_after, "maintenance mode");
+
+                let err = JsonRpcErrorData {
+                    message: format!("down for maintenance. retry in {} seconds", retry_after)
+                        .into(),
+                    code: Web3ProxyErrorCode::Unavailable.jsonrpc_code(),
+                    data: None,
+                };
+
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    JsonRpcResponseEnum::from(err),
+                );
             }
             Self::MsgPackEncode(err) => {
                 warn!(?err, "MsgPackEncode");
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: format!("msgpack encode error: {}", err).into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Internal,
+                    format!("msgpack encode error: {}", err).into(),
                 )
             }
             Self::NoBlockNumberOrHash => {
                 warn!("NoBlockNumberOrHash");
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "Blocks here must have a number or hash".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Internal,
+                    "Blocks here must have a number or hash".into(),
                 )
             }
             Self::NoBlocksKnown => {
                 error!("NoBlocksKnown");
-                (
-                    StatusCode::BAD_GATEWAY,
-                    JsonRpcErrorData {
-                        message: "no blocks known".into(),
-                        code: StatusCode::BAD_GATEWAY.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Unavailable, "no blocks known".into())
             }
             Self::NoConsensusHeadBlock => {
                 error!("NoConsensusHeadBlock");
                 (
-                    StatusCode::BAD_GATEWAY,
-                    JsonRpcErrorData {
-                        message: "no consensus head block".into(),
-                        code: StatusCode::BAD_GATEWAY.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Unavailable,
+                    "no consensus head block".into(),
                 )
             }
             Self::NoDatabase => {
                 error!("no database configured");
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "no database configured!".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Internal,
+                    "no database configured!".into(),
                 )
             }
             Self::NoHandleReady => {
                 error!("NoHandleReady");
                 (
-                    StatusCode::BAD_GATEWAY,
-                    JsonRpcErrorData {
-                        message: "unable to retry for request handle".into(),
-                        code: StatusCode::BAD_GATEWAY.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Unavailable,
+                    "unable to retry for request handle".into(),
                 )
             }
             Self::NoVolatileRedisDatabase => {
                 error!("no volatile redis database configured");
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "no volatile redis database configured!".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Internal,
+                    "no volatile redis database configured!".into(),
                 )
             }
             Self::NoServersSynced => {
                 warn!("NoServersSynced");
-                (
-                    StatusCode::BAD_GATEWAY,
-                    JsonRpcErrorData {
-                        message: "no servers synced".into(),
-                        code: StatusCode::BAD_GATEWAY.as_u16().into(),
-                        data: None,
-                    },
-                )
+
+                let retry_after = no_servers_retry_after();
+
+                // 503 so that clients and load balancers know to retry. the http frontend adds `Retry-After`
+                let err = JsonRpcErrorData {
+                    message: "no synced backends".into(),
+                    code: Web3ProxyErrorCode::Unavailable.jsonrpc_code(),
+                    data: Some(serde_json::json!({ "retry_after": retry_after })),
+                };
+
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    JsonRpcResponseEnum::from(err),
+                );
             }
             Self::NotEnoughRpcs {
                 num_known,
@@ -664,127 +669,84 @@ impl Web3ProxyError {
             } => {
                 error!(%num_known, %min_head_rpcs, "NotEnoughRpcs");
                 (
-                    StatusCode::BAD_GATEWAY,
-                    JsonRpcErrorData {
-                        message: format!(
-                            "not enough rpcs connected {}/{}",
-                            num_known, min_head_rpcs
-                        )
-                        .into(),
-                        code: StatusCode::BAD_GATEWAY.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Unavailable,
+                    format!("not enough rpcs connected {}/{}", num_known, min_head_rpcs).into(),
                 )
             }
             Self::NotEnoughSoftLimit { available, needed } => {
                 error!(available, needed, "NotEnoughSoftLimit");
                 (
-                    StatusCode::BAD_GATEWAY,
-                    JsonRpcErrorData {
-                        message: format!(
-                            "not enough soft limit available {}/{}",
-                            available, needed
-                        )
-                        .into(),
-                        code: StatusCode::BAD_GATEWAY.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Unavailable,
+                    format!("not enough soft limit available {}/{}", available, needed).into(),
                 )
             }
             Self::NotFound => {
                 // TODO: emit a stat?
                 // TODO: instead of an error, show a normal html page for 404?
-                (
-                    StatusCode::NOT_FOUND,
-                    JsonRpcErrorData {
-                        message: "not found!".into(),
-                        code: StatusCode::NOT_FOUND.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::NotFound, "not found!".into())
             }
             Self::NotImplemented(msg) => {
                 warn!("NotImplemented: {}", msg);
                 (
-                    StatusCode::NOT_IMPLEMENTED,
-                    JsonRpcErrorData {
-                        message: format!(
-                            "{} is not yet implemented. contact us if you need this",
-                            msg
-                        )
-                        .into(),
-                        code: StatusCode::NOT_IMPLEMENTED.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::NotImplemented,
+                    format!(
+                        "{} is not yet implemented. contact us if you need this",
+                        msg
+                    )
+                    .into(),
                 )
             }
             Self::OriginRequired => {
                 trace!("OriginRequired");
-                (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "Origin required".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::InvalidRequest, "Origin required".into())
             }
             Self::OriginNotAllowed(origin) => {
                 trace!(?origin, "OriginNotAllowed");
                 (
-                    StatusCode::FORBIDDEN,
-                    JsonRpcErrorData {
-                        message: format!("Origin ({}) is not allowed!", origin).into(),
-                        code: StatusCode::FORBIDDEN.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::AccessDenied,
+                    format!("Origin ({}) is not allowed!", origin).into(),
                 )
             }
             Self::ParseBytesError(err) => {
                 trace!(?err, "ParseBytesError");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "parse bytes error!".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidParams,
+                    "parse bytes error!".into(),
                 )
             }
             Self::ParseMsgError(err) => {
                 trace!(?err, "ParseMsgError");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "parse message error!".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidParams,
+                    "parse message error!".into(),
                 )
             }
             Self::ParseAddressError => {
                 trace!("ParseAddressError");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "unable to parse address".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidParams,
+                    "unable to parse address".into(),
                 )
             }
             Self::PaymentRequired => {
                 trace!("PaymentRequiredError");
                 (
-                    StatusCode::PAYMENT_REQUIRED,
-                    JsonRpcErrorData {
-                        message: "Payment is required to activate premium".into(),
-                        code: StatusCode::PAYMENT_REQUIRED.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::PaymentRequired,
+                    "Payment is required to activate premium".into(),
                 )
             }
             // TODO: this should actually by the id of the key. multiple users might control one key
+            Self::QuotaExceeded(window, reset_at) => {
+                trace!(%window, reset_at, "quota exceeded");
+                (
+                    Web3ProxyErrorCode::RateLimited,
+                    format!(
+                        "request quota for this {} is used up. it resets at {} (unix time)",
+                        window, reset_at
+                    )
+                    .into(),
+                )
+            }
             Self::RateLimited(authorization, retry_at) => {
                 // TODO: emit a stat
 
@@ -807,92 +769,75 @@ impl Web3ProxyError {
                     )
                 };
 
+                (Web3ProxyErrorCode::RateLimited, msg.into())
+            }
+            Self::KeyedIpRateLimited(authorization, retry_at) => {
+                let retry_msg = if let Some(retry_at) = retry_at {
+                    let retry_in = retry_at.duration_since(Instant::now()).as_secs();
+
+                    format!(" Retry in {} seconds", retry_in)
+                } else {
+                    "".to_string()
+                };
+
                 (
-                    StatusCode::TOO_MANY_REQUESTS,
-                    JsonRpcErrorData {
-                        message: msg.into(),
-                        code: StatusCode::TOO_MANY_REQUESTS.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::RateLimited,
+                    format!(
+                        "too many requests from {} across all rpc keys.{}",
+                        authorization.ip, retry_msg
+                    )
+                    .into(),
                 )
             }
             Self::Redis(err) => {
                 warn!(?err, "redis");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "redis error!".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Internal, "redis error!".into())
             }
             Self::RedisDeadpool(err) => {
                 error!(?err, "redis deadpool");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        // TODO: is it safe to expose our io error strings?
-                        message: err.to_string().into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                // TODO: is it safe to expose our io error strings?
+                (Web3ProxyErrorCode::Internal, err.to_string().into())
             }
             Self::RefererRequired => {
                 trace!("referer required");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "Referer required".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidRequest,
+                    "Referer required".into(),
                 )
             }
             Self::RefererNotAllowed(referer) => {
                 trace!(?referer, "referer not allowed");
                 (
-                    StatusCode::FORBIDDEN,
-                    JsonRpcErrorData {
-                        message: format!("Referer ({:?}) is not allowed", referer).into(),
-                        code: StatusCode::FORBIDDEN.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::AccessDenied,
+                    format!("Referer ({:?}) is not allowed", referer).into(),
                 )
             }
             Self::SemaphoreAcquireError(err) => {
                 error!(?err, "semaphore acquire");
                 (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        // TODO: is it safe to expose all of our anyhow strings?
-                        message: "semaphore acquire error".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Internal,
+                    "semaphore acquire error".into(),
                 )
             }
             Self::SerdeJson(err) => {
                 trace!(?err, "serde json");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: format!("de/serialization error! {}", err).into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::ParseError,
+                    format!("de/serialization error! {}", err).into(),
                 )
             }
             Self::SiweVerification(err) => {
                 trace!(?err, "Siwe Verification");
                 (
-                    StatusCode::UNAUTHORIZED,
-                    JsonRpcErrorData {
-                        message: format!("siwe verification error: {}", err.to_string()).into(),
-                        code: StatusCode::UNAUTHORIZED.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Unauthorized,
+                    format!("siwe verification error: {}", err.to_string()).into(),
+                )
+            }
+            Self::StatsBusy => {
+                debug!("StatsBusy");
+                (
+                    Web3ProxyErrorCode::RateLimited,
+                    "too many stats queries are running. try again soon".into(),
                 )
             }
             Self::StatusCode(status_code, err_msg, err) => {
@@ -904,155 +849,91 @@ impl Web3ProxyError {
                     trace!(%err_msg, ?err, "user error {}", code);
                 }
 
-                (
-                    *status_code,
-                    JsonRpcErrorData {
-                        message: err_msg.clone(),
-                        code: code.into(),
-                        data: None,
-                    },
-                )
+                // this error picked its own status. keep it
+                let err = JsonRpcErrorData {
+                    message: err_msg.clone(),
+                    code: Web3ProxyErrorCode::from_status_code(*status_code).jsonrpc_code(),
+                    data: None,
+                };
+
+                return (*status_code, JsonRpcResponseEnum::from(err));
             }
             Self::StripeWebhookError(err) => {
                 trace!(?err, "StripeWebhookError");
+                // TODO: include the stripe signature? anything else?
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: format!("stripe webhook error: {}", err).into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        // TODO: include the stripe signature? anything else?
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidRequest,
+                    format!("stripe webhook error: {}", err).into(),
                 )
             }
             Self::Timeout(x) => (
-                StatusCode::REQUEST_TIMEOUT,
-                JsonRpcErrorData {
-                    // TODO: prettier message
-                    message: format!("request timed out: {:?}", x).into(),
-                    code: StatusCode::REQUEST_TIMEOUT.as_u16().into(),
-                    // TODO: include the actual id!
-                    data: None,
-                },
+                // TODO: prettier message
+                Web3ProxyErrorCode::Timeout,
+                format!("request timed out: {:?}", x).into(),
             ),
             Self::UlidDecode(err) => {
                 trace!(?err, "UlidDecodeError");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: format!("ulid decode error: {}", err).into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidParams,
+                    format!("ulid decode error: {}", err).into(),
                 )
             }
             Self::UnknownBlockHash(hash) => {
                 debug!(%hash, "UnknownBlockHash");
                 (
-                    StatusCode::OK,
-                    JsonRpcErrorData {
-                        message: format!("block {} not found", hash).into(),
-                        code: -32000,
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::UnknownBlock,
+                    format!("block {} not found", hash).into(),
                 )
             }
             Self::UnknownBlockNumber { known, unknown } => {
                 debug!(%known, %unknown, "UnknownBlockNumber");
                 (
-                    StatusCode::OK,
-                    JsonRpcErrorData {
-                        message: format!("block #{} not found. best known is #{}", unknown, known)
-                            .into(),
-                        code: -32000,
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::UnknownBlock,
+                    format!("block #{} not found. best known is #{}", unknown, known).into(),
                 )
             }
-            Self::UnknownKey => (
-                StatusCode::UNAUTHORIZED,
-                JsonRpcErrorData {
-                    message: "unknown api key!".into(),
-                    code: StatusCode::UNAUTHORIZED.as_u16().into(),
-                    data: None,
-                },
-            ),
             Self::UnknownReferralCode => {
                 trace!("UnknownReferralCode");
                 (
-                    StatusCode::UNAUTHORIZED,
-                    JsonRpcErrorData {
-                        message: "invalid referral code".into(),
-                        code: StatusCode::UNAUTHORIZED.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Unauthorized,
+                    "invalid referral code".into(),
                 )
             }
             Self::UserAgentRequired => {
                 trace!("UserAgentRequired");
                 (
-                    StatusCode::UNAUTHORIZED,
-                    JsonRpcErrorData {
-                        message: "User agent required".into(),
-                        code: StatusCode::UNAUTHORIZED.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::Unauthorized,
+                    "User agent required".into(),
                 )
             }
             Self::UserAgentNotAllowed(ua) => {
                 trace!(%ua, "UserAgentNotAllowed");
                 (
-                    StatusCode::FORBIDDEN,
-                    JsonRpcErrorData {
-                        message: format!("User agent ({}) is not allowed!", ua).into(),
-                        code: StatusCode::FORBIDDEN.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::AccessDenied,
+                    format!("User agent ({}) is not allowed!", ua).into(),
                 )
             }
             Self::UserIdZero => {
                 warn!("UserIdZero");
                 // TODO: this might actually be an application error and not a BAD_REQUEST
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "user ids should always be non-zero".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidRequest,
+                    "user ids should always be non-zero".into(),
                 )
             }
             Self::WatchRecvError(err) => {
                 error!(?err, "WatchRecvError");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "watch recv error!".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Internal, "watch recv error!".into())
             }
             Self::WatchSendError => {
                 error!("WatchSendError");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    JsonRpcErrorData {
-                        message: "watch send error!".into(),
-                        code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                        data: None,
-                    },
-                )
+                (Web3ProxyErrorCode::Internal, "watch send error!".into())
             }
             Self::WebsocketOnly => {
                 trace!("WebsocketOnly. redirect_public_url not set");
                 (
-                    StatusCode::BAD_REQUEST,
-                    JsonRpcErrorData {
-                        message: "only websockets work here".into(),
-                        code: StatusCode::BAD_REQUEST.as_u16().into(),
-                        data: None,
-                    },
+                    Web3ProxyErrorCode::InvalidRequest,
+                    "only websockets work here".into(),
                 )
             }
             Self::WithContext(err, msg) => match err {
@@ -1062,19 +943,18 @@ impl Web3ProxyError {
                 }
                 None => {
                     warn!(%msg, "error w/ context");
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        JsonRpcErrorData {
-                            message: msg.clone(),
-                            code: StatusCode::INTERNAL_SERVER_ERROR.as_u16().into(),
-                            data: None,
-                        },
-                    )
+                    (Web3ProxyErrorCode::Internal, msg.clone())
                 }
             },
         };
 
-        (code, JsonRpcResponseEnum::from(err))
+        let err = JsonRpcErrorData {
+            message,
+            code: code.jsonrpc_code(),
+            data: None,
+        };
+
+        (code.status_code(), JsonRpcResponseEnum::from(err))
     }
 
     #[inline]
@@ -1085,7 +965,59 @@ impl Web3ProxyError {
 
         let response = JsonRpcForwardedResponse::from_response_data(response_data, id);
 
-        (status_code, Json(response)).into_response()
+        let mut response = (status_code, Json(response)).into_response();
+
+        let retry_after = match self {
+            Self::MaintenanceMode(retry_after) => Some(retry_after),
+            Self::NoServersSynced => Some(no_servers_retry_after()),
+            Self::StatsBusy => Some(1),
+            Self::QuotaExceeded(_, reset_at) => {
+                Some((reset_at - Utc::now().timestamp()).max(0) as u64)
+            }
+            _ => None,
+        };
+
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+
+        // requests with a key can be limited by the key or by their ip
+        let rate_limit = match &self {
+            Self::RateLimited(authorization, _)
+                if authorization.checks.rpc_secret_key_id.is_some() =>
+            {
+                Some("key")
+            }
+            Self::RateLimited(..) | Self::KeyedIpRateLimited(..) => Some("ip"),
+            _ => None,
+        };
+
+        if let Some(rate_limit) = rate_limit {
+            response
+                .headers_mut()
+                .insert(RATE_LIMIT_HEADER, HeaderValue::from_static(rate_limit));
+        }
+
+        response
+    }
+
+    /// True if the backends could not answer. A JSON-RPC error from a backend (like a revert) is an answer
+    pub fn is_backend_outage(&self) -> bool {
+        match self {
+            Self::Arc(err) => err.is_backend_outage(),
+            Self::BadResponse(_)
+            | Self::NoBlocksKnown
+            | Self::NoConsensusHeadBlock
+            | Self::NoHandleReady
+            | Self::NoServersSynced
+            | Self::Timeout(_) => true,
+            Self::EthersHttpClient(err) => JsonRpcErrorData::try_from(err).is_err(),
+            Self::EthersProvider(err) => JsonRpcErrorData::try_from(err).is_err(),
+            Self::EthersWsClient(err) => JsonRpcErrorData::try_from(err).is_err(),
+            _ => false,
+        }
     }
 }
 
@@ -1141,3 +1073,77 @@ impl Web3ProxyError {
         Message::Text(msg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Web3ProxyError, Web3ProxyErrorCode};
+    use crate::frontend::authorization::Authorization;
+    use crate::jsonrpc::JsonRpcErrorData;
+    use crate::response_cache::JsonRpcResponseEnum;
+    use http::StatusCode;
+    use std::sync::Arc;
+
+    fn parts(err: Web3ProxyError) -> (StatusCode, i64) {
+        match err.as_response_parts::<()>() {
+            (status_code, JsonRpcResponseEnum::RpcError { error_data, .. }) => {
+                (status_code, error_data.code)
+            }
+            (_, JsonRpcResponseEnum::Result { .. }) => panic!("errors should never be a result"),
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(
+            parts(Web3ProxyError::ParseAddressError),
+            (StatusCode::BAD_REQUEST, -32602)
+        );
+        assert_eq!(
+            parts(Web3ProxyError::NoServersSynced),
+            (StatusCode::SERVICE_UNAVAILABLE, -32000)
+        );
+        assert_eq!(
+            parts(Web3ProxyError::InvalidUserTier),
+            (StatusCode::INTERNAL_SERVER_ERROR, -32603)
+        );
+        assert_eq!(
+            parts(Web3ProxyError::StatusCode(
+                StatusCode::TOO_MANY_REQUESTS,
+                "slow down".into(),
+                None
+            )),
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                Web3ProxyErrorCode::RateLimited.jsonrpc_code()
+            )
+        );
+    }
+
+    #[test]
+    fn test_keyed_ip_rate_limited() {
+        let authorization = Authorization::internal(None).unwrap();
+
+        assert_eq!(
+            parts(Web3ProxyError::KeyedIpRateLimited(authorization, None)),
+            (StatusCode::TOO_MANY_REQUESTS, -32005)
+        );
+    }
+
+    #[test]
+    fn test_is_backend_outage() {
+        assert!(Web3ProxyError::NoServersSynced.is_backend_outage());
+        assert!(Web3ProxyError::Timeout(None).is_backend_outage());
+        assert!(Web3ProxyError::Arc(Arc::new(Web3ProxyError::NoHandleReady)).is_backend_outage());
+
+        // the backends answered. the answer just wasn't a result
+        assert!(!Web3ProxyError::JsonRpcErrorData(JsonRpcErrorData {
+            code: 3,
+            message: "execution reverted".into(),
+            data: None,
+        })
+        .is_backend_outage());
+
+        // the user's fault, not the backends'
+        assert!(!Web3ProxyError::ParseAddressError.is_backend_outage());
+    }
+}
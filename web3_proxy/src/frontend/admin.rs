@@ -1,19 +1,24 @@
 //! Handle admin helper logic
 
 use super::authorization::login_is_authorized;
+use super::client_ip::ClientIp;
+use super::idempotency_key::IdempotencyKey;
+use super::optional_header::OptionalHeader;
 use crate::admin_queries::query_admin_modify_usertier;
 use crate::app::Web3ProxyApp;
-use crate::errors::Web3ProxyResponse;
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext};
+use crate::errors::{Web3ProxyResponse, Web3ProxyResult};
 use crate::frontend::users::authentication::PostLogin;
+use crate::response_cache::{
+    flush_response_cache, ResponseCacheStats, RESPONSE_CACHE_LOOKUPS, RESPONSE_CACHE_MISSES,
+};
 use crate::user_token::UserBearerToken;
 use axum::{
     extract::{Path, Query},
     headers::{authorization::Bearer, Authorization},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Extension, Json, TypedHeader,
 };
-use axum_client_ip::InsecureClientIp;
 use axum_macros::debug_handler;
 use chrono::{TimeZone, Utc};
 use entities::{
@@ -21,16 +26,19 @@ use entities::{
 };
 use ethers::{prelude::Address, types::Bytes};
 use hashbrown::HashMap;
-use http::StatusCode;
+use http::{HeaderValue, StatusCode};
 use migration::sea_orm::prelude::{Decimal, Uuid};
 use migration::sea_orm::{
-    self, ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter,
+    self, ActiveModelTrait, ColumnTrait, Condition, EntityTrait, IntoActiveModel, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use siwe::{Message, VerificationOpts};
+use std::num::NonZeroU64;
 use std::ops::Add;
 use std::str::FromStr;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use time_03::{Duration, OffsetDateTime};
 use tracing::{info, trace, warn};
@@ -47,10 +55,13 @@ pub struct AdminIncreaseBalancePost {
 ///
 /// - user_address that is to credited balance
 /// - user_role_tier that is supposed to be adapted
+///
+/// With an `Idempotency-Key` header, a retry gets the first response instead of crediting the user again
 #[debug_handler]
 pub async fn admin_increase_balance(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
     TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    OptionalHeader(idempotency_key): OptionalHeader<IdempotencyKey>,
     Json(payload): Json<AdminIncreaseBalancePost>,
 ) -> Web3ProxyResponse {
     let caller = app.bearer_is_authorized(bearer).await?;
@@ -73,14 +84,46 @@ pub async fn admin_increase_balance(
             format!("No user found with {:?}", payload.user_address).into(),
         ))?;
 
+    if let Some(idempotency_key) = idempotency_key.as_ref() {
+        if let Some(receipt) = admin_increase_balance_receipt::Entity::find()
+            .filter(admin_increase_balance_receipt::Column::IdempotencyKey.eq(idempotency_key))
+            .one(&txn)
+            .await?
+        {
+            return replay_increase_balance(&payload, user_entry.id, &receipt);
+        }
+    }
+
     let increase_balance_receipt = admin_increase_balance_receipt::ActiveModel {
         amount: sea_orm::Set(payload.amount),
         admin_id: sea_orm::Set(admin_entry.id),
         deposit_to_user_id: sea_orm::Set(user_entry.id),
-        note: sea_orm::Set(payload.note.unwrap_or_default()),
+        note: sea_orm::Set(payload.note.clone().unwrap_or_default()),
+        idempotency_key: sea_orm::Set(idempotency_key.clone()),
         ..Default::default()
     };
-    increase_balance_receipt.save(&txn).await?;
+    let receipt = match increase_balance_receipt.insert(&txn).await {
+        Ok(x) => x,
+        Err(err) => {
+            txn.rollback().await?;
+
+            // if two requests with the same key race past the check above, the unique index makes one of them fail.
+            // the other one committed, so the loser replays its receipt
+            if let Some(idempotency_key) = idempotency_key {
+                if let Some(receipt) = admin_increase_balance_receipt::Entity::find()
+                    .filter(
+                        admin_increase_balance_receipt::Column::IdempotencyKey.eq(idempotency_key),
+                    )
+                    .one(app.db_conn()?)
+                    .await?
+                {
+                    return replay_increase_balance(&payload, user_entry.id, &receipt);
+                }
+            }
+
+            return Err(err.into());
+        }
+    };
     txn.commit().await?;
 
     // Invalidate the user_balance_cache for this user:
@@ -92,14 +135,425 @@ pub async fn admin_increase_balance(
         warn!(?err, "unable to invalidate caches");
     };
 
+    Ok(increase_balance_response(payload.user_address, &receipt))
+}
+
+/// The response for a retry. The first request with this `Idempotency-Key` already credited the user
+fn replay_increase_balance(
+    payload: &AdminIncreaseBalancePost,
+    user_id: u64,
+    receipt: &admin_increase_balance_receipt::Model,
+) -> Web3ProxyResponse {
+    // a key can't be reused for a different change. that is a bug in the client, not a retry
+    if receipt.deposit_to_user_id != user_id || receipt.amount != payload.amount {
+        return Err(Web3ProxyError::StatusCode(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "this Idempotency-Key was already used for a different balance change".into(),
+            None,
+        ));
+    }
+
+    let mut response = increase_balance_response(payload.user_address, receipt);
+
+    response
+        .headers_mut()
+        .insert("Idempotent-Replayed", HeaderValue::from_static("true"));
+
+    Ok(response)
+}
+
+fn increase_balance_response(
+    user_address: Address,
+    receipt: &admin_increase_balance_receipt::Model,
+) -> Response {
+    let out = json!({
+        "user": user_address,
+        "amount": receipt.amount,
+        "receipt_id": receipt.id,
+    });
+
+    Json(out).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminWsConnectionsParams {
+    pub rpc_key_id: Option<NonZeroU64>,
+}
+
+/// `GET /admin/ws_connections` -- As an admin, list the open websockets
+///
+/// - rpc_key_id optionally limits the list to one key
+#[debug_handler]
+pub async fn admin_ws_connections_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Query(params): Query<AdminWsConnectionsParams>,
+) -> Web3ProxyResponse {
+    app.bearer_is_admin(bearer).await?;
+
+    let connections = app.ws_connections.list(params.rpc_key_id).await;
+
+    let out = json!({
+        "connections": connections,
+    });
+
+    Ok(Json(out).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminWsConnectionsDisconnectPost {
+    pub rpc_key_id: NonZeroU64,
+}
+
+/// `POST /admin/ws_connections/disconnect` -- As an admin, close every open websocket for an rpc key
+///
+/// - rpc_key_id whose websockets are closed. The key stays active, so deactivate it too if they should not reconnect
+#[debug_handler]
+pub async fn admin_ws_connections_disconnect_post(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<AdminWsConnectionsDisconnectPost>,
+) -> Web3ProxyResponse {
+    let caller = app.bearer_is_admin(bearer).await?;
+
+    let disconnected = app.ws_connections.disconnect_key(payload.rpc_key_id).await;
+
+    info!(admin_id=%caller.id, rpc_key_id=%payload.rpc_key_id, %disconnected, "admin disconnected websockets");
+
+    let out = json!({
+        "rpc_key_id": payload.rpc_key_id,
+        "disconnected": disconnected,
+    });
+
+    Ok(Json(out).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminRpcDisabledPost {
+    pub rpc_name: String,
+    pub disabled: bool,
+}
+
+/// `POST /admin/rpcs/disabled` -- As an admin, take a backend rpc out of rotation or put it back
+///
+/// - rpc_name as it is named in the config
+/// - disabled stays set until an admin changes it again. Requests already sent to the rpc are left to finish
+#[debug_handler]
+pub async fn admin_rpc_disabled_post(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<AdminRpcDisabledPost>,
+) -> Web3ProxyResponse {
+    let caller = app.bearer_is_admin(bearer).await?;
+
+    // the same name could be used in more than one group of rpcs
+    let mut found = false;
+    for rpcs in [
+        Some(&app.balanced_rpcs),
+        app.private_rpcs.as_ref(),
+        app.bundler_4337_rpcs.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        found |= rpcs
+            .set_admin_disabled(&payload.rpc_name, payload.disabled)
+            .is_some();
+    }
+
+    if !found {
+        return Err(Web3ProxyError::BadRequest(
+            format!("no rpc named {}", payload.rpc_name).into(),
+        ));
+    }
+
+    info!(admin_id=%caller.id, rpc_name=%payload.rpc_name, disabled=%payload.disabled, "admin changed rpc");
+
+    let out = json!({
+        "rpc_name": payload.rpc_name,
+        "disabled": payload.disabled,
+    });
+
+    Ok(Json(out).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminMaintenancePost {
+    pub enabled: bool,
+}
+
+/// `POST /admin/maintenance` -- As an admin, turn maintenance mode on or off
+///
+/// - enabled makes the proxy routes return 503 with a `Retry-After`. Health, status, user, and admin routes keep working
+/// - the setting is only kept in memory. a restart goes back to `maintenance_mode` in the config
+#[debug_handler]
+pub async fn admin_maintenance_post(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<AdminMaintenancePost>,
+) -> Web3ProxyResponse {
+    let caller = app.bearer_is_admin(bearer).await?;
+
+    let was_enabled = app.maintenance_mode.swap(payload.enabled, Ordering::AcqRel);
+
+    info!(admin_id=%caller.id, enabled=%payload.enabled, %was_enabled, "admin changed maintenance mode");
+
+    let out = json!({
+        "enabled": payload.enabled,
+        "was_enabled": was_enabled,
+        "retry_after_seconds": app.config.load().maintenance_retry_after_seconds,
+    });
+
+    Ok(Json(out).into_response())
+}
+
+/// `GET /admin/cache` -- As an admin, see how full the response caches are and how often they are used
+///
+/// - entries are counted per method, so this walks every cached response
+/// - hits and misses are counted since the proxy started
+#[debug_handler]
+pub async fn admin_cache_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Web3ProxyResponse {
+    app.bearer_is_admin(bearer).await?;
+
+    let caches: Vec<_> = app
+        .response_caches()
+        .into_iter()
+        .map(|(name, cache)| ResponseCacheStats::new(name, cache))
+        .collect();
+
+    let lookups = RESPONSE_CACHE_LOOKUPS.load(Ordering::Relaxed);
+    let misses = RESPONSE_CACHE_MISSES.load(Ordering::Relaxed).min(lookups);
+
+    let hit_rate = if lookups == 0 {
+        None
+    } else {
+        Some((lookups - misses) as f64 / lookups as f64)
+    };
+
+    let out = json!({
+        "chain_id": app.config.load().chain_id,
+        "lookups": lookups,
+        "hits": lookups - misses,
+        "misses": misses,
+        "hit_rate": hit_rate,
+        "caches": caches,
+    });
+
+    Ok(Json(out).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminCacheFlushPost {
+    pub method: Option<String>,
+    pub chain_id: Option<u64>,
+}
+
+/// `POST /admin/cache/flush` -- As an admin, throw away cached responses without a restart
+///
+/// - method only flushes that method's responses. Without it, everything is flushed
+/// - chain_id must match this proxy's chain if it is given. It guards against flushing the wrong proxy
+#[debug_handler]
+pub async fn admin_cache_flush_post(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<AdminCacheFlushPost>,
+) -> Web3ProxyResponse {
+    let caller = app.bearer_is_admin(bearer).await?;
+
+    let chain_id = app.config.load().chain_id;
+
+    if let Some(x) = payload.chain_id {
+        if x != chain_id {
+            return Err(Web3ProxyError::BadRequest(
+                format!("this proxy serves chain {}, not {}", chain_id, x).into(),
+            ));
+        }
+    }
+
+    let mut flushed = vec![];
+    for (name, cache) in app.response_caches() {
+        flush_response_cache(cache, payload.method.as_deref())
+            .map_err(anyhow::Error::from)
+            .web3_context(format!("failed flushing {}", name))?;
+
+        flushed.push(name);
+    }
+
+    warn!(admin_id=%caller.id, method=?payload.method, %chain_id, "admin flushed response caches");
+
+    let out = json!({
+        "chain_id": chain_id,
+        "method": payload.method,
+        "flushed": flushed,
+    });
+
+    Ok(Json(out).into_response())
+}
+
+/// Results per page for `GET /admin/search`
+const ADMIN_SEARCH_PAGE_SIZE: u64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct AdminSearchParams {
+    pub description: Option<String>,
+    pub email: Option<String>,
+    pub address: Option<String>,
+    #[serde(default)]
+    pub page: u64,
+}
+
+/// `GET /admin/search` -- As an admin, find users and rpc keys by description, email, or address
+///
+/// - description matches part of a user's or a key's description
+/// - email matches part of a user's email
+/// - address matches the start of a user's address
+/// - keys are limited to the users that match email and address. secret keys are never included
+#[debug_handler]
+pub async fn admin_search_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Query(params): Query<AdminSearchParams>,
+) -> Web3ProxyResponse {
+    app.bearer_is_admin(bearer).await?;
+
+    let db_replica = app.db_replica()?;
+
+    let description = params
+        .description
+        .as_deref()
+        .map(str::trim)
+        .filter(|x| !x.is_empty());
+    let email = params
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|x| !x.is_empty());
+    let address = params
+        .address
+        .as_deref()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(address_prefix_range)
+        .transpose()?;
+
+    if description.is_none() && email.is_none() && address.is_none() {
+        return Err(Web3ProxyError::BadRequest(
+            "search needs a description, email, or address".into(),
+        ));
+    }
+
+    // the address is a range on the unique index. the substring searches scan the tables, which is fine for admins
+    let mut owner_condition = Condition::all();
+
+    if let Some(email) = email {
+        owner_condition = owner_condition.add(user::Column::Email.like(&like_contains(email)));
+    }
+
+    if let Some((low, high)) = address {
+        owner_condition = owner_condition
+            .add(user::Column::Address.between(low.as_bytes().to_vec(), high.as_bytes().to_vec()));
+    }
+
+    let mut user_condition = owner_condition.clone();
+
+    if let Some(description) = description {
+        user_condition =
+            user_condition.add(user::Column::Description.like(&like_contains(description)));
+    }
+
+    let users = user::Entity::find()
+        .filter(user_condition)
+        .order_by_asc(user::Column::Id)
+        .paginate(db_replica.as_ref(), ADMIN_SEARCH_PAGE_SIZE)
+        .fetch_page(params.page)
+        .await?;
+
+    let mut key_query = rpc_key::Entity::find()
+        .inner_join(user::Entity)
+        .filter(owner_condition);
+
+    if let Some(description) = description {
+        key_query =
+            key_query.filter(rpc_key::Column::Description.like(&like_contains(description)));
+    }
+
+    let rpc_keys = key_query
+        .order_by_asc(rpc_key::Column::Id)
+        .paginate(db_replica.as_ref(), ADMIN_SEARCH_PAGE_SIZE)
+        .fetch_page(params.page)
+        .await?;
+
+    let users: Vec<_> = users
+        .into_iter()
+        .map(|x| {
+            json!({
+                "id": x.id,
+                "address": Address::from_slice(&x.address),
+                "description": x.description,
+                "email": x.email,
+                "user_tier_id": x.user_tier_id,
+            })
+        })
+        .collect();
+
+    // built by hand so that the secret key can't end up in here
+    let rpc_keys: Vec<_> = rpc_keys
+        .into_iter()
+        .map(|x| {
+            json!({
+                "id": x.id,
+                "user_id": x.user_id,
+                "description": x.description,
+                "active": x.active,
+                "private_txs": x.private_txs,
+                "chain_id": x.chain_id,
+                "scope": x.scope,
+                "last_used_at": x.last_used_at,
+            })
+        })
+        .collect();
+
     let out = json!({
-        "user": payload.user_address,
-        "amount": payload.amount,
+        "users": users,
+        "rpc_keys": rpc_keys,
+        "page": params.page,
+        "page_size": ADMIN_SEARCH_PAGE_SIZE,
     });
 
     Ok(Json(out).into_response())
 }
 
+/// A LIKE pattern that matches any value containing `x`
+fn like_contains(x: &str) -> String {
+    let escaped = x
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{}%", escaped)
+}
+
+/// The lowest and highest addresses that start with the given hex
+fn address_prefix_range(prefix: &str) -> Web3ProxyResult<(Address, Address)> {
+    let prefix = prefix.strip_prefix("0x").unwrap_or(prefix);
+
+    if prefix.len() > 40 || !prefix.chars().all(|x| x.is_ascii_hexdigit()) {
+        return Err(Web3ProxyError::BadRequest(
+            "address must be hex and at most 20 bytes".into(),
+        ));
+    }
+
+    let low = Address::from_str(&format!("{:0<40}", prefix))
+        .map_err(|_| Web3ProxyError::BadRequest("invalid address".into()))?;
+    let high = Address::from_str(&format!("{:f<40}", prefix))
+        .map_err(|_| Web3ProxyError::BadRequest("invalid address".into()))?;
+
+    Ok((low, high))
+}
+
 /// `POST /admin/modify_role` -- As an admin, modify a user's user-tier
 ///
 /// - user_address that is to be modified
@@ -124,7 +578,7 @@ pub async fn admin_change_user_roles(
 #[debug_handler]
 pub async fn admin_imitate_login_get(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
     Path(mut params): Path<HashMap<String, String>>,
 ) -> Web3ProxyResponse {
     // First check if the login is authorized
@@ -162,11 +616,9 @@ pub async fn admin_imitate_login_get(
         })?;
 
     // We want to login to llamanodes.com
-    let domain = app
-        .config
-        .login_domain
-        .as_deref()
-        .unwrap_or("llamanodes.com");
+    let config = app.config.load_full();
+
+    let domain = config.login_domain.as_deref().unwrap_or("llamanodes.com");
 
     let message_domain = domain.parse()?;
     // TODO: don't unwrap
@@ -181,7 +633,7 @@ pub async fn admin_imitate_login_get(
         statement: Some("👑👑👑👑👑".to_string()),
         uri: message_uri,
         version: siwe::Version::V1,
-        chain_id: app.config.chain_id,
+        chain_id: app.config.load().chain_id,
         expiration_time: Some(expiration_time.into()),
         issued_at: issued_at.into(),
         nonce: nonce.to_string(),
@@ -218,6 +670,8 @@ pub async fn admin_imitate_login_get(
         .exec(db_conn)
         .await?;
     trace!("cleared expired pending_logins: {:?}", delete_result);
+    app.expired_logins_cleared
+        .fetch_add(delete_result.rows_affected, Ordering::Relaxed);
 
     // Note that the admin is trying to log in as this user
     let trail = admin_trail::ActiveModel {
@@ -281,7 +735,7 @@ pub async fn admin_imitate_login_get(
 #[debug_handler]
 pub async fn admin_imitate_login_post(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<PostLogin>,
 ) -> Web3ProxyResponse {
     login_is_authorized(&app, ip).await?;
@@ -443,3 +897,37 @@ pub async fn admin_imitate_login_post(
 
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{address_prefix_range, like_contains};
+    use ethers::prelude::Address;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_like_contains() {
+        assert_eq!(like_contains("acme"), "%acme%");
+        assert_eq!(like_contains("100%_off"), "%100\\%\\_off%");
+    }
+
+    #[test]
+    fn test_address_prefix_range() {
+        let (low, high) = address_prefix_range("0xabc").unwrap();
+
+        assert_eq!(
+            low,
+            Address::from_str("0xabc0000000000000000000000000000000000000").unwrap()
+        );
+        assert_eq!(
+            high,
+            Address::from_str("0xabcfffffffffffffffffffffffffffffffffffff").unwrap()
+        );
+
+        let full = "0x0000000000000000000000000000000000000001";
+        let (low, high) = address_prefix_range(full).unwrap();
+        assert_eq!(low, high);
+
+        assert!(address_prefix_range("0xnothex").is_err());
+        assert!(address_prefix_range(&format!("{}0", full)).is_err());
+    }
+}
@@ -1,37 +1,46 @@
 //! Utilities for authorization of logged in and anonymous users.
 
+use super::request_signature::{HmacSecret, SignedHeaders};
 use super::rpc_proxy_ws::ProxyMode;
 use crate::app::{Web3ProxyApp, APP_USER_AGENT};
 use crate::balance::Balance;
 use crate::caches::RegisteredUserRateLimitKey;
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
 use crate::jsonrpc::{JsonRpcForwardedResponse, JsonRpcRequest};
+use crate::key_scope::KeyScope;
+use crate::quota::RequestQuota;
+use crate::redact::redact_params;
+use crate::response_cache::CachePolicy;
 use crate::rpcs::blockchain::Web3ProxyBlock;
 use crate::rpcs::one::Web3Rpc;
+use crate::stats::slo::SloMetrics;
+use crate::stats::tier_metrics::TierMetrics;
 use crate::stats::{AppStat, BackendRequests};
 use crate::user_token::UserBearerToken;
+use crate::webhooks::{RevertWebhooks, WebhookEvent};
 use anyhow::Context;
 use axum::headers::authorization::Bearer;
-use axum::headers::{Header, Origin, Referer, UserAgent};
-use chrono::Utc;
+use axum::headers::{CacheControl, Header, Origin, Referer, UserAgent};
+use chrono::{DateTime, Utc};
 use core::fmt;
 use deferred_rate_limiter::DeferredRateLimitResult;
 use derivative::Derivative;
 use derive_more::From;
-use entities::{login, rpc_key, user, user_tier};
+use entities::{admin, login, rpc_key, user, user_tier};
 use ethers::types::{Bytes, U64};
 use ethers::utils::keccak256;
 use futures::TryFutureExt;
 use hashbrown::HashMap;
-use http::HeaderValue;
-use ipnet::IpNet;
+use http::{HeaderValue, StatusCode};
+use ipnet::{IpNet, Ipv6Net};
 use migration::sea_orm::prelude::Decimal;
-use migration::sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use migration::sea_orm::{ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter};
+use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use rdkafka::message::{Header as KafkaHeader, OwnedHeaders as KafkaOwnedHeaders, OwnedMessage};
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::util::Timeout as KafkaTimeout;
-use redis_rate_limiter::redis::AsyncCommands;
+use redis_rate_limiter::redis::{self, AsyncCommands};
 use redis_rate_limiter::RedisRateLimitResult;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
@@ -43,6 +52,7 @@ use std::num::NonZeroU64;
 use std::sync::atomic::{self, AtomicBool, AtomicI64, AtomicU64, AtomicUsize};
 use std::time::Duration;
 use std::{net::IpAddr, str::FromStr, sync::Arc};
+use subtle::{Choice, ConstantTimeEq};
 use tokio::sync::RwLock as AsyncRwLock;
 use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinHandle;
@@ -51,6 +61,32 @@ use tracing::{error, info, trace, warn};
 use ulid::Ulid;
 use uuid::Uuid;
 
+/// The secret mixed into every hashed rpc key. Set once at startup from the app config.
+/// Changing this invalidates every hashed key in the database!
+pub static RPC_KEY_HASH_SECRET: OnceCell<Vec<u8>> = OnceCell::new();
+
+/// Set `RPC_KEY_HASH_SECRET` from the config. The secret cannot be changed once it is set.
+/// Keys are saved and found by their hash, so the secret is required whenever there is a database.
+/// An empty secret is almost certainly a mistake in the config, so it is an error instead of a weak hash.
+pub fn set_rpc_key_hash_secret(secret: Option<&str>, db_configured: bool) -> anyhow::Result<()> {
+    let secret = match secret {
+        Some(x) if x.trim().is_empty() => {
+            anyhow::bail!("rpc_key_hash_secret is set but empty. remove it or set a real secret")
+        }
+        Some(x) => x.as_bytes(),
+        None if db_configured => {
+            anyhow::bail!("rpc_key_hash_secret is required when a database is configured")
+        }
+        None => return Ok(()),
+    };
+
+    if RPC_KEY_HASH_SECRET.get_or_init(|| secret.to_vec()) != secret {
+        warn!("rpc_key_hash_secret cannot be changed without a restart");
+    }
+
+    Ok(())
+}
+
 /// This lets us use UUID and ULID while we transition to only ULIDs
 /// TODO: custom deserialize that can also go from String to Ulid
 #[derive(Copy, Clone, Deserialize)]
@@ -64,6 +100,55 @@ impl RpcSecretKey {
         Ulid::new().into()
     }
 
+    /// A keyed hash of the secret key. This is what gets stored in the database instead of the raw key.
+    /// Lookups are by exact key, so a fast hash is fine.
+    /// Errors if `rpc_key_hash_secret` was never loaded. Hashing without it would make the key unfindable later.
+    pub fn hashed(&self) -> anyhow::Result<Uuid> {
+        let secret = RPC_KEY_HASH_SECRET
+            .get()
+            .context("rpc_key_hash_secret is not set. refusing to hash rpc keys without it")?;
+
+        let mut input = Vec::with_capacity(secret.len() + 16);
+        input.extend_from_slice(secret);
+        input.extend_from_slice(&self.as_128().to_be_bytes());
+
+        let hash = keccak256(input);
+
+        Ok(Uuid::from_slice(&hash[..16]).expect("16 bytes is always a valid uuid"))
+    }
+
+    /// Match this key in the `rpc_key` table.
+    /// Keys created before hashing was added are stored raw until `hash_rpc_keys` is run.
+    pub fn db_condition(&self) -> anyhow::Result<Condition> {
+        let condition = Condition::any()
+            .add(
+                Condition::all()
+                    .add(rpc_key::Column::SecretKey.eq(self.hashed()?))
+                    .add(rpc_key::Column::SecretKeyHashed.eq(true)),
+            )
+            .add(
+                Condition::all()
+                    .add(rpc_key::Column::SecretKey.eq(<Uuid>::from(*self)))
+                    .add(rpc_key::Column::SecretKeyHashed.eq(false)),
+            );
+
+        Ok(condition)
+    }
+
+    /// Check a row found by `db_condition` without leaking how much of the key matched.
+    /// Both forms of the key are always computed so that hashed and raw rows take the same time
+    pub fn matches_stored(&self, stored: Uuid, stored_hashed: bool) -> anyhow::Result<bool> {
+        let hashed = self.hashed()?;
+        let raw = Uuid::from(*self);
+
+        let stored_hashed = Choice::from(stored_hashed as u8);
+
+        let matches = (hashed.as_bytes().ct_eq(stored.as_bytes()) & stored_hashed)
+            | (raw.as_bytes().ct_eq(stored.as_bytes()) & !stored_hashed);
+
+        Ok(matches.into())
+    }
+
     fn as_128(&self) -> u128 {
         match self {
             Self::Ulid(x) => x.0,
@@ -119,10 +204,35 @@ pub enum RateLimitResult {
         /// when their rate limit resets and they can try more requests
         Option<Instant>,
     ),
+    /// The key is under its limit, but the ip is over `keyed_ip_requests_per_period`
+    KeyedIpRateLimited(Authorization, Option<Instant>),
     /// This key is not in our database. Deny access!
     UnknownKey,
 }
 
+/// `Err` with when they can retry if the limit was hit. A failing limiter is logged and allows the request
+fn check_throttle(
+    result: anyhow::Result<DeferredRateLimitResult>,
+    limiter: &str,
+) -> Result<(), Option<Instant>> {
+    match result {
+        Ok(DeferredRateLimitResult::Allowed) => Ok(()),
+        // TODO: set headers so they know when they can retry
+        Ok(DeferredRateLimitResult::RetryAt(retry_at)) => Err(Some(retry_at)),
+        // TODO: i don't think we'll get here. maybe if we ban an IP forever? seems unlikely
+        Ok(DeferredRateLimitResult::RetryNever) => Err(None),
+        Err(err) => {
+            // internal error, not rate limit being hit
+            // TODO: i really want axum to do this for us in a single place.
+            error!(
+                ?err,
+                "{} rate limiter is unhappy. allowing request", limiter
+            );
+            Ok(())
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum AuthorizationType {
     Internal,
@@ -159,6 +269,8 @@ pub struct AuthorizationChecks {
     /// depending on the caller, errors might be expected. this keeps us from bloating our database
     /// u16::MAX == 100%
     pub log_revert_chance: u16,
+    /// if true, saved reverts are also sent to the user's webhook
+    pub webhook_reverts: bool,
     /// if true, transactions are broadcast only to private mempools.
     /// IMPORTANT! Once confirmed by a miner, they will be public on the blockchain!
     pub private_txs: bool,
@@ -167,6 +279,24 @@ pub struct AuthorizationChecks {
     /// they might spend slightly more than they've paid, but we are okay with that
     /// TODO: we could price the request now and if its too high, downgrade. but thats more complex than we need
     pub paid_credits_used: bool,
+    /// title of the user's tier. None for anonymous and internal requests
+    pub user_tier_title: Option<String>,
+    /// if None, the key can make unlimited requests per day/month. set per key
+    pub quota: Option<RequestQuota>,
+    /// if set, every request with this key must be signed with this secret
+    pub hmac_secret: Option<HmacSecret>,
+    /// if None, allow any method
+    pub scope: Option<KeyScope>,
+    /// if None, allow any chain
+    pub chain_id: Option<u64>,
+    /// which cached responses are good enough. inherited from the user_tier
+    pub cache_policy: CachePolicy,
+    /// if set, `eth_estimateGas` results are multiplied by this percent. inherited from the user_tier
+    pub gas_estimate_percent: Option<u16>,
+    /// larger http request bodies get a 413. inherited from the user_tier. None only has the app's `max_request_bytes`
+    pub max_request_bytes: Option<u32>,
+    /// larger responses are replaced with an error. inherited from the user_tier
+    pub max_response_bytes: Option<u32>,
 }
 
 /// TODO: include the authorization checks in this?
@@ -178,7 +308,17 @@ pub struct Authorization {
     pub origin: Option<Origin>,
     pub referer: Option<Referer>,
     pub user_agent: Option<UserAgent>,
+    /// set by the client with the `X-Web3-Proxy-Tag` header. saved with the accounting stats
+    pub tag: Option<String>,
+    /// set by the client with the `X-Timeout` header. limits how long we keep retrying on their behalf
+    pub timeout: Option<Duration>,
+    /// set by the client with the `X-Gas-Multiplier` header. overrides the tier's `gas_estimate_percent`
+    pub gas_estimate_percent: Option<u16>,
+    /// set by the client with the `X-Result-Pointer` header. only this part of each result is returned
+    pub result_pointer: Option<String>,
     pub authorization_type: AuthorizationType,
+    /// sends reverts for keys with `webhook_reverts`. None for internal and anonymous requests
+    pub revert_webhooks: Option<Arc<RevertWebhooks>>,
 }
 
 pub struct KafkaDebugLogger {
@@ -230,7 +370,7 @@ impl KafkaDebugLogger {
         let kafka_key =
             rmp_serde::to_vec(&rpc_secret_key_id).expect("ids should always serialize with rmp");
 
-        let chain_id = app.config.chain_id;
+        let chain_id = app.config.load().chain_id;
 
         let head_block_num = head_block_num
             .copied()
@@ -312,6 +452,11 @@ impl KafkaDebugLogger {
     /// for opt-in debug usage, log the request to kafka
     /// TODO: generic type for request
     pub fn log_debug_request(&self, request: &JsonRpcRequest) -> JoinHandle<KafkaLogResult> {
+        let request = JsonRpcRequest {
+            params: redact_params(&request.method, &request.params),
+            ..request.clone()
+        };
+
         // TODO: is rust message pack a good choice? try rkyv instead
         let payload =
             rmp_serde::to_vec(&request).expect("requests should always serialize with rmp");
@@ -347,11 +492,17 @@ pub struct RequestMetadata {
 
     pub usd_per_cu: Decimal,
 
+    /// compute units for the method from `method_costs`. None uses the built in cost
+    pub configured_cu: Option<u64>,
+
     pub request_ulid: Ulid,
 
     /// Size of the JSON request. Does not include headers or things like that.
     pub request_bytes: usize,
 
+    /// An eth_call with a state override set. Only rpcs that support state overrides can serve it
+    pub state_override_request: AtomicBool,
+
     /// The JSON-RPC request method.
     pub method: Cow<'static, str>,
 
@@ -386,6 +537,12 @@ pub struct RequestMetadata {
 
     /// Cancel-safe channel for sending stats to the buffer
     pub stat_sender: Option<mpsc::UnboundedSender<AppStat>>,
+
+    /// Rolling success rate and latency for the public SLO page
+    pub slo_metrics: Option<Arc<SloMetrics>>,
+
+    /// Request counts grouped by user tier
+    pub tier_metrics: Option<Arc<TierMetrics>>,
 }
 
 impl Default for Authorization {
@@ -510,13 +667,18 @@ impl RequestMetadata {
             }
         }
 
-        let chain_id = app.config.chain_id;
+        let config = app.config.load();
+
+        let chain_id = config.chain_id;
+
+        let configured_cu = config.method_costs.get(chain_id, &method);
 
         let x = Self {
             archive_request: false.into(),
             authorization: Some(authorization),
             backend_requests: Default::default(),
             chain_id,
+            configured_cu,
             error_response: false.into(),
             kafka_debug_logger,
             method,
@@ -529,7 +691,10 @@ impl RequestMetadata {
             response_timestamp: 0.into(),
             start_instant: Instant::now(),
             stat_sender: app.stat_sender.clone(),
-            usd_per_cu: app.config.usd_per_cu.unwrap_or_default(),
+            state_override_request: false.into(),
+            slo_metrics: Some(app.slo_metrics.clone()),
+            tier_metrics: Some(app.tier_metrics.clone()),
+            usd_per_cu: config.usd_per_cu.unwrap_or_default(),
             user_error_response: false.into(),
         };
 
@@ -541,6 +706,14 @@ impl RequestMetadata {
     }
 
     pub fn try_send_stat(mut self) -> Web3ProxyResult<()> {
+        if let Some(slo_metrics) = self.slo_metrics.take() {
+            slo_metrics.record(&self);
+        }
+
+        if let Some(tier_metrics) = self.tier_metrics.take() {
+            tier_metrics.record(&self);
+        }
+
         if let Some(stat_sender) = self.stat_sender.take() {
             trace!(?self, "sending stat");
 
@@ -599,7 +772,7 @@ impl RequestMetadata {
 // TODO: is this where the panic comes from?
 impl Drop for RequestMetadata {
     fn drop(&mut self) {
-        if self.stat_sender.is_some() {
+        if self.stat_sender.is_some() || self.tier_metrics.is_some() {
             // turn `&mut self` into `self`
             let x = mem::take(self);
 
@@ -633,7 +806,7 @@ impl FromStr for RpcSecretKey {
         } else if let Ok(uuid) = s.parse::<Uuid>() {
             Ok(uuid.into())
         } else {
-            // TODO: custom error type so that this shows as a 400
+            // this gets the same response as an unknown key
             Err(Web3ProxyError::InvalidUserKey)
         }
     }
@@ -670,6 +843,18 @@ impl From<RpcSecretKey> for Uuid {
 }
 
 impl Authorization {
+    /// The percent to multiply `eth_estimateGas` results by. The client's header wins over their tier. None returns the estimate as is
+    pub fn gas_estimate_percent(&self) -> Option<u16> {
+        self.gas_estimate_percent
+            .or(self.checks.gas_estimate_percent)
+    }
+
+    /// A request to the public endpoint without an api key
+    pub fn is_public(&self) -> bool {
+        self.checks.rpc_secret_key_id.is_none()
+            && matches!(self.authorization_type, AuthorizationType::Frontend)
+    }
+
     pub fn internal(db_conn: Option<DatabaseConnection>) -> Web3ProxyResult<Self> {
         let authorization_checks = AuthorizationChecks {
             // any error logs on a local (internal) query are likely problems. log them all
@@ -684,6 +869,7 @@ impl Authorization {
         Self::try_new(
             authorization_checks,
             db_conn,
+            None,
             &ip,
             None,
             None,
@@ -720,6 +906,7 @@ impl Authorization {
         Self::try_new(
             authorization_checks,
             db_conn,
+            None,
             ip,
             origin,
             referer,
@@ -728,10 +915,40 @@ impl Authorization {
         )
     }
 
+    /// The tier's `max_request_bytes`. Http bodies and websocket messages are both checked
+    pub fn check_request_size(&self, num_bytes: usize) -> Web3ProxyResult<()> {
+        if let Some(max_request_bytes) = self.checks.max_request_bytes {
+            if num_bytes > max_request_bytes as usize {
+                return Err(Web3ProxyError::StatusCode(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "the request is larger than {} bytes. larger requests require a higher tier",
+                        max_request_bytes
+                    )
+                    .into(),
+                    None,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A request can ask for fresher responses than its tier gets, but not for older ones
+    pub fn limit_cache_age(&mut self, cache_control: Option<&CacheControl>) {
+        if let Some(cache_control) = cache_control {
+            self.checks.cache_policy = self
+                .checks
+                .cache_policy
+                .stricter(CachePolicy::from_cache_control(cache_control));
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn try_new(
         authorization_checks: AuthorizationChecks,
         db_conn: Option<DatabaseConnection>,
+        revert_webhooks: Option<Arc<RevertWebhooks>>,
         ip: &IpAddr,
         origin: Option<&Origin>,
         referer: Option<&Referer>,
@@ -791,7 +1008,12 @@ impl Authorization {
             origin: origin.cloned(),
             referer: referer.cloned(),
             user_agent: user_agent.cloned(),
+            tag: None,
+            timeout: None,
+            gas_estimate_percent: None,
+            result_pointer: None,
             authorization_type,
+            revert_webhooks,
         })
     }
 }
@@ -811,6 +1033,44 @@ pub async fn login_is_authorized(app: &Web3ProxyApp, ip: IpAddr) -> Web3ProxyRes
     Ok(authorization)
 }
 
+/// The longest a login message is delayed by `login_tarpit`
+const LOGIN_TARPIT_MAX: Duration = Duration::from_secs(10);
+
+/// How long to delay an ip's `count`th login message of the minute. None while it is under the soft limit
+fn login_tarpit_delay(count: u64, soft_limit: u64) -> Option<Duration> {
+    if soft_limit == 0 || count <= soft_limit {
+        return None;
+    }
+
+    Some(Duration::from_secs(count - soft_limit).min(LOGIN_TARPIT_MAX))
+}
+
+/// Slow down ips that request lots of login messages. Each message is a pending_login row.
+/// They are never refused so that real users behind a shared ip can still log in.
+pub async fn login_tarpit(app: &Web3ProxyApp, ip: IpAddr) {
+    let soft_limit = app.config.load().login_tarpit_per_minute;
+
+    if soft_limit == 0 {
+        return;
+    }
+
+    let counter = app
+        .login_messages_per_ip
+        .get_with(app.ip_limit_key(&ip), async { Arc::new(AtomicU64::new(0)) })
+        .await;
+
+    let count = counter.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+
+    if let Some(delay) = login_tarpit_delay(count, soft_limit) {
+        app.login_messages_tarpitted
+            .fetch_add(1, atomic::Ordering::Relaxed);
+
+        trace!(%ip, count, ?delay, "tarpitting login message");
+
+        tokio::time::sleep(delay).await;
+    }
+}
+
 /// semaphore won't ever be None, but its easier if key auth and ip auth work the same way
 /// keep the semaphore alive until the user's request is entirely complete
 pub async fn ip_is_authorized(
@@ -821,9 +1081,11 @@ pub async fn ip_is_authorized(
 ) -> Web3ProxyResult<(Authorization, Option<OwnedSemaphorePermit>)> {
     // TODO: i think we could write an `impl From` for this
     // TODO: move this to an AuthorizedUser extrator
+    let config = app.config.load_full();
+
     let (authorization, semaphore) = match app
         .rate_limit_by_ip(
-            &app.config.allowed_origin_requests_per_period,
+            &config.allowed_origin_requests_per_period,
             ip,
             origin,
             proxy_mode,
@@ -840,7 +1102,7 @@ pub async fn ip_is_authorized(
     };
 
     // in the background, add the ip to a recent_users map
-    if app.config.public_recent_ips_salt.is_some() {
+    if app.config.load().public_recent_ips_salt.is_some() {
         let app = app.clone();
         let ip = *ip;
 
@@ -850,15 +1112,16 @@ pub async fn ip_is_authorized(
             if let Ok(mut redis_conn) = app.redis_conn().await {
                 let salt = app
                     .config
+                    .load()
                     .public_recent_ips_salt
-                    .as_ref()
+                    .clone()
                     .expect("public_recent_ips_salt must exist in here");
 
                 let salted_ip = format!("{}:{}", salt, ip);
 
                 let hashed_ip = Bytes::from(keccak256(salted_ip.as_bytes()));
 
-                let recent_ip_key = format!("recent_users:ip:{}", app.config.chain_id);
+                let recent_ip_key = format!("recent_users:ip:{}", app.config.load().chain_id);
 
                 redis_conn
                     .zadd(recent_ip_key, hashed_ip.to_string(), now)
@@ -900,12 +1163,24 @@ pub async fn key_is_authorized(
         RateLimitResult::RateLimited(authorization, retry_at) => {
             return Err(Web3ProxyError::RateLimited(authorization, retry_at));
         }
+        RateLimitResult::KeyedIpRateLimited(authorization, retry_at) => {
+            return Err(Web3ProxyError::KeyedIpRateLimited(authorization, retry_at));
+        }
         RateLimitResult::UnknownKey => return Err(Web3ProxyError::UnknownKey),
     };
 
+    // only requests that passed the rate limits count against the quota
+    if let (_, Some(err)) = app.use_quota(&authorization, 1).await {
+        return Err(err);
+    }
+
+    if let Some(rpc_key_id) = authorization.checks.rpc_secret_key_id {
+        app.key_last_used.record(rpc_key_id);
+    }
+
     // TODO: DRY and maybe optimize the hashing
     // in the background, add the ip to a recent_users map
-    if app.config.public_recent_ips_salt.is_some() {
+    if app.config.load().public_recent_ips_salt.is_some() {
         let app = app.clone();
         let user_id = authorization.checks.user_id;
         let f = async move {
@@ -914,15 +1189,16 @@ pub async fn key_is_authorized(
             if let Ok(mut redis_conn) = app.redis_conn().await {
                 let salt = app
                     .config
+                    .load()
                     .public_recent_ips_salt
-                    .as_ref()
+                    .clone()
                     .expect("public_recent_ips_salt must exist in here");
 
                 let salted_user_id = format!("{}:{}", salt, user_id);
 
                 let hashed_user_id = Bytes::from(keccak256(salted_user_id.as_bytes()));
 
-                let recent_user_id_key = format!("recent_users:id:{}", app.config.chain_id);
+                let recent_user_id_key = format!("recent_users:id:{}", app.config.load().chain_id);
 
                 redis_conn
                     .zadd(recent_user_id_key, hashed_user_id.to_string(), now)
@@ -943,13 +1219,80 @@ pub async fn key_is_authorized(
     Ok((authorization, semaphore))
 }
 
+/// Keys with an HMAC secret need a valid signature of the body. Other keys ignore any signature.
+/// This is checked before `key_is_authorized` so that unsigned requests do not use the key's rate limits.
+/// Websocket upgrades sign an empty body. Messages on the socket are not signed.
+pub async fn signature_is_authorized(
+    app: &Arc<Web3ProxyApp>,
+    rpc_key: &RpcSecretKey,
+    proxy_mode: ProxyMode,
+    signature: Option<&SignedHeaders>,
+    body: &[u8],
+) -> Web3ProxyResult<()> {
+    let authorization_checks = app.authorization_checks(proxy_mode, rpc_key).await?;
+
+    let hmac_secret = match authorization_checks.hmac_secret.as_ref() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    let signature = signature.ok_or_else(|| {
+        Web3ProxyError::InvalidRequestSignature("this key only accepts signed requests".into())
+    })?;
+
+    signature.verify(
+        hmac_secret,
+        body,
+        Utc::now().timestamp(),
+        app.config.load().hmac_max_age_seconds,
+    )?;
+
+    // a valid signature can only be used once. this is only checked on this proxy
+    let seen = app
+        .hmac_signatures_seen
+        .entry(signature.signature)
+        .or_insert(())
+        .await;
+
+    if !seen.is_fresh() {
+        return Err(Web3ProxyError::InvalidRequestSignature(
+            "this signature has already been used".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// The key to use when limiting requests from an ip address.
+/// IPv4 addresses are used as is. IPv6 addresses are truncated to their network prefix.
+pub fn ip_limit_key(ip: IpAddr, ipv6_prefix_len: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ipv6) => {
+            let prefix_len = ipv6_prefix_len.min(128);
+
+            Ipv6Net::new(ipv6, prefix_len)
+                .expect("prefix_len is always <= 128")
+                .network()
+                .into()
+        }
+    }
+}
+
 impl Web3ProxyApp {
+    /// The key to use when limiting requests from an ip address. See `ip_limit_key`
+    pub fn ip_limit_key(&self, ip: &IpAddr) -> IpAddr {
+        ip_limit_key(*ip, self.config.load().public_ipv6_prefix_len)
+    }
+
     /// Limit the number of concurrent requests from the given ip address.
     pub async fn ip_semaphore(&self, ip: &IpAddr) -> Web3ProxyResult<Option<OwnedSemaphorePermit>> {
-        if let Some(max_concurrent_requests) = self.config.public_max_concurrent_requests {
+        if let Some(max_concurrent_requests) = self.config.load().public_max_concurrent_requests {
+            let ip = self.ip_limit_key(ip);
+
             let semaphore = self
                 .ip_semaphores
-                .get_with_by_ref(ip, async {
+                .get_with_by_ref(&ip, async {
                     // TODO: set max_concurrent_requests dynamically based on load?
                     let s = Semaphore::new(max_concurrent_requests);
                     Arc::new(s)
@@ -1017,6 +1360,19 @@ impl Web3ProxyApp {
         Ok(user)
     }
 
+    /// Like `bearer_is_authorized`, but the user must also have the admin role
+    pub async fn bearer_is_admin(&self, bearer: Bearer) -> Web3ProxyResult<user::Model> {
+        let user = self.bearer_is_authorized(bearer).await?;
+
+        admin::Entity::find()
+            .filter(admin::Column::UserId.eq(user.id))
+            .one(self.db_replica()?.as_ref())
+            .await?
+            .ok_or_else(|| Web3ProxyError::AccessDenied("not an admin".into()))?;
+
+        Ok(user)
+    }
+
     pub async fn rate_limit_login(
         &self,
         ip: IpAddr,
@@ -1026,7 +1382,7 @@ impl Web3ProxyApp {
 
         // we don't care about user agent or origin or referer
         let authorization = Authorization::external(
-            &self.config.allowed_origin_requests_per_period,
+            &self.config.load().allowed_origin_requests_per_period,
             self.db_conn().ok().cloned(),
             &ip,
             None,
@@ -1090,7 +1446,7 @@ impl Web3ProxyApp {
 
         // ip rate limits don't check referer or user agent
         // they do check origin because we can override rate limits for some origins
-        let authorization = Authorization::external(
+        let mut authorization = Authorization::external(
             allowed_origin_requests_per_period,
             self.db_conn().ok().cloned(),
             ip,
@@ -1100,45 +1456,30 @@ impl Web3ProxyApp {
             None,
         )?;
 
-        if let Some(rate_limiter) = &self.frontend_ip_rate_limiter {
-            match rate_limiter
-                .throttle(*ip, authorization.checks.max_requests_per_period, 1)
-                .await
-            {
-                Ok(DeferredRateLimitResult::Allowed) => {
-                    // rate limit allowed us. check concurrent request limits
-                    let semaphore = self.ip_semaphore(ip).await?;
-
-                    Ok(RateLimitResult::Allowed(authorization, semaphore))
-                }
-                Ok(DeferredRateLimitResult::RetryAt(retry_at)) => {
-                    // TODO: set headers so they know when they can retry
-                    // // trace!(?ip, "rate limit exceeded until {:?}", retry_at);
-                    Ok(RateLimitResult::RateLimited(authorization, Some(retry_at)))
-                }
-                Ok(DeferredRateLimitResult::RetryNever) => {
-                    // TODO: i don't think we'll get here. maybe if we ban an IP forever? seems unlikely
-                    // // trace!(?ip, "rate limit is 0");
-                    Ok(RateLimitResult::RateLimited(authorization, None))
-                }
-                Err(err) => {
-                    // this an internal error of some kind, not the rate limit being hit
-                    // TODO: i really want axum to do this for us in a single place.
-                    error!("rate limiter is unhappy. allowing ip. err={:?}", err);
+        authorization.checks.cache_policy =
+            CachePolicy::from_max_age_seconds(self.config.load().response_cache_max_age_seconds);
 
-                    // at least we can still check the semaphore
-                    let semaphore = self.ip_semaphore(ip).await?;
+        if let Some(rate_limiter) = &self.frontend_ip_rate_limiter {
+            let result = rate_limiter
+                .throttle(
+                    self.ip_limit_key(ip),
+                    authorization.checks.max_requests_per_period,
+                    1,
+                )
+                .await;
 
-                    Ok(RateLimitResult::Allowed(authorization, semaphore))
-                }
+            if let Err(retry_at) = check_throttle(result, "ip") {
+                // // trace!(?ip, "rate limit exceeded until {:?}", retry_at);
+                return Ok(RateLimitResult::RateLimited(authorization, retry_at));
             }
         } else {
-            // no redis, but we can still check the ip semaphore
-            let semaphore = self.ip_semaphore(ip).await?;
-
             // TODO: if no redis, rate limit with a local cache? "warn!" probably isn't right
-            Ok(RateLimitResult::Allowed(authorization, semaphore))
         }
+
+        // rate limit allowed us (or there is no redis). check concurrent request limits
+        let semaphore = self.ip_semaphore(ip).await?;
+
+        Ok(RateLimitResult::Allowed(authorization, semaphore))
     }
 
     // check the local cache for user data, or query the database
@@ -1153,9 +1494,15 @@ impl Web3ProxyApp {
 
         let fresh_clone = fresh.clone();
 
+        // the cache is keyed by the hash so that it can be invalidated with only what is in the database
+        // the secret is always loaded when there is a database. without either, there is nothing to check the key against
+        let hashed_secret_key = rpc_secret_key
+            .hashed()
+            .map_err(|_| Web3ProxyError::NoDatabase)?;
+
         let x = self
             .rpc_secret_key_cache
-            .try_get_with_by_ref(rpc_secret_key, async move {
+            .try_get_with(hashed_secret_key, async move {
                 {
                     let mut f = fresh.lock_arc();
                     *f = true;
@@ -1163,150 +1510,150 @@ impl Web3ProxyApp {
 
                 let db_replica = self.db_replica()?;
 
-                // TODO: join the user table to this to return the User? we don't always need it
+                // inactive keys are filtered in the same query as unknown keys and both are cached as the default checks.
+                // misses do the same hashing, comparison, and user lookup as hits so that they take about as long
                 // TODO: join on secondary users
                 // TODO: join on user tier
-                match rpc_key::Entity::find()
-                    .filter(rpc_key::Column::SecretKey.eq(<Uuid>::from(*rpc_secret_key)))
+                let rpc_key_model = rpc_key::Entity::find()
+                    .filter(rpc_secret_key.db_condition()?)
                     .filter(rpc_key::Column::Active.eq(true))
                     .one(db_replica.as_ref())
-                    .await?
-                {
-                    Some(rpc_key_model) => {
-                        // TODO: move these splits into helper functions
-                        // TODO: can we have sea orm handle this for us?
-                        let allowed_ips: Option<Vec<IpNet>> =
-                            if let Some(allowed_ips) = rpc_key_model.allowed_ips {
-                                let x = allowed_ips
-                                    .split(',')
-                                    .map(|x| x.trim().parse::<IpNet>())
-                                    .collect::<Result<Vec<_>, _>>()?;
-                                Some(x)
-                            } else {
-                                None
-                            };
-
-                        let allowed_origins: Option<Vec<Origin>> =
-                            if let Some(allowed_origins) = rpc_key_model.allowed_origins {
-                                // TODO: do this without collecting twice?
-                                let x = allowed_origins
-                                    .split(',')
-                                    .map(|x| HeaderValue::from_str(x.trim()))
-                                    .collect::<Result<Vec<_>, _>>()?
-                                    .into_iter()
-                                    .map(|x| Origin::decode(&mut [x].iter()))
-                                    .collect::<Result<Vec<_>, _>>()?;
-
-                                Some(x)
-                            } else {
-                                None
-                            };
-
-                        let allowed_referers: Option<Vec<Referer>> =
-                            if let Some(allowed_referers) = rpc_key_model.allowed_referers {
-                                let x = allowed_referers
-                                    .split(',')
-                                    .map(|x| {
-                                        x.trim()
-                                            .parse::<Referer>()
-                                            .or(Err(Web3ProxyError::InvalidReferer))
-                                    })
-                                    .collect::<Result<Vec<_>, _>>()?;
-
-                                Some(x)
-                            } else {
-                                None
-                            };
-
-                        let allowed_user_agents: Option<Vec<UserAgent>> =
-                            if let Some(allowed_user_agents) = rpc_key_model.allowed_user_agents {
-                                let x: Result<Vec<_>, _> = allowed_user_agents
-                                    .split(',')
-                                    .map(|x| {
-                                        x.trim()
-                                            .parse::<UserAgent>()
-                                            .or(Err(Web3ProxyError::InvalidUserAgent))
-                                    })
-                                    .collect();
-
-                                Some(x?)
-                            } else {
-                                None
-                            };
-
-                        // Get the user_tier
-                        let user_model = user::Entity::find_by_id(rpc_key_model.user_id)
-                            .one(db_replica.as_ref())
-                            .await?
-                            .web3_context(
-                                "user model was not found, but every rpc_key should have a user",
-                            )?;
-
-                        let mut user_tier_model = user_tier::Entity::find_by_id(
-                            user_model.user_tier_id,
-                        )
-                        .one(db_replica.as_ref())
-                        .await?
-                        .web3_context(
-                            "related user tier not found, but every user should have a tier",
-                        )?;
-
-                        let latest_balance = self
-                            .user_balance_cache
-                            .get_or_insert(db_replica.as_ref(), rpc_key_model.user_id)
-                            .await?;
-
-                        let paid_credits_used: bool;
-                        if let Some(downgrade_user_tier) = user_tier_model.downgrade_tier_id {
-                            trace!("user belongs to a premium tier. checking balance");
-
-                            let active_premium = latest_balance.read().await.active_premium();
-
-                            // only consider the user premium if they have paid at least $10 and have a balance > $.01
-                            // otherwise, set user_tier_model to the downograded tier
-                            if active_premium {
-                                paid_credits_used = true;
-                            } else {
-                                paid_credits_used = false;
-
-                                // TODO: include boolean to mark that the user is downgraded
-                                user_tier_model =
-                                    user_tier::Entity::find_by_id(downgrade_user_tier)
-                                        .one(db_replica.as_ref())
-                                        .await?
-                                        .web3_context(format!(
-                                            "downgrade user tier ({}) is missing!",
-                                            downgrade_user_tier
-                                        ))?;
-                            }
-                        } else {
-                            paid_credits_used = false;
-                        }
-
-                        let rpc_key_id =
-                            Some(rpc_key_model.id.try_into().context("db ids are never 0")?);
-
-                        Ok(AuthorizationChecks {
-                            allowed_ips,
-                            allowed_origins,
-                            allowed_referers,
-                            allowed_user_agents,
-                            latest_balance,
-                            // TODO: is floating point math going to scale this correctly?
-                            log_revert_chance: (rpc_key_model.log_revert_chance * u16::MAX as f64)
-                                as u16,
-                            max_concurrent_requests: user_tier_model.max_concurrent_requests,
-                            max_requests_per_period: user_tier_model.max_requests_per_period,
-                            private_txs: rpc_key_model.private_txs,
-                            proxy_mode,
-                            rpc_secret_key: Some(*rpc_secret_key),
-                            rpc_secret_key_id: rpc_key_id,
-                            user_id: rpc_key_model.user_id,
-                            paid_credits_used,
-                        })
-                    }
-                    None => Ok(AuthorizationChecks::default()),
-                }
+                    .await?;
+
+                // the database's index lookup is not constant time. this comparison is
+                let (stored, stored_hashed) = rpc_key_model
+                    .as_ref()
+                    .map(|x| (x.secret_key, x.secret_key_hashed))
+                    .unwrap_or((Uuid::nil(), true));
+
+                let matches = rpc_secret_key.matches_stored(stored, stored_hashed)?;
+
+                // 0 is never a user id. a miss still makes the query
+                // TODO: join the user table to the key query instead?
+                let user_model = user::Entity::find_by_id(
+                    rpc_key_model
+                        .as_ref()
+                        .map(|x| x.user_id)
+                        .unwrap_or_default(),
+                )
+                .one(db_replica.as_ref())
+                .await?;
+
+                let rpc_key_model = match rpc_key_model.filter(|_| matches) {
+                    Some(x) => x,
+                    None => return Ok(AuthorizationChecks::default()),
+                };
+
+                // TODO: move these splits into helper functions
+                // TODO: can we have sea orm handle this for us?
+                let allowed_ips: Option<Vec<IpNet>> =
+                    if let Some(allowed_ips) = rpc_key_model.allowed_ips {
+                        let x = allowed_ips
+                            .split(',')
+                            .map(|x| x.trim().parse::<IpNet>())
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Some(x)
+                    } else {
+                        None
+                    };
+
+                let allowed_origins: Option<Vec<Origin>> =
+                    if let Some(allowed_origins) = rpc_key_model.allowed_origins {
+                        // TODO: do this without collecting twice?
+                        let x = allowed_origins
+                            .split(',')
+                            .map(|x| HeaderValue::from_str(x.trim()))
+                            .collect::<Result<Vec<_>, _>>()?
+                            .into_iter()
+                            .map(|x| Origin::decode(&mut [x].iter()))
+                            .collect::<Result<Vec<_>, _>>()?;
+
+                        Some(x)
+                    } else {
+                        None
+                    };
+
+                let allowed_referers: Option<Vec<Referer>> =
+                    if let Some(allowed_referers) = rpc_key_model.allowed_referers {
+                        let x = allowed_referers
+                            .split(',')
+                            .map(|x| {
+                                x.trim()
+                                    .parse::<Referer>()
+                                    .or(Err(Web3ProxyError::InvalidReferer))
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+
+                        Some(x)
+                    } else {
+                        None
+                    };
+
+                let allowed_user_agents: Option<Vec<UserAgent>> =
+                    if let Some(allowed_user_agents) = rpc_key_model.allowed_user_agents {
+                        let x: Result<Vec<_>, _> = allowed_user_agents
+                            .split(',')
+                            .map(|x| {
+                                x.trim()
+                                    .parse::<UserAgent>()
+                                    .or(Err(Web3ProxyError::InvalidUserAgent))
+                            })
+                            .collect();
+
+                        Some(x?)
+                    } else {
+                        None
+                    };
+
+                let user_model = user_model.web3_context(
+                    "user model was not found, but every rpc_key should have a user",
+                )?;
+
+                let (user_tier_model, latest_balance, paid_credits_used) = self
+                    .effective_user_tier(
+                        db_replica.as_ref(),
+                        rpc_key_model.user_id,
+                        user_model.user_tier_id,
+                    )
+                    .await?;
+
+                let rpc_key_id = Some(rpc_key_model.id.try_into().context("db ids are never 0")?);
+
+                Ok(AuthorizationChecks {
+                    allowed_ips,
+                    allowed_origins,
+                    allowed_referers,
+                    allowed_user_agents,
+                    latest_balance,
+                    // TODO: is floating point math going to scale this correctly?
+                    log_revert_chance: (rpc_key_model.log_revert_chance * u16::MAX as f64) as u16,
+                    max_concurrent_requests: user_tier_model.max_concurrent_requests,
+                    max_requests_per_period: user_tier_model.max_requests_per_period,
+                    private_txs: rpc_key_model.private_txs,
+                    proxy_mode,
+                    rpc_secret_key: Some(*rpc_secret_key),
+                    rpc_secret_key_id: rpc_key_id,
+                    user_id: rpc_key_model.user_id,
+                    user_tier_title: Some(user_tier_model.title),
+                    webhook_reverts: rpc_key_model.webhook_reverts,
+                    paid_credits_used,
+                    quota: RequestQuota::try_from_rpc_key(
+                        rpc_key_model.quota_requests,
+                        &rpc_key_model.quota_window,
+                    )?,
+                    hmac_secret: rpc_key_model.hmac_secret.map(Into::into),
+                    scope: rpc_key_model.scope.as_deref().map(str::parse).transpose()?,
+                    chain_id: rpc_key_model.chain_id,
+                    // the tier can have older or fresher responses than the default
+                    cache_policy: CachePolicy::from_max_age_seconds(
+                        user_tier_model
+                            .cache_max_age_seconds
+                            .or(self.config.load().response_cache_max_age_seconds),
+                    ),
+                    gas_estimate_percent: user_tier_model.gas_estimate_percent,
+                    max_request_bytes: user_tier_model.max_request_bytes,
+                    max_response_bytes: user_tier_model.max_response_bytes,
+                })
             })
             .await?;
 
@@ -1319,6 +1666,160 @@ impl Web3ProxyApp {
         Ok(x)
     }
 
+    /// The tier whose limits are enforced for a user right now.
+    /// Premium tiers fall back to their downgrade tier unless the user has an active premium balance.
+    /// Also returns the user's balance and true if paid credits are being used.
+    pub async fn effective_user_tier(
+        &self,
+        db_conn: &DatabaseConnection,
+        user_id: u64,
+        user_tier_id: u64,
+    ) -> Web3ProxyResult<(user_tier::Model, Arc<AsyncRwLock<Balance>>, bool)> {
+        let mut user_tier_model = self
+            .user_tier_cache
+            .get_or_load(db_conn, user_tier_id)
+            .await?
+            .web3_context("related user tier not found, but every user should have a tier")?;
+
+        let latest_balance = self
+            .user_balance_cache
+            .get_or_insert(db_conn, user_id)
+            .await?;
+
+        let paid_credits_used: bool;
+        if let Some(downgrade_user_tier) = user_tier_model.downgrade_tier_id {
+            trace!("user belongs to a premium tier. checking balance");
+
+            let active_premium = latest_balance.read().await.active_premium();
+
+            // only consider the user premium if they have paid at least $10 and have a balance > $.01
+            // otherwise, set user_tier_model to the downograded tier
+            if active_premium {
+                paid_credits_used = true;
+            } else {
+                paid_credits_used = false;
+
+                // TODO: include boolean to mark that the user is downgraded
+                user_tier_model = self
+                    .user_tier_cache
+                    .get_or_load(db_conn, downgrade_user_tier)
+                    .await?
+                    .web3_context(format!(
+                        "downgrade user tier ({}) is missing!",
+                        downgrade_user_tier
+                    ))?;
+            }
+        } else {
+            paid_credits_used = false;
+        }
+
+        Ok((user_tier_model, latest_balance, paid_credits_used))
+    }
+
+    /// How many requests a key has made in the current quota window and when the window resets
+    pub async fn quota_used(
+        &self,
+        rpc_key_id: u64,
+        quota: &RequestQuota,
+    ) -> Web3ProxyResult<(u64, DateTime<Utc>)> {
+        let (window_start, reset_at) = quota.window.bounds(Utc::now());
+
+        let mut redis_conn = self.redis_conn().await?;
+
+        let used: Option<u64> = redis_conn
+            .get(quota.redis_key(rpc_key_id, window_start))
+            .await?;
+
+        Ok((used.unwrap_or_default(), reset_at))
+    }
+
+    /// Count requests against their key's quota. Requests that don't fit in this window are not counted.
+    /// Returns how many of them fit and the error for the rest. If redis is down, requests are allowed.
+    pub async fn use_quota(
+        &self,
+        authorization: &Authorization,
+        num_requests: u64,
+    ) -> (u64, Option<Web3ProxyError>) {
+        let (quota, rpc_key_id) = match (
+            authorization.checks.quota.as_ref(),
+            authorization.checks.rpc_secret_key_id,
+        ) {
+            (Some(quota), Some(rpc_key_id)) => (quota, rpc_key_id.get()),
+            _ => return (num_requests, None),
+        };
+
+        let (window_start, reset_at) = quota.window.bounds(Utc::now());
+
+        let redis_key = quota.redis_key(rpc_key_id, window_start);
+
+        let mut redis_conn = match self.redis_conn().await {
+            Ok(x) => x,
+            Err(err) => {
+                error!(?err, "no redis for quotas. allowing rpc_key");
+                return (num_requests, None);
+            }
+        };
+
+        // keep the count around a little past the reset so that clock skew between servers doesn't restart it
+        let used: Result<(u64,), _> = redis::pipe()
+            .atomic()
+            .incr(&redis_key, num_requests)
+            .expire_at(&redis_key, (reset_at.timestamp() + 3600) as usize)
+            .ignore()
+            .query_async(&mut redis_conn)
+            .await;
+
+        let used = match used {
+            Ok((used,)) => used,
+            Err(err) => {
+                error!(?err, "quota counter is unhappy. allowing rpc_key");
+                return (num_requests, None);
+            }
+        };
+
+        // give back the requests that went over. only the ones that are allowed count against the quota
+        let num_over = used.saturating_sub(quota.max_requests).min(num_requests);
+
+        if num_over > 0 {
+            if let Err(err) = redis_conn.decr::<_, _, u64>(&redis_key, num_over).await {
+                warn!(?err, num_over, "unable to give back quota");
+            }
+        }
+
+        let num_allowed = num_requests - num_over;
+        let used = used - num_over;
+
+        // the counter is atomic and never goes back under the max once it gets there, so exactly one increment crosses the warning count in each window
+        let percent = self.config.load().webhook_quota_percent as u64;
+
+        let warn_at = (quota.max_requests * percent / 100).max(1);
+
+        if percent > 0 && num_allowed > 0 && used >= warn_at && used - num_allowed < warn_at {
+            self.notify_webhook(
+                authorization.checks.user_id,
+                WebhookEvent::QuotaNearLimit {
+                    rpc_key_id,
+                    used,
+                    max_requests: quota.max_requests,
+                    window: quota.window,
+                    reset_at: reset_at.timestamp(),
+                },
+            );
+        }
+
+        if num_over > 0 {
+            (
+                num_allowed,
+                Some(Web3ProxyError::QuotaExceeded(
+                    quota.window,
+                    reset_at.timestamp(),
+                )),
+            )
+        } else {
+            (num_allowed, None)
+        }
+    }
+
     /// Authorized the ip/origin/referer/useragent and rate limit and concurrency
     pub async fn rate_limit_by_rpc_key(
         &self,
@@ -1336,6 +1837,15 @@ impl Web3ProxyApp {
             return Ok(RateLimitResult::UnknownKey);
         }
 
+        // keys scoped to one chain do not work on proxies for other chains
+        if let Some(chain_id) = authorization_checks.chain_id {
+            if chain_id != self.config.load().chain_id {
+                return Err(Web3ProxyError::AccessDenied(
+                    format!("this key is only allowed on chain {}", chain_id).into(),
+                ));
+            }
+        }
+
         // only allow this rpc_key to run a limited amount of concurrent requests
         // TODO: rate limit should be BEFORE the semaphore!
         let semaphore = self.user_semaphore(&authorization_checks, ip).await?;
@@ -1343,6 +1853,7 @@ impl Web3ProxyApp {
         let authorization = Authorization::try_new(
             authorization_checks,
             self.db_conn().ok().cloned(),
+            self.revert_webhooks.clone(),
             ip,
             origin,
             referer,
@@ -1351,48 +1862,138 @@ impl Web3ProxyApp {
         )?;
 
         // user key is valid. now check rate limits
-        if let Some(user_max_requests_per_period) = authorization.checks.max_requests_per_period {
-            if let Some(rate_limiter) = &self.frontend_registered_user_rate_limiter {
-                match rate_limiter
+        match self.throttle_rpc_key(&authorization, 1).await {
+            Ok(()) => Ok(RateLimitResult::Allowed(authorization, semaphore)),
+            Err(Web3ProxyError::RateLimited(_, retry_at)) => {
+                // TODO: emit a stat
+                Ok(RateLimitResult::RateLimited(authorization, retry_at))
+            }
+            Err(Web3ProxyError::KeyedIpRateLimited(_, retry_at)) => {
+                Ok(RateLimitResult::KeyedIpRateLimited(authorization, retry_at))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Count requests with a key against the key's limits and then against the ip's limit.
+    /// The ip's limit is shared by every key the ip uses, so requests that the key's own limits reject are not counted there.
+    async fn throttle_rpc_key(
+        &self,
+        authorization: &Authorization,
+        num_requests: u64,
+    ) -> Web3ProxyResult<()> {
+        let ip = &authorization.ip;
+
+        // the tier's limit is per user and ip
+        if let (Some(user_max_requests_per_period), Some(rate_limiter)) = (
+            authorization.checks.max_requests_per_period,
+            &self.frontend_registered_user_rate_limiter,
+        ) {
+            let result = rate_limiter
+                .throttle(
+                    RegisteredUserRateLimitKey(authorization.checks.user_id, *ip),
+                    Some(user_max_requests_per_period),
+                    num_requests,
+                )
+                .await;
+
+            if let Err(retry_at) = check_throttle(result, "rpc_key") {
+                return Err(Web3ProxyError::RateLimited(authorization.clone(), retry_at));
+            }
+        }
+
+        // this stops one key from being shared by many ips
+        if let (Some(rpc_key_id), Some(rate_limiter)) = (
+            authorization.checks.rpc_secret_key_id,
+            &self.frontend_rpc_key_rate_limiter,
+        ) {
+            let result = rate_limiter.throttle(rpc_key_id, None, num_requests).await;
+
+            if let Err(retry_at) = check_throttle(result, "rpc_key across ips") {
+                return Err(Web3ProxyError::RateLimited(authorization.clone(), retry_at));
+            }
+        }
+
+        // and this stops one ip from spreading requests over many keys
+        if let Some(rate_limiter) = &self.frontend_keyed_ip_rate_limiter {
+            let result = rate_limiter
+                .throttle(self.ip_limit_key(ip), None, num_requests)
+                .await;
+
+            if let Err(retry_at) = check_throttle(result, "keyed ip") {
+                return Err(Web3ProxyError::KeyedIpRateLimited(
+                    authorization.clone(),
+                    retry_at,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The first request of a batch is counted when the batch is authorized. This counts the rest of them all at once.
+    /// Returns how many requests from the start of the batch are allowed and the error for the ones after them.
+    /// If the rest of the batch goes over a rate limit, only the first request is allowed.
+    /// If it goes over the quota, the requests that still fit are allowed. Only allowed requests count against the quota.
+    pub async fn rate_limit_batch(
+        &self,
+        authorization: &Authorization,
+        num_requests: usize,
+    ) -> (usize, Option<Web3ProxyError>) {
+        if num_requests <= 1 {
+            return (num_requests, None);
+        }
+
+        let (num_allowed, err) = self
+            .rate_limit_batch_requests(authorization, num_requests as u64 - 1)
+            .await;
+
+        (1 + num_allowed as usize, err)
+    }
+
+    /// Count more requests against the same limits and quota that authorized the first one.
+    /// Returns how many of them are allowed and the error for the rest
+    async fn rate_limit_batch_requests(
+        &self,
+        authorization: &Authorization,
+        num_requests: u64,
+    ) -> (u64, Option<Web3ProxyError>) {
+        if matches!(
+            authorization.authorization_type,
+            AuthorizationType::Internal
+        ) {
+            return (num_requests, None);
+        }
+
+        let ip = &authorization.ip;
+
+        if authorization.checks.rpc_secret_key_id.is_none() {
+            if let Some(rate_limiter) = &self.frontend_ip_rate_limiter {
+                let result = rate_limiter
                     .throttle(
-                        RegisteredUserRateLimitKey(authorization.checks.user_id, *ip),
-                        Some(user_max_requests_per_period),
-                        1,
+                        self.ip_limit_key(ip),
+                        authorization.checks.max_requests_per_period,
+                        num_requests,
                     )
-                    .await
-                {
-                    Ok(DeferredRateLimitResult::Allowed) => {
-                        return Ok(RateLimitResult::Allowed(authorization, semaphore))
-                    }
-                    Ok(DeferredRateLimitResult::RetryAt(retry_at)) => {
-                        // TODO: set headers so they know when they can retry
-                        // TODO: debug or trace?
-                        // this is too verbose, but a stat might be good
-                        // TODO: keys are secrets! use the id instead
-                        // TODO: emit a stat
-                        // trace!(?rpc_key, "rate limit exceeded until {:?}", retry_at);
-                        return Ok(RateLimitResult::RateLimited(authorization, Some(retry_at)));
-                    }
-                    Ok(DeferredRateLimitResult::RetryNever) => {
-                        // TODO: keys are secret. don't log them!
-                        // trace!(?rpc_key, "rate limit is 0");
-                        // TODO: emit a stat
-                        return Ok(RateLimitResult::RateLimited(authorization, None));
-                    }
-                    Err(err) => {
-                        // internal error, not rate limit being hit
-                        // TODO: i really want axum to do this for us in a single place.
-                        error!(?err, "rate limiter is unhappy. allowing rpc_key");
-
-                        return Ok(RateLimitResult::Allowed(authorization, semaphore));
-                    }
+                    .await;
+
+                if let Err(retry_at) = check_throttle(result, "ip") {
+                    return (
+                        0,
+                        Some(Web3ProxyError::RateLimited(authorization.clone(), retry_at)),
+                    );
                 }
-            } else {
-                // TODO: if no redis, rate limit with just a local cache?
             }
+
+            return (num_requests, None);
         }
 
-        Ok(RateLimitResult::Allowed(authorization, semaphore))
+        // rate limited requests never reach the quota
+        if let Err(err) = self.throttle_rpc_key(authorization, num_requests).await {
+            return (0, Some(err));
+        }
+
+        self.use_quota(authorization, num_requests).await
     }
 }
 
@@ -1417,8 +2018,136 @@ impl Authorization {
             ip_is_authorized(app, &self.ip, self.origin.as_ref(), self.checks.proxy_mode).await?
         };
 
-        let a = Arc::new(a);
+        let a = Arc::new(self.keep_header_settings(a));
 
         Ok((a, s))
     }
+
+    /// The tag and cache policy came from the original request's headers. Fresh checks can't know them
+    fn keep_header_settings(&self, fresh: Self) -> Self {
+        Self {
+            checks: AuthorizationChecks {
+                cache_policy: self.checks.cache_policy,
+                ..fresh.checks
+            },
+            tag: self.tag.clone(),
+            ..fresh
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ip_limit_key, login_tarpit_delay, set_rpc_key_hash_secret, Authorization, RpcSecretKey,
+        LOGIN_TARPIT_MAX,
+    };
+    use crate::errors::Web3ProxyError;
+    use crate::response_cache::CachePolicy;
+    use http::StatusCode;
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_empty_rpc_key_hash_secret() {
+        assert!(set_rpc_key_hash_secret(Some(""), false).is_err());
+        assert!(set_rpc_key_hash_secret(Some("  "), false).is_err());
+    }
+
+    #[test]
+    fn test_missing_rpc_key_hash_secret() {
+        assert!(set_rpc_key_hash_secret(None, true).is_err());
+        assert!(set_rpc_key_hash_secret(None, false).is_ok());
+    }
+
+    #[test]
+    fn test_rpc_key_matches_stored() {
+        set_rpc_key_hash_secret(Some("test secret"), false).unwrap();
+
+        let key = RpcSecretKey::new();
+        let hashed = key.hashed().unwrap();
+        let raw = Uuid::from(key);
+
+        assert!(key.matches_stored(hashed, true).unwrap());
+        assert!(key.matches_stored(raw, false).unwrap());
+
+        // the right bytes with the wrong flag are not a match
+        assert!(!key.matches_stored(hashed, false).unwrap());
+        assert!(!key.matches_stored(raw, true).unwrap());
+
+        // misses are compared against nil
+        assert!(!key.matches_stored(Uuid::nil(), true).unwrap());
+        assert!(!RpcSecretKey::new().matches_stored(hashed, true).unwrap());
+    }
+
+    #[test]
+    fn test_check_request_size() {
+        let mut x = Authorization::internal(None).unwrap();
+
+        // no tier limit
+        assert!(x.check_request_size(usize::MAX).is_ok());
+
+        x.checks.max_request_bytes = Some(1024);
+
+        assert!(x.check_request_size(1024).is_ok());
+        assert!(matches!(
+            x.check_request_size(1025),
+            Err(Web3ProxyError::StatusCode(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ..
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_check_again_keeps_header_settings() {
+        let mut original = Authorization::internal(None).unwrap();
+        original.tag = Some("frontend".to_string());
+        original.checks.cache_policy = CachePolicy::Bypass;
+
+        let fresh = Authorization::internal(None).unwrap();
+        assert_eq!(fresh.checks.cache_policy, CachePolicy::Any);
+
+        let x = original.keep_header_settings(fresh);
+
+        assert_eq!(x.tag.as_deref(), Some("frontend"));
+        assert_eq!(x.checks.cache_policy, CachePolicy::Bypass);
+    }
+
+    #[test]
+    fn test_login_tarpit_delay() {
+        assert_eq!(login_tarpit_delay(5, 5), None);
+        assert_eq!(login_tarpit_delay(6, 5), Some(Duration::from_secs(1)));
+        assert_eq!(login_tarpit_delay(8, 5), Some(Duration::from_secs(3)));
+        assert_eq!(login_tarpit_delay(1_000, 5), Some(LOGIN_TARPIT_MAX));
+
+        // 0 disables the tarpit
+        assert_eq!(login_tarpit_delay(1_000, 0), None);
+    }
+
+    #[test]
+    fn ipv4_limit_key_is_the_address() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        assert_eq!(ip_limit_key(ip, 64), ip);
+    }
+
+    #[test]
+    fn ipv6_limit_key_is_the_prefix() {
+        let a: IpAddr = "2001:db8:1:2:aaaa:bbbb:cccc:dddd".parse().unwrap();
+        let b: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+        let c: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+
+        let expected: IpAddr = "2001:db8:1:2::".parse().unwrap();
+
+        assert_eq!(ip_limit_key(a, 64), expected);
+        assert_eq!(ip_limit_key(a, 64), ip_limit_key(b, 64));
+        assert_ne!(ip_limit_key(a, 64), ip_limit_key(c, 64));
+
+        // 128 keeps the full address
+        assert_eq!(ip_limit_key(a, 128), a);
+        // values that are too large are treated as 128
+        assert_eq!(ip_limit_key(a, 200), a);
+    }
 }
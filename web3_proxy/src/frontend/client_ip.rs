@@ -0,0 +1,254 @@
+//! Find the ip of the client. Forwarded headers are only trusted if the request came from a trusted proxy.
+
+use crate::app::Web3ProxyApp;
+use crate::config::ClientIpHeader;
+use crate::errors::Web3ProxyError;
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use http::request::Parts;
+use http::HeaderMap;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing::trace;
+
+/// The ip of the client that sent the request.
+///
+/// This is the socket peer unless the peer is in `trusted_proxies`.
+/// Then the ip is read from the configured `client_ip_header`.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Web3ProxyError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+            .ok_or_else(|| {
+                Web3ProxyError::Anyhow(anyhow::anyhow!(
+                    "client ip requires into_make_service_with_connect_info"
+                ))
+            })?;
+
+        let app = parts
+            .extensions
+            .get::<Arc<Web3ProxyApp>>()
+            .ok_or_else(|| Web3ProxyError::Anyhow(anyhow::anyhow!("app is not in extensions")))?;
+
+        let ip = client_ip(
+            peer,
+            &parts.headers,
+            app.config.load().client_ip_header,
+            &app.config.load().trusted_proxies,
+        );
+
+        Ok(Self(ip))
+    }
+}
+
+fn is_trusted(ip: IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(&ip))
+}
+
+/// Pick the client's ip for a request from `peer`.
+///
+/// Headers are ignored unless `peer` is a trusted proxy. Invalid or missing headers fall back to `peer`.
+pub fn client_ip(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    client_ip_header: Option<ClientIpHeader>,
+    trusted_proxies: &[IpNet],
+) -> IpAddr {
+    let client_ip_header = match client_ip_header {
+        Some(x) if is_trusted(peer, trusted_proxies) => x,
+        _ => return peer,
+    };
+
+    let values: Vec<&str> = headers
+        .get_all(client_ip_header.header_name())
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .collect();
+
+    let found = match client_ip_header {
+        ClientIpHeader::CfConnectingIp => values.last().and_then(|x| x.trim().parse().ok()),
+        ClientIpHeader::Forwarded => {
+            let hops = values
+                .iter()
+                .flat_map(|x| x.split(','))
+                .map(parse_forwarded_for);
+
+            rightmost_untrusted(hops, trusted_proxies)
+        }
+        ClientIpHeader::XForwardedFor => {
+            let hops = values
+                .iter()
+                .flat_map(|x| x.split(','))
+                .map(|x| parse_ip(x.trim()));
+
+            rightmost_untrusted(hops, trusted_proxies)
+        }
+    };
+
+    match found {
+        Some(ip) => ip,
+        None => {
+            trace!(%peer, ?client_ip_header, "no valid client ip in header");
+            peer
+        }
+    }
+}
+
+/// Each proxy appends the ip it received the request from, so anything left of an untrusted hop could be spoofed.
+/// Walk from the right and return the first hop that isn't a trusted proxy.
+fn rightmost_untrusted(
+    hops: impl DoubleEndedIterator<Item = Option<IpAddr>>,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let mut leftmost = None;
+
+    for hop in hops.rev() {
+        // an invalid hop means we can't trust anything further left
+        let ip = hop?;
+
+        if !is_trusted(ip, trusted_proxies) {
+            return Some(ip);
+        }
+
+        leftmost = Some(ip);
+    }
+
+    // every hop was a trusted proxy
+    leftmost
+}
+
+/// Get the ip from the `for=` parameter of one element of an RFC 7239 `Forwarded` header.
+fn parse_forwarded_for(element: &str) -> Option<IpAddr> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+
+        if !key.trim().eq_ignore_ascii_case("for") {
+            return None;
+        }
+
+        parse_ip(value.trim().trim_matches('"'))
+    })
+}
+
+/// Parse "1.2.3.4", "1.2.3.4:80", "::1", or "[::1]:80"
+fn parse_ip(x: &str) -> Option<IpAddr> {
+    if let Ok(ip) = x.parse() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = x.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    x.strip_prefix('[')
+        .and_then(|x| x.strip_suffix(']'))
+        .and_then(|x| x.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let peer: IpAddr = "1.1.1.1".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-connecting-ip", HeaderValue::from_static("2.2.2.2"));
+
+        let ip = client_ip(
+            peer,
+            &headers,
+            Some(ClientIpHeader::CfConnectingIp),
+            &trusted(),
+        );
+
+        assert_eq!(ip, peer);
+    }
+
+    #[test]
+    fn test_cf_connecting_ip() {
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-connecting-ip", HeaderValue::from_static("2.2.2.2"));
+
+        let ip = client_ip(
+            peer,
+            &headers,
+            Some(ClientIpHeader::CfConnectingIp),
+            &trusted(),
+        );
+
+        assert_eq!(ip, "2.2.2.2".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_x_forwarded_for_skips_trusted_hops() {
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("3.3.3.3, 2.2.2.2, 10.0.0.2"),
+        );
+
+        let ip = client_ip(
+            peer,
+            &headers,
+            Some(ClientIpHeader::XForwardedFor),
+            &trusted(),
+        );
+
+        assert_eq!(ip, "2.2.2.2".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "forwarded",
+            HeaderValue::from_static(r#"for="[2001:db8:cafe::17]:4711";proto=https, for=10.0.0.2"#),
+        );
+
+        let ip = client_ip(peer, &headers, Some(ClientIpHeader::Forwarded), &trusted());
+
+        assert_eq!(ip, "2001:db8:cafe::17".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_header_uses_peer() {
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("unknown"));
+
+        let ip = client_ip(
+            peer,
+            &headers,
+            Some(ClientIpHeader::XForwardedFor),
+            &trusted(),
+        );
+
+        assert_eq!(ip, peer);
+    }
+}
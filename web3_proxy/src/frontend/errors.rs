@@ -1,7 +1,27 @@
+use crate::app::Web3ProxyApp;
 use crate::errors::Web3ProxyError;
 use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use http::{Method, StatusCode, Uri};
+use std::sync::Arc;
 
+/// Clients that only parse JSON get a JSON-RPC error instead of a plain 404
 #[inline]
-pub async fn handler_404() -> Response {
-    Web3ProxyError::NotFound.into_response()
+pub async fn handler_404(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    method: Method,
+    uri: Uri,
+) -> Response {
+    Web3ProxyError::StatusCode(
+        StatusCode::NOT_FOUND,
+        format!(
+            "{} ({} {})",
+            app.config.load().not_found_message,
+            method,
+            uri.path()
+        )
+        .into(),
+        None,
+    )
+    .into_response()
 }
@@ -0,0 +1,75 @@
+//! Let clients ask for padded `eth_estimateGas` results instead of padding them themselves.
+
+use super::optional_header::OptionalHeaderValue;
+use crate::errors::Web3ProxyError;
+use ethers::types::U256;
+
+/// Larger multipliers are almost certainly a mistake
+const MAX_GAS_MULTIPLIER: f64 = 10.0;
+
+/// Clients set `X-Gas-Multiplier` to the multiplier for gas estimates. "1.2" returns 1.2x the estimate.
+/// The value is a percent. 120 == 1.2x
+pub struct GasEstimatePercent;
+
+impl OptionalHeaderValue for GasEstimatePercent {
+    const HEADER: &'static str = "x-gas-multiplier";
+    const MAX_LEN: usize = 32;
+    const NAME: &'static str = "gas multiplier";
+
+    type Value = u16;
+
+    /// "1.2" -> 120. Multipliers below 1 would make transactions run out of gas, so they are rejected
+    fn parse(multiplier: &str) -> Result<Option<u16>, Web3ProxyError> {
+        match multiplier.parse::<f64>() {
+            Ok(x) if (1.0..=MAX_GAS_MULTIPLIER).contains(&x) => {
+                Ok(Some((x * 100.0).round() as u16))
+            }
+            _ => Err(Web3ProxyError::BadRequest(
+                format!(
+                    "gas multiplier must be a number from 1 to {}",
+                    MAX_GAS_MULTIPLIER
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+/// Multiply a gas estimate by `percent`. Rounds down
+pub fn pad_gas_estimate(gas_estimate: U256, percent: u16) -> U256 {
+    gas_estimate.saturating_mul(percent.into()) / U256::from(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pad_gas_estimate, GasEstimatePercent};
+    use crate::frontend::optional_header::{OptionalHeader, OptionalHeaderValue};
+    use ethers::types::U256;
+
+    fn parse_gas_multiplier(value: &str) -> Result<Option<u16>, crate::errors::Web3ProxyError> {
+        OptionalHeader::<GasEstimatePercent>::parse_value(value)
+    }
+
+    #[test]
+    fn test_pad_gas_estimate() {
+        assert_eq!(pad_gas_estimate(21_000.into(), 100), U256::from(21_000));
+        assert_eq!(pad_gas_estimate(21_000.into(), 120), U256::from(25_200));
+        assert_eq!(pad_gas_estimate(21_001.into(), 150), U256::from(31_501));
+        assert_eq!(pad_gas_estimate(U256::MAX, 200), U256::MAX / 100);
+    }
+
+    #[test]
+    fn test_parse_gas_multiplier() {
+        assert_eq!(parse_gas_multiplier("1").unwrap(), Some(100));
+        assert_eq!(parse_gas_multiplier(" 1.2 ").unwrap(), Some(120));
+        assert_eq!(parse_gas_multiplier("1.125").unwrap(), Some(113));
+        assert_eq!(parse_gas_multiplier("10").unwrap(), Some(1000));
+
+        assert!(parse_gas_multiplier("").is_err());
+        assert!(parse_gas_multiplier("0.9").is_err());
+        assert!(parse_gas_multiplier("11").is_err());
+        assert!(parse_gas_multiplier("NaN").is_err());
+        assert!(parse_gas_multiplier("lots").is_err());
+        assert!(parse_gas_multiplier(&"1".repeat(GasEstimatePercent::MAX_LEN + 1)).is_err());
+    }
+}
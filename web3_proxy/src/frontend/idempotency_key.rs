@@ -0,0 +1,56 @@
+//! Let admins safely retry requests that change balances. A retry with the same key gets the first response instead of applying twice.
+
+use super::optional_header::OptionalHeaderValue;
+use crate::errors::Web3ProxyError;
+
+/// Clients set `Idempotency-Key` on requests that should only happen once.
+/// Keys are stored in a unique index, so they are kept short
+pub struct IdempotencyKey;
+
+impl OptionalHeaderValue for IdempotencyKey {
+    const HEADER: &'static str = "idempotency-key";
+    const MAX_LEN: usize = 255;
+    const NAME: &'static str = "idempotency key";
+
+    type Value = String;
+
+    /// An empty key is the same as no key
+    fn parse(key: &str) -> Result<Option<String>, Web3ProxyError> {
+        if key.is_empty() {
+            return Ok(None);
+        }
+
+        if !key.chars().all(|c| c.is_ascii_graphic()) {
+            return Err(Web3ProxyError::BadRequest(
+                "idempotency keys may only contain visible ascii characters".into(),
+            ));
+        }
+
+        Ok(Some(key.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencyKey;
+    use crate::frontend::optional_header::{OptionalHeader, OptionalHeaderValue};
+
+    fn parse_idempotency_key(value: &str) -> Result<Option<String>, crate::errors::Web3ProxyError> {
+        OptionalHeader::<IdempotencyKey>::parse_value(value)
+    }
+
+    #[test]
+    fn test_parse_idempotency_key() {
+        assert_eq!(parse_idempotency_key("").unwrap(), None);
+        assert_eq!(parse_idempotency_key("  ").unwrap(), None);
+        assert_eq!(
+            parse_idempotency_key(" 01H5ZJ9Q0V2N3K4M5P6R7S8T9V ")
+                .unwrap()
+                .as_deref(),
+            Some("01H5ZJ9Q0V2N3K4M5P6R7S8T9V")
+        );
+
+        assert!(parse_idempotency_key("has space").is_err());
+        assert!(parse_idempotency_key(&"a".repeat(IdempotencyKey::MAX_LEN + 1)).is_err());
+    }
+}
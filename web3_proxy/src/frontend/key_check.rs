@@ -0,0 +1,112 @@
+//! Let integrators check that an rpc key works without sending a request to the backends.
+use super::authorization::{login_is_authorized, RpcSecretKey};
+use super::client_ip::ClientIp;
+use crate::app::Web3ProxyApp;
+use crate::errors::{Web3ProxyErrorContext, Web3ProxyResponse};
+use crate::frontend::rpc_proxy_ws::ProxyMode;
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use entities::rpc_key;
+use migration::sea_orm::EntityTrait;
+use serde_json::json;
+use std::sync::Arc;
+
+/// `GET /key/check/:rpc_key` -- Check if a key is valid and which restrictions apply to it.
+///
+/// Malformed, unknown, and inactive keys all get the same `{"valid": false}` so that this does not say which keys exist.
+/// Rate limited by ip with the login limit so that it can't be used to guess keys.
+#[debug_handler]
+pub async fn key_check_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    ClientIp(ip): ClientIp,
+    Path(rpc_key): Path<String>,
+) -> Web3ProxyResponse {
+    login_is_authorized(&app, ip).await?;
+
+    let rpc_key: RpcSecretKey = match rpc_key.parse() {
+        Ok(x) => x,
+        Err(_) => return Ok(invalid_key()),
+    };
+
+    let checks = app.authorization_checks(ProxyMode::Best, &rpc_key).await?;
+
+    let rpc_key_id = match checks.rpc_secret_key_id {
+        Some(x) => x,
+        None => return Ok(invalid_key()),
+    };
+
+    // the checks have the restrictions parsed. the row has them the way the user wrote them
+    let db_replica = app.db_replica()?;
+
+    let rpc_key_model = rpc_key::Entity::find_by_id(rpc_key_id.get())
+        .one(db_replica.as_ref())
+        .await
+        .web3_context("failed loading rpc key")?
+        .web3_context("rpc key was not found")?;
+
+    let chain_id = app.config.load().chain_id;
+
+    let quota = checks.quota.map(|x| {
+        json!({
+            "max_requests": x.max_requests,
+            "window": x.window,
+        })
+    });
+
+    let response_json = json!({
+        "valid": true,
+        "rpc_key_id": rpc_key_id,
+        "tier": checks.user_tier_title,
+        "max_requests_per_period": checks.max_requests_per_period,
+        "max_concurrent_requests": checks.max_concurrent_requests,
+        "quota": quota,
+        "scope": checks.scope,
+        "chain_id": checks.chain_id,
+        "chain_allowed": checks.chain_id.map_or(true, |x| x == chain_id),
+        "allowed_ips": split_list(rpc_key_model.allowed_ips),
+        "allowed_origins": split_list(rpc_key_model.allowed_origins),
+        "allowed_referers": split_list(rpc_key_model.allowed_referers),
+        "allowed_user_agents": split_list(rpc_key_model.allowed_user_agents),
+        "ip": ip,
+        "ip_allowed": checks.allowed_ips.as_ref().map_or(true, |x| x.iter().any(|x| x.contains(&ip))),
+        "hmac_required": checks.hmac_secret.is_some(),
+        "private_txs": checks.private_txs,
+    });
+
+    Ok(Json(response_json).into_response())
+}
+
+fn invalid_key() -> Response {
+    Json(json!({ "valid": false })).into_response()
+}
+
+/// Restrictions are saved as comma separated lists. None means anything is allowed
+fn split_list(x: Option<String>) -> Option<Vec<String>> {
+    x.map(|x| {
+        x.split(',')
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_list;
+
+    #[test]
+    fn test_split_list() {
+        assert_eq!(split_list(None), None);
+        assert_eq!(
+            split_list(Some("https://a.com, https://b.com".to_string())),
+            Some(vec![
+                "https://a.com".to_string(),
+                "https://b.com".to_string()
+            ])
+        );
+    }
+}
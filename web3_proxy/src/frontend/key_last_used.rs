@@ -0,0 +1,136 @@
+//! Remember which rpc keys were used and save `rpc_key.last_used_at` in the background.
+//!
+//! Writing on every request would be a database write per request. Instead, keys used since the last flush are
+//! saved together with one update. The saved time is the time of the flush, so it is accurate to within the flush interval.
+use crate::app::Web3ProxyJoinHandle;
+use crate::errors::Web3ProxyResult;
+use chrono::Utc;
+use entities::rpc_key;
+use hashbrown::HashSet;
+use migration::sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use migration::{Expr, Value};
+use parking_lot::Mutex;
+use std::mem;
+use std::num::NonZeroU64;
+use std::sync::Arc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, trace, warn};
+
+#[derive(Default)]
+pub struct KeyLastUsed {
+    /// keys used since the last flush
+    pending: Mutex<HashSet<NonZeroU64>>,
+}
+
+impl KeyLastUsed {
+    /// Cheap enough to call on every request
+    pub fn record(&self, rpc_key_id: NonZeroU64) {
+        self.pending.lock().insert(rpc_key_id);
+    }
+
+    fn take(&self) -> HashSet<NonZeroU64> {
+        mem::take(&mut *self.pending.lock())
+    }
+
+    /// Save the used keys every `flush_seconds`.
+    /// Returns None if there is no database or flushing is disabled.
+    pub fn try_spawn(
+        self: &Arc<Self>,
+        db_conn: Option<DatabaseConnection>,
+        flush_seconds: u64,
+        shutdown_token: CancellationToken,
+    ) -> Option<Web3ProxyJoinHandle<()>> {
+        let db_conn = db_conn?;
+
+        if flush_seconds == 0 {
+            return None;
+        }
+
+        let x = self.clone();
+
+        let handle = tokio::spawn(async move {
+            x.flush_loop(db_conn, Duration::from_secs(flush_seconds), shutdown_token)
+                .await
+        });
+
+        Some(handle)
+    }
+
+    async fn flush_loop(
+        &self,
+        db_conn: DatabaseConnection,
+        flush_interval: Duration,
+        shutdown_token: CancellationToken,
+    ) -> Web3ProxyResult<()> {
+        let mut flush_interval = interval(flush_interval);
+        flush_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = flush_interval.tick() => {
+                    self.flush(&db_conn).await;
+                }
+                _ = shutdown_token.cancelled() => {
+                    info!("key last used flusher shutting down");
+                    break;
+                }
+            }
+        }
+
+        self.flush(&db_conn).await;
+
+        Ok(())
+    }
+
+    /// Errors are logged and the keys are put back to be saved with the next flush
+    async fn flush(&self, db_conn: &DatabaseConnection) {
+        let used = self.take();
+
+        if used.is_empty() {
+            return;
+        }
+
+        let ids: Vec<u64> = used.iter().map(|x| x.get()).collect();
+
+        match rpc_key::Entity::update_many()
+            .col_expr(
+                rpc_key::Column::LastUsedAt,
+                Expr::value(Value::ChronoDateTimeUtc(Some(Box::new(Utc::now())))),
+            )
+            .filter(rpc_key::Column::Id.is_in(ids))
+            .exec(db_conn)
+            .await
+        {
+            Ok(x) => {
+                trace!(keys = x.rows_affected, "saved key last used");
+            }
+            Err(err) => {
+                warn!(?err, keys = used.len(), "failed saving key last used");
+
+                self.pending.lock().extend(used);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyLastUsed;
+    use std::num::NonZeroU64;
+
+    #[test]
+    fn test_record_dedupes() {
+        let x = KeyLastUsed::default();
+
+        let a = NonZeroU64::new(1).unwrap();
+        let b = NonZeroU64::new(2).unwrap();
+
+        x.record(a);
+        x.record(a);
+        x.record(b);
+
+        assert_eq!(x.take().len(), 2);
+        assert!(x.take().is_empty());
+    }
+}
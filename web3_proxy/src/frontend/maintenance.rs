@@ -0,0 +1,27 @@
+//! Turn off the proxy routes without stopping the process.
+
+use crate::app::Web3ProxyApp;
+use crate::errors::Web3ProxyError;
+use axum::{
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use http::Request;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Layer for the proxy routes. While the app is in maintenance mode, http and new websocket requests get a 503.
+/// Websockets that are already connected are left open.
+pub async fn maintenance_mode<B>(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if app.maintenance_mode.load(Ordering::Acquire) {
+        return Web3ProxyError::MaintenanceMode(app.config.load().maintenance_retry_after_seconds)
+            .into_response();
+    }
+
+    next.run(request).await
+}
@@ -4,15 +4,31 @@
 // TODO: these are only public so docs are generated. What's a better way to do this?
 pub mod admin;
 pub mod authorization;
+pub mod client_ip;
 pub mod errors;
+pub mod gas_estimate;
+pub mod idempotency_key;
+pub mod key_check;
+pub mod key_last_used;
+pub mod maintenance;
+pub mod optional_header;
+pub mod request_signature;
+pub mod request_tag;
+pub mod request_timeout;
+pub mod result_pointer;
 pub mod rpc_proxy_http;
 pub mod rpc_proxy_ws;
 pub mod status;
+pub mod tls;
 pub mod users;
+pub mod version_header;
+pub mod ws_connections;
 
 use crate::app::Web3ProxyApp;
 use crate::errors::Web3ProxyResult;
 use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
     routing::{get, post},
     Extension, Router,
 };
@@ -35,6 +51,7 @@ use ulid::Ulid;
 pub enum ResponseCacheKey {
     BackupsNeeded,
     Health,
+    Slo,
     Status,
 }
 
@@ -136,10 +153,16 @@ pub async fn serve(
             post(rpc_proxy_http::versus_proxy_web3_rpc_with_key)
                 .get(rpc_proxy_ws::versus_websocket_handler_with_key),
         )
+        // only the routes above this are turned off in maintenance mode
+        .route_layer(middleware::from_fn(maintenance::maintenance_mode))
         //
         // System things
         //
         .route("/health", get(status::health))
+        .route("/slo", get(status::slo))
+        .route("/heads", get(status::heads))
+        .route("/key/check/:rpc_key", get(key_check::key_check_get))
+        .route("/rpc/methods", get(status::rpc_methods))
         .route("/status", get(status::status))
         .route("/status/backups_needed", get(status::backups_needed))
         .route("/status/debug_request", get(status::debug_request))
@@ -155,6 +178,19 @@ pub async fn serve(
             get(users::authentication::user_login_get),
         )
         .route("/user/login", post(users::authentication::user_login_post))
+        .route("/user/addresses", get(users::addresses::user_addresses_get))
+        .route(
+            "/user/addresses/link/:new_address",
+            get(users::addresses::user_address_link_get),
+        )
+        .route(
+            "/user/addresses/link/:new_address/:message_eip",
+            get(users::addresses::user_address_link_get),
+        )
+        .route(
+            "/user/addresses/link",
+            post(users::addresses::user_address_link_post),
+        )
         .route(
             // /:rpc_key/:subuser_address/:new_status/:new_role
             "/user/subuser",
@@ -167,6 +203,8 @@ pub async fn serve(
         )
         .route("/user", get(users::user_get).post(users::user_post))
         .route("/user/balance", get(users::payment::user_balance_get))
+        .route("/user/limits", get(users::user_limits_get))
+        .route("/user/quota", get(users::user_quota_get))
         .route(
             "/user/deposits/chain",
             get(users::payment::user_chain_deposits_get),
@@ -198,6 +236,20 @@ pub async fn serve(
                 .post(users::rpc_keys::rpc_keys_management)
                 .put(users::rpc_keys::rpc_keys_management),
         )
+        .route(
+            "/user/keys/scopes",
+            get(users::rpc_keys::rpc_key_scopes_get),
+        )
+        .route(
+            "/user/keys/:key_id/rotate",
+            post(users::rpc_keys::rpc_keys_rotate),
+        )
+        .route(
+            "/user/webhook",
+            get(users::webhook::user_webhook_get)
+                .post(users::webhook::user_webhook_post)
+                .delete(users::webhook::user_webhook_delete),
+        )
         // .route("/user/referral/:referral_link", get(users::user_referral_link_get))
         .route(
             "/user/referral",
@@ -224,6 +276,10 @@ pub async fn serve(
             "/user/stats/detailed",
             get(users::stats::user_stats_detailed_get),
         )
+        .route(
+            "/user/stats/export",
+            get(users::stats::user_stats_export_get),
+        )
         .route(
             "/user/logout",
             post(users::authentication::user_logout_post),
@@ -245,11 +301,30 @@ pub async fn serve(
             "/admin/imitate_login",
             post(admin::admin_imitate_login_post),
         )
+        .route(
+            "/admin/ws_connections",
+            get(admin::admin_ws_connections_get),
+        )
+        .route(
+            "/admin/ws_connections/disconnect",
+            post(admin::admin_ws_connections_disconnect_post),
+        )
+        .route("/admin/rpcs/disabled", post(admin::admin_rpc_disabled_post))
+        .route("/admin/maintenance", post(admin::admin_maintenance_post))
+        .route("/admin/search", get(admin::admin_search_get))
+        .route("/admin/cache", get(admin::admin_cache_get))
+        .route("/admin/cache/flush", post(admin::admin_cache_flush_post))
+        // 404 for any unknown routes. this is set before the layers so that it can use the app extension
+        .fallback(errors::handler_404)
         //
         // Axum layers
         // layers are ordered bottom up
         // the last layer is first for requests and last for responses
         //
+        // user tiers can lower this, but never raise it
+        .layer(DefaultBodyLimit::max(app.config.load().max_request_bytes))
+        // say which build served the request. this needs the app extension, so it is above it
+        .layer(middleware::from_fn(version_header::add_version_header))
         // Mark the `Authorization` request header as sensitive so it doesn't show in logs
         .layer(SetSensitiveRequestHeadersLayer::new(once(AUTHORIZATION)))
         // handle cors
@@ -279,9 +354,42 @@ pub async fn serve(
                     // path = %request.uri().path(),
                 )
             }),
-        )
-        // 404 for any unknown routes
-        .fallback(errors::handler_404);
+        );
+
+    // ClientIp needs ConnectInfo. headers are only used when the socket's peer is in `trusted_proxies`
+    let make_service = {
+        info!("connectinfo feature enabled");
+        router.into_make_service_with_connect_info::<SocketAddr>()
+    };
+
+    // #[cfg(not(feature = "connectinfo"))]
+    // let make_service = {
+    //     info!("connectinfo feature disabled");
+    //     router.into_make_service()
+    // };
+
+    let tls_paths = {
+        let config = app.config.load();
+
+        (config.tls_cert_path.clone(), config.tls_key_path.clone())
+    };
+
+    match tls_paths {
+        (None, None) => {}
+        (Some(cert_path), Some(key_path)) => {
+            let server =
+                tls::serve(app, make_service, cert_path, key_path, shutdown_receiver).await;
+
+            let _ = shutdown_complete_sender.send(());
+
+            return server;
+        }
+        _ => {
+            return Err(
+                anyhow::anyhow!("tls_cert_path and tls_key_path must be set together").into(),
+            );
+        }
+    }
 
     let server_builder = if let Some(listener) = ListenFd::from_env().take_tcp_listener(0)? {
         // use systemd socket magic for no downtime deploys
@@ -297,25 +405,6 @@ pub async fn serve(
         axum::Server::try_bind(&addr)?
     };
 
-    // into_make_service is enough if we always run behind a proxy
-    /*
-    It sequentially looks for an IP in:
-      - x-forwarded-for header (de-facto standard)
-      - x-real-ip header
-      - forwarded header (new standard)
-      - axum::extract::ConnectInfo (if not behind proxy)
-    */
-    let make_service = {
-        info!("connectinfo feature enabled");
-        router.into_make_service_with_connect_info::<SocketAddr>()
-    };
-
-    // #[cfg(not(feature = "connectinfo"))]
-    // let make_service = {
-    //     info!("connectinfo feature disabled");
-    //     router.into_make_service()
-    // };
-
     // TODO: get settings from app config
     let server = server_builder
         .http2_keep_alive_timeout(Duration::from_secs(70))
@@ -0,0 +1,116 @@
+//! One extractor for the optional headers that change how a request is handled. Each header only says how its value is parsed.
+
+use crate::errors::Web3ProxyError;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use http::request::Parts;
+use http::HeaderMap;
+
+/// A header that clients may set. Missing headers are None
+pub trait OptionalHeaderValue {
+    /// The lowercase header name
+    const HEADER: &'static str;
+
+    /// Longer values are almost certainly a mistake, so they are rejected before they are parsed
+    const MAX_LEN: usize;
+
+    /// What the header is called in error messages
+    const NAME: &'static str;
+
+    type Value;
+
+    /// The value is already trimmed and at most `MAX_LEN` bytes. Ok(None) means the same thing as no header
+    fn parse(value: &str) -> Result<Option<Self::Value>, Web3ProxyError>;
+}
+
+/// The parsed value of `H::HEADER`. None if it wasn't set
+pub struct OptionalHeader<H: OptionalHeaderValue>(pub Option<H::Value>);
+
+impl<H: OptionalHeaderValue> OptionalHeader<H> {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, Web3ProxyError> {
+        let value = match headers.get(H::HEADER) {
+            Some(x) => x,
+            None => return Ok(Self(None)),
+        };
+
+        let value = value
+            .to_str()
+            .map_err(|_| Web3ProxyError::BadRequest(format!("invalid {}", H::NAME).into()))?
+            .trim();
+
+        if value.len() > H::MAX_LEN {
+            return Err(Web3ProxyError::BadRequest(
+                format!("{} is longer than {} bytes", H::NAME, H::MAX_LEN).into(),
+            ));
+        }
+
+        H::parse(value).map(Self)
+    }
+
+    /// Parse `value` as if a request had sent it in `H::HEADER`
+    #[cfg(test)]
+    pub fn parse_value(value: &str) -> Result<Option<H::Value>, Web3ProxyError> {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            H::HEADER,
+            value.parse().expect("test values should be valid"),
+        );
+
+        Self::from_headers(&headers).map(|x| x.0)
+    }
+}
+
+#[async_trait]
+impl<H, S> FromRequestParts<S> for OptionalHeader<H>
+where
+    H: OptionalHeaderValue,
+    H::Value: Send,
+    S: Send + Sync,
+{
+    type Rejection = Web3ProxyError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::from_headers(&parts.headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OptionalHeader, OptionalHeaderValue};
+    use crate::errors::Web3ProxyError;
+    use http::{HeaderMap, HeaderValue};
+
+    struct TestHeader;
+
+    impl OptionalHeaderValue for TestHeader {
+        const HEADER: &'static str = "x-test";
+        const MAX_LEN: usize = 4;
+        const NAME: &'static str = "test header";
+
+        type Value = String;
+
+        fn parse(value: &str) -> Result<Option<String>, Web3ProxyError> {
+            Ok(Some(value.to_string()).filter(|x| !x.is_empty()))
+        }
+    }
+
+    fn parse(value: Option<&'static str>) -> Result<Option<String>, Web3ProxyError> {
+        let mut headers = HeaderMap::new();
+
+        if let Some(value) = value {
+            headers.insert(TestHeader::HEADER, HeaderValue::from_static(value));
+        }
+
+        OptionalHeader::<TestHeader>::from_headers(&headers).map(|x| x.0)
+    }
+
+    #[test]
+    fn test_optional_header() {
+        assert_eq!(parse(None).unwrap(), None);
+        assert_eq!(parse(Some("")).unwrap(), None);
+        assert_eq!(parse(Some(" abcd ")).unwrap().as_deref(), Some("abcd"));
+
+        assert!(parse(Some("abcde")).is_err());
+    }
+}
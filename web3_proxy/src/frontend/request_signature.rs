@@ -0,0 +1,193 @@
+//! Let clients sign their requests with a shared secret instead of trusting the rpc key in the url.
+//!
+//! The signature is a hex HMAC-SHA256 of `{timestamp}.{body}`. The timestamp is unix seconds.
+//! Keys with an HMAC secret reject any request that is not signed.
+
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use ethers::utils::hex;
+use hmac::{Hmac, Mac};
+use http::request::Parts;
+use sha2::Sha256;
+use std::fmt;
+use uuid::Uuid;
+
+/// The header with the unix time (in seconds) that the request was signed
+pub const TIMESTAMP_HEADER: &str = "x-w3p-timestamp";
+
+/// The header with the hex HMAC-SHA256 of `{timestamp}.{body}`
+pub const SIGNATURE_HEADER: &str = "x-w3p-signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The shared secret for a key. Debug does not print it
+#[derive(Clone, PartialEq, Eq)]
+pub struct HmacSecret(String);
+
+impl fmt::Debug for HmacSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HmacSecret(..)")
+    }
+}
+
+impl From<String> for HmacSecret {
+    fn from(x: String) -> Self {
+        Self(x)
+    }
+}
+
+impl HmacSecret {
+    /// 64 random hex characters
+    pub fn random() -> Self {
+        Self(format!(
+            "{}{}",
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        ))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn mac(&self, timestamp: i64, body: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(self.0.as_bytes()).expect("hmac takes keys of any size");
+
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+
+        mac
+    }
+
+    /// The value a client should send in the `X-W3P-SIGNATURE` header
+    pub fn sign(&self, timestamp: i64, body: &[u8]) -> String {
+        hex::encode(self.mac(timestamp, body).finalize().into_bytes())
+    }
+}
+
+/// The parsed `X-W3P-TIMESTAMP` and `X-W3P-SIGNATURE` headers
+#[derive(Clone, Debug)]
+pub struct SignedHeaders {
+    pub timestamp: i64,
+    pub signature: [u8; 32],
+}
+
+impl SignedHeaders {
+    /// Check the signature and that the timestamp is within `max_age_seconds` of `now`.
+    /// The comparison is constant time.
+    pub fn verify(
+        &self,
+        secret: &HmacSecret,
+        body: &[u8],
+        now: i64,
+        max_age_seconds: u64,
+    ) -> Web3ProxyResult<()> {
+        if now.abs_diff(self.timestamp) > max_age_seconds {
+            return Err(Web3ProxyError::InvalidRequestSignature(
+                format!(
+                    "signature timestamp must be within {} seconds of the server's time",
+                    max_age_seconds
+                )
+                .into(),
+            ));
+        }
+
+        secret
+            .mac(self.timestamp, body)
+            .verify_slice(&self.signature)
+            .map_err(|_| Web3ProxyError::InvalidRequestSignature("signature does not match".into()))
+    }
+}
+
+/// The optional signature headers. Both or neither must be set
+#[derive(Clone, Debug, Default)]
+pub struct RequestSignature(pub Option<SignedHeaders>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestSignature
+where
+    S: Send + Sync,
+{
+    type Rejection = Web3ProxyError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let timestamp = parts.headers.get(TIMESTAMP_HEADER);
+        let signature = parts.headers.get(SIGNATURE_HEADER);
+
+        match (timestamp, signature) {
+            (None, None) => Ok(Self(None)),
+            (Some(timestamp), Some(signature)) => {
+                let timestamp = timestamp
+                    .to_str()
+                    .ok()
+                    .and_then(|x| x.trim().parse().ok())
+                    .ok_or_else(|| {
+                        Web3ProxyError::InvalidRequestSignature(
+                            "timestamp must be unix seconds".into(),
+                        )
+                    })?;
+
+                let signature = signature
+                    .to_str()
+                    .ok()
+                    .and_then(|x| parse_signature(x.trim()))
+                    .ok_or_else(|| {
+                        Web3ProxyError::InvalidRequestSignature(
+                            "signature must be 64 hex characters".into(),
+                        )
+                    })?;
+
+                Ok(Self(Some(SignedHeaders {
+                    timestamp,
+                    signature,
+                })))
+            }
+            _ => Err(Web3ProxyError::InvalidRequestSignature(
+                "both the timestamp and signature headers are required".into(),
+            )),
+        }
+    }
+}
+
+fn parse_signature(x: &str) -> Option<[u8; 32]> {
+    let x = x.strip_prefix("0x").unwrap_or(x);
+
+    hex::decode(x).ok()?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_signature, HmacSecret, SignedHeaders};
+
+    #[test]
+    fn test_verify_signature() {
+        let secret = HmacSecret::from("shared secret".to_string());
+        let body = br#"{"jsonrpc":"2.0","id":1,"method":"eth_blockNumber"}"#;
+
+        let signed = SignedHeaders {
+            timestamp: 1_700_000_000,
+            signature: parse_signature(&secret.sign(1_700_000_000, body)).unwrap(),
+        };
+
+        assert!(signed.verify(&secret, body, 1_700_000_030, 300).is_ok());
+
+        // a changed body, a different secret, or an old timestamp all fail
+        assert!(signed.verify(&secret, b"{}", 1_700_000_030, 300).is_err());
+        assert!(signed
+            .verify(&HmacSecret::random(), body, 1_700_000_030, 300)
+            .is_err());
+        assert!(signed.verify(&secret, body, 1_700_000_301, 300).is_err());
+        assert!(signed.verify(&secret, body, 1_699_999_699, 300).is_err());
+    }
+
+    #[test]
+    fn test_parse_signature() {
+        assert!(parse_signature(&"ab".repeat(32)).is_some());
+        assert!(parse_signature(&format!("0x{}", "ab".repeat(32))).is_some());
+        assert!(parse_signature(&"ab".repeat(31)).is_none());
+        assert!(parse_signature("not hex").is_none());
+    }
+}
@@ -0,0 +1,62 @@
+//! Let clients label their requests so that one rpc key shared by several services can be split up in the stats.
+
+use super::optional_header::OptionalHeaderValue;
+use crate::errors::Web3ProxyError;
+
+/// Clients set `X-Web3-Proxy-Tag` to tag their requests.
+/// Tags are stored in the accounting table's unique index, so they are kept short
+pub struct RequestTag;
+
+impl OptionalHeaderValue for RequestTag {
+    const HEADER: &'static str = "x-web3-proxy-tag";
+    const MAX_LEN: usize = 64;
+    const NAME: &'static str = "request tag";
+
+    type Value = String;
+
+    /// An empty tag is the same as no tag
+    fn parse(tag: &str) -> Result<Option<String>, Web3ProxyError> {
+        if tag.is_empty() {
+            return Ok(None);
+        }
+
+        if !tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+        {
+            return Err(Web3ProxyError::BadRequest(
+                "request tags may only contain letters, numbers, '-', '_', '.', and ':'".into(),
+            ));
+        }
+
+        Ok(Some(tag.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestTag;
+    use crate::frontend::optional_header::{OptionalHeader, OptionalHeaderValue};
+
+    fn parse_tag(value: &str) -> Result<Option<String>, crate::errors::Web3ProxyError> {
+        OptionalHeader::<RequestTag>::parse_value(value)
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(parse_tag("").unwrap(), None);
+        assert_eq!(parse_tag("  ").unwrap(), None);
+        assert_eq!(
+            parse_tag(" frontend-mainnet ").unwrap().as_deref(),
+            Some("frontend-mainnet")
+        );
+        assert_eq!(
+            parse_tag("backend_cron:v2").unwrap().as_deref(),
+            Some("backend_cron:v2")
+        );
+
+        assert!(parse_tag("has space").is_err());
+        assert!(parse_tag("quote\"").is_err());
+        assert!(parse_tag(&"a".repeat(RequestTag::MAX_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+//! Let clients say how long they will wait so that we stop retrying once they have given up.
+
+use super::optional_header::OptionalHeaderValue;
+use crate::errors::Web3ProxyError;
+use std::time::Duration;
+
+/// Clients set `X-Timeout` to the number of seconds they will wait
+pub struct RequestTimeout;
+
+impl OptionalHeaderValue for RequestTimeout {
+    const HEADER: &'static str = "x-timeout";
+    const MAX_LEN: usize = 32;
+    const NAME: &'static str = "request timeout";
+
+    type Value = Duration;
+
+    /// Seconds. Fractions are allowed so that clients can ask for less than a second
+    fn parse(timeout: &str) -> Result<Option<Duration>, Web3ProxyError> {
+        match timeout.parse::<f64>() {
+            Ok(x) if x.is_finite() && x > 0.0 => {
+                Ok(Some(Duration::from_secs_f64(x.min(u32::MAX as f64))))
+            }
+            _ => Err(Web3ProxyError::BadRequest(
+                "request timeout must be a positive number of seconds".into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestTimeout;
+    use crate::frontend::optional_header::{OptionalHeader, OptionalHeaderValue};
+    use std::time::Duration;
+
+    fn parse_timeout(value: &str) -> Result<Option<Duration>, crate::errors::Web3ProxyError> {
+        OptionalHeader::<RequestTimeout>::parse_value(value)
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("5").unwrap(), Some(Duration::from_secs(5)));
+        assert_eq!(
+            parse_timeout(" 0.25 ").unwrap(),
+            Some(Duration::from_millis(250))
+        );
+
+        assert!(parse_timeout("").is_err());
+        assert!(parse_timeout("0").is_err());
+        assert!(parse_timeout("-1").is_err());
+        assert!(parse_timeout("NaN").is_err());
+        assert!(parse_timeout("soon").is_err());
+        assert!(parse_timeout(&"1".repeat(RequestTimeout::MAX_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+//! Let clients ask for only part of a result. A client polling for block numbers doesn't need the rest of the block.
+
+use super::optional_header::OptionalHeaderValue;
+use crate::errors::Web3ProxyError;
+
+/// Clients set `X-Result-Pointer` to a JSON pointer into the result. "/number" returns only a block's number
+pub struct ResultPointer;
+
+impl OptionalHeaderValue for ResultPointer {
+    const HEADER: &'static str = "x-result-pointer";
+    const MAX_LEN: usize = 256;
+    const NAME: &'static str = "result pointer";
+
+    type Value = String;
+
+    /// JSON pointers are empty or start with a "/"
+    fn parse(pointer: &str) -> Result<Option<String>, Web3ProxyError> {
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            return Err(Web3ProxyError::BadRequest(
+                "result pointer must be a JSON pointer like \"/number\"".into(),
+            ));
+        }
+
+        Ok(Some(pointer.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResultPointer;
+    use crate::frontend::optional_header::{OptionalHeader, OptionalHeaderValue};
+
+    fn parse(value: &str) -> Option<String> {
+        OptionalHeader::<ResultPointer>::parse_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_parse_result_pointer() {
+        assert_eq!(parse(" /number ").as_deref(), Some("/number"));
+        assert_eq!(
+            parse("/transactions/0/hash").as_deref(),
+            Some("/transactions/0/hash")
+        );
+        assert_eq!(parse("").as_deref(), Some(""));
+
+        assert!(OptionalHeader::<ResultPointer>::parse_value("number").is_err());
+        assert!(OptionalHeader::<ResultPointer>::parse_value("result.number").is_err());
+        assert!(OptionalHeader::<ResultPointer>::parse_value(&format!(
+            "/{}",
+            "a".repeat(ResultPointer::MAX_LEN)
+        ))
+        .is_err());
+    }
+}
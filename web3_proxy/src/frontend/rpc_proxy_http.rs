@@ -1,110 +1,163 @@
 //! Take a user's HTTP JSON-RPC requests and either respond from local data or proxy the request to a backend rpc server.
 
-use super::authorization::{ip_is_authorized, key_is_authorized};
+use super::authorization::{ip_is_authorized, key_is_authorized, signature_is_authorized};
+use super::client_ip::ClientIp;
+use super::gas_estimate::GasEstimatePercent;
+use super::optional_header::OptionalHeader;
+use super::request_signature::{RequestSignature, SignedHeaders};
+use super::request_tag::RequestTag;
+use super::request_timeout::RequestTimeout;
+use super::result_pointer::ResultPointer;
 use super::rpc_proxy_ws::ProxyMode;
-use crate::errors::Web3ProxyError;
-use crate::{app::Web3ProxyApp, jsonrpc::JsonRpcRequestEnum};
+use crate::config::AppConfig;
+use crate::errors::{no_servers_retry_after, Web3ProxyError};
+use crate::rpcs::one::Web3Rpc;
+use crate::{
+    app::Web3ProxyApp,
+    jsonrpc::{JsonRpcForwardedResponseEnum, JsonRpcRequestEnum},
+};
+use axum::body::Bytes;
 use axum::extract::Path;
-use axum::headers::{Origin, Referer, UserAgent};
+use axum::headers::{CacheControl, Origin, Referer, UserAgent};
 use axum::response::Response;
 use axum::TypedHeader;
 use axum::{response::IntoResponse, Extension, Json};
-use axum_client_ip::InsecureClientIp;
 use axum_macros::debug_handler;
-use http::HeaderMap;
+use http::header::{RETRY_AFTER, WARNING};
+use http::{HeaderMap, HeaderValue, StatusCode};
 use itertools::Itertools;
 use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 /// POST /rpc -- Public entrypoint for HTTP JSON-RPC requests. Web3 wallets use this.
 /// Defaults to rate limiting by IP address, but can also read the Authorization header for a bearer token.
 /// If possible, please use a WebSocket instead.
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn proxy_web3_rpc(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(timeout): OptionalHeader<RequestTimeout>,
+    OptionalHeader(gas_estimate_percent): OptionalHeader<GasEstimatePercent>,
+    OptionalHeader(result_pointer): OptionalHeader<ResultPointer>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     Json(payload): Json<JsonRpcRequestEnum>,
 ) -> Result<Response, Response> {
-    _proxy_web3_rpc(app, &ip, origin.as_deref(), payload, ProxyMode::Best).await
+    _proxy_web3_rpc(
+        app,
+        &ip,
+        timeout,
+        gas_estimate_percent,
+        result_pointer,
+        cache_control.as_deref(),
+        origin.as_deref(),
+        payload,
+        ProxyMode::Best,
+    )
+    .await
 }
 
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn fastest_proxy_web3_rpc(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(timeout): OptionalHeader<RequestTimeout>,
+    OptionalHeader(gas_estimate_percent): OptionalHeader<GasEstimatePercent>,
+    OptionalHeader(result_pointer): OptionalHeader<ResultPointer>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     Json(payload): Json<JsonRpcRequestEnum>,
 ) -> Result<Response, Response> {
     // TODO: read the fastest number from params
     // TODO: check that the app allows this without authentication
-    _proxy_web3_rpc(app, &ip, origin.as_deref(), payload, ProxyMode::Fastest(0)).await
+    _proxy_web3_rpc(
+        app,
+        &ip,
+        timeout,
+        gas_estimate_percent,
+        result_pointer,
+        cache_control.as_deref(),
+        origin.as_deref(),
+        payload,
+        ProxyMode::Fastest(0),
+    )
+    .await
 }
 
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn versus_proxy_web3_rpc(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(timeout): OptionalHeader<RequestTimeout>,
+    OptionalHeader(gas_estimate_percent): OptionalHeader<GasEstimatePercent>,
+    OptionalHeader(result_pointer): OptionalHeader<ResultPointer>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     Json(payload): Json<JsonRpcRequestEnum>,
 ) -> Result<Response, Response> {
-    _proxy_web3_rpc(app, &ip, origin.as_deref(), payload, ProxyMode::Versus).await
+    _proxy_web3_rpc(
+        app,
+        &ip,
+        timeout,
+        gas_estimate_percent,
+        result_pointer,
+        cache_control.as_deref(),
+        origin.as_deref(),
+        payload,
+        ProxyMode::Versus,
+    )
+    .await
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn _proxy_web3_rpc(
     app: Arc<Web3ProxyApp>,
     ip: &IpAddr,
+    timeout: Option<Duration>,
+    gas_estimate_percent: Option<u16>,
+    result_pointer: Option<String>,
+    cache_control: Option<&CacheControl>,
     origin: Option<&Origin>,
     payload: JsonRpcRequestEnum,
     proxy_mode: ProxyMode,
 ) -> Result<Response, Response> {
+    // one snapshot for the whole request. a reload in the middle doesn't mix old and new settings
+    let config = app.config.load_full();
+
     let first_id = payload.first_id();
 
-    let (authorization, _semaphore) = ip_is_authorized(&app, ip, origin, proxy_mode)
+    let (mut authorization, _semaphore) = ip_is_authorized(&app, ip, origin, proxy_mode)
         .await
         .map_err(|e| e.into_response_with_id(first_id.clone()))?;
 
+    authorization.timeout = timeout;
+    authorization.gas_estimate_percent = gas_estimate_percent;
+    authorization.result_pointer = result_pointer;
+    authorization.limit_cache_age(cache_control);
+
     let authorization = Arc::new(authorization);
 
     // TODO: calculate payload bytes here (before turning into serde_json::Value). that will save serializing later
 
     // TODO: is first_id the right thing to attach to this error?
     let (status_code, response, rpcs) = app
-        .proxy_web3_rpc(authorization, payload)
+        .proxy_web3_rpc(&config, authorization, payload)
         .await
         .map_err(|e| e.into_response_with_id(first_id))?;
 
-    let mut response = (status_code, Json(response)).into_response();
-
-    // TODO: DRY this up. it is the same code for public and private queries
-    let response_headers = response.headers_mut();
-
-    // TODO: this might be slow. think about this more
-    // TODO: special string if no rpcs were used (cache hit)?
-    let mut backup_used = false;
-
-    let rpcs: String = rpcs
-        .into_iter()
-        .map(|x| {
-            if x.backup {
-                backup_used = true;
-            }
-            x.name.clone()
-        })
-        .join(",");
+    let stale_seconds = response.stale_seconds();
+    let warnings = warning_headers(&response);
 
-    response_headers.insert(
-        "X-W3P-BACKEND-RPCS",
-        rpcs.parse().expect("W3P-BACKEND-RPCS should always parse"),
-    );
+    let mut response = (status_code, Json(response)).into_response();
 
-    response_headers.insert(
-        "X-W3P-BACKUP-RPC",
-        backup_used
-            .to_string()
-            .parse()
-            .expect("W3P-BACKEND-RPCS should always parse"),
-    );
+    add_retry_after(status_code, response.headers_mut());
+    add_stale_seconds(stale_seconds, response.headers_mut());
+    add_warnings(warnings, response.headers_mut());
+    add_backend_headers(&config, proxy_mode, response.headers_mut(), rpcs);
 
     Ok(response)
 }
@@ -114,14 +167,21 @@ async fn _proxy_web3_rpc(
 /// Can optionally authorized based on origin, referer, or user agent.
 /// If possible, please use a WebSocket instead.
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn proxy_web3_rpc_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(tag): OptionalHeader<RequestTag>,
+    OptionalHeader(timeout): OptionalHeader<RequestTimeout>,
+    OptionalHeader(gas_estimate_percent): OptionalHeader<GasEstimatePercent>,
+    OptionalHeader(result_pointer): OptionalHeader<ResultPointer>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
     Path(rpc_key): Path<String>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    RequestSignature(signature): RequestSignature,
+    body: Bytes,
 ) -> Result<Response, Response> {
     _proxy_web3_rpc_with_key(
         app,
@@ -130,7 +190,13 @@ pub async fn proxy_web3_rpc_with_key(
         referer.as_deref(),
         user_agent.as_deref(),
         rpc_key,
-        payload,
+        tag,
+        timeout,
+        gas_estimate_percent,
+        result_pointer,
+        cache_control.as_deref(),
+        signature,
+        body,
         ProxyMode::Best,
     )
     .await
@@ -141,13 +207,19 @@ pub async fn proxy_web3_rpc_with_key(
 #[allow(clippy::too_many_arguments)]
 pub async fn debug_proxy_web3_rpc_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(tag): OptionalHeader<RequestTag>,
+    OptionalHeader(timeout): OptionalHeader<RequestTimeout>,
+    OptionalHeader(gas_estimate_percent): OptionalHeader<GasEstimatePercent>,
+    OptionalHeader(result_pointer): OptionalHeader<ResultPointer>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
     request_headers: HeaderMap,
     Path(rpc_key): Path<String>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    RequestSignature(signature): RequestSignature,
+    body: Bytes,
 ) -> Result<Response, Response> {
     let mut response = match _proxy_web3_rpc_with_key(
         app,
@@ -156,7 +228,13 @@ pub async fn debug_proxy_web3_rpc_with_key(
         referer.as_deref(),
         user_agent.as_deref(),
         rpc_key,
-        payload,
+        tag,
+        timeout,
+        gas_estimate_percent,
+        result_pointer,
+        cache_control.as_deref(),
+        signature,
+        body,
         ProxyMode::Debug,
     )
     .await
@@ -183,14 +261,21 @@ pub async fn debug_proxy_web3_rpc_with_key(
 }
 
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn fastest_proxy_web3_rpc_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(tag): OptionalHeader<RequestTag>,
+    OptionalHeader(timeout): OptionalHeader<RequestTimeout>,
+    OptionalHeader(gas_estimate_percent): OptionalHeader<GasEstimatePercent>,
+    OptionalHeader(result_pointer): OptionalHeader<ResultPointer>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
     Path(rpc_key): Path<String>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    RequestSignature(signature): RequestSignature,
+    body: Bytes,
 ) -> Result<Response, Response> {
     _proxy_web3_rpc_with_key(
         app,
@@ -199,21 +284,34 @@ pub async fn fastest_proxy_web3_rpc_with_key(
         referer.as_deref(),
         user_agent.as_deref(),
         rpc_key,
-        payload,
+        tag,
+        timeout,
+        gas_estimate_percent,
+        result_pointer,
+        cache_control.as_deref(),
+        signature,
+        body,
         ProxyMode::Fastest(0),
     )
     .await
 }
 
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn versus_proxy_web3_rpc_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(tag): OptionalHeader<RequestTag>,
+    OptionalHeader(timeout): OptionalHeader<RequestTimeout>,
+    OptionalHeader(gas_estimate_percent): OptionalHeader<GasEstimatePercent>,
+    OptionalHeader(result_pointer): OptionalHeader<ResultPointer>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
     Path(rpc_key): Path<String>,
-    Json(payload): Json<JsonRpcRequestEnum>,
+    RequestSignature(signature): RequestSignature,
+    body: Bytes,
 ) -> Result<Response, Response> {
     _proxy_web3_rpc_with_key(
         app,
@@ -222,7 +320,13 @@ pub async fn versus_proxy_web3_rpc_with_key(
         referer.as_deref(),
         user_agent.as_deref(),
         rpc_key,
-        payload,
+        tag,
+        timeout,
+        gas_estimate_percent,
+        result_pointer,
+        cache_control.as_deref(),
+        signature,
+        body,
         ProxyMode::Versus,
     )
     .await
@@ -236,38 +340,152 @@ async fn _proxy_web3_rpc_with_key(
     referer: Option<&Referer>,
     user_agent: Option<&UserAgent>,
     rpc_key: String,
-    payload: JsonRpcRequestEnum,
+    tag: Option<String>,
+    timeout: Option<Duration>,
+    gas_estimate_percent: Option<u16>,
+    result_pointer: Option<String>,
+    cache_control: Option<&CacheControl>,
+    signature: Option<SignedHeaders>,
+    body: Bytes,
     proxy_mode: ProxyMode,
 ) -> Result<Response, Response> {
     // TODO: DRY w/ proxy_web3_rpc
 
+    // one snapshot for the whole request. a reload in the middle doesn't mix old and new settings
+    let config = app.config.load_full();
+
+    // the body is parsed here instead of by axum because signatures are checked against the raw bytes
+    let payload: JsonRpcRequestEnum =
+        serde_json::from_slice(&body).map_err(|e| Web3ProxyError::from(e).into_response())?;
+
     let first_id = payload.first_id();
 
     let rpc_key = rpc_key
         .parse()
         .map_err(|e: Web3ProxyError| e.into_response_with_id(first_id.clone()))?;
 
-    let (authorization, _semaphore) =
+    signature_is_authorized(&app, &rpc_key, proxy_mode, signature.as_ref(), &body)
+        .await
+        .map_err(|e| e.into_response_with_id(first_id.clone()))?;
+
+    let (mut authorization, _semaphore) =
         key_is_authorized(&app, &rpc_key, ip, origin, proxy_mode, referer, user_agent)
             .await
             .map_err(|e| e.into_response_with_id(first_id.clone()))?;
 
+    // the app's `max_request_bytes` already stopped anything larger than every tier allows
+    authorization
+        .check_request_size(body.len())
+        .map_err(|e| e.into_response_with_id(first_id))?;
+
+    authorization.tag = tag;
+    authorization.timeout = timeout;
+    authorization.gas_estimate_percent = gas_estimate_percent;
+    authorization.result_pointer = result_pointer;
+
+    authorization.limit_cache_age(cache_control);
+
     let authorization = Arc::new(authorization);
 
     let rpc_secret_key_id = authorization.checks.rpc_secret_key_id;
 
+    // checked before sending so that the header matches where the transaction went
+    let private_tx_fallback = payload.has_method("eth_sendRawTransaction")
+        && app
+            .private_tx_below_min_balance(&config, &authorization)
+            .await;
+
     let (status_code, response, rpcs) = app
-        .proxy_web3_rpc(authorization, payload)
+        .proxy_web3_rpc(&config, authorization, payload)
         .await
         .map_err(|e| e.into_response_with_id(first_id))?;
 
+    let stale_seconds = response.stale_seconds();
+    let warnings = warning_headers(&response);
+
     let mut response = (status_code, Json(response)).into_response();
 
     let headers = response.headers_mut();
 
+    add_retry_after(status_code, headers);
+    add_stale_seconds(stale_seconds, headers);
+    add_warnings(warnings, headers);
+    add_backend_headers(&config, proxy_mode, headers, rpcs);
+
+    if let Some(rpc_secret_key_id) = rpc_secret_key_id {
+        headers.insert(
+            "X-W3P-KEY-ID",
+            rpc_secret_key_id
+                .to_string()
+                .parse()
+                .expect("X-CLIENT-IP should always parse"),
+        );
+    }
+
+    if private_tx_fallback {
+        headers.insert(
+            "X-W3P-PRIVATE-TX-FALLBACK",
+            HeaderValue::from_static(
+                "balance is below the minimum for private transactions. sent to the public mempool",
+            ),
+        );
+    }
+
+    Ok(response)
+}
+
+/// Errors inside a request don't go through `into_response_with_id`, so the 503 for no synced backends needs its `Retry-After` here
+fn add_retry_after(status_code: StatusCode, headers: &mut HeaderMap) {
+    if status_code == StatusCode::SERVICE_UNAVAILABLE {
+        headers.insert(RETRY_AFTER, HeaderValue::from(no_servers_retry_after()));
+    }
+}
+
+/// Tell the client that the backends were down and some responses came from the stale cache
+fn add_stale_seconds(stale_seconds: Option<u64>, headers: &mut HeaderMap) {
+    if let Some(stale_seconds) = stale_seconds {
+        headers.insert("X-W3P-STALE-SECONDS", HeaderValue::from(stale_seconds));
+    }
+}
+
+/// `299 - "{deprecation}"` for each deprecated or aliased method in the request. Deprecations that can't be a header are skipped
+fn warning_headers(response: &JsonRpcForwardedResponseEnum) -> Vec<HeaderValue> {
+    response
+        .deprecations()
+        .into_iter()
+        .filter_map(|x| {
+            let x = x.replace('\\', "\\\\").replace('"', "\\\"");
+
+            HeaderValue::from_str(&format!("299 - \"{}\"", x)).ok()
+        })
+        .collect()
+}
+
+/// Tell the client that some of the methods they used are going away
+fn add_warnings(warnings: Vec<HeaderValue>, headers: &mut HeaderMap) {
+    for x in warnings {
+        headers.append(WARNING, x);
+    }
+}
+
+/// Tell the client which backend rpcs answered and if the response came from the cache.
+/// This shows our backend topology, so it is only done for debug requests or if `backend_rpc_headers` is enabled.
+fn add_backend_headers(
+    config: &AppConfig,
+    proxy_mode: ProxyMode,
+    headers: &mut HeaderMap,
+    rpcs: Vec<Arc<Web3Rpc>>,
+) {
+    if !config.backend_rpc_headers && !matches!(proxy_mode, ProxyMode::Debug) {
+        return;
+    }
+
+    // no rpcs are used when the response is cached
+    let cache_hit = rpcs.is_empty();
+
     let mut backup_used = false;
 
-    // TODO: special string if no rpcs were used (cache hit)? or is an empty string fine? maybe the rpc name + "cached"
+    // TODO: this might be slow. think about this more
     let rpcs: String = rpcs
         .into_iter()
         .map(|x| {
@@ -279,7 +497,7 @@ async fn _proxy_web3_rpc_with_key(
         .join(",");
 
     headers.insert(
-        "X-W3P-BACKEND-RPCs",
+        "X-W3P-BACKEND-RPCS",
         rpcs.parse().expect("W3P-BACKEND-RPCS should always parse"),
     );
 
@@ -288,18 +506,14 @@ async fn _proxy_web3_rpc_with_key(
         backup_used
             .to_string()
             .parse()
-            .expect("W3P-BACKEND-RPCS should always parse"),
+            .expect("W3P-BACKUP-RPC should always parse"),
     );
 
-    if let Some(rpc_secret_key_id) = rpc_secret_key_id {
-        headers.insert(
-            "X-W3P-KEY-ID",
-            rpc_secret_key_id
-                .to_string()
-                .parse()
-                .expect("X-CLIENT-IP should always parse"),
-        );
-    }
-
-    Ok(response)
+    headers.insert(
+        "X-W3P-CACHE-HIT",
+        cache_hit
+            .to_string()
+            .parse()
+            .expect("W3P-CACHE-HIT should always parse"),
+    );
 }
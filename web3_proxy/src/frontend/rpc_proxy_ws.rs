@@ -2,23 +2,29 @@
 //!
 //! WebSockets are the preferred method of receiving requests, but not all clients have good support.
 
-use super::authorization::{ip_is_authorized, key_is_authorized, Authorization, RequestMetadata};
+use super::authorization::{
+    ip_is_authorized, key_is_authorized, signature_is_authorized, Authorization, RequestMetadata,
+};
+use super::client_ip::ClientIp;
+use super::optional_header::OptionalHeader;
+use super::request_signature::{RequestSignature, SignedHeaders};
+use super::request_tag::RequestTag;
+use crate::config::AppConfig;
 use crate::errors::{Web3ProxyError, Web3ProxyResponse};
 use crate::jsonrpc::JsonRpcId;
 use crate::{
     app::Web3ProxyApp,
     errors::Web3ProxyResult,
-    jsonrpc::{JsonRpcForwardedResponse, JsonRpcForwardedResponseEnum, JsonRpcRequest},
+    jsonrpc::{JsonRpcForwardedResponse, JsonRpcForwardedResponseEnum, JsonRpcRequestEnum},
 };
 use anyhow::Context;
-use axum::headers::{Origin, Referer, UserAgent};
+use axum::headers::{CacheControl, Origin, Referer, UserAgent};
 use axum::{
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
     extract::Path,
     response::{IntoResponse, Redirect},
     Extension, TypedHeader,
 };
-use axum_client_ip::InsecureClientIp;
 use axum_macros::debug_handler;
 use ethers::types::U64;
 use futures::SinkExt;
@@ -32,7 +38,7 @@ use http::{HeaderMap, StatusCode};
 use serde_json::json;
 use std::net::IpAddr;
 use std::str::from_utf8_mut;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit, RwLock as AsyncRwLock};
 use tracing::{info, trace};
@@ -57,11 +63,20 @@ pub enum ProxyMode {
 #[debug_handler]
 pub async fn websocket_handler(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
-    _websocket_handler(ProxyMode::Best, app, &ip, origin.as_deref(), ws_upgrade).await
+    _websocket_handler(
+        ProxyMode::Best,
+        app,
+        &ip,
+        cache_control.as_deref(),
+        origin.as_deref(),
+        ws_upgrade,
+    )
+    .await
 }
 
 /// Public entrypoint for WebSocket JSON-RPC requests that uses all synced servers.
@@ -69,7 +84,8 @@ pub async fn websocket_handler(
 #[debug_handler]
 pub async fn fastest_websocket_handler(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
@@ -79,6 +95,7 @@ pub async fn fastest_websocket_handler(
         ProxyMode::Fastest(0),
         app,
         &ip,
+        cache_control.as_deref(),
         origin.as_deref(),
         ws_upgrade,
     )
@@ -90,31 +107,45 @@ pub async fn fastest_websocket_handler(
 #[debug_handler]
 pub async fn versus_websocket_handler(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     // TODO: config to disable this
-    _websocket_handler(ProxyMode::Versus, app, &ip, origin.as_deref(), ws_upgrade).await
+    _websocket_handler(
+        ProxyMode::Versus,
+        app,
+        &ip,
+        cache_control.as_deref(),
+        origin.as_deref(),
+        ws_upgrade,
+    )
+    .await
 }
 
 async fn _websocket_handler(
     proxy_mode: ProxyMode,
     app: Arc<Web3ProxyApp>,
     ip: &IpAddr,
+    cache_control: Option<&CacheControl>,
     origin: Option<&Origin>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
-    let (authorization, _semaphore) = ip_is_authorized(&app, ip, origin, proxy_mode).await?;
+    let (mut authorization, _semaphore) = ip_is_authorized(&app, ip, origin, proxy_mode).await?;
+
+    authorization.limit_cache_age(cache_control);
 
     let authorization = Arc::new(authorization);
 
+    let config = app.config.load_full();
+
     match ws_upgrade {
-        Some(ws) => Ok(ws
+        Some(ws) => Ok(limit_message_size(&config, ws)
             .on_upgrade(move |socket| proxy_web3_socket(app, authorization, socket))
             .into_response()),
         None => {
-            if let Some(redirect) = &app.config.redirect_public_url {
+            if let Some(redirect) = &config.redirect_public_url {
                 // this is not a websocket. redirect to a friendly page
                 Ok(Redirect::permanent(redirect).into_response())
             } else {
@@ -128,13 +159,17 @@ async fn _websocket_handler(
 /// Rate limit and billing based on the api key in the url.
 /// Can optionally authorized based on origin, referer, or user agent.
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn websocket_handler_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(tag): OptionalHeader<RequestTag>,
     Path(rpc_key): Path<String>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    RequestSignature(signature): RequestSignature,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     _websocket_handler_with_key(
@@ -142,9 +177,12 @@ pub async fn websocket_handler_with_key(
         app,
         &ip,
         rpc_key,
+        tag,
+        cache_control.as_deref(),
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        signature,
         ws_upgrade,
     )
     .await
@@ -154,11 +192,14 @@ pub async fn websocket_handler_with_key(
 #[allow(clippy::too_many_arguments)]
 pub async fn debug_websocket_handler_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(tag): OptionalHeader<RequestTag>,
     Path(rpc_key): Path<String>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    RequestSignature(signature): RequestSignature,
     headers: HeaderMap,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
@@ -167,9 +208,12 @@ pub async fn debug_websocket_handler_with_key(
         app,
         &ip,
         rpc_key,
+        tag,
+        cache_control.as_deref(),
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        signature,
         ws_upgrade,
     )
     .await?;
@@ -191,13 +235,17 @@ pub async fn debug_websocket_handler_with_key(
 }
 
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn fastest_websocket_handler_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(tag): OptionalHeader<RequestTag>,
     Path(rpc_key): Path<String>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    RequestSignature(signature): RequestSignature,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     // TODO: get the fastest number from the url params (default to 0/all)
@@ -206,22 +254,29 @@ pub async fn fastest_websocket_handler_with_key(
         app,
         &ip,
         rpc_key,
+        tag,
+        cache_control.as_deref(),
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        signature,
         ws_upgrade,
     )
     .await
 }
 
 #[debug_handler]
+#[allow(clippy::too_many_arguments)]
 pub async fn versus_websocket_handler_with_key(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
+    OptionalHeader(tag): OptionalHeader<RequestTag>,
     Path(rpc_key): Path<String>,
+    cache_control: Option<TypedHeader<CacheControl>>,
     origin: Option<TypedHeader<Origin>>,
     referer: Option<TypedHeader<Referer>>,
     user_agent: Option<TypedHeader<UserAgent>>,
+    RequestSignature(signature): RequestSignature,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     _websocket_handler_with_key(
@@ -229,9 +284,12 @@ pub async fn versus_websocket_handler_with_key(
         app,
         &ip,
         rpc_key,
+        tag,
+        cache_control.as_deref(),
         origin.as_deref(),
         referer.as_deref(),
         user_agent.as_deref(),
+        signature,
         ws_upgrade,
     )
     .await
@@ -243,29 +301,40 @@ async fn _websocket_handler_with_key(
     app: Arc<Web3ProxyApp>,
     ip: &IpAddr,
     rpc_key: String,
+    tag: Option<String>,
+    cache_control: Option<&CacheControl>,
     origin: Option<&Origin>,
     referer: Option<&Referer>,
     user_agent: Option<&UserAgent>,
+    signature: Option<SignedHeaders>,
     ws_upgrade: Option<WebSocketUpgrade>,
 ) -> Web3ProxyResponse {
     let rpc_key = rpc_key.parse()?;
 
-    let (authorization, _semaphore) =
+    // the upgrade request has no body, so an empty body is signed
+    signature_is_authorized(&app, &rpc_key, proxy_mode, signature.as_ref(), &[]).await?;
+
+    let (mut authorization, _semaphore) =
         key_is_authorized(&app, &rpc_key, ip, origin, proxy_mode, referer, user_agent).await?;
 
+    authorization.tag = tag;
+    // the upgrade request's Cache-Control applies to every message on the socket
+    authorization.limit_cache_age(cache_control);
+
     trace!("websocket_handler_with_key {:?}", authorization);
 
     let authorization = Arc::new(authorization);
 
+    let config = app.config.load_full();
+
     match ws_upgrade {
-        Some(ws_upgrade) => {
-            Ok(ws_upgrade.on_upgrade(move |socket| proxy_web3_socket(app, authorization, socket)))
-        }
+        Some(ws_upgrade) => Ok(limit_message_size(&config, ws_upgrade)
+            .on_upgrade(move |socket| proxy_web3_socket(app, authorization, socket))),
         None => {
             // if no websocket upgrade, this is probably a user loading the url with their browser
             match (
-                &app.config.redirect_public_url,
-                &app.config.redirect_rpc_key_url,
+                &config.redirect_public_url,
+                &config.redirect_rpc_key_url,
                 authorization.checks.rpc_secret_key_id,
             ) {
                 (None, None, _) => Err(Web3ProxyError::StatusCode(
@@ -297,6 +366,21 @@ async fn _websocket_handler_with_key(
     }
 }
 
+/// Don't let a client make us buffer huge messages. tungstenite errors instead of reading past the limit.
+fn limit_message_size(config: &AppConfig, ws_upgrade: WebSocketUpgrade) -> WebSocketUpgrade {
+    ws_upgrade
+        .max_message_size(config.ws_max_message_bytes)
+        .max_frame_size(config.ws_max_message_bytes)
+}
+
+/// True if the client sent a message larger than `ws_max_message_bytes`
+fn is_oversized(err: axum::Error) -> bool {
+    matches!(
+        err.into_inner().downcast::<tungstenite::Error>().as_deref(),
+        Ok(tungstenite::Error::Capacity(_))
+    )
+}
+
 async fn proxy_web3_socket(
     app: Arc<Web3ProxyApp>,
     authorization: Arc<Authorization>,
@@ -322,11 +406,42 @@ async fn handle_socket_payload(
     subscription_count: &AtomicU64,
     subscriptions: Arc<AsyncRwLock<HashMap<U64, AbortHandle>>>,
 ) -> Web3ProxyResult<(Message, Option<OwnedSemaphorePermit>)> {
-    let (authorization, semaphore) = authorization.check_again(&app).await?;
+    let request = serde_json::from_str::<JsonRpcRequestEnum>(payload);
+
+    // the same rate and concurrency limits as http are checked for every message, not just when the socket opens.
+    // going over a limit sends an error for this request. the socket stays open so the client can slow down
+    let (authorization, semaphore) = match authorization.check_again(&app).await {
+        Ok(x) => x,
+        Err(err) => {
+            let response_id = match &request {
+                Ok(JsonRpcRequestEnum::Single(x)) => Some(x.id.clone()),
+                _ => None,
+            };
+
+            return Ok((err.into_message(response_id), None));
+        }
+    };
+
+    // `ws_max_message_bytes` is for every socket. the tier's limit is checked for each message like it is for http bodies
+    if let Err(err) = authorization.check_request_size(payload.len()) {
+        let response_id = match &request {
+            Ok(JsonRpcRequestEnum::Single(x)) => Some(x.id.clone()),
+            _ => None,
+        };
+
+        return Ok((err.into_message(response_id), semaphore));
+    }
+
+    // every request in this message sees the same config, even if it is reloaded in the middle
+    let config = app.config.load_full();
+
+    let (response_id, response) = match request {
+        Ok(JsonRpcRequestEnum::Single(mut json_request)) => {
+            // subscriptions skip proxy_request, so they need to be checked here
+            if let Err(err) = json_request.validate(config.strict_jsonrpc_version) {
+                return Ok((err.into_message(Some(json_request.id)), semaphore));
+            }
 
-    // TODO: handle batched requests
-    let (response_id, response) = match serde_json::from_str::<JsonRpcRequest>(payload) {
-        Ok(json_request) => {
             let response_id = json_request.id.clone();
 
             // TODO: move this to a seperate function so we can use the try operator
@@ -334,6 +449,18 @@ async fn handle_socket_payload(
                 [..]
             {
                 "eth_subscribe" => {
+                    if let Some(err) = Web3ProxyApp::method_access_error(
+                        &config,
+                        &authorization,
+                        &json_request.method,
+                        &json_request.params,
+                    ) {
+                        return Ok((
+                            Web3ProxyError::JsonRpcErrorData(err).into_message(Some(response_id)),
+                            semaphore,
+                        ));
+                    }
+
                     // TODO: how can we subscribe with proxy_mode?
                     match app
                         .eth_subscribe(
@@ -404,13 +531,24 @@ async fn handle_socket_payload(
                     Ok(response.into())
                 }
                 _ => app
-                    .proxy_web3_rpc(authorization.clone(), json_request.into())
+                    .proxy_web3_rpc(&config, authorization.clone(), json_request.into())
                     .await
                     .map(|(_, response, _)| response),
             };
 
             (response_id, response)
         }
+        Ok(batch) => {
+            // subscriptions need a single request. everything else takes the same path as http requests
+            let response_id = JsonRpcId::None.to_raw_value();
+
+            let response = app
+                .proxy_web3_rpc(&config, authorization.clone(), batch)
+                .await
+                .map(|(_, response, _)| response);
+
+            (response_id, response)
+        }
         Err(err) => {
             let id = JsonRpcId::None.to_raw_value();
             (id, Err(err.into()))
@@ -442,6 +580,33 @@ async fn read_web3_socket(
 
     let (close_sender, mut close_receiver) = broadcast::channel(1);
 
+    // admins can see this connection until the loop exits
+    let _connection_guard = match app.ws_connections.try_insert(
+        &authorization,
+        app.ip_limit_key(&authorization.ip),
+        subscriptions.clone(),
+        response_sender.clone(),
+        close_sender.clone(),
+        app.config.load().ws_max_connections_per_ip,
+    ) {
+        Some(x) => x,
+        None => {
+            app.ws_connections_per_ip_rejected
+                .fetch_add(1, Ordering::Relaxed);
+
+            trace!(ip=%authorization.ip, "too many websocket connections from this ip");
+
+            let close_frame = CloseFrame {
+                code: close_code::POLICY,
+                reason: "too many websocket connections from this ip".into(),
+            };
+
+            let _ = response_sender.send(Message::Close(Some(close_frame)));
+
+            return;
+        }
+    };
+
     loop {
         tokio::select! {
             msg = ws_rx.next() => {
@@ -527,6 +692,21 @@ async fn read_web3_socket(
 
                     tokio::spawn(f);
                 } else {
+                    if let Some(Err(err)) = msg {
+                        if is_oversized(err) {
+                            app.ws_oversized_messages.fetch_add(1, Ordering::Relaxed);
+
+                            trace!("closing websocket connection after an oversized message");
+
+                            let close_frame = CloseFrame {
+                                code: close_code::SIZE,
+                                reason: "message too big".into(),
+                            };
+
+                            let _ = response_sender.send(Message::Close(Some(close_frame)));
+                        }
+                    }
+
                     break;
                 }
             }
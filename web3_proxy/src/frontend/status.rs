@@ -3,10 +3,12 @@
 //! For ease of development, users can currently access these endponts.
 //! They will eventually move to another port.
 
+use super::client_ip::ClientIp;
 use super::{ResponseCache, ResponseCacheKey};
 use crate::{
     app::{Web3ProxyApp, APP_USER_AGENT},
     errors::Web3ProxyError,
+    method_route::served_methods,
 };
 use axum::{
     body::{Bytes, Full},
@@ -14,7 +16,6 @@ use axum::{
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use axum_client_ip::InsecureClientIp;
 use axum_macros::debug_handler;
 use hashbrown::HashMap;
 use http::HeaderMap;
@@ -38,7 +39,7 @@ static CONTENT_TYPE_PLAIN: &str = "text/plain";
 #[debug_handler]
 pub async fn debug_request(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    ip: InsecureClientIp,
+    ip: ClientIp,
     headers: HeaderMap,
 ) -> impl IntoResponse {
     let (_, _, status) = _status(app).await;
@@ -159,6 +160,74 @@ async fn _backups_needed(app: Arc<Web3ProxyApp>) -> (StatusCode, &'static str, B
     }
 }
 
+/// Rolling success rate and latency across all requests. This is the public health view.
+#[debug_handler]
+pub async fn slo(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    Extension(cache): Extension<Arc<ResponseCache>>,
+) -> Result<impl IntoResponse, Web3ProxyError> {
+    let (code, content_type, body) = timeout(
+        Duration::from_secs(3),
+        cache.get_with(ResponseCacheKey::Slo, async move { _slo(app).await }),
+    )
+    .await?;
+
+    let x = Response::builder()
+        .status(code)
+        .header("content-type", content_type)
+        .body(Full::from(body))
+        .unwrap();
+
+    Ok(x)
+}
+
+#[inline]
+async fn _slo(app: Arc<Web3ProxyApp>) -> (StatusCode, &'static str, Bytes) {
+    trace!("slo is not cached");
+
+    let body = json!({
+        "chain_id": app.config.load().chain_id,
+        "windows": app.slo_metrics.summary(),
+    });
+
+    let body = body.to_string().into_bytes();
+
+    (StatusCode::OK, CONTENT_TYPE_JSON, Bytes::from(body))
+}
+
+/// The methods this proxy serves, with whether each is cached and its relative cost.
+#[debug_handler]
+pub async fn rpc_methods(Extension(app): Extension<Arc<Web3ProxyApp>>) -> impl IntoResponse {
+    let config = app.config.load();
+
+    let methods = served_methods(&config, app.bundler_4337_rpcs.is_some());
+
+    Json(json!({
+        "chain_id": config.chain_id,
+        "methods": methods,
+    }))
+}
+
+/// The consensus head and every balanced rpc's head. For answering "why did I get stale data?"
+#[debug_handler]
+pub async fn heads(Extension(app): Extension<Arc<Web3ProxyApp>>) -> impl IntoResponse {
+    let ranked_rpcs = app.balanced_rpcs.watch_ranked_rpcs.borrow().clone();
+
+    let consensus = ranked_rpcs.as_ref().map(|x| {
+        json!({
+            "head_block": x.head_block,
+            "num_synced": x.num_synced,
+            "backups_needed": x.backups_needed,
+        })
+    });
+
+    Json(json!({
+        "chain_id": app.config.load().chain_id,
+        "consensus": consensus,
+        "rpcs": app.balanced_rpcs.heads(),
+    }))
+}
+
 /// Very basic status page.
 ///
 /// TODO: replace this with proper stats and monitoring. frontend uses it for their public dashboards though
@@ -199,15 +268,17 @@ async fn _status(app: Arc<Web3ProxyApp>) -> (StatusCode, &'static str, Bytes) {
             MokaCacheSerializer(&app.ip_semaphores),
             MokaCacheSerializer(&app.jsonrpc_response_cache),
             MokaCacheSerializer(&app.rpc_secret_key_cache),
+            MokaCacheSerializer(&app.trace_response_cache),
             MokaCacheSerializer(&app.user_balance_cache.0),
             MokaCacheSerializer(&app.user_semaphores),
         ],
-        "chain_id": app.config.chain_id,
+        "chain_id": app.config.load().chain_id,
         "head_block_num": head_block.as_ref().map(|x| x.number()),
         "head_block_hash": head_block.as_ref().map(|x| x.hash()),
         "hostname": app.hostname,
-        "payment_factory_address": app.config.deposit_factory_contract,
+        "payment_factory_address": app.config.load().deposit_factory_contract,
         "private_rpcs": app.private_rpcs,
+        "tiers": app.tier_metrics.summary(),
         "version": APP_USER_AGENT,
     });
 
@@ -0,0 +1,95 @@
+//! Optional TLS termination for deployments that do not have a reverse proxy in front of them.
+//!
+//! The certificate and key are read again on SIGHUP so that renewed certificates are used without a restart.
+use crate::app::Web3ProxyApp;
+use crate::errors::Web3ProxyResult;
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use listenfd::ListenFd;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Serve https instead of http. Everything else matches `frontend::serve`
+pub async fn serve(
+    app: Arc<Web3ProxyApp>,
+    make_service: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    cert_path: String,
+    key_path: String,
+    mut shutdown_receiver: broadcast::Receiver<()>,
+) -> Web3ProxyResult<()> {
+    // fail on startup instead of on the first connection if the files are missing or invalid
+    let rustls_config = RustlsConfig::from_pem_file(&cert_path, &key_path).await?;
+
+    let listener = if let Some(listener) = ListenFd::from_env().take_tcp_listener(0)? {
+        // use systemd socket magic for no downtime deploys
+        info!("listening with fd at {}", listener.local_addr()?);
+
+        listener
+    } else {
+        let addr = SocketAddr::from(([0, 0, 0, 0], app.frontend_port.load(Ordering::Relaxed)));
+
+        TcpListener::bind(addr)?
+    };
+
+    let port = listener.local_addr()?.port();
+    info!("listening with tls on port {}", port);
+
+    app.frontend_port.store(port, Ordering::Relaxed);
+
+    let reload_handle = tokio::spawn(reload_on_sighup(rustls_config.clone(), cert_path, key_path));
+
+    let handle = Handle::new();
+
+    {
+        let handle = handle.clone();
+
+        tokio::spawn(async move {
+            let _ = shutdown_receiver.recv().await;
+
+            handle.graceful_shutdown(None);
+        });
+    }
+
+    let mut server = axum_server::from_tcp_rustls(listener, rustls_config).handle(handle);
+
+    // TODO: get settings from app config
+    server
+        .http_builder()
+        .http2()
+        .keep_alive_timeout(Duration::from_secs(70));
+
+    let x = server.serve(make_service).await.map_err(Into::into);
+
+    reload_handle.abort();
+
+    x
+}
+
+/// Replace the certificate whenever the process gets SIGHUP.
+/// If the new files are bad, the old certificate keeps being used.
+async fn reload_on_sighup(
+    rustls_config: RustlsConfig,
+    cert_path: String,
+    key_path: String,
+) -> Web3ProxyResult<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    while hangup.recv().await.is_some() {
+        match rustls_config
+            .reload_from_pem_file(&cert_path, &key_path)
+            .await
+        {
+            Ok(()) => info!(%cert_path, "reloaded tls certificate"),
+            Err(err) => warn!(?err, %cert_path, "failed reloading tls certificate"),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,281 @@
+//! Link additional addresses to a user's account.
+use super::authentication::PostLogin;
+use crate::app::Web3ProxyApp;
+use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResponse};
+use crate::frontend::authorization::login_is_authorized;
+use crate::frontend::client_ip::ClientIp;
+use crate::user_token::UserBearerToken;
+use axum::{
+    extract::Path,
+    headers::{authorization::Bearer, Authorization},
+    response::IntoResponse,
+    Extension, Json, TypedHeader,
+};
+use axum_macros::debug_handler;
+use chrono::{TimeZone, Utc};
+use entities::{pending_login, user, user_address};
+use ethers::{prelude::Address, types::Bytes};
+use hashbrown::HashMap;
+use http::StatusCode;
+use migration::sea_orm::prelude::Uuid;
+use migration::sea_orm::{
+    self, ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter,
+};
+use serde_json::json;
+use siwe::{Message, VerificationOpts};
+use std::ops::Add;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use time_03::{Duration, OffsetDateTime};
+use tracing::{error, info};
+use ulid::Ulid;
+
+/// The statement in the siwe message that links `new_address` to the account of `user`.
+/// This ties the pending login to the account, so a normal login message cannot be used to link an address.
+fn link_statement(user: &user::Model) -> String {
+    format!(
+        "🦙🦙🦙🦙🦙 Link this address to the account of {:?}",
+        Address::from_slice(&user.address)
+    )
+}
+
+/// `GET /user/addresses` -- Use a bearer token to get the addresses linked to the user's account.
+#[debug_handler]
+pub async fn user_addresses_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Web3ProxyResponse {
+    let user = app.bearer_is_authorized(bearer).await?;
+
+    let db_replica = app.db_replica()?;
+
+    let linked_addresses = user_address::Entity::find()
+        .filter(user_address::Column::UserId.eq(user.id))
+        .all(db_replica.as_ref())
+        .await
+        .web3_context("failed loading user's linked addresses")?;
+
+    let response_json = json!({
+        "user_id": user.id,
+        "address": Address::from_slice(&user.address),
+        "linked_addresses": linked_addresses,
+    });
+
+    Ok(Json(response_json).into_response())
+}
+
+/// `GET /user/addresses/link/:new_address` or `GET /user/addresses/link/:new_address/:message_eip` -- Start linking another address to the account.
+///
+/// The returned message must be signed by `new_address` and POSTed to `/user/addresses/link` with the same bearer token.
+/// After that, logging in with `new_address` logs in to this account.
+///
+/// `message_eip`s accepted are the same as for `GET /user/login/:user_address`.
+#[debug_handler]
+pub async fn user_address_link_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    ClientIp(ip): ClientIp,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(mut params): Path<HashMap<String, String>>,
+) -> Web3ProxyResponse {
+    login_is_authorized(&app, ip).await?;
+
+    let user = app.bearer_is_authorized(bearer).await?;
+
+    let new_address: Address = params
+        .remove("new_address")
+        .ok_or(Web3ProxyError::BadRouting)?
+        .parse()
+        .or(Err(Web3ProxyError::ParseAddressError))?;
+
+    // TODO: how many seconds? get from config?
+    let expire_seconds: usize = 20 * 60;
+
+    let nonce = Ulid::new();
+
+    let now = OffsetDateTime::now_utc();
+
+    let expiration_time = now.add(Duration::new(expire_seconds as i64, 0));
+
+    let config = app.config.load_full();
+
+    let domain = config.login_domain.as_deref().unwrap_or("llamanodes.com");
+
+    let message_domain = domain.parse().unwrap();
+    let message_uri = format!("https://{}/", domain).parse().unwrap();
+
+    let message = Message {
+        domain: message_domain,
+        // the new address needs to sign the message
+        address: new_address.to_fixed_bytes(),
+        statement: Some(link_statement(&user)),
+        uri: message_uri,
+        version: siwe::Version::V1,
+        chain_id: app.config.load().chain_id,
+        expiration_time: Some(expiration_time.into()),
+        issued_at: now.into(),
+        nonce: nonce.to_string(),
+        not_before: None,
+        request_id: None,
+        resources: vec![],
+    };
+
+    // we add 1 to expire_seconds just to be sure the database has the key for the full expiration_time
+    let expires_at = Utc
+        .timestamp_opt(expiration_time.unix_timestamp() + 1, 0)
+        .unwrap();
+
+    let user_pending_login = pending_login::ActiveModel {
+        id: sea_orm::NotSet,
+        nonce: sea_orm::Set(nonce.into()),
+        message: sea_orm::Set(message.to_string()),
+        expires_at: sea_orm::Set(expires_at),
+        imitating_user: sea_orm::Set(None),
+    };
+
+    let db_conn = app.db_conn()?;
+
+    user_pending_login
+        .save(db_conn)
+        .await
+        .web3_context("saving pending_login for linking an address")?;
+
+    app.pending_logins_created.fetch_add(1, Ordering::Relaxed);
+
+    // there are multiple ways to sign messages and not all wallets support them
+    let message_eip = params
+        .remove("message_eip")
+        .unwrap_or_else(|| "eip4361".to_string());
+
+    let message: String = match message_eip.as_str() {
+        "eip191_bytes" => Bytes::from(message.eip191_bytes().unwrap()).to_string(),
+        "eip191_hash" => Bytes::from(&message.eip191_hash().unwrap()).to_string(),
+        "eip4361" => message.to_string(),
+        _ => {
+            return Err(Web3ProxyError::InvalidEip);
+        }
+    };
+
+    Ok(message.into_response())
+}
+
+/// `POST /user/addresses/link` -- Link another address to the account by posting a "siwe" message signed by that address.
+#[debug_handler]
+pub async fn user_address_link_post(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    ClientIp(ip): ClientIp,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<PostLogin>,
+) -> Web3ProxyResponse {
+    login_is_authorized(&app, ip).await?;
+
+    let user = app.bearer_is_authorized(bearer).await?;
+
+    // TODO: this seems too verbose. how can we simply convert a String into a [u8; 65]
+    let their_sig_bytes = Bytes::from_str(&payload.sig).web3_context("parsing sig")?;
+    if their_sig_bytes.len() != 65 {
+        return Err(Web3ProxyError::InvalidSignatureLength);
+    }
+    let mut their_sig: [u8; 65] = [0; 65];
+    for x in 0..65 {
+        their_sig[x] = their_sig_bytes[x]
+    }
+
+    // we can't trust that they didn't tamper with the message in some way. like some clients return it hex encoded
+    let their_msg: Message = if payload.msg.starts_with("0x") {
+        let their_msg_bytes =
+            Bytes::from_str(&payload.msg).web3_context("parsing payload message")?;
+
+        String::from_utf8_lossy(their_msg_bytes.as_ref())
+            .parse::<siwe::Message>()
+            .web3_context("parsing hex string message")?
+    } else {
+        payload
+            .msg
+            .parse::<siwe::Message>()
+            .web3_context("parsing string message")?
+    };
+
+    // the only part of the message we will trust is their nonce
+    let login_nonce = UserBearerToken::from_str(&their_msg.nonce)?;
+
+    let db_replica = app.db_replica()?;
+
+    let user_pending_login = pending_login::Entity::find()
+        .filter(pending_login::Column::Nonce.eq(Uuid::from(login_nonce)))
+        .one(db_replica.as_ref())
+        .await
+        .web3_context("database error while finding pending_login")?
+        .web3_context("login nonce not found")?;
+
+    let our_msg: siwe::Message = user_pending_login
+        .message
+        .parse()
+        .web3_context("parsing siwe message")?;
+
+    // a message for logging in (or for linking to someone else's account) does not link anything
+    if our_msg.statement.as_ref() != Some(&link_statement(&user)) {
+        return Err(Web3ProxyError::AccessDenied(
+            "this message was not created for linking an address to your account".into(),
+        ));
+    }
+
+    // mostly default options are fine. the message includes timestamp and domain and nonce
+    let verify_config = VerificationOpts {
+        rpc_provider: Some(app.internal_provider().clone()),
+        ..Default::default()
+    };
+
+    our_msg
+        .verify(&their_sig, &verify_config)
+        .await
+        .web3_context("verifying signature against our local message")?;
+
+    let new_address = our_msg.address;
+
+    // an address that has its own account cannot also be linked to another account
+    if user::Entity::find()
+        .filter(user::Column::Address.eq(new_address.as_ref()))
+        .one(db_replica.as_ref())
+        .await?
+        .is_some()
+    {
+        return Err(Web3ProxyError::BadRequest(
+            "this address already has its own account".into(),
+        ));
+    }
+
+    if user_address::Entity::find()
+        .filter(user_address::Column::Address.eq(new_address.as_ref()))
+        .one(db_replica.as_ref())
+        .await?
+        .is_some()
+    {
+        return Err(Web3ProxyError::BadRequest(
+            "this address is already linked to an account".into(),
+        ));
+    }
+
+    let db_conn = app.db_conn()?;
+
+    let linked_address = user_address::ActiveModel {
+        user_id: sea_orm::Set(user.id),
+        address: sea_orm::Set(new_address.to_vec()),
+        ..Default::default()
+    };
+
+    let linked_address = linked_address
+        .insert(db_conn)
+        .await
+        .web3_context("saving linked address")?;
+
+    info!(user_id=%user.id, address=?Address::from(new_address), "linked address");
+
+    if let Err(err) = user_pending_login.into_active_model().delete(db_conn).await {
+        error!("Failed to delete nonce:{}: {}", login_nonce, err);
+    }
+
+    let response = (StatusCode::CREATED, Json(linked_address)).into_response();
+
+    Ok(response)
+}
@@ -1,42 +1,77 @@
 //! Handle registration, logins, and managing account data.
 use crate::app::Web3ProxyApp;
-use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResponse};
-use crate::frontend::authorization::{login_is_authorized, RpcSecretKey};
+use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResponse, Web3ProxyResult};
+use crate::frontend::authorization::{login_is_authorized, login_tarpit, RpcSecretKey};
+use crate::frontend::client_ip::ClientIp;
+use crate::frontend::users::rpc_keys::UserRpcKey;
 use crate::user_token::UserBearerToken;
+use crate::webhooks::WebhookEvent;
 use axum::{
     extract::{Path, Query},
     headers::{authorization::Bearer, Authorization},
     response::IntoResponse,
     Extension, Json, TypedHeader,
 };
-use axum_client_ip::InsecureClientIp;
 use axum_macros::debug_handler;
 use chrono::{TimeZone, Utc};
-use entities::{self, login, pending_login, referee, referrer, rpc_key, user};
-use ethers::{prelude::Address, types::Bytes};
+use entities::{self, login, pending_login, referee, referrer, rpc_key, user, user_address};
+use ethers::{
+    prelude::Address,
+    types::{Bytes, Signature},
+};
 use hashbrown::HashMap;
 use http::StatusCode;
 use migration::sea_orm::prelude::{Decimal, Uuid};
 use migration::sea_orm::{
-    self, ActiveModelTrait, ColumnTrait, DatabaseTransaction, EntityTrait, IntoActiveModel,
-    QueryFilter, TransactionTrait,
+    self, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseTransaction, DbErr, EntityTrait,
+    IntoActiveModel, QueryFilter, QuerySelect, TransactionTrait,
 };
+use redis_rate_limiter::redis;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use siwe::{Message, VerificationOpts};
 use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::ops::Add;
 use std::str::FromStr;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use time_03::{Duration, OffsetDateTime};
 use tracing::{error, trace, warn};
 use ulid::Ulid;
 
+/// Logins from an ip the account hasn't used in this long send an `unusual_login` webhook
+const LOGIN_IPS_TTL_SECONDS: usize = 90 * 86_400;
+
 /// Query params for our `post_login` handler.
 #[derive(Debug, Deserialize)]
 pub struct PostLoginQuery {
     /// While we are in alpha/beta, we require users to supply an invite code.
     /// The invite code (if any) is set in the application's config.
     pub invite_code: Option<String>,
+    /// Only return the bearer token and user id. The keys can be fetched from `/user/keys`.
+    #[serde(default)]
+    pub minimal: bool,
+}
+
+/// How `get_login` returns the message
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginMessageFormat {
+    /// only the message
+    #[default]
+    Text,
+    /// `{"message": ..., "nonce": ...}` so the nonce doesn't have to be parsed out of the message
+    Json,
+}
+
+/// Query params for our `get_login` handler.
+#[derive(Debug, Deserialize)]
+pub struct GetLoginQuery {
+    /// The chain id in the siwe message. Defaults to the app's chain id.
+    pub chain_id: Option<u64>,
+    #[serde(default)]
+    pub format: LoginMessageFormat,
 }
 
 /// JSON body to our `post_login` handler.
@@ -52,8 +87,24 @@ pub struct PostLogin {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LoginPostResponse {
     pub bearer_token: UserBearerToken,
-    pub rpc_keys: BTreeMap<u64, rpc_key::Model>,
+    pub rpc_keys: BTreeMap<u64, UserRpcKey>,
     pub user: user::Model,
+    /// deposits minus paid credits spent. The same as `balance` at `GET /user/balance`
+    pub available_balance: Decimal,
+    pub total_deposits: Decimal,
+    /// The title of the tier whose limits are enforced right now.
+    /// This is the downgrade tier if the user is in a premium tier without an active premium balance.
+    pub user_tier: String,
+}
+
+/// `LoginPostResponse` without the user's keys or profile.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoginPostMinimalResponse {
+    pub bearer_token: UserBearerToken,
+    pub user_id: u64,
+    /// Only set for new users. This is the one time that their first key's secret is shown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_rpc_key: Option<UserRpcKey>,
 }
 
 /// `GET /user/login/:user_address` or `GET /user/login/:user_address/:message_eip` -- Start the "Sign In with Ethereum" (siwe) login flow.
@@ -75,12 +126,23 @@ pub struct LoginPostResponse {
 #[debug_handler]
 pub async fn user_login_get(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
     // TODO: what does axum's error handling look like if the path fails to parse?
     Path(mut params): Path<HashMap<String, String>>,
+    Query(query): Query<GetLoginQuery>,
 ) -> Web3ProxyResponse {
     login_is_authorized(&app, ip).await?;
 
+    login_tarpit(&app, ip).await;
+
+    let chain_id = query.chain_id.unwrap_or(app.config.load().chain_id);
+
+    if !login_chain_id_allowed(&app, chain_id) {
+        return Err(Web3ProxyError::BadRequest(
+            format!("logins are not allowed on chain {}", chain_id).into(),
+        ));
+    }
+
     // create a message and save it in redis
     // TODO: how many seconds? get from config?
     let expire_seconds: usize = 20 * 60;
@@ -100,6 +162,7 @@ pub async fn user_login_get(
 
     let domain = app
         .config
+        .load()
         .login_domain
         .clone()
         .unwrap_or_else(|| "llamanodes.com".to_string());
@@ -107,41 +170,63 @@ pub async fn user_login_get(
     let message_domain = domain.parse().unwrap();
     let message_uri = format!("https://{}/", domain).parse().unwrap();
 
+    // if we have terms of service, link to them in the resources and have the user accept them in the statement
+    let (statement, resources) = match app.config.load().tos_uri.as_ref() {
+        Some(tos_uri) => {
+            let tos_resource = tos_uri
+                .parse()
+                .map_err(|err| anyhow::anyhow!("invalid tos_uri in config: {:?}", err))?;
+
+            let statement = format!("🦙🦙🦙🦙🦙 I accept the Terms of Service: {}", tos_uri);
+
+            (statement, vec![tos_resource])
+        }
+        None => ("🦙🦙🦙🦙🦙".to_string(), vec![]),
+    };
+
     // TODO: get most of these from the app config
     let message = Message {
         domain: message_domain,
         address: user_address.to_fixed_bytes(),
         // TODO: config for statement
-        statement: Some("🦙🦙🦙🦙🦙".to_string()),
+        statement: Some(statement),
         uri: message_uri,
         version: siwe::Version::V1,
-        chain_id: app.config.chain_id,
+        chain_id,
         expiration_time: Some(expiration_time.into()),
         issued_at: now.into(),
         nonce: nonce.to_string(),
         not_before: None,
         request_id: None,
-        resources: vec![],
+        resources,
     };
 
     let db_conn = app.db_conn()?;
 
     // delete any expired logins
-    if let Err(err) = login::Entity::delete_many()
+    match login::Entity::delete_many()
         .filter(login::Column::ExpiresAt.lte(now))
         .exec(db_conn)
         .await
     {
-        warn!(?err, "expired_logins");
+        Ok(x) => {
+            app.expired_logins_cleared
+                .fetch_add(x.rows_affected, Ordering::Relaxed);
+        }
+        Err(err) => warn!(?err, "expired_logins"),
     };
 
     // delete any expired pending logins
-    if let Err(err) = pending_login::Entity::delete_many()
+    match pending_login::Entity::delete_many()
         .filter(pending_login::Column::ExpiresAt.lte(now))
         .exec(db_conn)
         .await
     {
-        warn!(?err, "expired_pending_logins");
+        Ok(x) => {
+            app.expired_logins_cleared
+                .fetch_add(x.rows_affected, Ordering::Relaxed);
+        }
+        Err(err) => warn!(?err, "expired_pending_logins"),
     };
 
     // we add 1 to expire_seconds just to be sure the database has the key for the full expiration_time
@@ -150,6 +235,7 @@ pub async fn user_login_get(
         .unwrap();
 
     // we do not store a maximum number of attempted logins. anyone can request so we don't want to allow DOS attacks
+    // ips that request a lot are slowed down by `login_tarpit` instead
     // add a row to the database for this user
     let user_pending_login = pending_login::ActiveModel {
         id: sea_orm::NotSet,
@@ -164,6 +250,8 @@ pub async fn user_login_get(
         .await
         .web3_context("saving user's pending_login")?;
 
+    app.pending_logins_created.fetch_add(1, Ordering::Relaxed);
+
     // there are multiple ways to sign messages and not all wallets support them
     // TODO: default message eip from config?
     let message_eip = params
@@ -179,14 +267,43 @@ pub async fn user_login_get(
         }
     };
 
-    Ok(message.into_response())
+    match query.format {
+        LoginMessageFormat::Text => Ok(message.into_response()),
+        LoginMessageFormat::Json => Ok(Json(json!({
+            "message": message,
+            "nonce": nonce.to_string(),
+        }))
+        .into_response()),
+    }
+}
+
+/// Find the user that owns this address.
+/// This is either the address the user registered with, or an address they linked to their account.
+pub async fn find_user_by_address<C: ConnectionTrait>(
+    db: &C,
+    address: &[u8],
+) -> Result<Option<user::Model>, DbErr> {
+    let user = user::Entity::find()
+        .filter(user::Column::Address.eq(address))
+        .one(db)
+        .await?;
+
+    if user.is_some() {
+        return Ok(user);
+    }
+
+    user::Entity::find()
+        .inner_join(user_address::Entity)
+        .filter(user_address::Column::Address.eq(address))
+        .one(db)
+        .await
 }
 
 /// you MUST commit the `txn` after calling this function!
 pub async fn register_new_user(
     txn: &DatabaseTransaction,
     address: Address,
-) -> anyhow::Result<(user::Model, rpc_key::Model)> {
+) -> anyhow::Result<(user::Model, UserRpcKey)> {
     // the only thing we need from them is an address
     // everything else is optional
     // TODO: different invite codes should allow different levels
@@ -204,7 +321,8 @@ pub async fn register_new_user(
 
     let user_rpc_key = rpc_key::ActiveModel {
         user_id: sea_orm::Set(new_user.id),
-        secret_key: sea_orm::Set(rpc_secret_key.into()),
+        secret_key: sea_orm::Set(rpc_secret_key.hashed()?),
+        secret_key_hashed: sea_orm::Set(true),
         description: sea_orm::Set(None),
         ..Default::default()
     };
@@ -214,16 +332,81 @@ pub async fn register_new_user(
         .await
         .web3_context("Failed saving new user key")?;
 
+    // only the hash is stored. this is the one time that the raw key is shown to the user
+    let user_rpc_key = UserRpcKey::new(user_rpc_key, Some(rpc_secret_key));
+
     Ok((new_user, user_rpc_key))
 }
 
+/// Login messages can be for the app's chain or for any of `login_chain_ids`
+fn login_chain_id_allowed(app: &Web3ProxyApp, chain_id: u64) -> bool {
+    chain_id == app.config.load().chain_id || app.config.load().login_chain_ids.contains(&chain_id)
+}
+
+/// Wallets sign messages in different ways. Try eip4361, then eip191, then a `personal_sign` of the exact message we stored.
+async fn verify_login_signature(
+    our_msg: &Message,
+    our_msg_str: &str,
+    their_sig: &[u8; 65],
+    verify_config: &VerificationOpts,
+) -> Web3ProxyResult<()> {
+    let err_4361 = match our_msg.verify(their_sig, verify_config).await {
+        Ok(_) => return Ok(()),
+        Err(err) => err,
+    };
+
+    // the fallbacks only check the signature. the time checks from `verify` still need to happen
+    if !our_msg.valid_now() {
+        return Err(err_4361.into());
+    }
+
+    let err_191 = match our_msg.verify_eip191(their_sig) {
+        Ok(_) => return Ok(()),
+        Err(err) => err,
+    };
+
+    let err_personal_sign =
+        match verify_personal_sign(our_msg_str, their_sig, our_msg.address.into()) {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+
+    Err(Web3ProxyError::EipVerificationFailed(
+        Box::new(err_4361.into()),
+        Box::new(err_191.into()),
+        Box::new(err_personal_sign),
+    ))
+}
+
+/// Recover the signer from the `personal_sign` hash of `msg` and check that it is `address`
+fn verify_personal_sign(msg: &str, sig: &[u8; 65], address: Address) -> Web3ProxyResult<()> {
+    let sig = Signature::try_from(&sig[..])
+        .map_err(|err| Web3ProxyError::BadRequest(format!("invalid signature: {}", err).into()))?;
+
+    let signer = sig.recover(msg).map_err(|err| {
+        Web3ProxyError::BadRequest(format!("unable to recover signer: {}", err).into())
+    })?;
+
+    if signer != address {
+        return Err(Web3ProxyError::AccessDenied(
+            format!(
+                "personal_sign signer {:?} does not match {:?}",
+                signer, address
+            )
+            .into(),
+        ));
+    }
+
+    Ok(())
+}
+
 /// `POST /user/login` - Register or login by posting a signed "siwe" message.
 /// It is recommended to save the returned bearer token in a cookie.
 /// The bearer token can be used to authenticate other requests, such as getting the user's stats or modifying the user's profile.
 #[debug_handler]
 pub async fn user_login_post(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
     Query(query): Query<PostLoginQuery>,
     Json(payload): Json<PostLogin>,
 ) -> Web3ProxyResponse {
@@ -281,17 +464,29 @@ pub async fn user_login_post(
         ..Default::default()
     };
 
-    // Check with both verify and verify_eip191
-    our_msg
-        .verify(&their_sig, &verify_config)
-        .await
-        .web3_context("verifying signature against our local message")?;
+    // the config might have changed since this message was created
+    if !login_chain_id_allowed(&app, our_msg.chain_id) {
+        return Err(Web3ProxyError::AccessDenied(
+            format!("logins are not allowed on chain {}", our_msg.chain_id).into(),
+        ));
+    }
+
+    if let Err(err) = verify_login_signature(
+        &our_msg,
+        &user_pending_login.message,
+        &their_sig,
+        &verify_config,
+    )
+    .await
+    {
+        app.failed_login_verifications
+            .fetch_add(1, Ordering::Relaxed);
+
+        return Err(err);
+    }
 
     // TODO: limit columns or load whole user?
-    let caller = user::Entity::find()
-        .filter(user::Column::Address.eq(our_msg.address.as_ref()))
-        .one(db_replica.as_ref())
-        .await?;
+    let caller = find_user_by_address(db_replica.as_ref(), our_msg.address.as_ref()).await?;
 
     let db_conn = app.db_conn()?;
 
@@ -304,7 +499,7 @@ pub async fn user_login_post(
             // Do nothing if app config is none (then there is basically no authentication invitation, and the user can process with a free tier ...
 
             // Prematurely return if there is a wrong invite code
-            if let Some(invite_code) = &app.config.invite_code {
+            if let Some(invite_code) = &app.config.load().invite_code {
                 if query.invite_code.as_ref() != Some(invite_code) {
                     return Err(Web3ProxyError::InvalidInviteCode);
                 }
@@ -380,14 +575,38 @@ pub async fn user_login_post(
             // the user is already registered
             let user_rpc_keys = rpc_key::Entity::find()
                 .filter(rpc_key::Column::UserId.eq(caller.id))
-                .all(db_conn)
+                .all(db_replica.as_ref())
                 .await
-                .web3_context("failed loading user's key")?;
+                .web3_context("failed loading user's key")?
+                .into_iter()
+                .map(|x| UserRpcKey::new(x, None))
+                .collect::<Vec<_>>();
 
             (caller, user_rpc_keys, StatusCode::OK)
         }
     };
 
+    // if the message they signed linked to our terms of service, they have accepted them
+    let config = app.config.load_full();
+
+    let caller = match (config.tos_uri.as_ref(), config.tos_version.as_ref()) {
+        (Some(tos_uri), Some(tos_version))
+            if caller.tos_version.as_ref() != Some(tos_version)
+                && our_msg.resources.iter().any(|x| x.as_str() == tos_uri) =>
+        {
+            let mut caller = caller.into_active_model();
+
+            caller.tos_version = sea_orm::Set(Some(tos_version.clone()));
+            caller.tos_accepted_at = sea_orm::Set(Some(Utc::now()));
+
+            caller
+                .update(db_conn)
+                .await
+                .web3_context("saving user's terms of service acceptance")?
+        }
+        _ => caller,
+    };
+
     // create a bearer token for the user.
     let user_bearer_token = UserBearerToken::default();
 
@@ -415,9 +634,45 @@ pub async fn user_login_post(
         error!("Failed to delete nonce:{}: {}", login_nonce, err);
     }
 
+    match login_ip_is_new(&app, caller.id, ip).await {
+        Ok(true) => app.notify_webhook(caller.id, WebhookEvent::UnusualLogin { ip }),
+        Ok(false) => {}
+        Err(err) => warn!(?err, user_id = caller.id, "unable to check login ip"),
+    }
+
+    if query.minimal {
+        let new_rpc_key = if status_code == StatusCode::CREATED {
+            user_rpc_keys.into_iter().next()
+        } else {
+            None
+        };
+
+        let response_json = LoginPostMinimalResponse {
+            bearer_token: user_bearer_token,
+            user_id: caller.id,
+            new_rpc_key,
+        };
+
+        return Ok((status_code, Json(response_json)).into_response());
+    }
+
+    // include the balance and tier so that clients don't need another request to show the account state
+    let (user_tier, user_balance, _) = app
+        .effective_user_tier(db_conn, caller.id, caller.user_tier_id)
+        .await?;
+
+    let (available_balance, total_deposits) = {
+        let user_balance = user_balance.read().await;
+
+        (user_balance.remaining(), user_balance.total_deposits())
+    };
+
     // json response with everything in it
-    // we could return just the bearer token, but I think they will always request api keys and the user profile
+    // most clients will request api keys and the user profile next. clients that don't can use `minimal`
     let response_json = LoginPostResponse {
+        available_balance,
+        total_deposits,
+        user_tier: user_tier.title,
         rpc_keys: user_rpc_keys
             .into_iter()
             .map(|user_rpc_key| (user_rpc_key.id, user_rpc_key))
@@ -431,6 +686,26 @@ pub async fn user_login_post(
     Ok(response)
 }
 
+/// True if the account has logged in before, but never from this ip.
+/// The ips are kept in redis and forgotten if the account doesn't log in for `LOGIN_IPS_TTL_SECONDS`.
+async fn login_ip_is_new(app: &Web3ProxyApp, user_id: u64, ip: IpAddr) -> Web3ProxyResult<bool> {
+    let mut redis_conn = app.redis_conn().await?;
+
+    let redis_key = format!("login_ips:{}", user_id);
+
+    let (added, known): (u64, u64) = redis::pipe()
+        .atomic()
+        .sadd(&redis_key, ip.to_string())
+        .scard(&redis_key)
+        .expire(&redis_key, LOGIN_IPS_TTL_SECONDS)
+        .ignore()
+        .query_async(&mut redis_conn)
+        .await?;
+
+    // the first login is not unusual
+    Ok(added == 1 && known > 1)
+}
+
 /// `POST /user/logout` - Forget the bearer token in the `Authentication` header.
 #[debug_handler]
 pub async fn user_logout_post(
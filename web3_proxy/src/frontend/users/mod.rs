@@ -1,4 +1,5 @@
 //! Handle registration, logins, and managing account data.
+pub mod addresses;
 pub mod authentication;
 pub mod payment;
 pub mod payment_stripe;
@@ -6,9 +7,13 @@ pub mod referral;
 pub mod rpc_keys;
 pub mod stats;
 pub mod subuser;
+pub mod webhook;
 
+use super::client_ip::ClientIp;
 use crate::app::Web3ProxyApp;
+use crate::caches::RegisteredUserRateLimitKey;
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResponse};
+use crate::quota::RequestQuota;
 use axum::{
     headers::{authorization::Bearer, Authorization},
     response::IntoResponse,
@@ -16,14 +21,26 @@ use axum::{
 };
 use axum_macros::debug_handler;
 use check_if_email_exists::{check_email, CheckEmailInput, Reachable};
-use entities::{self, referee, referrer, user};
-use migration::sea_orm::{self, ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter};
+use entities::{self, referee, referrer, rpc_key, user, user_tier};
+use ethers::types::Address;
+use migration::sea_orm::{
+    self, ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QuerySelect,
+};
 use serde::Deserialize;
+use serde_json::json;
+use std::num::NonZeroU64;
 use std::sync::Arc;
 
 /// `GET /user` -- Use a bearer token to get the user's profile.
 ///
+/// This is the "who am i" call for a frontend to make on page load.
+///
+/// - the user's id and address
+/// - the title of the user's tier
+/// - the user's balance
 /// - the email address of a user if they opted in to get contacted via email
+/// - `email_verified`, which is always false for now. Confirmation emails are not sent yet, so no email is verified
+/// - the number of rpc keys the user has. The keys themselves are at `GET /user/keys`
 ///
 /// TODO: this will change as we add better support for secondary users.
 #[debug_handler]
@@ -33,7 +50,158 @@ pub async fn user_get(
 ) -> Web3ProxyResponse {
     let user = app.bearer_is_authorized(bearer_token).await?;
 
-    Ok(Json(user).into_response())
+    let db_replica = app.db_replica()?;
+
+    // get the tier and count the keys with one query
+    let (tier, rpc_key_count) = user::Entity::find_by_id(user.id)
+        .select_only()
+        .column_as(user_tier::Column::Title, "tier")
+        .column_as(rpc_key::Column::Id.count(), "rpc_key_count")
+        .inner_join(user_tier::Entity)
+        .left_join(rpc_key::Entity)
+        .group_by(user_tier::Column::Title)
+        .into_tuple::<(String, i64)>()
+        .one(db_replica.as_ref())
+        .await
+        .web3_context("fetching user tier and key count")?
+        .web3_context("user not found")?;
+
+    // the balance is usually already cached
+    let balance = app
+        .user_balance_cache
+        .get_or_insert(db_replica.as_ref(), user.id)
+        .await?;
+    let balance = balance.read().await.remaining();
+
+    // saving an email does not verify it
+    // TODO: store a verified flag once confirmation emails are sent
+    let email_verified = false;
+
+    let response_json = json!({
+        "address": Address::from_slice(&user.address),
+        "balance": balance,
+        "description": user.description,
+        "email": user.email,
+        "email_verified": email_verified,
+        "id": user.id,
+        "rpc_key_count": rpc_key_count,
+        "tier": tier,
+        "user_tier_id": user.user_tier_id,
+    });
+
+    Ok(Json(response_json).into_response())
+}
+
+/// `GET /user/limits` -- Use a bearer token to get the rate limits that currently apply to the user.
+///
+/// Premium tiers are downgraded when the user's balance runs out, so this is the tier that is actually enforced.
+/// Rate limits are tracked per user and ip, so the remaining budget is for the ip making this request.
+/// Limits that are null are unlimited.
+#[debug_handler]
+pub async fn user_limits_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    ClientIp(ip): ClientIp,
+    TypedHeader(Authorization(bearer_token)): TypedHeader<Authorization<Bearer>>,
+) -> Web3ProxyResponse {
+    let user = app.bearer_is_authorized(bearer_token).await?;
+
+    let db_replica = app.db_replica()?;
+
+    let (user_tier_model, _, paid_credits_used) = app
+        .effective_user_tier(db_replica.as_ref(), user.id, user.user_tier_id)
+        .await?;
+
+    let max_requests_per_period = user_tier_model.max_requests_per_period;
+    let max_concurrent_requests = user_tier_model.max_concurrent_requests;
+
+    let (period_seconds, requests_used) = match &app.frontend_registered_user_rate_limiter {
+        Some(rate_limiter) => {
+            let requests_used = rate_limiter
+                .peek(RegisteredUserRateLimitKey(user.id, ip))
+                .await;
+
+            (Some(rate_limiter.period()), Some(requests_used))
+        }
+        None => (None, None),
+    };
+
+    let requests_remaining = max_requests_per_period
+        .zip(requests_used)
+        .map(|(max, used)| max.saturating_sub(used));
+
+    // the semaphore is only created once the user sends a request
+    let concurrent_requests_available = max_concurrent_requests.map(|max| {
+        NonZeroU64::try_from(user.id)
+            .ok()
+            .and_then(|user_id| app.user_semaphores.get(&(user_id, ip)))
+            .map(|semaphore| semaphore.available_permits() as u32)
+            .unwrap_or(max)
+    });
+
+    let response_json = json!({
+        "concurrent_requests_available": concurrent_requests_available,
+        "ip": ip,
+        "max_concurrent_requests": max_concurrent_requests,
+        "max_requests_per_period": max_requests_per_period,
+        "paid_credits_used": paid_credits_used,
+        "period_seconds": period_seconds,
+        "requests_remaining": requests_remaining,
+        "requests_used": requests_used,
+        "tier": user_tier_model.title,
+        "user_tier_id": user_tier_model.id,
+    });
+
+    Ok(Json(response_json).into_response())
+}
+
+/// `GET /user/quota` -- Use a bearer token to get the request quotas on the user's keys.
+///
+/// Quotas are hard caps on requests per day or month. They are separate from the rate limits at `GET /user/limits`.
+/// Keys without a quota are not included.
+#[debug_handler]
+pub async fn user_quota_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer_token)): TypedHeader<Authorization<Bearer>>,
+) -> Web3ProxyResponse {
+    let user = app.bearer_is_authorized(bearer_token).await?;
+
+    let db_replica = app.db_replica()?;
+
+    let rpc_keys = rpc_key::Entity::find()
+        .filter(rpc_key::Column::UserId.eq(user.id))
+        .filter(rpc_key::Column::QuotaRequests.is_not_null())
+        .all(db_replica.as_ref())
+        .await
+        .web3_context("failed loading user's keys")?;
+
+    let mut quotas = Vec::with_capacity(rpc_keys.len());
+
+    for rpc_key in rpc_keys {
+        let quota =
+            match RequestQuota::try_from_rpc_key(rpc_key.quota_requests, &rpc_key.quota_window)? {
+                Some(x) => x,
+                None => continue,
+            };
+
+        let (used, reset_at) = app.quota_used(rpc_key.id, &quota).await?;
+
+        quotas.push(json!({
+            "rpc_key_id": rpc_key.id,
+            "description": rpc_key.description,
+            "max_requests": quota.max_requests,
+            "remaining": quota.max_requests.saturating_sub(used),
+            "reset_at": reset_at,
+            "used": used,
+            "window": quota.window,
+        }));
+    }
+
+    let response_json = json!({
+        "user_id": user.id,
+        "quotas": quotas,
+    });
+
+    Ok(Json(response_json).into_response())
 }
 
 /// the JSON input to the `post_user` handler.
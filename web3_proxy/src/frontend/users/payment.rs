@@ -4,6 +4,7 @@ use crate::errors::{Web3ProxyError, Web3ProxyResponse, Web3ProxyResult};
 use crate::frontend::authorization::{
     login_is_authorized, Authorization as Web3ProxyAuthorization,
 };
+use crate::frontend::client_ip::ClientIp;
 use crate::frontend::users::authentication::register_new_user;
 use anyhow::Context;
 use axum::{
@@ -12,7 +13,6 @@ use axum::{
     response::IntoResponse,
     Extension, Json, TypedHeader,
 };
-use axum_client_ip::InsecureClientIp;
 use axum_macros::debug_handler;
 use entities::{
     admin_increase_balance_receipt, increase_on_chain_balance_receipt,
@@ -180,7 +180,7 @@ pub async fn user_admin_deposits_get(
 #[debug_handler]
 pub async fn user_balance_post(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    ip: Option<InsecureClientIp>,
+    ip: Option<ClientIp>,
     Path(mut params): Path<HashMap<String, String>>,
     bearer: Option<TypedHeader<Authorization<Bearer>>>,
 ) -> Web3ProxyResponse {
@@ -190,7 +190,7 @@ pub async fn user_balance_post(
 
         // TODO: is handling this as internal fine?
         Web3ProxyAuthorization::internal(app.db_conn().ok().cloned())?
-    } else if let Some(InsecureClientIp(ip)) = ip {
+    } else if let Some(ClientIp(ip)) = ip {
         login_is_authorized(&app, ip).await?
     } else {
         return Err(Web3ProxyError::AccessDenied("no bearer token or ip".into()));
@@ -223,7 +223,7 @@ pub async fn user_balance_post(
     // check for uncles
     let mut find_uncles = increase_on_chain_balance_receipt::Entity::find()
         .filter(increase_on_chain_balance_receipt::Column::TxHash.eq(tx_hash.encode_hex()))
-        .filter(increase_on_chain_balance_receipt::Column::ChainId.eq(app.config.chain_id));
+        .filter(increase_on_chain_balance_receipt::Column::ChainId.eq(app.config.load().chain_id));
 
     let tx_pending =
         if let Some(block_hash) = transaction_receipt.as_ref().and_then(|x| x.block_hash) {
@@ -242,6 +242,7 @@ pub async fn user_balance_post(
             true
         };
 
+    // check the primary and not the replica. a receipt saved by a request a moment ago might not be on the replica yet
     let uncle_hashes = find_uncles.all(db_conn).await?;
 
     let uncle_hashes: HashSet<_> = uncle_hashes
@@ -275,12 +276,14 @@ pub async fn user_balance_post(
     // TODO: if the transaction doesn't have enough confirmations yet, add it to a queue to try again later
     // 1 confirmation should be fine though
 
+    let chain_id = app.config.load().chain_id;
+
     let txn = db_conn.begin().await?;
 
     // if the transaction is already saved, return early
     if increase_on_chain_balance_receipt::Entity::find()
         .filter(increase_on_chain_balance_receipt::Column::TxHash.eq(tx_hash.encode_hex()))
-        .filter(increase_on_chain_balance_receipt::Column::ChainId.eq(app.config.chain_id))
+        .filter(increase_on_chain_balance_receipt::Column::ChainId.eq(chain_id))
         .filter(increase_on_chain_balance_receipt::Column::BlockHash.eq(block_hash.encode_hex()))
         .one(&txn)
         .await?
@@ -294,6 +297,7 @@ pub async fn user_balance_post(
 
     let payment_factory_address = app
         .config
+        .load()
         .deposit_factory_contract
         .context("A deposit_contract must be provided in the config to parse payments")?;
 
@@ -381,7 +385,7 @@ pub async fn user_balance_post(
                 id: sea_orm::ActiveValue::NotSet,
                 amount: sea_orm::ActiveValue::Set(payment_token_amount),
                 block_hash: sea_orm::ActiveValue::Set(block_hash.encode_hex()),
-                chain_id: sea_orm::ActiveValue::Set(app.config.chain_id),
+                chain_id: sea_orm::ActiveValue::Set(chain_id),
                 deposit_to_user_id: sea_orm::ActiveValue::Set(recipient.id),
                 log_index: sea_orm::ActiveValue::Set(log_index),
                 token_address: sea_orm::ActiveValue::Set(payment_token_address.encode_hex()),
@@ -430,7 +434,7 @@ pub async fn user_balance_post(
 #[debug_handler]
 pub async fn user_balance_uncle_post(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    InsecureClientIp(ip): InsecureClientIp,
+    ClientIp(ip): ClientIp,
     Path(mut params): Path<HashMap<String, String>>,
 ) -> Web3ProxyResponse {
     let authorization = login_is_authorized(&app, ip).await?;
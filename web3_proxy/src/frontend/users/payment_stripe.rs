@@ -51,7 +51,7 @@ pub async fn user_stripe_deposits_get(
 #[debug_handler]
 pub async fn user_balance_stripe_post(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
-    // InsecureClientIp(ip): InsecureClientIp,
+    // ClientIp(ip): ClientIp,
     headers: HeaderMap,
     payload: String,
 ) -> Web3ProxyResponse {
@@ -83,6 +83,7 @@ pub async fn user_balance_stripe_post(
 
     let secret = app
         .config
+        .load()
         .stripe_whsec_key
         .clone()
         .web3_context("Stripe API key not found in config!")?;
@@ -102,6 +103,7 @@ pub async fn user_balance_stripe_post(
 
     let db_conn = app.db_conn().context("query_user_stats needs a db")?;
 
+    // check the primary and not the replica. a retried webhook must not be able to race replication
     if stripe_increase_balance_receipt::Entity::find()
         .filter(
             stripe_increase_balance_receipt::Column::StripePaymentIntendId.eq(intent.id.as_str()),
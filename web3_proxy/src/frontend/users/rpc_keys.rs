@@ -1,14 +1,19 @@
 //! Handle registration, logins, and managing account data.
 use super::super::authorization::RpcSecretKey;
+use super::super::request_signature::HmacSecret;
 use crate::app::Web3ProxyApp;
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResponse};
+use crate::key_scope::KeyScope;
+use crate::webhooks::WebhookEvent;
 use axum::headers::{Header, Origin, Referer, UserAgent};
 use axum::{
+    extract::Path,
     headers::{authorization::Bearer, Authorization},
     response::IntoResponse,
     Extension, Json, TypedHeader,
 };
 use axum_macros::debug_handler;
+use chrono::{DateTime, Utc};
 use entities;
 use entities::sea_orm_active_enums::Role;
 use entities::{rpc_key, secondary_user};
@@ -17,11 +22,73 @@ use http::HeaderValue;
 use ipnet::IpNet;
 use itertools::Itertools;
 use migration::sea_orm::{
-    self, ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, TryIntoModel,
+    self, ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter,
+    TransactionTrait, TryIntoModel,
 };
+use migration::Expr;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::sync::Arc;
+use tracing::info;
+use ulid::Ulid;
+
+/// A key as its users see it. The stored secret key is usually a hash, so it is left out
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserRpcKey {
+    pub id: u64,
+    pub user_id: u64,
+    /// None if the key is hashed. Hashed keys are only shown once when they are created
+    pub secret_key: Option<Ulid>,
+    pub description: Option<String>,
+    pub private_txs: bool,
+    pub active: bool,
+    pub allowed_ips: Option<String>,
+    pub allowed_origins: Option<String>,
+    pub allowed_referers: Option<String>,
+    pub allowed_user_agents: Option<String>,
+    pub log_revert_chance: f64,
+    /// true if requests with this key must be signed. the secret is only shown when it is created
+    pub hmac: bool,
+    pub quota_requests: Option<u64>,
+    pub quota_window: String,
+    pub scope: Option<String>,
+    pub chain_id: Option<u64>,
+    /// saved every `key_last_used_flush_seconds`. None if the key was never used
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// true if saved reverts are also sent to the user's webhook
+    pub webhook_reverts: bool,
+}
+
+impl UserRpcKey {
+    /// `new_secret_key` is the raw key of a key that was just created
+    pub fn new(x: rpc_key::Model, new_secret_key: Option<RpcSecretKey>) -> Self {
+        let secret_key = match new_secret_key {
+            Some(x) => Some(x.into()),
+            None => (!x.secret_key_hashed).then(|| RpcSecretKey::from(x.secret_key).into()),
+        };
+
+        Self {
+            id: x.id,
+            user_id: x.user_id,
+            secret_key,
+            description: x.description,
+            private_txs: x.private_txs,
+            active: x.active,
+            allowed_ips: x.allowed_ips,
+            allowed_origins: x.allowed_origins,
+            allowed_referers: x.allowed_referers,
+            allowed_user_agents: x.allowed_user_agents,
+            log_revert_chance: x.log_revert_chance,
+            hmac: x.hmac_secret.is_some(),
+            quota_requests: x.quota_requests,
+            quota_window: x.quota_window,
+            scope: x.scope,
+            chain_id: x.chain_id,
+            last_used_at: x.last_used_at,
+            webhook_reverts: x.webhook_reverts,
+        }
+    }
+}
 
 /// `GET /user/keys` -- Use a bearer token to get the user's api keys and their settings.
 #[debug_handler]
@@ -33,22 +100,10 @@ pub async fn rpc_keys_get(
 
     let db_replica = app.db_replica()?;
 
-    // This is basically completely copied from sea-orm. Not optimal, but it keeps the format identical to before (while adding the final key)
-    // We could also pack the below stuff into it's subfield, but then we would destroy the format. Both options are fine for now though
     #[derive(Serialize)]
     struct ReturnType<'a> {
-        id: u64,
-        user_id: u64,
-        secret_key: RpcSecretKey,
-        description: Option<String>,
-        private_txs: bool,
-        active: bool,
-        allowed_ips: Option<String>,
-        allowed_origins: Option<String>,
-        allowed_referers: Option<String>,
-        allowed_user_agents: Option<String>,
-        log_revert_chance: f64,
-        // Addition
+        #[serde(flatten)]
+        key: UserRpcKey,
         // role is optional only to handle an inconsistent database. it should always be set
         role: Option<&'a Role>,
     }
@@ -60,17 +115,7 @@ pub async fn rpc_keys_get(
         .web3_context("failed loading user's key")?
         .into_iter()
         .map(|x| ReturnType {
-            id: x.id,
-            user_id: x.user_id,
-            secret_key: x.secret_key.into(),
-            description: x.description,
-            private_txs: x.private_txs,
-            active: x.active,
-            allowed_ips: x.allowed_ips,
-            allowed_origins: x.allowed_origins,
-            allowed_referers: x.allowed_referers,
-            allowed_user_agents: x.allowed_user_agents,
-            log_revert_chance: x.log_revert_chance,
+            key: UserRpcKey::new(x, None),
             role: Some(&Role::Owner),
         })
         .collect::<Vec<_>>();
@@ -92,18 +137,8 @@ pub async fn rpc_keys_get(
         .await?
         .into_iter()
         .map(|x| ReturnType {
-            id: x.id,
-            user_id: x.user_id,
-            secret_key: x.secret_key.into(),
-            description: x.description,
-            private_txs: x.private_txs,
-            active: x.active,
-            allowed_ips: x.allowed_ips,
-            allowed_origins: x.allowed_origins,
-            allowed_referers: x.allowed_referers,
-            allowed_user_agents: x.allowed_user_agents,
-            log_revert_chance: x.log_revert_chance,
             role: secondary_user_entities.get(&x.id).map(|x| &x.role),
+            key: UserRpcKey::new(x, None),
         })
         .collect::<Vec<_>>();
 
@@ -111,14 +146,30 @@ pub async fn rpc_keys_get(
         "user_id": user.id,
         "user_rpc_keys": uks
             .into_iter()
-            .map(|uk| (uk.id, uk))
-            .chain(secondary_rpc_key_entities.into_iter().map(|sk| (sk.id, sk)))
+            .map(|uk| (uk.key.id, uk))
+            .chain(secondary_rpc_key_entities.into_iter().map(|sk| (sk.key.id, sk)))
             .collect::<HashMap::<_, _>>()
     });
 
     Ok(Json(response_json).into_response())
 }
 
+/// `GET /user/keys/scopes` -- The presets that can be set as a key's `scope`.
+#[debug_handler]
+pub async fn rpc_key_scopes_get() -> Web3ProxyResponse {
+    let scopes: Vec<_> = KeyScope::ALL
+        .iter()
+        .map(|x| {
+            json!({
+                "scope": x,
+                "description": x.description(),
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({ "scopes": scopes })).into_response())
+}
+
 /// `DELETE /user/keys` -- Use a bearer token to delete an existing key.
 #[debug_handler]
 pub async fn rpc_keys_delete(
@@ -147,6 +198,14 @@ pub struct UserKeyManagement {
     description: Option<String>,
     // TODO: enable log_revert_trace: Option<f64>,
     private_txs: Option<bool>,
+    /// true creates a new HMAC secret, replacing any old one. false removes it so that requests do not need to be signed
+    hmac: Option<bool>,
+    /// the name of a `KeyScope`. an empty string removes the scope
+    scope: Option<String>,
+    /// 0 allows any chain
+    chain_id: Option<u64>,
+    /// true sends the key's saved reverts to the user's webhook
+    webhook_reverts: Option<bool>,
 }
 
 /// `POST /user/keys` or `PUT /user/keys` -- Use a bearer token to create or update an existing key.
@@ -162,6 +221,9 @@ pub async fn rpc_keys_management(
 
     let db_replica = app.db_replica()?;
 
+    // if a new key is created, this is the one time that the user sees it
+    let mut new_secret_key = None;
+
     let mut uk = match payload.key_id {
         Some(existing_key_id) => {
             if let Some(x) = rpc_key::Entity::find()
@@ -213,9 +275,12 @@ pub async fn rpc_keys_management(
             // TODO: limit to 10 keys?
             let secret_key = RpcSecretKey::new();
 
+            new_secret_key = Some(secret_key);
+
             Ok(rpc_key::ActiveModel {
                 user_id: sea_orm::Set(user.id),
-                secret_key: sea_orm::Set(secret_key.into()),
+                secret_key: sea_orm::Set(secret_key.hashed()?),
+                secret_key_hashed: sea_orm::Set(true),
                 ..Default::default()
             })
         }
@@ -234,10 +299,55 @@ pub async fn rpc_keys_management(
         uk.private_txs = sea_orm::Set(private_txs);
     }
 
+    // new keys are NotSet, so only existing keys that were on count as deactivated
+    let mut deactivated = false;
+
     if let Some(active) = payload.active {
+        deactivated = !active && matches!(uk.active, sea_orm::ActiveValue::Unchanged(true));
+
         uk.active = sea_orm::Set(active);
     }
 
+    if let Some(scope) = payload.scope {
+        if scope.is_empty() {
+            uk.scope = sea_orm::Set(None);
+        } else {
+            // parse to reject unknown scopes. store the name so that changes to the preset apply to existing keys
+            let scope: KeyScope = scope.trim().parse()?;
+
+            uk.scope = sea_orm::Set(Some(scope.to_string()));
+        }
+    }
+
+    if let Some(chain_id) = payload.chain_id {
+        if chain_id == 0 {
+            uk.chain_id = sea_orm::Set(None);
+        } else {
+            uk.chain_id = sea_orm::Set(Some(chain_id));
+        }
+    }
+
+    if let Some(webhook_reverts) = payload.webhook_reverts {
+        uk.webhook_reverts = sea_orm::Set(webhook_reverts);
+    }
+
+    // like the secret key, a new hmac secret is only shown in this response
+    let mut new_hmac_secret = None;
+
+    match payload.hmac {
+        Some(true) => {
+            let hmac_secret = HmacSecret::random();
+
+            uk.hmac_secret = sea_orm::Set(Some(hmac_secret.as_str().to_string()));
+
+            new_hmac_secret = Some(hmac_secret);
+        }
+        Some(false) => {
+            uk.hmac_secret = sea_orm::Set(None);
+        }
+        None => {}
+    }
+
     if let Some(allowed_ips) = payload.allowed_ips {
         if allowed_ips.is_empty() {
             uk.allowed_ips = sea_orm::Set(None);
@@ -342,14 +452,164 @@ pub async fn rpc_keys_management(
     let uk = if uk.is_changed() {
         let db_conn = app.db_conn()?;
 
-        uk.save(db_conn)
+        let uk = uk
+            .save(db_conn)
             .await
             .web3_context("Failed saving user key")?
-    } else {
+            .try_into_model()?;
+
+        invalidate_rpc_key(&app, &uk).await?;
+
+        // secondary users can deactivate keys too. the event goes to the key's owner
+        if deactivated {
+            app.notify_webhook(
+                uk.user_id,
+                WebhookEvent::KeyDeactivated {
+                    rpc_key_id: uk.id,
+                    replaced_by_key_id: None,
+                },
+            );
+        }
+
         uk
+    } else {
+        uk.try_into_model()?
+    };
+
+    // only the hash is stored. show the raw key this one time
+    let mut response = serde_json::to_value(UserRpcKey::new(uk, new_secret_key))?;
+
+    if let Some(new_hmac_secret) = new_hmac_secret {
+        response["hmac_secret"] = new_hmac_secret.as_str().into();
+    }
+
+    Ok(Json(response).into_response())
+}
+
+/// `POST /user/keys/:key_id/rotate` -- Use a bearer token to replace one of the user's keys.
+/// The old key is deactivated and a new key with the same settings is created.
+/// The new secret key is only shown in this response.
+#[debug_handler]
+pub async fn rpc_keys_rotate(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Path(key_id): Path<u64>,
+) -> Web3ProxyResponse {
+    let user = app.bearer_is_authorized(bearer).await?;
+
+    let db_conn = app.db_conn()?;
+
+    // only the key's owner can rotate it. subusers keep their access through the new key
+    // this check is only for a helpful error. the conditional update below is what stops a key from being rotated twice
+    let old_key = rpc_key::Entity::find()
+        .filter(rpc_key::Column::UserId.eq(user.id))
+        .filter(rpc_key::Column::Id.eq(key_id))
+        .one(db_conn)
+        .await
+        .web3_context("failed loading user's key")?
+        .ok_or_else(|| {
+            Web3ProxyError::BadRequest(
+                "key does not exist or is not owned by this bearer token".into(),
+            )
+        })?;
+
+    if !old_key.active {
+        return Err(Web3ProxyError::BadRequest(
+            "inactive keys cannot be rotated".into(),
+        ));
+    }
+
+    let new_secret_key = RpcSecretKey::new();
+
+    let txn = db_conn.begin().await?;
+
+    // rotating twice would leave two working keys. only one of any concurrent rotations can deactivate the old key
+    let deactivated = rpc_key::Entity::update_many()
+        .col_expr(rpc_key::Column::Active, Expr::value(false))
+        .filter(rpc_key::Column::Id.eq(old_key.id))
+        .filter(rpc_key::Column::UserId.eq(user.id))
+        .filter(rpc_key::Column::Active.eq(true))
+        .exec(&txn)
+        .await?;
+
+    if deactivated.rows_affected == 0 {
+        return Err(Web3ProxyError::BadRequest(
+            "inactive keys cannot be rotated".into(),
+        ));
+    }
+
+    let new_key = rpc_key::ActiveModel {
+        user_id: sea_orm::Set(user.id),
+        secret_key: sea_orm::Set(new_secret_key.hashed()?),
+        secret_key_hashed: sea_orm::Set(true),
+        description: sea_orm::Set(old_key.description.clone()),
+        private_txs: sea_orm::Set(old_key.private_txs),
+        active: sea_orm::Set(true),
+        allowed_ips: sea_orm::Set(old_key.allowed_ips.clone()),
+        allowed_origins: sea_orm::Set(old_key.allowed_origins.clone()),
+        allowed_referers: sea_orm::Set(old_key.allowed_referers.clone()),
+        allowed_user_agents: sea_orm::Set(old_key.allowed_user_agents.clone()),
+        log_revert_chance: sea_orm::Set(old_key.log_revert_chance),
+        quota_requests: sea_orm::Set(old_key.quota_requests),
+        quota_window: sea_orm::Set(old_key.quota_window.clone()),
+        hmac_secret: sea_orm::Set(old_key.hmac_secret.clone()),
+        scope: sea_orm::Set(old_key.scope.clone()),
+        chain_id: sea_orm::Set(old_key.chain_id),
+        webhook_reverts: sea_orm::Set(old_key.webhook_reverts),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await
+    .web3_context("Failed saving new user key")?;
+
+    secondary_user::Entity::update_many()
+        .col_expr(
+            secondary_user::Column::RpcSecretKeyId,
+            Expr::value(new_key.id),
+        )
+        .filter(secondary_user::Column::RpcSecretKeyId.eq(old_key.id))
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    // the old key stops working on this proxy right away. other proxies stop when their cache expires
+    invalidate_rpc_key(&app, &old_key).await?;
+
+    app.notify_webhook(
+        user.id,
+        WebhookEvent::KeyDeactivated {
+            rpc_key_id: old_key.id,
+            replaced_by_key_id: Some(new_key.id),
+        },
+    );
+
+    info!(
+        user_id = user.id,
+        old_key_id = old_key.id,
+        new_key_id = new_key.id,
+        "rotated rpc key"
+    );
+
+    // only the hash is stored. show the raw key this one time
+    let mut response = serde_json::to_value(UserRpcKey::new(new_key, Some(new_secret_key)))?;
+
+    response["replaced_key_id"] = old_key.id.into();
+
+    Ok(Json(response).into_response())
+}
+
+/// The cache is keyed by the hashed secret key. Keys from before hashing have their raw key in the database
+async fn invalidate_rpc_key(app: &Web3ProxyApp, uk: &rpc_key::Model) -> Web3ProxyResult<()> {
+    let hashed_secret_key = if uk.secret_key_hashed {
+        uk.secret_key
+    } else {
+        RpcSecretKey::from(uk.secret_key).hashed()?
     };
 
-    let uk = uk.try_into_model()?;
+    app.rpc_secret_key_cache
+        .invalidate(&hashed_secret_key)
+        .await;
 
-    Ok(Json(uk).into_response())
+    Ok(())
 }
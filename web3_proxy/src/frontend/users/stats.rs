@@ -1,12 +1,15 @@
 //! Handle registration, logins, and managing account data.
 use crate::app::Web3ProxyApp;
-use crate::errors::{Web3ProxyErrorContext, Web3ProxyResponse};
+use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResponse};
 use crate::http_params::{
     get_chain_id_from_params, get_page_from_params, get_query_start_from_params,
+    get_query_stop_from_params, get_revert_method_from_params, get_rpc_key_id_from_params,
+    get_to_address_from_params,
 };
 use crate::stats::influxdb_queries::query_user_stats;
 use crate::stats::StatType;
 use axum::{
+    body::StreamBody,
     extract::Query,
     headers::{authorization::Bearer, Authorization},
     response::IntoResponse,
@@ -15,15 +18,25 @@ use axum::{
 use axum_macros::debug_handler;
 use entities;
 use entities::sea_orm_active_enums::Role;
-use entities::{revert_log, rpc_key, secondary_user};
+use entities::{revert_log, rpc_accounting_v2, rpc_key, secondary_user};
+use futures::stream;
 use hashbrown::HashMap;
-use migration::sea_orm::{ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder};
+use http::header::CONTENT_TYPE;
+use migration::sea_orm::{
+    ActiveEnum, ColumnTrait, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+    QuerySelect,
+};
 use serde::Serialize;
 use serde_json::json;
 use std::collections::HashSet;
 use std::sync::Arc;
+use tracing::warn;
+
+/// How many rows `user_stats_export_get` loads from the database at a time
+const EXPORT_PAGE_SIZE: u64 = 1_000;
 
 /// `GET /user/revert_logs` -- Use a bearer token to get the user's revert logs.
+/// Filters by key, method, `to` address, and time range.
 #[debug_handler]
 pub async fn user_revert_logs_get(
     Extension(app): Extension<Arc<Web3ProxyApp>>,
@@ -32,8 +45,14 @@ pub async fn user_revert_logs_get(
 ) -> Web3ProxyResponse {
     let user = app.bearer_is_authorized(bearer).await?;
 
+    let _permit = app.stats_permit()?;
+
     let chain_id = get_chain_id_from_params(app.as_ref(), &params)?;
     let query_start = get_query_start_from_params(&params)?;
+    let query_stop = get_query_stop_from_params(&params)?;
+    let rpc_key_id = get_rpc_key_id_from_params(user.id, &params)?;
+    let method = get_revert_method_from_params(&params)?;
+    let to = get_to_address_from_params(&params)?;
     let page = get_page_from_params(&params)?;
 
     // TODO: page size from config
@@ -45,6 +64,7 @@ pub async fn user_revert_logs_get(
     response.insert("page_size", json!(page_size));
     response.insert("chain_id", json!(chain_id));
     response.insert("query_start", json!(query_start.timestamp() as u64));
+    response.insert("query_stop", json!(query_stop.timestamp() as u64));
 
     let db_replica = app.db_replica()?;
 
@@ -83,15 +103,39 @@ pub async fn user_revert_logs_get(
         .chain(shared_rpc_keys)
         .collect();
 
+    let mut rpc_key_ids: HashSet<_> = uks.into_iter().map(|x| x.id).collect();
+
+    if rpc_key_id != 0 {
+        if !rpc_key_ids.contains(&rpc_key_id) {
+            return Err(Web3ProxyError::AccessDenied(
+                "key does not exist or is not controlled by this bearer token".into(),
+            ));
+        }
+
+        rpc_key_ids = HashSet::from([rpc_key_id]);
+
+        response.insert("rpc_key_id", json!(rpc_key_id));
+    }
+
     // get revert logs
     let mut q = revert_log::Entity::find()
         .filter(revert_log::Column::Timestamp.gte(query_start))
-        .filter(
-            revert_log::Column::RpcKeyId
-                .is_in(uks.into_iter().map(|x| x.id).collect::<HashSet<_>>()),
-        )
+        .filter(revert_log::Column::Timestamp.lte(query_stop))
+        .filter(revert_log::Column::RpcKeyId.is_in(rpc_key_ids))
         .order_by_asc(revert_log::Column::Timestamp);
 
+    if let Some(method) = method {
+        response.insert("method", json!(method.to_value()));
+
+        q = q.filter(revert_log::Column::Method.eq(method));
+    }
+
+    if let Some(to) = to {
+        response.insert("to", json!(to));
+
+        q = q.filter(revert_log::Column::To.eq(to.as_bytes().to_vec()));
+    }
+
     if chain_id == 0 {
         // don't do anything
     } else {
@@ -127,6 +171,8 @@ pub async fn user_stats_aggregated_get(
     bearer: Option<TypedHeader<Authorization<Bearer>>>,
     Query(params): Query<HashMap<String, String>>,
 ) -> Web3ProxyResponse {
+    let _permit = app.stats_permit()?;
+
     let response = query_user_stats(&app, bearer, &params, StatType::Aggregated).await?;
 
     Ok(response)
@@ -147,7 +193,109 @@ pub async fn user_stats_detailed_get(
     bearer: Option<TypedHeader<Authorization<Bearer>>>,
     Query(params): Query<HashMap<String, String>>,
 ) -> Web3ProxyResponse {
+    let _permit = app.stats_permit()?;
+
     let response = query_user_stats(&app, bearer, &params, StatType::Detailed).await?;
 
     Ok(response)
 }
+
+/// `GET /user/stats/export` -- Use a bearer token to download the raw accounting rows for the user's keys.
+///
+/// The response is newline-delimited JSON with one `rpc_accounting_v2` row per line.
+/// Rows are loaded a page at a time while the response is sent, so large exports are never held in memory.
+/// Filter with `?query_start=$x`, `?query_stop=$x`, and `?chain_id=$x` like the other stats endpoints.
+#[debug_handler]
+pub async fn user_stats_export_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Web3ProxyResponse {
+    let user = app.bearer_is_authorized(bearer).await?;
+
+    let chain_id = get_chain_id_from_params(app.as_ref(), &params)?;
+    let query_start = get_query_start_from_params(&params)?;
+    let query_stop = get_query_stop_from_params(&params)?;
+
+    if query_start >= query_stop {
+        return Err(Web3ProxyError::BadRequest(
+            "query_start must be before query_stop".into(),
+        ));
+    }
+
+    // held until the whole export is sent
+    let permit = Arc::new(app.stats_permit()?);
+
+    let db_replica = app.db_replica()?.as_ref().clone();
+
+    let rpc_key_ids: Vec<u64> = rpc_key::Entity::find()
+        .filter(rpc_key::Column::UserId.eq(user.id))
+        .all(&db_replica)
+        .await
+        .web3_context("failed loading user's keys")?
+        .into_iter()
+        .map(|x| x.id)
+        .collect();
+
+    let mut q = rpc_accounting_v2::Entity::find()
+        .filter(rpc_accounting_v2::Column::RpcKeyId.is_in(rpc_key_ids))
+        .filter(rpc_accounting_v2::Column::PeriodDatetime.gte(query_start))
+        .filter(rpc_accounting_v2::Column::PeriodDatetime.lt(query_stop));
+
+    if chain_id != 0 {
+        q = q.filter(rpc_accounting_v2::Column::ChainId.eq(chain_id));
+    }
+
+    let user_id = user.id;
+
+    // page by id instead of by offset so that later pages are just as fast as the first
+    let rows = stream::unfold(Some(0u64), move |last_id| {
+        let db_replica = db_replica.clone();
+        let q = q.clone();
+        let permit = permit.clone();
+
+        async move {
+            let _permit = permit;
+
+            let last_id = last_id?;
+
+            let page = match q
+                .filter(rpc_accounting_v2::Column::Id.gt(last_id))
+                .order_by_asc(rpc_accounting_v2::Column::Id)
+                .limit(EXPORT_PAGE_SIZE)
+                .all(&db_replica)
+                .await
+            {
+                Ok(x) => x,
+                Err(err) => {
+                    warn!(?err, user_id, "failed loading stats export page");
+                    // end the stream. the client sees a truncated body instead of a clean finish
+                    return Some((Err(err), None));
+                }
+            };
+
+            let next_id = match page.last() {
+                Some(x) if page.len() as u64 == EXPORT_PAGE_SIZE => Some(x.id),
+                Some(_) => None,
+                None => return None,
+            };
+
+            let mut buf = Vec::with_capacity(page.len() * 512);
+
+            for row in page.iter() {
+                if let Err(err) = serde_json::to_writer(&mut buf, row) {
+                    return Some((Err(DbErr::Custom(err.to_string())), None));
+                }
+                buf.push(b'\n');
+            }
+
+            Some((Ok::<_, DbErr>(buf), next_id))
+        }
+    });
+
+    Ok((
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(rows),
+    )
+        .into_response())
+}
@@ -73,7 +73,8 @@ pub async fn get_keys_as_subuser(
                 match rpc_owner {
                     Some(inner_rpc_owner) => {
                         let x = json!({
-                            "rpc-key": Ulid::from(rpc_key.secret_key),
+                            // hashed keys are only shown to their owner when they are created
+                            "rpc-key": (!rpc_key.secret_key_hashed).then(|| Ulid::from(rpc_key.secret_key)),
                             "rpc-owner": Address::from_slice(&inner_rpc_owner.address),
                             // TODO: prettier serialize for role
                             "role": format!("{:?}", secondary_user_entities.get(&rpc_key.id).unwrap().role),
@@ -274,7 +275,8 @@ pub async fn modify_subuser(
 
             let subuser_rpc_key = rpc_key::ActiveModel {
                 user_id: sea_orm::Set(subuser.id),
-                secret_key: sea_orm::Set(rpc_secret_key.into()),
+                secret_key: sea_orm::Set(rpc_secret_key.hashed()?),
+                secret_key_hashed: sea_orm::Set(true),
                 description: sea_orm::Set(None),
                 ..Default::default()
             };
@@ -0,0 +1,129 @@
+//! Let users receive account events at their own url.
+use crate::app::Web3ProxyApp;
+use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResponse};
+use crate::frontend::request_signature::HmacSecret;
+use crate::webhooks::resolve_webhook_url;
+use axum::{
+    headers::{authorization::Bearer, Authorization},
+    response::IntoResponse,
+    Extension, Json, TypedHeader,
+};
+use axum_macros::debug_handler;
+use entities::user_webhook;
+use migration::sea_orm::{
+    self, ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, TryIntoModel,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::info;
+use url::Url;
+
+/// Long enough for any reasonable url. Matches the column
+const MAX_URL_LEN: usize = 2048;
+
+/// the JSON input to the `user_webhook_post` handler.
+#[derive(Debug, Deserialize)]
+pub struct UserWebhookPost {
+    url: String,
+}
+
+/// `GET /user/webhook` -- Use a bearer token to get the user's webhook. The secret is not included.
+#[debug_handler]
+pub async fn user_webhook_get(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Web3ProxyResponse {
+    let user = app.bearer_is_authorized(bearer).await?;
+
+    let db_replica = app.db_replica()?;
+
+    let webhook = user_webhook::Entity::find()
+        .filter(user_webhook::Column::UserId.eq(user.id))
+        .one(db_replica.as_ref())
+        .await
+        .web3_context("failed loading user's webhook")?;
+
+    Ok(Json(json!({ "webhook": webhook })).into_response())
+}
+
+/// `POST /user/webhook` -- Use a bearer token to set the url that account events are sent to.
+/// Every call creates a new secret. The secret is only shown in this response.
+#[debug_handler]
+pub async fn user_webhook_post(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<UserWebhookPost>,
+) -> Web3ProxyResponse {
+    let user = app.bearer_is_authorized(bearer).await?;
+
+    if payload.url.len() > MAX_URL_LEN {
+        return Err(Web3ProxyError::BadRequest(
+            format!("webhook urls must be at most {} characters", MAX_URL_LEN).into(),
+        ));
+    }
+
+    let url: Url = payload
+        .url
+        .trim()
+        .parse()
+        .map_err(|_| Web3ProxyError::BadRequest("invalid webhook url".into()))?;
+
+    // https only, and never our own network. deliveries check again in case the host's dns changes
+    resolve_webhook_url(&url).await?;
+
+    let db_conn = app.db_conn()?;
+
+    let secret = HmacSecret::random();
+
+    // read the primary. a row saved a moment ago might not be on the replica yet and a second one would be inserted
+    let existing = user_webhook::Entity::find()
+        .filter(user_webhook::Column::UserId.eq(user.id))
+        .one(db_conn)
+        .await
+        .web3_context("failed loading user's webhook")?;
+
+    let mut webhook = match existing {
+        Some(x) => x.into_active_model(),
+        None => user_webhook::ActiveModel {
+            user_id: sea_orm::Set(user.id),
+            ..Default::default()
+        },
+    };
+
+    webhook.url = sea_orm::Set(url.to_string());
+    webhook.secret = sea_orm::Set(secret.as_str().to_string());
+
+    let webhook = webhook
+        .save(db_conn)
+        .await
+        .web3_context("failed saving user's webhook")?
+        .try_into_model()?;
+
+    info!(user_id = user.id, "saved webhook");
+
+    let mut response = serde_json::to_value(&webhook)?;
+
+    response["secret"] = secret.as_str().into();
+
+    Ok(Json(response).into_response())
+}
+
+/// `DELETE /user/webhook` -- Use a bearer token to stop sending account events.
+#[debug_handler]
+pub async fn user_webhook_delete(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Web3ProxyResponse {
+    let user = app.bearer_is_authorized(bearer).await?;
+
+    let db_conn = app.db_conn()?;
+
+    let deleted = user_webhook::Entity::delete_many()
+        .filter(user_webhook::Column::UserId.eq(user.id))
+        .exec(db_conn)
+        .await
+        .web3_context("failed deleting user's webhook")?;
+
+    Ok(Json(json!({ "deleted": deleted.rows_affected > 0 })).into_response())
+}
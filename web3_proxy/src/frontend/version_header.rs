@@ -0,0 +1,59 @@
+//! Say which build served a request. Useful when a fleet is running several versions.
+
+use crate::app::Web3ProxyApp;
+use axum::{middleware::Next, response::Response, Extension};
+use http::{HeaderValue, Request};
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+
+pub const VERSION_HEADER: &str = "X-Web3-Proxy-Version";
+
+/// The crate version. The git commit is appended if `WEB3_PROXY_GIT_COMMIT` was set at build time
+static VERSION_HEADER_VALUE: Lazy<HeaderValue> = Lazy::new(|| {
+    let version = version_string(
+        env!("CARGO_PKG_VERSION"),
+        option_env!("WEB3_PROXY_GIT_COMMIT"),
+    );
+
+    HeaderValue::from_str(&version).expect("version should be a valid header")
+});
+
+fn version_string(version: &str, git_commit: Option<&str>) -> String {
+    match git_commit.map(str::trim).filter(|x| !x.is_empty()) {
+        Some(git_commit) => format!("{}+{}", version, git_commit),
+        None => version.to_string(),
+    }
+}
+
+/// Layer for every route. Adds the version header if `version_header` is enabled.
+/// This is checked on every response so that it can be toggled without a restart.
+pub async fn add_version_header<B>(
+    Extension(app): Extension<Arc<Web3ProxyApp>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    if app.config.load().version_header {
+        response
+            .headers_mut()
+            .insert(VERSION_HEADER, VERSION_HEADER_VALUE.clone());
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::version_string;
+
+    #[test]
+    fn test_version_string() {
+        assert_eq!(version_string("1.43.8", None), "1.43.8");
+        assert_eq!(version_string("1.43.8", Some("")), "1.43.8");
+        assert_eq!(
+            version_string("1.43.8", Some("abc1234\n")),
+            "1.43.8+abc1234"
+        );
+    }
+}
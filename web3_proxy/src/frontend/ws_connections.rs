@@ -0,0 +1,251 @@
+//! Track open websockets so that admins can see who is connected and cut off abusive clients.
+
+use super::authorization::Authorization;
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use chrono::Utc;
+use ethers::types::U64;
+use futures::future::AbortHandle;
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, RwLock as AsyncRwLock};
+
+struct WsConnection {
+    rpc_secret_key_id: Option<NonZeroU64>,
+    user_id: u64,
+    ip: IpAddr,
+    /// the ip as counted for `ws_max_connections_per_ip`. IPv6 addresses are grouped by prefix
+    limit_key: IpAddr,
+    /// unix timestamp
+    connected_at: i64,
+    subscriptions: Arc<AsyncRwLock<HashMap<U64, AbortHandle>>>,
+    response_sender: mpsc::UnboundedSender<Message>,
+    close_sender: broadcast::Sender<bool>,
+}
+
+/// One open websocket as shown to admins
+#[derive(Debug, Serialize)]
+pub struct WsConnectionInfo {
+    pub id: u64,
+    pub rpc_secret_key_id: Option<NonZeroU64>,
+    pub user_id: u64,
+    pub ip: IpAddr,
+    pub connected_at: i64,
+    pub subscriptions: usize,
+}
+
+#[derive(Default)]
+pub struct WsConnections {
+    next_id: AtomicU64,
+    connections: Mutex<OpenConnections>,
+}
+
+/// The connections and how many each ip has. They are kept under one lock so that the counts always match
+#[derive(Default)]
+struct OpenConnections {
+    by_id: HashMap<u64, WsConnection>,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+impl OpenConnections {
+    fn insert(&mut self, id: u64, connection: WsConnection) {
+        *self.per_ip.entry(connection.limit_key).or_default() += 1;
+
+        self.by_id.insert(id, connection);
+    }
+
+    fn remove(&mut self, id: &u64) -> Option<WsConnection> {
+        let connection = self.by_id.remove(id)?;
+
+        if let Some(count) = self.per_ip.get_mut(&connection.limit_key) {
+            *count -= 1;
+
+            if *count == 0 {
+                self.per_ip.remove(&connection.limit_key);
+            }
+        }
+
+        Some(connection)
+    }
+}
+
+/// Removes the connection from `WsConnections` when the socket's read loop exits
+pub struct WsConnectionGuard {
+    id: u64,
+    connections: Arc<WsConnections>,
+}
+
+impl Drop for WsConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.connections.lock().remove(&self.id);
+    }
+}
+
+impl WsConnections {
+    /// Returns None if `limit_key` already has `max_per_ip` open connections.
+    /// The check and the insert happen under the same lock so that a burst of connections can't get past the limit.
+    pub fn try_insert(
+        self: &Arc<Self>,
+        authorization: &Authorization,
+        limit_key: IpAddr,
+        subscriptions: Arc<AsyncRwLock<HashMap<U64, AbortHandle>>>,
+        response_sender: mpsc::UnboundedSender<Message>,
+        close_sender: broadcast::Sender<bool>,
+        max_per_ip: Option<usize>,
+    ) -> Option<WsConnectionGuard> {
+        let connection = WsConnection {
+            rpc_secret_key_id: authorization.checks.rpc_secret_key_id,
+            user_id: authorization.checks.user_id,
+            ip: authorization.ip,
+            limit_key,
+            connected_at: Utc::now().timestamp(),
+            subscriptions,
+            response_sender,
+            close_sender,
+        };
+
+        let mut connections = self.connections.lock();
+
+        if let Some(max_per_ip) = max_per_ip {
+            let ip_connections = connections
+                .per_ip
+                .get(&limit_key)
+                .copied()
+                .unwrap_or_default();
+
+            if ip_connections >= max_per_ip {
+                return None;
+            }
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        connections.insert(id, connection);
+
+        Some(WsConnectionGuard {
+            id,
+            connections: self.clone(),
+        })
+    }
+
+    /// Open connections, oldest first. Optionally only the connections for one rpc key.
+    pub async fn list(&self, rpc_secret_key_id: Option<NonZeroU64>) -> Vec<WsConnectionInfo> {
+        // clone out of the lock so that it isn't held while waiting on the subscriptions
+        let connections: Vec<_> = self
+            .connections
+            .lock()
+            .by_id
+            .iter()
+            .filter(|(_, x)| {
+                rpc_secret_key_id.is_none() || x.rpc_secret_key_id == rpc_secret_key_id
+            })
+            .map(|(id, x)| {
+                (
+                    WsConnectionInfo {
+                        id: *id,
+                        rpc_secret_key_id: x.rpc_secret_key_id,
+                        user_id: x.user_id,
+                        ip: x.ip,
+                        connected_at: x.connected_at,
+                        subscriptions: 0,
+                    },
+                    x.subscriptions.clone(),
+                )
+            })
+            .collect();
+
+        let mut list = Vec::with_capacity(connections.len());
+
+        for (mut info, subscriptions) in connections {
+            info.subscriptions = subscriptions.read().await.len();
+
+            list.push(info);
+        }
+
+        list.sort_by_key(|x| x.id);
+
+        list
+    }
+
+    /// Close every connection that uses this rpc key. Returns how many were closed.
+    pub async fn disconnect_key(&self, rpc_secret_key_id: NonZeroU64) -> usize {
+        let closed: Vec<_> = {
+            let mut connections = self.connections.lock();
+
+            let ids: Vec<_> = connections
+                .by_id
+                .iter()
+                .filter(|(_, x)| x.rpc_secret_key_id == Some(rpc_secret_key_id))
+                .map(|(id, _)| *id)
+                .collect();
+
+            ids.into_iter()
+                .filter_map(|id| connections.remove(&id))
+                .collect()
+        };
+
+        for connection in closed.iter() {
+            for (_, handle) in connection.subscriptions.write().await.drain() {
+                handle.abort();
+            }
+
+            let close_frame = CloseFrame {
+                code: close_code::POLICY,
+                reason: "disconnected by an admin".into(),
+            };
+
+            let _ = connection
+                .response_sender
+                .send(Message::Close(Some(close_frame)));
+            let _ = connection.close_sender.send(true);
+        }
+
+        closed.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WsConnections;
+    use crate::frontend::authorization::Authorization;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, mpsc};
+
+    #[test]
+    fn test_max_per_ip() {
+        let connections = Arc::new(WsConnections::default());
+        let authorization = Authorization::internal(None).unwrap();
+        let a: IpAddr = "2001:db8::1".parse().unwrap();
+        let b: IpAddr = "2001:db8::2".parse().unwrap();
+
+        let try_insert = |limit_key| {
+            let (response_sender, _) = mpsc::unbounded_channel();
+            let (close_sender, _) = broadcast::channel(1);
+
+            connections.try_insert(
+                &authorization,
+                limit_key,
+                Default::default(),
+                response_sender,
+                close_sender,
+                Some(2),
+            )
+        };
+
+        let first = try_insert(a).unwrap();
+        let _second = try_insert(a).unwrap();
+        assert!(try_insert(a).is_none());
+
+        // other keys have their own count
+        let _other = try_insert(b).unwrap();
+
+        // closing a connection makes room for another
+        drop(first);
+        assert!(try_insert(a).is_some());
+    }
+}
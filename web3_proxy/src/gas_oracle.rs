@@ -0,0 +1,104 @@
+//! Answer `eth_maxPriorityFeePerGas` for backends that do not implement it.
+//!
+//! The suggestion is the median of one reward percentile over recent blocks, like geth's gas price oracle.
+use crate::jsonrpc::JsonRpcErrorData;
+use ethers::types::{FeeHistory, U256};
+use serde_json::json;
+
+/// How many blocks of `eth_feeHistory` to look at
+pub const FEE_HISTORY_BLOCKS: u64 = 20;
+
+/// The reward percentile from each block. geth uses the 60th percentile
+pub const FEE_HISTORY_PERCENTILE: f64 = 60.0;
+
+/// The params for the `eth_feeHistory` request that feeds `suggested_priority_fee`
+pub fn fee_history_params() -> serde_json::Value {
+    json!([
+        format!("{:#x}", FEE_HISTORY_BLOCKS),
+        "latest",
+        [FEE_HISTORY_PERCENTILE]
+    ])
+}
+
+/// Backends do not agree on how to say that a method does not exist
+pub fn is_method_not_found(err: &JsonRpcErrorData) -> bool {
+    if err.code == -32601 {
+        return true;
+    }
+
+    let message = err.message.to_lowercase();
+
+    message.contains("method not found")
+        || message.contains("does not exist")
+        || message.contains("not supported")
+}
+
+/// The median reward of the blocks that had transactions. Zero if every block was empty
+pub fn suggested_priority_fee(fee_history: &FeeHistory) -> U256 {
+    let mut rewards: Vec<U256> = fee_history
+        .reward
+        .iter()
+        .filter_map(|x| x.first().copied())
+        .filter(|x| !x.is_zero())
+        .collect();
+
+    if rewards.is_empty() {
+        return U256::zero();
+    }
+
+    rewards.sort_unstable();
+
+    rewards[rewards.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_method_not_found, suggested_priority_fee};
+    use crate::jsonrpc::JsonRpcErrorData;
+    use ethers::types::{FeeHistory, U256};
+
+    #[test]
+    fn test_suggested_priority_fee() {
+        let fee_history = FeeHistory {
+            base_fee_per_gas: vec![],
+            gas_used_ratio: vec![],
+            oldest_block: U256::zero(),
+            // empty blocks report 0 and are skipped
+            reward: vec![
+                vec![3.into()],
+                vec![0.into()],
+                vec![1.into()],
+                vec![100.into()],
+                vec![2.into()],
+            ],
+        };
+
+        assert_eq!(suggested_priority_fee(&fee_history), U256::from(3));
+
+        let empty = FeeHistory {
+            reward: vec![vec![0.into()], vec![]],
+            ..fee_history
+        };
+
+        assert_eq!(suggested_priority_fee(&empty), U256::zero());
+    }
+
+    #[test]
+    fn test_is_method_not_found() {
+        assert!(is_method_not_found(&JsonRpcErrorData {
+            code: -32601,
+            message: "whatever".into(),
+            data: None,
+        }));
+        assert!(is_method_not_found(&JsonRpcErrorData {
+            code: -32000,
+            message: "the method eth_maxPriorityFeePerGas does not exist/is not available".into(),
+            data: None,
+        }));
+        assert!(!is_method_not_found(&JsonRpcErrorData {
+            code: -32000,
+            message: "execution reverted".into(),
+            data: None,
+        }));
+    }
+}
@@ -8,9 +8,13 @@ use axum::{
 };
 use chrono::{NaiveDateTime, Utc};
 use entities::login;
+use entities::sea_orm_active_enums::Method;
+use ethers::types::Address;
 use hashbrown::HashMap;
-use migration::sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use migration::sea_orm::{ActiveEnum, ColumnTrait, EntityTrait, QueryFilter};
 use redis_rate_limiter::{redis::AsyncCommands, RedisConnection};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
 use tracing::{trace, warn};
 
 /// get the attached address for the given bearer token.
@@ -18,6 +22,7 @@ use tracing::{trace, warn};
 /// 0 means all users.
 /// This authenticates that the bearer is allowed to view this user_id's stats
 pub async fn get_user_id_from_params(
+    app: &Web3ProxyApp,
     redis_conn: &mut RedisConnection,
     db_conn: &DatabaseConnection,
     db_replica: &DatabaseReplica,
@@ -56,8 +61,9 @@ pub async fn get_user_id_from_params(
                             .exec(db_conn)
                             .await?;
 
-                        // TODO: emit a stat? if this is high something weird might be happening
                         trace!("cleared expired logins: {:?}", delete_result);
+                        app.expired_logins_cleared
+                            .fetch_add(delete_result.rows_affected, Ordering::Relaxed);
 
                         return Err(Web3ProxyError::AccessDenied("login expired".into()));
                     }
@@ -121,14 +127,15 @@ pub async fn get_user_id_from_params(
 pub fn get_rpc_key_id_from_params(
     user_id: u64,
     params: &HashMap<String, String>,
-) -> anyhow::Result<u64> {
+) -> Web3ProxyResult<u64> {
     if user_id > 0 {
         params.get("rpc_key_id").map_or_else(
             || Ok(0),
-            |c| {
-                let c = c.parse()?;
-
-                Ok(c)
+            |x| {
+                x.parse().map_err(|_| Web3ProxyError::InvalidQueryParam {
+                    name: "rpc_key_id",
+                    value: x.clone(),
+                })
             },
         )
     } else {
@@ -141,7 +148,7 @@ pub fn get_chain_id_from_params(
     params: &HashMap<String, String>,
 ) -> anyhow::Result<u64> {
     params.get("chain_id").map_or_else(
-        || Ok(app.config.chain_id),
+        || Ok(app.config.load().chain_id),
         |c| {
             let c = c.parse()?;
 
@@ -150,26 +157,36 @@ pub fn get_chain_id_from_params(
     )
 }
 
-pub fn get_page_from_params(params: &HashMap<String, String>) -> anyhow::Result<u64> {
-    params.get("page").map_or_else::<anyhow::Result<u64>, _, _>(
+pub fn get_page_from_params(params: &HashMap<String, String>) -> Web3ProxyResult<u64> {
+    params.get("page").map_or_else(
         || {
             // no page in params. set default
             Ok(0)
         },
         |x: &String| {
-            // parse the given timestamp
-            // TODO: error code 401
-            let x = x.parse().context("parsing page query from params")?;
-
-            Ok(x)
+            x.parse().map_err(|_| Web3ProxyError::InvalidQueryParam {
+                name: "page",
+                value: x.clone(),
+            })
         },
     )
 }
 
+/// Parse a unix timestamp (in seconds) from the query params
+fn parse_timestamp_param(name: &'static str, x: &str) -> Web3ProxyResult<NaiveDateTime> {
+    x.parse::<i64>()
+        .ok()
+        .and_then(|x| NaiveDateTime::from_timestamp_opt(x, 0))
+        .ok_or_else(|| Web3ProxyError::InvalidQueryParam {
+            name,
+            value: x.to_string(),
+        })
+}
+
 // TODO: return chrono::Utc instead?
 pub fn get_query_start_from_params(
     params: &HashMap<String, String>,
-) -> anyhow::Result<chrono::NaiveDateTime> {
+) -> Web3ProxyResult<chrono::NaiveDateTime> {
     params.get("query_start").map_or_else(
         || {
             // no timestamp in params. set default
@@ -177,24 +194,14 @@ pub fn get_query_start_from_params(
 
             Ok(x.naive_utc())
         },
-        |x: &String| {
-            // parse the given timestamp
-            let x = x
-                .parse::<i64>()
-                .context("parsing start timestamp query param")?;
-
-            let x = NaiveDateTime::from_timestamp_opt(x, 0)
-                .context("parsing start timestamp query param")?;
-
-            Ok(x)
-        },
+        |x: &String| parse_timestamp_param("query_start", x),
     )
 }
 
 // TODO: return chrono::Utc instead?
 pub fn get_query_stop_from_params(
     params: &HashMap<String, String>,
-) -> anyhow::Result<chrono::NaiveDateTime> {
+) -> Web3ProxyResult<chrono::NaiveDateTime> {
     params.get("query_stop").map_or_else(
         || {
             // no timestamp in params. set default
@@ -202,17 +209,7 @@ pub fn get_query_stop_from_params(
 
             Ok(x.naive_utc())
         },
-        |x: &String| {
-            // parse the given timestamp
-            let x = x
-                .parse::<i64>()
-                .context("parsing stop timestamp query param")?;
-
-            let x = NaiveDateTime::from_timestamp_opt(x, 0)
-                .context("parsing stop timestamp query param")?;
-
-            Ok(x)
-        },
+        |x: &String| parse_timestamp_param("query_stop", x),
     )
 }
 
@@ -225,10 +222,12 @@ pub fn get_query_window_seconds_from_params(
             Ok(60)
         },
         |query_window_seconds: &String| {
-            // parse the given timestamp
-            query_window_seconds.parse::<u64>().map_err(|_| {
-                Web3ProxyError::BadRequest("Unable to parse query_window_seconds".into())
-            })
+            query_window_seconds
+                .parse::<u64>()
+                .map_err(|_| Web3ProxyError::InvalidQueryParam {
+                    name: "query_window_seconds",
+                    value: query_window_seconds.clone(),
+                })
         },
     )
 }
@@ -268,3 +267,119 @@ pub fn get_stats_column_from_params(params: &HashMap<String, String>) -> Web3Pro
         },
     )
 }
+
+/// None means any method. Only methods that are saved in the revert logs are allowed
+pub fn get_revert_method_from_params(
+    params: &HashMap<String, String>,
+) -> Web3ProxyResult<Option<Method>> {
+    params
+        .get("method")
+        .map(|x| {
+            Method::try_from_value(x).map_err(|_| Web3ProxyError::InvalidQueryParam {
+                name: "method",
+                value: x.clone(),
+            })
+        })
+        .transpose()
+}
+
+/// None means any address
+pub fn get_to_address_from_params(
+    params: &HashMap<String, String>,
+) -> Web3ProxyResult<Option<Address>> {
+    params
+        .get("to")
+        .map(|x| {
+            Address::from_str(x).map_err(|_| Web3ProxyError::InvalidQueryParam {
+                name: "to",
+                value: x.clone(),
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_query_start_from_params, get_query_window_seconds_from_params,
+        get_revert_method_from_params, get_rpc_key_id_from_params, get_to_address_from_params,
+    };
+    use crate::errors::Web3ProxyError;
+    use entities::sea_orm_active_enums::Method;
+    use hashbrown::HashMap;
+
+    #[test]
+    fn test_invalid_query_params() {
+        let mut params = HashMap::new();
+
+        params.insert("query_start".to_string(), "1687000000".to_string());
+        assert_eq!(
+            get_query_start_from_params(&params).unwrap().timestamp(),
+            1687000000
+        );
+
+        params.insert("query_start".to_string(), "yesterday".to_string());
+        assert!(matches!(
+            get_query_start_from_params(&params),
+            Err(Web3ProxyError::InvalidQueryParam {
+                name: "query_start",
+                ..
+            })
+        ));
+
+        params.insert("query_window_seconds".to_string(), "-1".to_string());
+        assert!(matches!(
+            get_query_window_seconds_from_params(&params),
+            Err(Web3ProxyError::InvalidQueryParam {
+                name: "query_window_seconds",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_revert_log_params() {
+        let mut params = HashMap::new();
+
+        assert_eq!(get_revert_method_from_params(&params).unwrap(), None);
+        assert_eq!(get_to_address_from_params(&params).unwrap(), None);
+
+        params.insert("method".to_string(), "eth_call".to_string());
+        params.insert(
+            "to".to_string(),
+            "0xDe0B295669a9FD93d5F28D9Ec85E40f4cb697BAe".to_string(),
+        );
+
+        assert_eq!(
+            get_revert_method_from_params(&params).unwrap(),
+            Some(Method::EthCall)
+        );
+        assert!(get_to_address_from_params(&params).unwrap().is_some());
+
+        params.insert("method".to_string(), "eth_getBalance".to_string());
+        params.insert("to".to_string(), "0x1234".to_string());
+
+        assert!(matches!(
+            get_revert_method_from_params(&params),
+            Err(Web3ProxyError::InvalidQueryParam { name: "method", .. })
+        ));
+        assert!(matches!(
+            get_to_address_from_params(&params),
+            Err(Web3ProxyError::InvalidQueryParam { name: "to", .. })
+        ));
+
+        assert_eq!(get_rpc_key_id_from_params(1, &params).unwrap(), 0);
+
+        params.insert("rpc_key_id".to_string(), "5".to_string());
+        assert_eq!(get_rpc_key_id_from_params(1, &params).unwrap(), 5);
+
+        params.insert("rpc_key_id".to_string(), "abc".to_string());
+        assert!(matches!(
+            get_rpc_key_id_from_params(1, &params),
+            Err(Web3ProxyError::InvalidQueryParam {
+                name: "rpc_key_id",
+                ..
+            })
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use crate::redact::redact_params;
 use crate::response_cache::JsonRpcResponseEnum;
 use derive_more::From;
 use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
@@ -15,6 +17,8 @@ pub trait JsonRpcResultData = serde::Serialize + serde::de::DeserializeOwned + f
 // TODO: generic type for params?
 #[derive(Clone, Deserialize, Serialize)]
 pub struct JsonRpcRequest {
+    /// empty if the client left it out. `validate` fills it in
+    #[serde(default)]
     pub jsonrpc: String,
     /// id could be a stricter type, but many rpcs do things against the spec
     pub id: Box<RawValue>,
@@ -54,6 +58,49 @@ impl JsonRpcRequest {
 
         Ok(x)
     }
+
+    /// Check the request's envelope before it is dispatched.
+    ///
+    /// With `strict_version`, the request must declare `"jsonrpc": "2.0"`. Otherwise a missing version is treated as "2.0".
+    /// An id that isn't a number, string, or null is replaced with null so that the error response is still valid.
+    pub fn validate(&mut self, strict_version: bool) -> Web3ProxyResult<()> {
+        let valid_id = match self.id.get().as_bytes().first() {
+            Some(b'"' | b'-' | b'0'..=b'9') => true,
+            _ => self.id.get() == "null",
+        };
+
+        if !valid_id {
+            self.id = JsonRpcId::None.to_raw_value();
+
+            return Err(Web3ProxyError::BadRequest(
+                "id must be a number, string, or null".into(),
+            ));
+        }
+
+        if self.jsonrpc.is_empty() && !strict_version {
+            // some clients don't follow the spec and dont include the jsonrpc key
+            self.jsonrpc = "2.0".to_string();
+        } else if strict_version && self.jsonrpc != "2.0" {
+            return Err(Web3ProxyError::BadRequest(
+                r#"jsonrpc must be "2.0""#.into(),
+            ));
+        }
+
+        if self.method.is_empty() {
+            return Err(Web3ProxyError::BadRequest("method is required".into()));
+        }
+
+        if !matches!(
+            self.params,
+            serde_json::Value::Array(_) | serde_json::Value::Object(_) | serde_json::Value::Null
+        ) {
+            return Err(Web3ProxyError::BadRequest(
+                "params must be an array or an object".into(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl fmt::Debug for JsonRpcRequest {
@@ -63,7 +110,7 @@ impl fmt::Debug for JsonRpcRequest {
         f.debug_struct("JsonRpcRequest")
             .field("id", &self.id)
             .field("method", &self.method)
-            .field("params", &self.params)
+            .field("params", &redact_params(&self.method, &self.params))
             .finish()
     }
 }
@@ -82,6 +129,14 @@ impl JsonRpcRequestEnum {
             Self::Single(x) => Some(x.id.clone()),
         }
     }
+
+    /// true if any of the requests are for `method`
+    pub fn has_method(&self, method: &str) -> bool {
+        match self {
+            Self::Batch(x) => x.iter().any(|x| x.method == method),
+            Self::Single(x) => x.method == method,
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for JsonRpcRequestEnum {
@@ -135,9 +190,7 @@ impl<'de> Deserialize<'de> for JsonRpcRequestEnum {
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::JsonRpc => {
-                            // throw away the value
-                            // TODO: should we check that it's 2.0?
-                            // TODO: how do we skip over this value entirely?
+                            // the version is checked by `JsonRpcRequest::validate`
                             jsonrpc = Some(map.next_value()?);
                         }
                         Field::Id => {
@@ -162,8 +215,8 @@ impl<'de> Deserialize<'de> for JsonRpcRequestEnum {
                 }
 
                 // some providers don't follow the spec and dont include the jsonrpc key
-                // i think "2.0" should be a fine default to handle these incompatible clones
-                let jsonrpc = jsonrpc.unwrap_or_else(|| "2.0".to_string());
+                // `JsonRpcRequest::validate` decides if that is allowed
+                let jsonrpc = jsonrpc.unwrap_or_default();
                 // TODO: Errors returned by the try operator get shown in an ugly way
                 let id = id.ok_or_else(|| de::Error::missing_field("id"))?;
                 let method = method.ok_or_else(|| de::Error::missing_field("method"))?;
@@ -229,6 +282,15 @@ pub struct JsonRpcForwardedResponse {
     pub result: Option<Arc<RawValue>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<JsonRpcErrorData>,
+    /// How old the response is if it was served from the stale cache because the backends were down
+    #[serde(skip)]
+    pub stale_seconds: Option<u64>,
+    /// Set if the method is deprecated or an alias. Sent as a `Warning` header
+    #[serde(skip)]
+    pub deprecation: Option<String>,
+    /// The deprecation in the response itself. Only set if `deprecation_notes` is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
 }
 
 impl JsonRpcRequest {
@@ -241,6 +303,19 @@ impl JsonRpcRequest {
 }
 
 impl JsonRpcForwardedResponse {
+    /// Replace the result with the part of it at a JSON pointer. Missing parts become null. Errors are left alone
+    pub fn project_result(&mut self, pointer: &str) -> Web3ProxyResult<()> {
+        if let Some(result) = self.result.as_ref() {
+            let result: serde_json::Value = serde_json::from_str(result.get())?;
+
+            let projected = result.pointer(pointer).unwrap_or(&serde_json::Value::Null);
+
+            self.result = Some(to_raw_value(projected)?.into());
+        }
+
+        Ok(())
+    }
+
     pub fn from_anyhow_error(
         err: anyhow::Error,
         code: Option<i64>,
@@ -268,6 +343,9 @@ impl JsonRpcForwardedResponse {
                 // TODO: accept data as an argument
                 data: None,
             }),
+            stale_seconds: None,
+            deprecation: None,
+            warning: None,
         }
     }
 
@@ -278,6 +356,9 @@ impl JsonRpcForwardedResponse {
             // TODO: since we only use the result here, should that be all we return from try_send_request?
             result: Some(result),
             error: None,
+            stale_seconds: None,
+            deprecation: None,
+            warning: None,
         }
     }
 
@@ -292,6 +373,9 @@ impl JsonRpcForwardedResponse {
             id,
             result: Some(partial_response),
             error: None,
+            stale_seconds: None,
+            deprecation: None,
+            warning: None,
         }
     }
 
@@ -305,6 +389,9 @@ impl JsonRpcForwardedResponse {
                 id,
                 result: None,
                 error: Some(value),
+                stale_seconds: None,
+                deprecation: None,
+                warning: None,
             },
         }
     }
@@ -318,6 +405,42 @@ pub enum JsonRpcForwardedResponseEnum {
     Batch(Vec<JsonRpcForwardedResponse>),
 }
 
+impl JsonRpcForwardedResponseEnum {
+    /// The age of the oldest stale response. None if every response is fresh
+    pub fn stale_seconds(&self) -> Option<u64> {
+        match self {
+            Self::Single(x) => x.stale_seconds,
+            Self::Batch(x) => x.iter().filter_map(|x| x.stale_seconds).max(),
+        }
+    }
+
+    /// Each deprecation once, in the order of the requests
+    pub fn deprecations(&self) -> Vec<&str> {
+        let mut x: Vec<&str> = vec![];
+
+        let responses = match self {
+            Self::Single(x) => std::slice::from_ref(x),
+            Self::Batch(x) => x.as_slice(),
+        };
+
+        for deprecation in responses.iter().filter_map(|x| x.deprecation.as_deref()) {
+            if !x.contains(&deprecation) {
+                x.push(deprecation);
+            }
+        }
+
+        x
+    }
+
+    /// Replace each result with the part of it at a JSON pointer. Missing parts become null. Errors are left alone
+    pub fn project_results(&mut self, pointer: &str) -> Web3ProxyResult<()> {
+        match self {
+            Self::Single(x) => x.project_result(pointer),
+            Self::Batch(x) => x.iter_mut().try_for_each(|x| x.project_result(pointer)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -363,4 +486,98 @@ mod tests {
 
         assert!(matches!(output, JsonRpcRequestEnum::Batch(_)));
     }
+
+    #[test]
+    fn this_validate() {
+        let input = r#"{"method":"eth_blockNumber","params":[],"id":"abc"}"#;
+
+        let mut output: JsonRpcRequest = serde_json::from_str(input).unwrap();
+
+        assert!(output.clone().validate(true).is_err());
+
+        output.validate(false).unwrap();
+
+        assert_eq!(output.jsonrpc, "2.0");
+        assert_eq!(output.id.to_string(), r#""abc""#);
+
+        let input = r#"{"jsonrpc":"1.0","method":"eth_blockNumber","params":[],"id":null}"#;
+
+        let mut output: JsonRpcRequest = serde_json::from_str(input).unwrap();
+
+        assert!(output.clone().validate(true).is_err());
+
+        output.validate(false).unwrap();
+
+        assert_eq!(output.id.to_string(), "null");
+
+        let input = r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":"0x1","id":1}"#;
+
+        let mut output: JsonRpcRequest = serde_json::from_str(input).unwrap();
+
+        assert!(output.validate(true).is_err());
+        assert_eq!(output.id.to_string(), "1");
+
+        let input = r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":{"a":1}}"#;
+
+        let mut output: JsonRpcRequest = serde_json::from_str(input).unwrap();
+
+        assert!(output.validate(false).is_err());
+        assert_eq!(output.id.to_string(), "null");
+    }
+
+    #[test]
+    fn this_project_result() {
+        let id = to_raw_value(&1).unwrap();
+
+        let mut block = JsonRpcForwardedResponse::from_value(
+            json!({"number": "0x10", "hash": "0xabc", "transactions": [{"hash": "0xdef"}]}),
+            id.clone(),
+        );
+
+        block.project_result("/number").unwrap();
+        assert_eq!(block.result.as_ref().unwrap().get(), r#""0x10""#);
+
+        let mut block = JsonRpcForwardedResponse::from_value(
+            json!({"transactions": [{"hash": "0xdef"}]}),
+            id.clone(),
+        );
+
+        block.project_result("/transactions/0/hash").unwrap();
+        assert_eq!(block.result.as_ref().unwrap().get(), r#""0xdef""#);
+
+        // missing parts are null
+        block.project_result("/number").unwrap();
+        assert_eq!(block.result.as_ref().unwrap().get(), "null");
+
+        // errors are left alone
+        let mut error = JsonRpcForwardedResponse::from_str("oops", Some(-32000), Some(id));
+
+        error.project_result("/number").unwrap();
+        assert!(error.result.is_none());
+        assert!(error.error.is_some());
+    }
+
+    #[test]
+    fn this_deprecations() {
+        let id = to_raw_value(&1).unwrap();
+
+        let mut a = JsonRpcForwardedResponse::from_value(json!(1), id.clone());
+        a.deprecation = Some("eth_foo is deprecated".to_string());
+
+        let b = JsonRpcForwardedResponse::from_value(json!(2), id);
+
+        let batch = JsonRpcForwardedResponseEnum::Batch(vec![a.clone(), b, a.clone()]);
+
+        assert_eq!(batch.deprecations(), vec!["eth_foo is deprecated"]);
+
+        // the note is only in the json if it was set
+        assert!(serde_json::to_value(&a).unwrap().get("warning").is_none());
+
+        a.warning = a.deprecation.clone();
+
+        assert_eq!(
+            serde_json::to_value(&a).unwrap()["warning"],
+            json!("eth_foo is deprecated")
+        );
+    }
 }
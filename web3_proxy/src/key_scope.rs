@@ -0,0 +1,126 @@
+//! Named presets that lock a key down to a set of methods.
+//!
+//! Keys store the preset's name instead of its method list, so changes to a preset apply to every key that uses it.
+//! Origins and chains are separate columns on the key. A scoped key can be created with all of them in one call.
+use crate::errors::Web3ProxyError;
+use derive_more::Display;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Methods that send transactions or sign things. Read-only keys cannot call these
+const WRITE_METHODS: &[&str] = &[
+    "eth_cancelPrivateTransaction",
+    "eth_sendBundle",
+    "eth_sendPrivateTransaction",
+    "eth_sendRawTransaction",
+    "eth_sendTransaction",
+    "eth_sendUserOperation",
+    "eth_sign",
+    "eth_signTransaction",
+    "eth_signTypedData",
+    "eth_signTypedData_v3",
+    "eth_signTypedData_v4",
+    "eth_submitHashrate",
+    "eth_submitWork",
+];
+
+/// Method prefixes that send transactions or sign things
+const WRITE_PREFIXES: &[&str] = &["personal_"];
+
+/// Expensive method prefixes that public frontends do not need
+const HEAVY_PREFIXES: &[&str] = &["debug_", "trace_"];
+
+/// Subscriptions that stream every pending transaction. Public frontends do not need these either
+const HEAVY_SUBSCRIPTIONS: &[&str] = &[
+    "newPendingFullTransactions",
+    "newPendingRawTransactions",
+    "newPendingTransactions",
+];
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyScope {
+    /// no methods that send transactions or sign
+    #[display(fmt = "read-only")]
+    ReadOnly,
+    /// read-only, no debug_ or trace_ methods, and no pending transaction subscriptions. for keys that are shipped in public dapp frontends
+    #[display(fmt = "frontend")]
+    Frontend,
+}
+
+impl FromStr for KeyScope {
+    type Err = Web3ProxyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" => Ok(Self::ReadOnly),
+            "frontend" => Ok(Self::Frontend),
+            x => Err(Web3ProxyError::BadRequest(
+                format!("unknown key scope: {}", x).into(),
+            )),
+        }
+    }
+}
+
+impl KeyScope {
+    pub const ALL: [Self; 2] = [Self::ReadOnly, Self::Frontend];
+
+    /// A short description for `GET /user/keys/scopes`
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::ReadOnly => "no methods that send transactions or sign",
+            Self::Frontend => {
+                "read-only, no debug_ or trace_ methods, and no pending transaction subscriptions"
+            }
+        }
+    }
+
+    pub fn allows_method(&self, method: &str) -> bool {
+        let is_write =
+            WRITE_METHODS.contains(&method) || WRITE_PREFIXES.iter().any(|x| method.starts_with(x));
+
+        match self {
+            Self::ReadOnly => !is_write,
+            Self::Frontend => !is_write && !HEAVY_PREFIXES.iter().any(|x| method.starts_with(x)),
+        }
+    }
+
+    /// `subscribe_to` is the first param of `eth_subscribe`
+    pub fn allows_subscription(&self, subscribe_to: &str) -> bool {
+        match self {
+            Self::ReadOnly => true,
+            Self::Frontend => !HEAVY_SUBSCRIPTIONS.contains(&subscribe_to),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyScope;
+
+    #[test]
+    fn test_key_scopes() {
+        for scope in KeyScope::ALL {
+            assert_eq!(scope.to_string().parse::<KeyScope>().unwrap(), scope);
+
+            assert!(scope.allows_method("eth_call"));
+            assert!(scope.allows_method("eth_getLogs"));
+            assert!(!scope.allows_method("eth_sendRawTransaction"));
+            assert!(!scope.allows_method("personal_sign"));
+        }
+
+        assert!(KeyScope::ReadOnly.allows_method("debug_traceTransaction"));
+        assert!(!KeyScope::Frontend.allows_method("debug_traceTransaction"));
+        assert!(!KeyScope::Frontend.allows_method("trace_block"));
+
+        for scope in KeyScope::ALL {
+            assert!(scope.allows_subscription("newHeads"));
+        }
+
+        assert!(KeyScope::ReadOnly.allows_subscription("newPendingTransactions"));
+        assert!(!KeyScope::Frontend.allows_subscription("newPendingTransactions"));
+        assert!(!KeyScope::Frontend.allows_subscription("newPendingRawTransactions"));
+
+        assert!("admin".parse::<KeyScope>().is_err());
+    }
+}
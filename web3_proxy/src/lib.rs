@@ -6,19 +6,27 @@ pub mod admin_queries;
 pub mod app;
 pub mod balance;
 pub mod block_number;
+pub mod cache_params;
 pub mod caches;
 pub mod compute_units;
 pub mod config;
 pub mod errors;
 pub mod frontend;
+pub mod gas_oracle;
 pub mod http_params;
 pub mod jsonrpc;
+pub mod key_scope;
+pub mod method_route;
 pub mod pagerduty;
 pub mod prometheus;
+pub mod quota;
+pub mod redact;
 pub mod referral_code;
 pub mod relational_db;
+pub mod request_recorder;
 pub mod response_cache;
 pub mod rpcs;
 pub mod stats;
 pub mod sub_commands;
 pub mod user_token;
+pub mod webhooks;
@@ -0,0 +1,445 @@
+//! Which methods the proxy serves and where it sends them.
+//!
+//! Backends are not asked which methods they support. `Web3ProxyApp::_proxy_request_with_caching` dispatches on `method_route`,
+//! so the routes shown to users are the routes requests take.
+use crate::block_number::TRACE_CACHE_METHODS;
+use crate::compute_units::ComputeUnit;
+use crate::config::{AppConfig, WalletMethods};
+use derive_more::Display;
+use migration::sea_orm::prelude::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Which group of rpcs the app sends a method to
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MethodRoute {
+    /// answered by the proxy without a backend
+    #[display(fmt = "answered by the proxy")]
+    Local,
+    /// rejected by the proxy
+    #[display(fmt = "not supported")]
+    Unsupported,
+    #[display(fmt = "balanced_rpcs")]
+    Balanced,
+    /// falls back to the balanced rpcs if there are no private rpcs
+    #[display(fmt = "private_rpcs")]
+    Private,
+    #[display(fmt = "bundler_4337_rpcs")]
+    Bundler4337,
+}
+
+/// Where the app sends a method. `Web3ProxyApp::_proxy_request_with_caching` matches on this, so add new routes here
+pub fn method_route(method: &str) -> MethodRoute {
+    match method {
+        "eth_accounts" | "eth_blockNumber" | "eth_chainId" | "eth_coinbase" | "eth_hashrate"
+        | "eth_mining" | "eth_syncing" | "net_listening" | "net_peerCount"
+        | "web3_clientVersion" | "web3_sha3" => MethodRoute::Local,
+        "db_getHex"
+        | "db_getString"
+        | "db_putHex"
+        | "db_putString"
+        | "debug_accountRange"
+        | "debug_backtraceAt"
+        | "debug_blockProfile"
+        | "debug_bundler_clearState"
+        | "debug_bundler_dumpMempool"
+        | "debug_bundler_sendBundleNow"
+        | "debug_chaindbCompact"
+        | "debug_chaindbProperty"
+        | "debug_cpuProfile"
+        | "debug_freeOSMemory"
+        | "debug_freezeClient"
+        | "debug_gcStats"
+        | "debug_goTrace"
+        | "debug_memStats"
+        | "debug_mutexProfile"
+        | "debug_setBlockProfileRate"
+        | "debug_setGCPercent"
+        | "debug_setHead"
+        | "debug_setMutexProfileFraction"
+        | "debug_standardTraceBadBlockToFile"
+        | "debug_standardTraceBlockToFile"
+        | "debug_startCPUProfile"
+        | "debug_startGoTrace"
+        | "debug_stopCPUProfile"
+        | "debug_stopGoTrace"
+        | "debug_writeBlockProfile"
+        | "debug_writeMemProfile"
+        | "debug_writeMutexProfile"
+        | "erigon_cacheCheck"
+        | "eth_compileLLL"
+        | "eth_compileSerpent"
+        | "eth_compileSolidity"
+        | "eth_getCompilers"
+        | "eth_sendTransaction"
+        | "eth_sign"
+        | "eth_signTransaction"
+        | "eth_submitHashrate"
+        | "eth_submitWork"
+        | "les_addBalance"
+        | "les_setClientParams"
+        | "les_setDefaultParams"
+        | "miner_setEtherbase"
+        | "miner_setExtra"
+        | "miner_setGasLimit"
+        | "miner_setGasPrice"
+        | "miner_start"
+        | "miner_stop"
+        | "personal_ecRecover"
+        | "personal_importRawKey"
+        | "personal_listAccounts"
+        | "personal_lockAccount"
+        | "personal_newAccount"
+        | "personal_sendTransaction"
+        | "personal_sign"
+        | "personal_unlockAccount"
+        | "shh_addToGroup"
+        | "shh_getFilterChanges"
+        | "shh_getMessages"
+        | "shh_hasIdentity"
+        | "shh_newFilter"
+        | "shh_newGroup"
+        | "shh_newIdentity"
+        | "shh_post"
+        | "shh_uninstallFilter"
+        | "shh_version"
+        | "eth_getFilterChanges"
+        | "eth_getFilterLogs"
+        | "eth_newBlockFilter"
+        | "eth_newFilter"
+        | "eth_newPendingTransactionFilter"
+        | "eth_pollSubscriptions"
+        | "eth_uninstallFilter"
+        | "eth_subscribe"
+        | "eth_unsubscribe"
+        | "test" => MethodRoute::Unsupported,
+        "eth_sendUserOperation"
+        | "eth_estimateUserOperationGas"
+        | "eth_getUserOperationByHash"
+        | "eth_getUserOperationReceipt"
+        | "eth_supportedEntryPoints" => MethodRoute::Bundler4337,
+        "eth_sendRawTransaction" => MethodRoute::Private,
+        method if method.starts_with("admin_") => MethodRoute::Unsupported,
+        _ => MethodRoute::Balanced,
+    }
+}
+
+/// Methods that need the node to hold the caller's keys. Everything starting with `personal_` is included too
+const WALLET_METHODS: &[&str] = &[
+    "eth_accounts",
+    "eth_requestAccounts",
+    "eth_sendTransaction",
+    "eth_sign",
+    "eth_signTransaction",
+    "eth_signTypedData",
+    "eth_signTypedData_v3",
+    "eth_signTypedData_v4",
+];
+
+/// true if the method is rejected with an error that says to sign locally
+pub fn rejects_wallet_method(method: &str, wallet_methods: WalletMethods) -> bool {
+    if method == "eth_accounts" && wallet_methods == WalletMethods::EmptyAccounts {
+        return false;
+    }
+
+    WALLET_METHODS.contains(&method) || method.starts_with("personal_")
+}
+
+/// A migration hint for clients that use a deprecated method or an alias. Checked before aliases are renamed
+pub fn deprecation_warning(method: &str, config: &AppConfig) -> Option<String> {
+    if let Some(note) = config.deprecated_methods.get(method) {
+        if note.is_empty() {
+            return Some(format!("{} is deprecated", method));
+        }
+
+        return Some(format!("{} is deprecated. {}", method, note));
+    }
+
+    config
+        .method_aliases
+        .get(method)
+        .map(|x| format!("{} is an alias. use {} instead", method, x))
+}
+
+/// Methods with a known cost. Methods not listed here are still sent to the balanced rpcs, but there is no promise that they work.
+const KNOWN_METHODS: &[&str] = &[
+    "debug_traceBlockByHash",
+    "debug_traceBlockByNumber",
+    "debug_traceCall",
+    "debug_traceTransaction",
+    "erigon_forks",
+    "erigon_getHeaderByHash",
+    "erigon_getHeaderByNumber",
+    "erigon_getLogsByHash",
+    "erigon_issuance",
+    "eth_accounts",
+    "eth_blockNumber",
+    "eth_call",
+    "eth_chainId",
+    "eth_createAccessList",
+    "eth_estimateGas",
+    "eth_estimateUserOperationGas",
+    "eth_feeHistory",
+    "eth_gasPrice",
+    "eth_getBalance",
+    "eth_getBlockByHash",
+    "eth_getBlockByNumber",
+    "eth_getBlockReceipts",
+    "eth_getBlockTransactionCountByHash",
+    "eth_getBlockTransactionCountByNumber",
+    "eth_getCode",
+    "eth_getLogs",
+    "eth_getProof",
+    "eth_getStorageAt",
+    "eth_getTransactionByBlockHashAndIndex",
+    "eth_getTransactionByBlockNumberAndIndex",
+    "eth_getTransactionByHash",
+    "eth_getTransactionCount",
+    "eth_getTransactionReceipt",
+    "eth_getUncleByBlockHashAndIndex",
+    "eth_getUncleByBlockNumberAndIndex",
+    "eth_getUncleCountByBlockHash",
+    "eth_getUncleCountByBlockNumber",
+    "eth_getUserOperationByHash",
+    "eth_getUserOperationReceipt",
+    "eth_maxPriorityFeePerGas",
+    "eth_protocolVersion",
+    "eth_sendRawTransaction",
+    "eth_sendUserOperation",
+    "eth_supportedEntryPoints",
+    "eth_syncing",
+    "net_listening",
+    "net_version",
+    "trace_block",
+    "trace_call",
+    "trace_callMany",
+    "trace_filter",
+    "trace_get",
+    "trace_rawTransaction",
+    "trace_replayBlockTransactions",
+    "trace_replayTransaction",
+    "trace_transaction",
+    "web3_clientVersion",
+    "web3_sha3",
+];
+
+/// Methods that only exist on some chains
+fn chain_methods(chain_id: u64) -> &'static [&'static str] {
+    match chain_id {
+        137 => &[
+            "bor_getAuthor",
+            "bor_getCurrentProposer",
+            "bor_getCurrentValidators",
+            "bor_getRootHash",
+            "bor_getSignersAtHash",
+        ],
+        1101 => &[
+            "zkevm_batchNumber",
+            "zkevm_batchNumberByBlockNumber",
+            "zkevm_consolidatedBlockNumber",
+            "zkevm_getBatchByNumber",
+            "zkevm_getBroadcastURI",
+            "zkevm_isBlockConsolidated",
+            "zkevm_isBlockVirtualized",
+            "zkevm_verifiedBatchNumber",
+            "zkevm_virtualBatchNumber",
+        ],
+        _ => &[],
+    }
+}
+
+/// true if responses to this method can come from the response cache.
+/// This needs to be kept in sync with the `MethodRoute::Balanced` arm of `Web3ProxyApp::_proxy_request_with_caching` and `CacheMode::try_new`
+pub fn is_cached(method: &str, config: &AppConfig) -> bool {
+    if method_route(method) != MethodRoute::Balanced {
+        return false;
+    }
+
+    match method {
+        // these have their own branch that skips the cache
+        "eth_estimateGas" | "eth_getTransactionByHash" | "eth_getTransactionReceipt" => false,
+        "eth_maxPriorityFeePerGas" => !config.max_priority_fee_fallback,
+        method if TRACE_CACHE_METHODS.contains(&method) => config.trace_cache_max_bytes > 0,
+        method => !method.starts_with("trace_") && method != "debug_traceTransaction",
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MethodInfo {
+    pub route: MethodRoute,
+    pub cached: bool,
+    /// the cost relative to other methods. Responses from the cache and errors cost less
+    pub compute_units: Decimal,
+    /// keyless requests are not allowed to use this method
+    pub requires_key: bool,
+    /// set if this method is an alias for another method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias_for: Option<String>,
+}
+
+/// Every method the app will serve, minus `denied_methods` and plus `method_aliases`.
+/// Bundler methods are only included if there are bundler rpcs.
+pub fn served_methods(config: &AppConfig, has_bundler: bool) -> BTreeMap<String, MethodInfo> {
+    let info = |method: &str, alias_for: Option<String>| {
+        let route = method_route(method);
+
+        let requires_key = config
+            .public_allowed_methods
+            .as_ref()
+            .map_or(false, |x| !x.contains(method));
+
+        MethodInfo {
+            route,
+            cached: is_cached(method, config),
+            compute_units: ComputeUnit::new(
+                method,
+                config.chain_id,
+                0,
+                config.method_costs.get(config.chain_id, method),
+            )
+            .value(),
+            requires_key,
+            alias_for,
+        }
+    };
+
+    let is_served = |method: &str| match method_route(method) {
+        _ if rejects_wallet_method(method, config.wallet_methods) => false,
+        MethodRoute::Unsupported => false,
+        MethodRoute::Bundler4337 => has_bundler,
+        _ => !config.denied_methods.contains(method),
+    };
+
+    let mut methods: BTreeMap<String, MethodInfo> = KNOWN_METHODS
+        .iter()
+        .chain(chain_methods(config.chain_id))
+        .filter(|x| is_served(x))
+        .map(|x| (x.to_string(), info(x, None)))
+        .collect();
+
+    // aliases are replaced before anything else is checked
+    for (alias, method) in config.method_aliases.iter() {
+        if is_served(method) {
+            methods.insert(alias.clone(), info(method, Some(method.clone())));
+        } else {
+            methods.remove(alias);
+        }
+    }
+
+    methods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_route() {
+        assert_eq!(method_route("eth_chainId"), MethodRoute::Local);
+        assert_eq!(method_route("eth_sign"), MethodRoute::Unsupported);
+        assert_eq!(method_route("admin_peers"), MethodRoute::Unsupported);
+        assert_eq!(method_route("eth_sendRawTransaction"), MethodRoute::Private);
+        assert_eq!(
+            method_route("eth_sendUserOperation"),
+            MethodRoute::Bundler4337
+        );
+        assert_eq!(method_route("eth_getBalance"), MethodRoute::Balanced);
+    }
+
+    #[test]
+    fn test_rejects_wallet_method() {
+        assert!(rejects_wallet_method("eth_accounts", WalletMethods::Reject));
+        assert!(!rejects_wallet_method(
+            "eth_accounts",
+            WalletMethods::EmptyAccounts
+        ));
+        assert!(rejects_wallet_method(
+            "eth_sendTransaction",
+            WalletMethods::EmptyAccounts
+        ));
+        assert!(rejects_wallet_method(
+            "personal_unlockAccount",
+            WalletMethods::Reject
+        ));
+        assert!(!rejects_wallet_method(
+            "eth_sendRawTransaction",
+            WalletMethods::Reject
+        ));
+    }
+
+    #[test]
+    fn test_deprecation_warning() {
+        let mut config = AppConfig::default();
+
+        config.deprecated_methods.insert(
+            "bor_getAuthor".to_string(),
+            "it will be removed in march".to_string(),
+        );
+        config
+            .deprecated_methods
+            .insert("eth_foo".to_string(), "".to_string());
+        config.method_aliases.insert(
+            "alchemy_getTransactionReceipts".to_string(),
+            "eth_getBlockReceipts".to_string(),
+        );
+
+        assert_eq!(
+            deprecation_warning("bor_getAuthor", &config).as_deref(),
+            Some("bor_getAuthor is deprecated. it will be removed in march")
+        );
+        assert_eq!(
+            deprecation_warning("eth_foo", &config).as_deref(),
+            Some("eth_foo is deprecated")
+        );
+        assert_eq!(
+            deprecation_warning("alchemy_getTransactionReceipts", &config).as_deref(),
+            Some("alchemy_getTransactionReceipts is an alias. use eth_getBlockReceipts instead")
+        );
+        assert_eq!(deprecation_warning("eth_call", &config), None);
+    }
+
+    #[test]
+    fn test_known_methods_are_routed() {
+        for method in KNOWN_METHODS {
+            assert_ne!(method_route(method), MethodRoute::Unsupported, "{}", method);
+        }
+    }
+
+    #[test]
+    fn test_served_methods() {
+        let mut config = AppConfig {
+            chain_id: 1,
+            ..Default::default()
+        };
+
+        config.denied_methods.insert("eth_getProof".to_string());
+        config
+            .method_aliases
+            .insert("eth_foo".to_string(), "eth_getBalance".to_string());
+        config.public_allowed_methods = Some(["eth_call".to_string()].into_iter().collect());
+
+        let methods = served_methods(&config, false);
+
+        assert!(!methods.contains_key("eth_getProof"));
+        assert!(!methods.contains_key("eth_sendUserOperation"));
+        assert!(!methods.contains_key("eth_sign"));
+        assert!(!methods.contains_key("eth_accounts"));
+        assert!(!methods.contains_key("bor_getAuthor"));
+
+        assert!(methods["eth_getBalance"].cached);
+        assert!(!methods["eth_sendRawTransaction"].cached);
+        assert!(!methods["trace_call"].cached);
+        // confirmed traces have their own cache
+        assert!(methods["trace_block"].cached);
+        assert_eq!(methods["eth_call"].compute_units, Decimal::from(26));
+        assert!(!methods["eth_call"].requires_key);
+        assert!(methods["eth_getBalance"].requires_key);
+        assert_eq!(
+            methods["eth_foo"].alias_for.as_deref(),
+            Some("eth_getBalance")
+        );
+
+        assert!(served_methods(&config, true).contains_key("eth_sendUserOperation"));
+    }
+}
@@ -0,0 +1,137 @@
+//! Hard caps on how many requests a key can make in a day or a month.
+//!
+//! This is separate from the per-period rate limits. Rate limits smooth out bursts. Quotas are for metered plans.
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use derive_more::Display;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Windows are in UTC and reset on the boundary, not on a rolling basis.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaWindow {
+    #[display(fmt = "day")]
+    Day,
+    #[display(fmt = "month")]
+    Month,
+}
+
+impl FromStr for QuotaWindow {
+    type Err = Web3ProxyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Self::Day),
+            "month" => Ok(Self::Month),
+            x => Err(Web3ProxyError::BadRequest(
+                format!("unknown quota window: {}", x).into(),
+            )),
+        }
+    }
+}
+
+impl QuotaWindow {
+    /// The start of the window that `now` is in and the start of the next window.
+    pub fn bounds(&self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let today = Utc
+            .with_ymd_and_hms(now.year(), now.month(), now.day(), 0, 0, 0)
+            .unwrap();
+
+        match self {
+            Self::Day => (today, today + Duration::days(1)),
+            Self::Month => {
+                let start = Utc
+                    .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+                    .unwrap();
+
+                let end = if now.month() == 12 {
+                    Utc.with_ymd_and_hms(now.year() + 1, 1, 1, 0, 0, 0)
+                } else {
+                    Utc.with_ymd_and_hms(now.year(), now.month() + 1, 1, 0, 0, 0)
+                }
+                .unwrap();
+
+                (start, end)
+            }
+        }
+    }
+}
+
+/// The most requests a key can make in each window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestQuota {
+    pub max_requests: u64,
+    pub window: QuotaWindow,
+}
+
+impl RequestQuota {
+    /// None if the key does not have a quota
+    pub fn try_from_rpc_key(
+        quota_requests: Option<u64>,
+        quota_window: &str,
+    ) -> Web3ProxyResult<Option<Self>> {
+        let max_requests = match quota_requests {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let window = quota_window.parse()?;
+
+        Ok(Some(Self {
+            max_requests,
+            window,
+        }))
+    }
+
+    /// The redis key that counts a key's requests in the window that started at `window_start`.
+    /// Quotas are shared by every chain.
+    pub fn redis_key(&self, rpc_key_id: u64, window_start: DateTime<Utc>) -> String {
+        format!(
+            "quota:{}:{}:{}",
+            rpc_key_id,
+            self.window,
+            window_start.timestamp()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuotaWindow, RequestQuota};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_quota_window_bounds() {
+        let now = Utc.with_ymd_and_hms(2023, 12, 31, 23, 59, 59).unwrap();
+
+        let (start, end) = QuotaWindow::Day.bounds(now);
+        assert_eq!(start, Utc.with_ymd_and_hms(2023, 12, 31, 0, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+
+        let (start, end) = QuotaWindow::Month.bounds(now);
+        assert_eq!(start, Utc.with_ymd_and_hms(2023, 12, 1, 0, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+
+        let now = Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap();
+
+        let (start, end) = QuotaWindow::Month.bounds(now);
+        assert_eq!(start, now);
+        assert_eq!(end, Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_quota_from_rpc_key() {
+        assert_eq!(RequestQuota::try_from_rpc_key(None, "month").unwrap(), None);
+
+        assert_eq!(
+            RequestQuota::try_from_rpc_key(Some(10_000_000), "month").unwrap(),
+            Some(RequestQuota {
+                max_requests: 10_000_000,
+                window: QuotaWindow::Month,
+            })
+        );
+
+        assert!(RequestQuota::try_from_rpc_key(Some(1), "week").is_err());
+    }
+}
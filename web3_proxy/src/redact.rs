@@ -0,0 +1,83 @@
+//! Hide sensitive request params before they are logged or saved.
+//! Signed transactions and call data can be sensitive information.
+
+use crate::config::ParamsLogging;
+use ethers::types::{Bytes, H256};
+use ethers::utils::keccak256;
+use hashbrown::HashMap;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use serde_json::json;
+use tracing::warn;
+
+/// These are hashed unless `params_logging` says otherwise. We can't store raw signed transactions in plaintext.
+const RAW_TRANSACTION_METHODS: &[&str] = &[
+    "eth_sendPrivateTransaction",
+    "eth_sendRawTransaction",
+    "eth_sendRawTransactionConditional",
+];
+
+/// Set once at startup from the app config
+static PARAMS_LOGGING: OnceCell<HashMap<String, ParamsLogging>> = OnceCell::new();
+
+/// Set `PARAMS_LOGGING` from the config. It cannot be changed once it is set.
+pub fn set_params_logging(params_logging: &HashMap<String, ParamsLogging>) {
+    if PARAMS_LOGGING.get_or_init(|| params_logging.clone()) != params_logging {
+        warn!("params_logging cannot be changed without a restart");
+    }
+}
+
+pub fn params_logging(method: &str) -> ParamsLogging {
+    if let Some(x) = PARAMS_LOGGING.get().and_then(|x| x.get(method)) {
+        return *x;
+    }
+
+    if RAW_TRANSACTION_METHODS.contains(&method) {
+        ParamsLogging::Hash
+    } else {
+        ParamsLogging::Full
+    }
+}
+
+fn hash(data: &[u8]) -> String {
+    format!("{:?}", H256::from(keccak256(data)))
+}
+
+/// The params as they should appear in logs
+pub fn redact_params<P: Serialize>(method: &str, params: &P) -> serde_json::Value {
+    match params_logging(method) {
+        ParamsLogging::Full => json!(params),
+        ParamsLogging::Hash => {
+            let params = serde_json::to_vec(params).expect("params should always serialize");
+
+            json!(hash(&params))
+        }
+        ParamsLogging::Omit => serde_json::Value::Null,
+    }
+}
+
+/// The call data as it should be saved in the revert log
+pub fn redact_call_data(method: &str, data: Option<Bytes>) -> Option<String> {
+    match params_logging(method) {
+        ParamsLogging::Full => data.map(|x| x.to_string()),
+        ParamsLogging::Hash => data.map(|x| hash(&x)),
+        ParamsLogging::Omit => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_transactions_are_hashed() {
+        let params = json!(["0x1234"]);
+
+        let redacted = redact_params("eth_sendRawTransaction", &params);
+
+        assert_eq!(redacted, json!(hash(&serde_json::to_vec(&params).unwrap())));
+        assert_ne!(redacted.to_string(), params.to_string());
+
+        assert_eq!(redact_params("eth_blockNumber", &params), params);
+    }
+}
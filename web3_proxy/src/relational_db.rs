@@ -22,26 +22,50 @@ impl AsRef<DatabaseConnection> for DatabaseReplica {
     }
 }
 
+/// Size and timeouts for a database connection pool
+#[derive(Clone, Copy, Debug)]
+pub struct DbPoolOptions {
+    pub min_connections: u32,
+    pub max_connections: u32,
+    /// how long to wait for a free connection from the pool
+    pub acquire_timeout: Duration,
+    /// close connections that have been idle for this long. If none, idle connections are kept open
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for DbPoolOptions {
+    fn default() -> Self {
+        Self {
+            min_connections: 1,
+            max_connections: 4,
+            acquire_timeout: Duration::from_secs(5),
+            idle_timeout: None,
+        }
+    }
+}
+
 pub async fn get_db(
     db_url: String,
-    min_connections: u32,
-    max_connections: u32,
+    pool_options: DbPoolOptions,
 ) -> Result<DatabaseConnection, DbErr> {
     // TODO: scrub credentials and then include the db_url in logs
-    info!("Connecting to db");
+    info!(?pool_options, "Connecting to db");
 
     let mut db_opt = sea_orm::ConnectOptions::new(db_url);
 
-    // TODO: load all these options from the config file. i think docker mysql default max is 100
     // TODO: sqlx info logging is way too verbose for production.
     db_opt
-        .acquire_timeout(Duration::from_secs(5))
+        .acquire_timeout(pool_options.acquire_timeout)
         .connect_timeout(Duration::from_secs(5))
-        .min_connections(min_connections)
-        .max_connections(max_connections)
+        .min_connections(pool_options.min_connections)
+        .max_connections(pool_options.max_connections)
         .sqlx_logging_level(tracing::log::LevelFilter::Trace)
         .sqlx_logging(true);
 
+    if let Some(idle_timeout) = pool_options.idle_timeout {
+        db_opt.idle_timeout(idle_timeout);
+    }
+
     Database::connect(db_opt).await
 }
 
@@ -117,13 +141,10 @@ pub async fn migrate_db(
 /// Connect to the database and run migrations
 pub async fn get_migrated_db(
     db_url: String,
-    min_connections: u32,
-    max_connections: u32,
+    pool_options: DbPoolOptions,
 ) -> anyhow::Result<DatabaseConnection> {
     // TODO: this seems to fail silently
-    let db_conn = get_db(db_url, min_connections, max_connections)
-        .await
-        .context("getting db")?;
+    let db_conn = get_db(db_url, pool_options).await.context("getting db")?;
 
     migrate_db(&db_conn, false).await.context("migrating db")?;
 
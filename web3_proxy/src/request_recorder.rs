@@ -0,0 +1,301 @@
+//! Save a sample of real requests so that load tests can replay realistic traffic.
+//!
+//! Only the method and params are written, one JSON object per line. Ids, keys, ips, and origins are never saved.
+//! Methods that `params_logging` would hash or omit are skipped, as are methods that send transactions or need a websocket.
+//! Caller addresses in the params are replaced with a salted hash. The salt is random and never saved, so they can't be matched back.
+use crate::app::Web3ProxyJoinHandle;
+use crate::config::ParamsLogging;
+use crate::errors::Web3ProxyResult;
+use crate::redact::params_logging;
+use ethers::types::Address;
+use ethers::utils::{hex, keccak256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Replaying these would resend transactions or they only work on a websocket
+const SKIPPED_METHOD_PREFIXES: &[&str] = &[
+    "eth_send",
+    "eth_sign",
+    "eth_subscribe",
+    "eth_unsubscribe",
+    "personal_",
+];
+
+/// One line of the recording
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+impl RecordedRequest {
+    /// Replace the addresses of whoever made the request with a hash of them. The same address always gets the same replacement.
+    ///
+    /// Addresses that are top-level params (`eth_getBalance` and friends) and `from` fields are the caller's.
+    /// Contract addresses (`to` and `address`) are kept so that replays still call real contracts.
+    /// Calldata and log topics can include the caller's address too, so those copies are replaced as well
+    pub fn anonymize(&mut self, salt: &[u8]) {
+        let params = match self.params.as_array_mut() {
+            Some(x) => x,
+            None => return,
+        };
+
+        let mut replaced = vec![];
+
+        for param in params.iter_mut() {
+            let caller = match param {
+                Value::String(x) => x,
+                Value::Object(x) => match x.get_mut("from") {
+                    Some(Value::String(x)) => x,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            if let Ok(address) = caller.parse::<Address>() {
+                let anonymous = anonymous_address(address, salt);
+
+                *caller = format!("{:?}", anonymous);
+
+                replaced.push((address, anonymous));
+            }
+        }
+
+        for (address, anonymous) in replaced {
+            // abi encoding pads addresses, so look for the hex without the 0x
+            replace_hex(
+                &mut self.params,
+                &hex::encode(address),
+                &hex::encode(anonymous),
+            );
+        }
+    }
+}
+
+fn anonymous_address(address: Address, salt: &[u8]) -> Address {
+    let mut input = Vec::with_capacity(salt.len() + 20);
+    input.extend_from_slice(salt);
+    input.extend_from_slice(address.as_bytes());
+
+    Address::from_slice(&keccak256(input)[12..])
+}
+
+/// Replace `from` with `to` in every hex string. Hex is case-insensitive, so the strings are lowercased first
+fn replace_hex(value: &mut Value, from: &str, to: &str) {
+    match value {
+        Value::String(x) if x.starts_with("0x") => {
+            let lower = x.to_lowercase();
+
+            if lower.contains(from) {
+                *x = lower.replace(from, to);
+            }
+        }
+        Value::Array(x) => x.iter_mut().for_each(|x| replace_hex(x, from, to)),
+        Value::Object(x) => x.values_mut().for_each(|x| replace_hex(x, from, to)),
+        _ => {}
+    }
+}
+
+pub fn is_recordable(method: &str) -> bool {
+    !SKIPPED_METHOD_PREFIXES
+        .iter()
+        .any(|x| method.starts_with(x))
+        && params_logging(method) == ParamsLogging::Full
+}
+
+pub type RequestRecorderSender = mpsc::UnboundedSender<RecordedRequest>;
+
+pub struct RequestRecorder {
+    path: String,
+    max_requests: u64,
+    /// for `RecordedRequest::anonymize`. new every time the app starts and never saved
+    salt: Vec<u8>,
+}
+
+impl RequestRecorder {
+    /// Returns None if recording is disabled
+    pub async fn try_spawn(
+        path: Option<String>,
+        max_requests: u64,
+        shutdown_token: CancellationToken,
+    ) -> anyhow::Result<Option<(RequestRecorderSender, Web3ProxyJoinHandle<()>)>> {
+        let path = match path {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        // open now so that a bad path fails at startup instead of silently recording nothing
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let salt = [Uuid::new_v4(), Uuid::new_v4()]
+            .iter()
+            .flat_map(|x| *x.as_bytes())
+            .collect();
+
+        let x = Self {
+            path,
+            max_requests,
+            salt,
+        };
+
+        let handle = tokio::spawn(async move {
+            x.record_loop(BufWriter::new(file), receiver, shutdown_token)
+                .await
+        });
+
+        Ok(Some((sender, handle)))
+    }
+
+    async fn record_loop(
+        self,
+        mut file: BufWriter<tokio::fs::File>,
+        mut receiver: mpsc::UnboundedReceiver<RecordedRequest>,
+        shutdown_token: CancellationToken,
+    ) -> Web3ProxyResult<()> {
+        let mut flush_interval = interval(Duration::from_secs(1));
+        flush_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut recorded = 0;
+
+        loop {
+            tokio::select! {
+                x = receiver.recv() => {
+                    let mut x = match x {
+                        Some(x) => x,
+                        None => break,
+                    };
+
+                    if recorded >= self.max_requests {
+                        continue;
+                    }
+
+                    x.anonymize(&self.salt);
+
+                    let mut line = serde_json::to_vec(&x)?;
+                    line.push(b'\n');
+
+                    if let Err(err) = file.write_all(&line).await {
+                        warn!(?err, path = %self.path, "failed recording request");
+                        continue;
+                    }
+
+                    recorded += 1;
+
+                    if recorded == self.max_requests {
+                        info!(recorded, path = %self.path, "request recording is full");
+                    }
+                }
+                _ = flush_interval.tick() => {
+                    if let Err(err) = file.flush().await {
+                        warn!(?err, path = %self.path, "failed flushing recorded requests");
+                    }
+                }
+                _ = shutdown_token.cancelled() => {
+                    info!("request recorder shutting down");
+                    break;
+                }
+            }
+        }
+
+        file.flush().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_recordable, RecordedRequest};
+    use serde_json::json;
+
+    const CALLER: &str = "0x5f1b4f1c0b2b7f4d8c1e5a33e7a1c2d3b4a5f6e7";
+    const CONTRACT: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+
+    #[test]
+    fn test_is_recordable() {
+        assert!(is_recordable("eth_call"));
+        assert!(is_recordable("eth_getLogs"));
+        assert!(!is_recordable("eth_sendRawTransaction"));
+        assert!(!is_recordable("eth_sendTransaction"));
+        assert!(!is_recordable("eth_subscribe"));
+        assert!(!is_recordable("personal_unlockAccount"));
+    }
+
+    #[test]
+    fn test_recorded_request_json() {
+        let x = RecordedRequest {
+            method: "eth_getBalance".to_string(),
+            params: json!(["0x0000000000000000000000000000000000000000", "latest"]),
+        };
+
+        let line = serde_json::to_string(&x).unwrap();
+
+        // nothing but the method and params are saved
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&line).unwrap(),
+            json!({
+                "method": "eth_getBalance",
+                "params": ["0x0000000000000000000000000000000000000000", "latest"],
+            })
+        );
+
+        assert_eq!(serde_json::from_str::<RecordedRequest>(&line).unwrap(), x);
+    }
+
+    #[test]
+    fn test_anonymize() {
+        let salt = b"test salt";
+
+        // balanceOf(CALLER) from CALLER
+        let mut call = RecordedRequest {
+            method: "eth_call".to_string(),
+            params: json!([
+                {
+                    "from": CALLER,
+                    "to": CONTRACT,
+                    "data": format!("0x70a08231000000000000000000000000{}", &CALLER[2..].to_uppercase()),
+                },
+                "latest",
+            ]),
+        };
+        call.anonymize(salt);
+
+        let mut balance = RecordedRequest {
+            method: "eth_getBalance".to_string(),
+            params: json!([CALLER, "latest"]),
+        };
+        balance.anonymize(salt);
+
+        for x in [&call, &balance] {
+            let line = serde_json::to_string(x).unwrap().to_lowercase();
+
+            assert!(!line.contains(&CALLER[2..]), "{}", line);
+        }
+
+        // the same caller gets the same replacement everywhere
+        let anonymous = balance.params[0].as_str().unwrap();
+        assert_eq!(call.params[0]["from"], anonymous);
+        assert!(call.params[0]["data"]
+            .as_str()
+            .unwrap()
+            .ends_with(&anonymous[2..]));
+
+        // contracts and everything else are left alone
+        assert_eq!(call.params[0]["to"], CONTRACT);
+        assert_eq!(call.params[1], "latest");
+        assert_eq!(balance.params[1], "latest");
+    }
+}
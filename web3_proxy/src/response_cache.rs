@@ -1,16 +1,26 @@
-use crate::{block_number::BlockNumAndHash, errors::Web3ProxyError, jsonrpc::JsonRpcErrorData};
+use crate::{
+    block_number::BlockNumAndHash, cache_params::normalize_cache_params, errors::Web3ProxyError,
+    jsonrpc::JsonRpcErrorData,
+};
+use axum::headers::CacheControl;
 use derive_more::From;
 use ethers::{
     providers::{HttpClientError, JsonRpcError, ProviderError, WsClientError},
     types::U64,
 };
 use hashbrown::hash_map::DefaultHashBuilder;
-use moka::future::Cache;
+use moka::future::{Cache, ConcurrentCacheExt};
+use moka::PredicateError;
+use serde::Serialize;
 use serde_json::value::RawValue;
 use std::{
+    collections::BTreeMap,
     hash::{BuildHasher, Hash, Hasher},
+    sync::atomic::AtomicU64,
     sync::Arc,
+    time::Duration,
 };
+use tokio::time::Instant;
 
 #[derive(Clone, Debug, Eq, From)]
 pub struct JsonRpcQueryCacheKey {
@@ -50,12 +60,14 @@ impl Hash for JsonRpcQueryCacheKey {
 }
 
 impl JsonRpcQueryCacheKey {
+    /// `normalize` hashes the params the way `normalize_cache_params` writes them so that equivalent requests share a key
     pub fn new(
         from_block: Option<BlockNumAndHash>,
         to_block: Option<BlockNumAndHash>,
         method: &str,
         params: &serde_json::Value,
         cache_errors: bool,
+        normalize: bool,
     ) -> Self {
         let from_block_hash = from_block.as_ref().map(|x| x.hash());
         let to_block_hash = to_block.as_ref().map(|x| x.hash());
@@ -67,9 +79,28 @@ impl JsonRpcQueryCacheKey {
 
         method.hash(&mut hasher);
 
+        // the request id is not part of the key. requests that only differ by id share a response
+
+        // a range is already in the key. only a single block can replace a block param
+        let normalized = normalize
+            .then(|| {
+                let block = if to_block.is_none() {
+                    from_block.as_ref()
+                } else {
+                    None
+                };
+
+                normalize_cache_params(method, params, block)
+            })
+            .flatten();
+
         // TODO: make sure preserve_order feature is OFF
         // TODO: is there a faster way to do this?
-        params.to_string().hash(&mut hasher);
+        normalized
+            .as_ref()
+            .unwrap_or(params)
+            .to_string()
+            .hash(&mut hasher);
 
         cache_errors.hash(&mut hasher);
 
@@ -84,7 +115,143 @@ impl JsonRpcQueryCacheKey {
     }
 }
 
-pub type JsonRpcResponseCache = Cache<u64, JsonRpcResponseEnum<Arc<RawValue>>>;
+pub type JsonRpcResponseCache = Cache<u64, CachedResponse>;
+
+/// Requests that looked in the response caches
+pub static RESPONSE_CACHE_LOOKUPS: AtomicU64 = AtomicU64::new(0);
+/// Lookups that had to ask a backend. This includes requests whose cache policy refused the cached response
+pub static RESPONSE_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// A response and when it was cached
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub response: JsonRpcResponseEnum<Arc<RawValue>>,
+    pub cached_at: Instant,
+    /// the newest block this response depends on. None if it does not depend on a block number
+    pub block_num: Option<U64>,
+    /// kept so that admins can see and flush the responses for one method
+    pub method: Arc<str>,
+}
+
+impl CachedResponse {
+    pub fn new(
+        method: &str,
+        response: JsonRpcResponseEnum<Arc<RawValue>>,
+        block_num: Option<U64>,
+    ) -> Self {
+        Self {
+            response,
+            cached_at: Instant::now(),
+            block_num,
+            method: method.into(),
+        }
+    }
+
+    /// true if a reorg that starts at `reorg_block` could have changed this response
+    pub fn is_reorged(&self, reorg_block: &U64) -> bool {
+        self.block_num.map_or(false, |x| x >= *reorg_block)
+    }
+}
+
+/// What an admin sees about one of the response caches
+#[derive(Debug, Serialize)]
+pub struct ResponseCacheStats {
+    pub name: &'static str,
+    pub entries: u64,
+    pub weighted_size: u64,
+    pub max_capacity: Option<u64>,
+    pub entries_per_method: BTreeMap<String, u64>,
+}
+
+impl ResponseCacheStats {
+    /// Walks every entry, so this is only for admins
+    pub fn new(name: &'static str, cache: &JsonRpcResponseCache) -> Self {
+        // run pending maintenance so that the counts are current
+        cache.sync();
+
+        let mut entries_per_method = BTreeMap::<String, u64>::new();
+
+        for (_, v) in cache.iter() {
+            *entries_per_method.entry(v.method.to_string()).or_default() += 1;
+        }
+
+        Self {
+            name,
+            entries: cache.entry_count(),
+            weighted_size: cache.weighted_size(),
+            max_capacity: cache.policy().max_capacity(),
+            entries_per_method,
+        }
+    }
+}
+
+/// Throw away cached responses. None flushes every method. The cache must be built with `support_invalidation_closures`
+pub fn flush_response_cache(
+    cache: &JsonRpcResponseCache,
+    method: Option<&str>,
+) -> Result<(), PredicateError> {
+    match method {
+        None => cache.invalidate_all(),
+        Some(method) => {
+            let method: Arc<str> = method.into();
+
+            cache.invalidate_entries_if(move |_, v: &CachedResponse| v.method == method)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Which cached responses a request will accept.
+/// Set by the user's tier or the app's `response_cache_max_age_seconds`. The request's `Cache-Control` header can only make it stricter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// any cached response
+    #[default]
+    Any,
+    /// only responses cached less than this long ago. older responses are refreshed
+    MaxAge(Duration),
+    /// always ask a backend. the response still replaces the cached one
+    Bypass,
+}
+
+impl CachePolicy {
+    /// None accepts any age. 0 bypasses the cache
+    pub fn from_max_age_seconds(max_age_seconds: Option<u64>) -> Self {
+        match max_age_seconds {
+            None => Self::Any,
+            Some(0) => Self::Bypass,
+            Some(x) => Self::MaxAge(Duration::from_secs(x)),
+        }
+    }
+
+    /// `no-cache` and `no-store` bypass the cache. `max-age` limits the age
+    pub fn from_cache_control(cache_control: &CacheControl) -> Self {
+        if cache_control.no_cache() || cache_control.no_store() {
+            Self::Bypass
+        } else {
+            Self::from_max_age_seconds(cache_control.max_age().map(|x| x.as_secs()))
+        }
+    }
+
+    /// The policy that accepts fewer cached responses
+    pub fn stricter(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Bypass, _) | (_, Self::Bypass) => Self::Bypass,
+            (Self::MaxAge(a), Self::MaxAge(b)) => Self::MaxAge(a.min(b)),
+            (Self::MaxAge(x), Self::Any) | (Self::Any, Self::MaxAge(x)) => Self::MaxAge(x),
+            (Self::Any, Self::Any) => Self::Any,
+        }
+    }
+
+    pub fn accepts(&self, cached: &CachedResponse) -> bool {
+        match self {
+            Self::Any => true,
+            Self::MaxAge(max_age) => cached.cached_at.elapsed() <= *max_age,
+            Self::Bypass => false,
+        }
+    }
+}
 
 /// TODO: we might need one that holds RawValue and one that holds serde_json::Value
 #[derive(Clone, Debug)]
@@ -266,8 +433,12 @@ impl JsonRpcResponseWeigher {
 
 #[cfg(test)]
 mod tests {
-    use super::JsonRpcResponseEnum;
+    use super::{
+        flush_response_cache, CachePolicy, CachedResponse, JsonRpcResponseCache,
+        JsonRpcResponseEnum, ResponseCacheStats,
+    };
     use crate::response_cache::JsonRpcResponseWeigher;
+    use axum::headers::CacheControl;
     use moka::future::{Cache, CacheBuilder, ConcurrentCacheExt};
     use serde_json::value::RawValue;
     use std::{sync::Arc, time::Duration};
@@ -329,4 +500,96 @@ mod tests {
         // now it should be empty
         assert!(test_cache.get(&2).is_none());
     }
+
+    #[test]
+    fn test_is_reorged() {
+        let response = JsonRpcResponseEnum::from(Box::<RawValue>::default());
+
+        let at_100 = CachedResponse::new("eth_getBalance", response.clone(), Some(100.into()));
+        let forever = CachedResponse::new("eth_chainId", response, None);
+
+        assert!(at_100.is_reorged(&99.into()));
+        assert!(at_100.is_reorged(&100.into()));
+        assert!(!at_100.is_reorged(&101.into()));
+        assert!(!forever.is_reorged(&0.into()));
+    }
+
+    #[tokio::test]
+    async fn test_flush_response_cache() {
+        let cache: JsonRpcResponseCache = CacheBuilder::new(100)
+            .support_invalidation_closures()
+            .build();
+
+        let response = JsonRpcResponseEnum::from(Box::<RawValue>::default());
+
+        cache
+            .insert(1, CachedResponse::new("eth_call", response.clone(), None))
+            .await;
+        cache
+            .insert(2, CachedResponse::new("eth_call", response.clone(), None))
+            .await;
+        cache
+            .insert(3, CachedResponse::new("eth_getBalance", response, None))
+            .await;
+
+        let stats = ResponseCacheStats::new("test", &cache);
+
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.entries_per_method.get("eth_call"), Some(&2));
+        assert_eq!(stats.entries_per_method.get("eth_getBalance"), Some(&1));
+
+        flush_response_cache(&cache, Some("eth_call")).unwrap();
+
+        assert!(cache.get(&1).is_none());
+        assert!(cache.get(&2).is_none());
+        assert!(cache.get(&3).is_some());
+
+        flush_response_cache(&cache, None).unwrap();
+
+        assert!(cache.get(&3).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cache_policy() {
+        let cached = CachedResponse::new(
+            "eth_chainId",
+            JsonRpcResponseEnum::from(Box::<RawValue>::default()),
+            None,
+        );
+
+        let max_age = CachePolicy::from_max_age_seconds(Some(10));
+
+        assert!(CachePolicy::Any.accepts(&cached));
+        assert!(max_age.accepts(&cached));
+        assert!(!CachePolicy::Bypass.accepts(&cached));
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+
+        assert!(CachePolicy::Any.accepts(&cached));
+        assert!(!max_age.accepts(&cached));
+
+        assert_eq!(CachePolicy::from_max_age_seconds(None), CachePolicy::Any);
+        assert_eq!(
+            CachePolicy::from_max_age_seconds(Some(0)),
+            CachePolicy::Bypass
+        );
+
+        assert_eq!(
+            max_age.stricter(CachePolicy::from_max_age_seconds(Some(5))),
+            CachePolicy::MaxAge(Duration::from_secs(5))
+        );
+        assert_eq!(max_age.stricter(CachePolicy::Any), max_age);
+        assert_eq!(max_age.stricter(CachePolicy::Bypass), CachePolicy::Bypass);
+
+        assert_eq!(
+            CachePolicy::from_cache_control(&CacheControl::new().with_no_cache()),
+            CachePolicy::Bypass
+        );
+        assert_eq!(
+            CachePolicy::from_cache_control(
+                &CacheControl::new().with_max_age(Duration::from_secs(30))
+            ),
+            CachePolicy::MaxAge(Duration::from_secs(30))
+        );
+    }
 }
@@ -0,0 +1,128 @@
+//! Catch backends that silently disagree with each other.
+//!
+//! A sample of responses is requested again from a different backend and the two are compared.
+//! The params already have their block pinned by the time they are sent, so honest backends should give the same answer.
+use super::many::Web3Rpcs;
+use super::one::Web3Rpc;
+use super::request::{OpenRequestResult, RequestErrorHandler};
+use ethers::types::U64;
+use nanorand::Rng;
+use serde_json::value::RawValue;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::{debug, trace, warn};
+
+/// Responses that were compared with a second backend
+pub static RESPONSE_AUDITS: AtomicU64 = AtomicU64::new(0);
+
+/// Compared responses that did not match
+pub static RESPONSE_AUDIT_MISMATCHES: AtomicU64 = AtomicU64::new(0);
+
+/// Audits are best-effort. Give up on the second backend after this long
+const AUDIT_MAX_WAIT: Duration = Duration::from_secs(10);
+
+/// `chance` is out of `u16::MAX`, the same as `log_revert_chance`
+pub fn should_audit(chance: u16) -> bool {
+    chance > 0 && nanorand::tls_rng().generate_range(0u16..u16::MAX) < chance
+}
+
+impl Web3Rpcs {
+    /// Send the request to an rpc that is not in `skip_rpcs` and compare its response with `expected`.
+    /// Mismatches are logged and counted. Failures to get a second response are not.
+    pub async fn audit_response(
+        &self,
+        method: &str,
+        params: &serde_json::Value,
+        expected: &RawValue,
+        mut skip_rpcs: Vec<Arc<Web3Rpc>>,
+        min_block_needed: Option<&U64>,
+        max_block_needed: Option<&U64>,
+    ) {
+        let first_rpcs: Vec<_> = skip_rpcs.iter().map(|x| x.name.clone()).collect();
+
+        let handle = match self
+            .wait_for_best_rpc(
+                None,
+                &mut skip_rpcs,
+                min_block_needed,
+                max_block_needed,
+                Some(AUDIT_MAX_WAIT),
+                Some(RequestErrorHandler::DebugLevel),
+            )
+            .await
+        {
+            Ok(OpenRequestResult::Handle(x)) => x,
+            Ok(_) => {
+                trace!(%method, "no other rpc to audit with");
+                return;
+            }
+            Err(err) => {
+                debug!(?err, %method, "failed picking an rpc to audit with");
+                return;
+            }
+        };
+
+        let audit_rpc = handle.clone_connection();
+
+        let audited = match timeout(
+            AUDIT_MAX_WAIT,
+            handle.request::<_, Box<RawValue>>(method, params),
+        )
+        .await
+        {
+            Ok(Ok(x)) => x,
+            Ok(Err(err)) => {
+                debug!(?err, %method, rpc=%audit_rpc, "audit request failed");
+                return;
+            }
+            Err(_) => {
+                debug!(%method, rpc=%audit_rpc, "audit request timed out");
+                return;
+            }
+        };
+
+        RESPONSE_AUDITS.fetch_add(1, Ordering::Relaxed);
+
+        if !responses_match(expected, &audited) {
+            RESPONSE_AUDIT_MISMATCHES.fetch_add(1, Ordering::Relaxed);
+
+            warn!(%method, ?first_rpcs, audit_rpc=%audit_rpc, "backends returned different responses");
+            debug!(%method, %params, expected=%expected.get(), audited=%audited.get(), "mismatched responses");
+        }
+    }
+}
+
+/// Compared as parsed json so that whitespace and key order do not count as differences
+fn responses_match(a: &RawValue, b: &RawValue) -> bool {
+    match (
+        serde_json::from_str::<serde_json::Value>(a.get()),
+        serde_json::from_str::<serde_json::Value>(b.get()),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.get() == b.get(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{responses_match, should_audit};
+    use serde_json::value::RawValue;
+
+    #[test]
+    fn test_responses_match() {
+        let a = RawValue::from_string(r#"{"a":1,"b":"0x2"}"#.to_string()).unwrap();
+        let b = RawValue::from_string(r#"{ "b": "0x2", "a": 1 }"#.to_string()).unwrap();
+        let c = RawValue::from_string(r#"{"a":1,"b":"0x3"}"#.to_string()).unwrap();
+
+        assert!(responses_match(&a, &b));
+        assert!(!responses_match(&a, &c));
+    }
+
+    #[test]
+    fn test_should_audit() {
+        assert!(!should_audit(0));
+        assert!(should_audit(u16::MAX));
+    }
+}
@@ -13,6 +13,7 @@ use serde::ser::SerializeStruct;
 use serde::Serialize;
 use serde_json::json;
 use std::hash::Hash;
+use std::sync::atomic::AtomicU64;
 use std::time::Duration;
 use std::{fmt::Display, sync::Arc};
 use tokio::sync::{broadcast, mpsc};
@@ -25,6 +26,12 @@ pub type ArcBlock = Arc<Block<TxHash>>;
 pub type BlocksByHashCache = Cache<H256, Web3ProxyBlock>;
 pub type BlocksByNumberCache = Cache<U64, H256>;
 
+/// consensus head changes that did not build on the previous head
+pub static CHAIN_REORGS: AtomicU64 = AtomicU64::new(0);
+
+/// reorgs that orphaned a block with at least `finality_confirmations`. responses cached as final might have been wrong
+pub static DEEP_CHAIN_REORGS: AtomicU64 = AtomicU64::new(0);
+
 /// A block and its age.
 #[derive(Clone, Debug, Default, From)]
 pub struct Web3ProxyBlock {
@@ -136,6 +143,64 @@ impl Web3ProxyBlock {
     pub fn uncles(&self) -> &[H256] {
         &self.block.uncles
     }
+
+    /// How this block replaced `old` as the head.
+    /// Parent hashes are walked back through `blocks_by_hash` to the common ancestor, so skipped heads and deep reorgs are found too.
+    pub fn head_change(
+        &self,
+        old: &Web3ProxyBlock,
+        blocks_by_hash: &BlocksByHashCache,
+    ) -> HeadChange {
+        let mut new_side = self.clone();
+        let mut old_side = old.clone();
+
+        loop {
+            if new_side.hash() == old_side.hash() {
+                if old_side.hash() == old.hash() {
+                    return HeadChange::Extended;
+                }
+
+                return HeadChange::Reorg {
+                    start: *old_side.number() + 1,
+                    depth: (*old.number() - *old_side.number()).as_u64(),
+                };
+            }
+
+            // step back whichever side is higher. both step when they are at the same height
+            let step_new = new_side.number() >= old_side.number();
+            let step_old = old_side.number() >= new_side.number();
+
+            let unknown = HeadChange::Unknown {
+                start: *new_side.number().min(old_side.number()),
+            };
+
+            if step_new {
+                match blocks_by_hash.get(new_side.parent_hash()) {
+                    Some(parent) => new_side = parent,
+                    None => return unknown,
+                }
+            }
+
+            if step_old {
+                match blocks_by_hash.get(old_side.parent_hash()) {
+                    Some(parent) => old_side = parent,
+                    None => return unknown,
+                }
+            }
+        }
+    }
+}
+
+/// How a new head block relates to the previous head
+#[derive(Debug, PartialEq)]
+pub enum HeadChange {
+    /// the new head builds on the old head. blocks in between might have been skipped
+    Extended,
+    /// blocks of the old head's chain starting at `start` were orphaned. `depth` is how many
+    Reorg { start: U64, depth: u64 },
+    /// a parent was not in the cache before the common ancestor was found.
+    /// blocks starting at `start` might have been orphaned. a reorg could also go deeper than that
+    Unknown { start: U64 },
 }
 
 impl TryFrom<ArcBlock> for Web3ProxyBlock {
@@ -168,6 +233,11 @@ impl Display for Web3ProxyBlock {
 }
 
 impl Web3Rpcs {
+    /// How `new` replaced `old` as the head. See [`Web3ProxyBlock::head_change`]
+    pub fn head_change(&self, old: &Web3ProxyBlock, new: &Web3ProxyBlock) -> HeadChange {
+        new.head_change(old, &self.blocks_by_hash)
+    }
+
     /// add a block to our mappings and track the heaviest chain
     pub async fn try_cache_block(
         &self,
@@ -441,7 +511,15 @@ impl Web3Rpcs {
         let mut had_first_success = false;
 
         loop {
-            match timeout(double_block_time, block_receiver.recv()).await {
+            let x = tokio::select! {
+                x = timeout(double_block_time, block_receiver.recv()) => x,
+                _ = self.shutdown_token.cancelled() => {
+                    debug!("stopped processing blocks on {}", self);
+                    return Ok(());
+                }
+            };
+
+            match x {
                 Ok(Some((new_block, rpc))) => {
                     let rpc_name = rpc.name.clone();
                     let rpc_is_backup = rpc.backup;
@@ -510,3 +588,103 @@ impl Web3Rpcs {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{BlocksByHashCache, HeadChange, Web3ProxyBlock};
+    use ethers::prelude::{Block, H256};
+    use moka::future::Cache;
+    use std::sync::Arc;
+
+    fn block(num: u64, hash: u64, parent_hash: u64) -> Web3ProxyBlock {
+        Web3ProxyBlock::try_from(Arc::new(Block {
+            number: Some(num.into()),
+            hash: Some(H256::from_low_u64_be(hash)),
+            parent_hash: H256::from_low_u64_be(parent_hash),
+            ..Default::default()
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_head_change() {
+        let blocks_by_hash: BlocksByHashCache = Cache::new(100);
+
+        // 98 <- 99 <- 100 <- 101 on the main chain. 99 <- 100' <- 101' on a fork
+        for b in [
+            block(98, 98, 97),
+            block(99, 99, 98),
+            block(100, 100, 99),
+            block(101, 101, 100),
+            block(100, 200, 99),
+            block(101, 201, 200),
+        ] {
+            blocks_by_hash.insert(*b.hash(), b).await;
+        }
+
+        let head = block(100, 100, 99);
+
+        // the next block
+        assert_eq!(
+            block(101, 101, 100).head_change(&head, &blocks_by_hash),
+            HeadChange::Extended
+        );
+        // the same block
+        assert_eq!(
+            head.head_change(&head, &blocks_by_hash),
+            HeadChange::Extended
+        );
+        // skipped blocks are walked through
+        assert_eq!(
+            block(102, 102, 101).head_change(&head, &blocks_by_hash),
+            HeadChange::Extended
+        );
+
+        // built on a different block 100
+        assert_eq!(
+            block(101, 201, 200).head_change(&head, &blocks_by_hash),
+            HeadChange::Reorg {
+                start: 100.into(),
+                depth: 1
+            }
+        );
+        // skipped blocks on a different block 100
+        assert_eq!(
+            block(102, 202, 201).head_change(&head, &blocks_by_hash),
+            HeadChange::Reorg {
+                start: 100.into(),
+                depth: 1
+            }
+        );
+        // uncled
+        assert_eq!(
+            block(100, 200, 99).head_change(&head, &blocks_by_hash),
+            HeadChange::Reorg {
+                start: 100.into(),
+                depth: 1
+            }
+        );
+        // two blocks deep
+        assert_eq!(
+            block(100, 200, 99).head_change(&block(101, 101, 100), &blocks_by_hash),
+            HeadChange::Reorg {
+                start: 100.into(),
+                depth: 2
+            }
+        );
+        // rolled back
+        assert_eq!(
+            block(98, 98, 97).head_change(&head, &blocks_by_hash),
+            HeadChange::Reorg {
+                start: 99.into(),
+                depth: 2
+            }
+        );
+
+        // a parent that isn't cached
+        assert_eq!(
+            block(105, 105, 104).head_change(&head, &blocks_by_hash),
+            HeadChange::Unknown { start: 100.into() }
+        );
+    }
+}
@@ -1,5 +1,5 @@
-use super::blockchain::Web3ProxyBlock;
-use super::many::Web3Rpcs;
+use super::blockchain::{HeadChange, Web3ProxyBlock, CHAIN_REORGS};
+use super::many::{clear_no_servers_synced_alert, Web3Rpcs};
 use super::one::Web3Rpc;
 use super::transactions::TxStatus;
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
@@ -290,10 +290,12 @@ impl RankedRpcs {
 
     // TODO: better name for this
     // TODO: this should probably be on the rpcs as "can_serve_request"
-    // TODO: this should probably take the method, too
+    #[allow(clippy::too_many_arguments)]
     pub fn rpc_will_work_now(
         &self,
         skip: &[Arc<Web3Rpc>],
+        method: Option<&str>,
+        state_override_request: bool,
         min_block_needed: Option<&U64>,
         max_block_needed: Option<&U64>,
         rpc: &Arc<Web3Rpc>,
@@ -303,6 +305,21 @@ impl RankedRpcs {
             return false;
         }
 
+        if state_override_request && !rpc.state_overrides() {
+            trace!(
+                "{} does not support state overrides. will not work now",
+                rpc
+            );
+            return false;
+        }
+
+        if let Some(method) = method {
+            if !rpc.serves_method(method) {
+                trace!("{} does not serve {}. will not work now", rpc, method);
+                return false;
+            }
+        }
+
         if let Some(min_block_needed) = min_block_needed {
             if !self.has_block_data(rpc, min_block_needed) {
                 trace!(
@@ -454,6 +471,8 @@ impl ConsensusFinder {
             .watch_ranked_rpcs
             .send_replace(Some(new_ranked_rpcs.clone()));
 
+        clear_no_servers_synced_alert();
+
         let backups_voted_str = if backups_needed { "B " } else { "" };
 
         let rpc_head_str = if let Some(rpc) = rpc.as_ref() {
@@ -502,6 +521,18 @@ impl ConsensusFinder {
             Some(old_consensus_connections) => {
                 let old_head_block = &old_consensus_connections.head_block;
 
+                match web3_rpcs.head_change(old_head_block, &consensus_head_block) {
+                    HeadChange::Extended => {}
+                    HeadChange::Reorg { start, depth } => {
+                        CHAIN_REORGS.fetch_add(1, atomic::Ordering::Relaxed);
+
+                        info!(reorg_block=%start, depth, new=%consensus_head_block, old=%old_head_block, "reorg");
+                    }
+                    HeadChange::Unknown { start } => {
+                        debug!(reorg_block=%start, new=%consensus_head_block, old=%old_head_block, "unable to find common ancestor");
+                    }
+                }
+
                 match consensus_head_block.number().cmp(old_head_block.number()) {
                     Ordering::Equal => {
                         // multiple blocks with the same fork!
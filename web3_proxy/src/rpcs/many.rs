@@ -10,31 +10,79 @@ use crate::frontend::authorization::{Authorization, RequestMetadata};
 use crate::frontend::rpc_proxy_ws::ProxyMode;
 use crate::frontend::status::MokaCacheSerializer;
 use crate::jsonrpc::{JsonRpcErrorData, JsonRpcParams, JsonRpcResultData};
+use crate::redact::redact_params;
 use crate::rpcs::transactions::TxStatus;
 use counter::Counter;
 use derive_more::From;
-use ethers::prelude::{ProviderError, TxHash, U64};
+use ethers::prelude::{ProviderError, TxHash, H256, U64};
 use futures::future::try_join_all;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
 use migration::sea_orm::DatabaseConnection;
 use moka::future::{Cache, CacheBuilder};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
 use serde_json::json;
 use serde_json::value::RawValue;
 use std::cmp::min_by_key;
-use std::fmt::{self, Display};
-use std::sync::atomic::Ordering;
+use std::fmt::{self, Display, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::select;
 use tokio::sync::{broadcast, mpsc, watch, RwLock as AsyncRwLock};
-use tokio::time::{sleep, sleep_until, Duration, Instant};
+use tokio::time::{sleep_until, timeout, Duration, Instant};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, trace, warn};
 
+/// Requests that failed because no rpc had a head block
+pub static NO_SERVERS_SYNCED: AtomicU64 = AtomicU64::new(0);
+
+/// Times that requests started failing because no rpc had a head block
+pub static NO_SERVERS_SYNCED_ALERTS: AtomicU64 = AtomicU64::new(0);
+
+/// True from the first request that fails because no rpc had a head block until there is a consensus head again
+static NO_SERVERS_SYNCED_ALERTING: AtomicBool = AtomicBool::new(false);
+
+/// Alert once per outage instead of once per failed request
+fn alert_no_servers_synced(known: usize) {
+    if !NO_SERVERS_SYNCED_ALERTING.swap(true, Ordering::Relaxed) {
+        NO_SERVERS_SYNCED_ALERTS.fetch_add(1, Ordering::Relaxed);
+
+        error!(
+            known,
+            "ALERT: no synced backends. requests are failing with 503s"
+        );
+    }
+}
+
+/// Called whenever there is a consensus head. Ends the outage that `alert_no_servers_synced` started
+pub(super) fn clear_no_servers_synced_alert() {
+    if NO_SERVERS_SYNCED_ALERTING.swap(false, Ordering::Relaxed) {
+        info!("backends are synced again");
+    }
+}
+
+/// One rpc's head block compared to the consensus head. Used by `GET /heads`
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct RpcHead {
+    pub name: String,
+    pub backup: bool,
+    pub head_block_num: Option<U64>,
+    pub head_block_hash: Option<H256>,
+    /// blocks behind the consensus head. negative if this rpc is ahead
+    pub lag: Option<i64>,
+    /// true if this rpc is one of the ranked rpcs that agreed on the consensus head
+    pub in_consensus: bool,
+    pub syncing: bool,
+    pub admin_disabled: bool,
+    /// unix time in milliseconds of the last change to this rpc's head block
+    pub updated_at: Option<u64>,
+}
+
 /// A collection of web3 connections. Sends requests either the current best server or all servers.
 #[derive(From)]
 pub struct Web3Rpcs {
@@ -46,6 +94,12 @@ pub struct Web3Rpcs {
     /// any requests will be forwarded to one (or more) of these connections
     /// TODO: hopefully this not being an async lock will be okay. if you need it across awaits, clone the arc
     pub(crate) by_name: RwLock<HashMap<String, Arc<Web3Rpc>>>,
+    /// names of rpcs that an admin took out of rotation. kept here so that a config reload doesn't put them back
+    pub(super) admin_disabled: RwLock<HashSet<String>>,
+    /// cancelled when the app shuts down. each rpc gets a child token
+    pub(super) shutdown_token: CancellationToken,
+    /// the subscription tasks for each rpc. awaited in `wait_for_shutdown`
+    pub(super) rpc_handles: Mutex<Vec<Web3ProxyJoinHandle<()>>>,
     /// all providers with the same consensus head block. won't update if there is no `self.watch_consensus_head_sender`
     /// TODO: document that this is a watch sender and not a broadcast! if things get busy, blocks might get missed
     /// TODO: why is watch_consensus_head_sender in an Option, but this one isn't?
@@ -85,6 +139,7 @@ impl Web3Rpcs {
         name: String,
         pending_transaction_cache: Cache<TxHash, TxStatus>,
         pending_tx_sender: Option<broadcast::Sender<TxStatus>>,
+        shutdown_token: CancellationToken,
         watch_consensus_head_sender: Option<watch::Sender<Option<Web3ProxyBlock>>>,
     ) -> anyhow::Result<(
         Arc<Self>,
@@ -122,6 +177,7 @@ impl Web3Rpcs {
             average_block_interval(chain_id).mul_f32((max_head_block_lag.as_u64() * 10) as f32);
 
         let connections = Arc::new(Self {
+            admin_disabled: Default::default(),
             block_sender,
             blocks_by_hash,
             blocks_by_number,
@@ -135,6 +191,8 @@ impl Web3Rpcs {
             pending_transaction_cache,
             pending_tx_id_receiver: AsyncRwLock::new(pending_tx_id_receiver),
             pending_tx_id_sender,
+            rpc_handles: Default::default(),
+            shutdown_token,
             watch_head_block: watch_consensus_head_sender,
             watch_ranked_rpcs: watch_consensus_rpcs_sender,
         });
@@ -157,13 +215,13 @@ impl Web3Rpcs {
         rpc_configs: HashMap<String, Web3RpcConfig>,
     ) -> Web3ProxyResult<()> {
         // safety checks
-        if rpc_configs.len() < app.config.min_synced_rpcs {
+        if rpc_configs.len() < app.config.load().min_synced_rpcs {
             // TODO: don't count disabled servers!
             // TODO: include if this is balanced, private, or 4337
             warn!(
                 "Only {}/{} rpcs! Add more rpcs or reduce min_synced_rpcs.",
                 rpc_configs.len(),
-                app.config.min_synced_rpcs
+                app.config.load().min_synced_rpcs
             );
             return Ok(());
         }
@@ -180,22 +238,31 @@ impl Web3Rpcs {
             });
         }
 
-        let chain_id = app.config.chain_id;
+        let chain_id = app.config.load().chain_id;
 
         let block_interval = average_block_interval(chain_id);
 
         // turn configs into connections (in parallel)
         let mut spawn_handles: FuturesUnordered<_> = rpc_configs
             .into_iter()
-            .filter_map(|(server_name, server_config)| {
+            .filter_map(|(server_name, mut server_config)| {
                 if server_config.disabled {
                     info!("{} is disabled", server_name);
                     return None;
                 }
 
+                // rpcs without their own revert settings use the chain's
+                server_config
+                    .revert_handler
+                    .get_or_insert(app.config.load().revert_handler);
+                server_config
+                    .revert_methods
+                    .get_or_insert_with(|| app.config.load().revert_methods.clone());
+
                 let db_conn = app.db_conn().ok().cloned();
                 let http_client = app.http_client.clone();
                 let vredis_pool = app.vredis_pool.clone();
+                let vredis_breaker = app.vredis_breaker.clone();
 
                 let block_sender = if self.watch_head_block.is_some() {
                     Some(self.block_sender.clone())
@@ -205,6 +272,7 @@ impl Web3Rpcs {
 
                 let pending_tx_id_sender = Some(self.pending_tx_id_sender.clone());
                 let blocks_by_hash_cache = self.blocks_by_hash.clone();
+                let shutdown_token = self.shutdown_token.child_token();
 
                 debug!("spawning tasks for {}", server_name);
 
@@ -212,6 +280,7 @@ impl Web3Rpcs {
                     server_name,
                     db_conn,
                     vredis_pool,
+                    vredis_breaker,
                     chain_id,
                     block_interval,
                     http_client,
@@ -219,6 +288,7 @@ impl Web3Rpcs {
                     block_sender,
                     self.max_head_block_age,
                     pending_tx_id_sender,
+                    shutdown_token,
                 ));
 
                 Some(handle)
@@ -227,8 +297,19 @@ impl Web3Rpcs {
 
         while let Some(x) = spawn_handles.next().await {
             match x {
-                Ok(Ok((new_rpc, _handle))) => {
+                Ok(Ok((new_rpc, handle))) => {
                     // web3 connection worked
+                    {
+                        let mut rpc_handles = self.rpc_handles.lock();
+
+                        // rpcs replaced by a config reload exit on their own. don't keep their handles forever
+                        rpc_handles.retain(|x| !x.is_finished());
+                        rpc_handles.push(handle);
+                    }
+
+                    if self.admin_disabled.read().contains(&new_rpc.name) {
+                        new_rpc.admin_disabled.store(true, Ordering::Release);
+                    }
 
                     let old_rpc = self.by_name.read().get(&new_rpc.name).map(Arc::clone);
 
@@ -288,6 +369,19 @@ impl Web3Rpcs {
         Ok(())
     }
 
+    /// Wait for every rpc's subscriptions to exit. They only exit once `shutdown_token` is cancelled or the rpc is replaced.
+    pub async fn wait_for_shutdown(&self) {
+        let rpc_handles = mem::take(&mut *self.rpc_handles.lock());
+
+        for handle in rpc_handles {
+            if let Err(err) = flatten_handle(handle).await {
+                warn!(?err, "rpc in {} exited with an error", self);
+            }
+        }
+
+        trace!("all rpcs in {} exited", self);
+    }
+
     pub fn get(&self, conn_name: &str) -> Option<Arc<Web3Rpc>> {
         self.by_name.read().get(conn_name).cloned()
     }
@@ -300,6 +394,159 @@ impl Web3Rpcs {
         self.by_name.read().is_empty()
     }
 
+    /// Take an rpc out of rotation (or put it back) until an admin changes it again.
+    /// Returns None if there is no rpc with this name.
+    pub fn set_admin_disabled(&self, name: &str, disabled: bool) -> Option<Arc<Web3Rpc>> {
+        let rpc = self.get(name)?;
+
+        if disabled {
+            self.admin_disabled.write().insert(name.to_string());
+        } else {
+            self.admin_disabled.write().remove(name);
+        }
+
+        rpc.admin_disabled.store(disabled, Ordering::Release);
+
+        Some(rpc)
+    }
+
+    /// Wait until at least `min_synced` rpcs agree on the head block. Returns false if that takes longer than `max_wait`
+    pub async fn wait_for_synced_rpcs(&self, min_synced: usize, max_wait: Duration) -> bool {
+        let mut watch_ranked_rpcs = self.watch_ranked_rpcs.subscribe();
+
+        let wait = async {
+            loop {
+                let num_synced = watch_ranked_rpcs
+                    .borrow_and_update()
+                    .as_ref()
+                    .map_or(0, |x| x.num_synced);
+
+                if num_synced >= min_synced {
+                    return true;
+                }
+
+                trace!(num_synced, min_synced, "waiting for synced rpcs");
+
+                if watch_ranked_rpcs.changed().await.is_err() {
+                    return false;
+                }
+            }
+        };
+
+        timeout(max_wait, wait).await.unwrap_or(false)
+    }
+
+    /// true if any rpc can serve an eth_call with a state override set
+    pub fn supports_state_overrides(&self) -> bool {
+        self.by_name.read().values().any(|x| x.state_overrides())
+    }
+
+    /// true if any rpc serves the method. only trace methods are checked
+    pub fn serves_method(&self, method: &str) -> bool {
+        self.by_name
+            .read()
+            .values()
+            .any(|x| x.serves_method(method))
+    }
+
+    /// Per-rpc gauges in the prometheus text format: blocks behind the consensus head and seconds since the rpc's head block.
+    /// Rpcs without a head block are left out.
+    pub fn head_prometheus_text(&self) -> String {
+        let consensus_head_num = self.head_block_num();
+
+        let mut rpcs: Vec<_> = self
+            .by_name
+            .read()
+            .values()
+            .filter_map(|rpc| {
+                let head_block = rpc.head_block.as_ref()?.borrow().clone()?;
+
+                let lag = consensus_head_num.map(|x| x.saturating_sub(*head_block.number()));
+
+                Some((
+                    rpc.name.replace('\\', "\\\\").replace('"', "\\\""),
+                    lag,
+                    head_block.age(),
+                ))
+            })
+            .collect();
+
+        rpcs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut text = String::new();
+
+        writeln!(
+            text,
+            "# HELP web3_proxy_rpc_head_lag_blocks blocks behind the consensus head"
+        )
+        .unwrap();
+        writeln!(text, "# TYPE web3_proxy_rpc_head_lag_blocks gauge").unwrap();
+        for (name, lag, _) in rpcs.iter() {
+            if let Some(lag) = lag {
+                writeln!(
+                    text,
+                    "web3_proxy_rpc_head_lag_blocks{{rpc=\"{}\"}} {}",
+                    name, lag
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(
+            text,
+            "# HELP web3_proxy_rpc_head_age_seconds seconds since the rpc's head block was mined"
+        )
+        .unwrap();
+        writeln!(text, "# TYPE web3_proxy_rpc_head_age_seconds gauge").unwrap();
+        for (name, _, age) in rpcs.iter() {
+            writeln!(
+                text,
+                "web3_proxy_rpc_head_age_seconds{{rpc=\"{}\"}} {}",
+                name,
+                age.as_secs()
+            )
+            .unwrap();
+        }
+
+        text
+    }
+
+    /// Every rpc's head block and how far it is from the consensus head, sorted by name
+    pub fn heads(&self) -> Vec<RpcHead> {
+        let ranked_rpcs = self.watch_ranked_rpcs.borrow().clone();
+
+        let consensus_head_num = ranked_rpcs.as_ref().map(|x| *x.head_block.number());
+
+        let mut heads: Vec<_> = self
+            .by_name
+            .read()
+            .values()
+            .map(|rpc| {
+                let head_block = rpc.head_block.as_ref().and_then(|x| x.borrow().clone());
+
+                let head_block_num = head_block.as_ref().map(|x| *x.number());
+
+                RpcHead {
+                    name: rpc.name.clone(),
+                    backup: rpc.backup,
+                    head_block_num,
+                    head_block_hash: head_block.as_ref().map(|x| *x.hash()),
+                    lag: head_lag(consensus_head_num, head_block_num),
+                    in_consensus: ranked_rpcs
+                        .as_ref()
+                        .map_or(false, |x| x.all().contains(rpc)),
+                    syncing: rpc.syncing(),
+                    admin_disabled: rpc.admin_disabled(),
+                    updated_at: rpc.head_block_updated_at(),
+                }
+            })
+            .collect();
+
+        heads.sort_by(|a, b| a.name.cmp(&b.name));
+
+        heads
+    }
+
     /// TODO: rename to be consistent between "head" and "synced"
     pub fn min_head_rpcs(&self) -> usize {
         self.min_synced_rpcs
@@ -325,9 +572,17 @@ impl Web3Rpcs {
             let authorization = authorization.clone();
             let handle = tokio::task::spawn(async move {
                 // TODO: set up this future the same as the block funnel
-                while let Some((pending_tx_id, rpc)) =
-                    clone.pending_tx_id_receiver.write().await.recv().await
-                {
+                let mut pending_tx_id_receiver = clone.pending_tx_id_receiver.write().await;
+
+                loop {
+                    let (pending_tx_id, rpc) = select! {
+                        x = pending_tx_id_receiver.recv() => match x {
+                            Some(x) => x,
+                            None => break,
+                        },
+                        _ = clone.shutdown_token.cancelled() => break,
+                    };
+
                     let f = clone.clone().process_incoming_tx_id(
                         authorization.clone(),
                         rpc,
@@ -361,14 +616,15 @@ impl Web3Rpcs {
 
         if futures.is_empty() {
             // no transaction or block subscriptions.
+            let shutdown_token = self.shutdown_token.clone();
 
             let handle = tokio::task::Builder::default()
                 .name("noop")
                 .spawn(async move {
-                    loop {
-                        sleep(Duration::from_secs(600)).await;
-                        // TODO: "every interval, do a health check or disconnect the rpc"
-                    }
+                    // TODO: "every interval, do a health check or disconnect the rpc"
+                    shutdown_token.cancelled().await;
+
+                    Ok(())
                 })?;
 
             futures.push(flatten_handle(handle));
@@ -528,6 +784,12 @@ impl Web3Rpcs {
             .and_then(|x| x.authorization.clone())
             .unwrap_or_default();
 
+        let state_override_request = request_metadata
+            .map(|x| x.state_override_request.load(Ordering::Acquire))
+            .unwrap_or_default();
+
+        let method = request_metadata.map(|x| x.method.as_ref());
+
         let mut watch_ranked_rpcs = self.watch_ranked_rpcs.subscribe();
 
         let mut potential_rpcs = Vec::new();
@@ -548,6 +810,8 @@ impl Web3Rpcs {
                         .filter(|rpc| {
                             ranked_rpcs.rpc_will_work_now(
                                 skip_rpcs,
+                                method,
+                                state_override_request,
                                 min_block_needed,
                                 max_block_needed,
                                 rpc,
@@ -679,9 +943,27 @@ impl Web3Rpcs {
             .and_then(|x| x.authorization.clone())
             .unwrap_or_default();
 
+        let state_override_request = request_metadata
+            .map(|x| x.state_override_request.load(Ordering::Acquire))
+            .unwrap_or_default();
+
+        let method = request_metadata.map(|x| x.method.as_ref());
+
         for rpc in all_rpcs {
             trace!("trying {}", rpc);
 
+            if state_override_request && !rpc.state_overrides() {
+                trace!("{} does not support state overrides. skipping", rpc);
+                continue;
+            }
+
+            if let Some(method) = method {
+                if !rpc.serves_method(method) {
+                    trace!("{} does not serve {}. skipping", rpc, method);
+                    continue;
+                }
+            }
+
             // TODO: use a helper function for these
             if let Some(block_needed) = min_block_needed {
                 if !rpc.has_block_data(block_needed) {
@@ -781,7 +1063,7 @@ impl Web3Rpcs {
                 }
                 Err(err) => {
                     // TODO: only log params in dev
-                    warn!(rpc=%self, %method, ?params, ?err, %tries, "retry-able error");
+                    warn!(rpc=%self, %method, params=?redact_params(method, params), ?err, %tries, "retry-able error");
                     last_error = Some(err)
                 }
             }
@@ -1075,15 +1357,20 @@ impl Web3Rpcs {
 
         // TODO: error? warn? debug? trace?
         if head_block_num.is_none() {
+            NO_SERVERS_SYNCED.fetch_add(1, Ordering::Relaxed);
+            alert_no_servers_synced(num_conns);
+
             error!(
                 min=?min_block_needed,
                 max=?max_block_needed,
                 head=?head_block_num,
                 known=num_conns,
                 %method,
-                ?params,
+                params=?redact_params(method, params),
                 "No servers synced",
             );
+
+            return Err(Web3ProxyError::NoServersSynced);
         } else if head_block_num.as_ref() > needed {
             // we have synced past the needed block
             // TODO: log ranked rpcs
@@ -1094,7 +1381,7 @@ impl Web3Rpcs {
                 head=?head_block_num,
                 known=%num_conns,
                 %method,
-                ?params,
+                params=?redact_params(method, params),
                 "No archive servers synced",
             );
         } else {
@@ -1107,7 +1394,7 @@ impl Web3Rpcs {
                 skipped=%num_skipped,
                 known=%num_conns,
                 %method,
-                ?params,
+                params=?redact_params(method, params),
                 "Requested data is not available",
             );
         }
@@ -1293,6 +1580,14 @@ impl Display for Web3Rpcs {
     }
 }
 
+/// How many blocks `head_num` is behind `consensus_num`. None if either is unknown
+fn head_lag(consensus_num: Option<U64>, head_num: Option<U64>) -> Option<i64> {
+    let consensus_num = consensus_num?.as_u64() as i64;
+    let head_num = head_num?.as_u64() as i64;
+
+    Some(consensus_num - head_num)
+}
+
 impl fmt::Debug for Web3Rpcs {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // TODO: the default formatter takes forever to write. this is too quiet though
@@ -1533,6 +1828,7 @@ mod tests {
 
         // TODO: make a Web3Rpcs::new
         let rpcs = Web3Rpcs {
+            admin_disabled: Default::default(),
             block_sender: block_sender.clone(),
             by_name: RwLock::new(by_name),
             chain_id,
@@ -1544,6 +1840,8 @@ mod tests {
                 .build(),
             pending_tx_id_receiver: AsyncRwLock::new(pending_tx_id_receiver),
             pending_tx_id_sender,
+            rpc_handles: Default::default(),
+            shutdown_token: Default::default(),
             blocks_by_hash: CacheBuilder::new(100)
                 .time_to_live(Duration::from_secs(60))
                 .build(),
@@ -1802,6 +2100,7 @@ mod tests {
         by_name.insert(archive_rpc.name.clone(), archive_rpc.clone());
 
         let rpcs = Web3Rpcs {
+            admin_disabled: Default::default(),
             block_sender,
             by_name: RwLock::new(by_name),
             chain_id,
@@ -1813,6 +2112,8 @@ mod tests {
                 .build(),
             pending_tx_id_receiver: AsyncRwLock::new(pending_tx_id_receiver),
             pending_tx_id_sender,
+            rpc_handles: Default::default(),
+            shutdown_token: Default::default(),
             blocks_by_hash: CacheBuilder::new(100)
                 .time_to_live(Duration::from_secs(120))
                 .build(),
@@ -1990,6 +2291,7 @@ mod tests {
 
         // TODO: make a Web3Rpcs::new
         let rpcs = Web3Rpcs {
+            admin_disabled: Default::default(),
             block_sender,
             by_name: RwLock::new(by_name),
             chain_id,
@@ -1999,6 +2301,8 @@ mod tests {
             pending_transaction_cache: Cache::new(10_000),
             pending_tx_id_receiver: AsyncRwLock::new(pending_tx_id_receiver),
             pending_tx_id_sender,
+            rpc_handles: Default::default(),
+            shutdown_token: Default::default(),
             blocks_by_hash: Cache::new(10_000),
             blocks_by_number: Cache::new(10_000),
             min_synced_rpcs: 1,
@@ -2075,8 +2379,18 @@ mod tests {
 
 #[cfg(test)]
 mod test {
+    use super::head_lag;
+    use ethers::types::U64;
     use std::cmp::Reverse;
 
+    #[test]
+    fn test_head_lag() {
+        assert_eq!(head_lag(Some(U64::from(10)), Some(U64::from(7))), Some(3));
+        assert_eq!(head_lag(Some(U64::from(10)), Some(U64::from(11))), Some(-1));
+        assert_eq!(head_lag(None, Some(U64::from(11))), None);
+        assert_eq!(head_lag(Some(U64::from(10)), None), None);
+    }
+
     #[test]
     fn test_block_num_sort() {
         let test_vec = vec![
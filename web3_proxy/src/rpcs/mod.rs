@@ -1,8 +1,11 @@
 // TODO: all pub, or export useful things here instead?
+pub mod audit;
 pub mod blockchain;
 pub mod consensus;
 pub mod many;
 pub mod one;
 pub mod provider;
 pub mod request;
+pub mod revert_log_buffer;
+pub mod token_bucket;
 pub mod transactions;
@@ -2,10 +2,11 @@
 use super::blockchain::{ArcBlock, BlocksByHashCache, Web3ProxyBlock};
 use super::provider::{connect_http, connect_ws, EthersHttpProvider, EthersWsProvider};
 use super::request::{OpenRequestHandle, OpenRequestResult};
+use super::token_bucket::TokenBucket;
 use crate::app::{flatten_handle, Web3ProxyJoinHandle};
 use crate::config::{BlockAndRpc, Web3RpcConfig};
 use crate::errors::{Web3ProxyError, Web3ProxyErrorContext, Web3ProxyResult};
-use crate::frontend::authorization::Authorization;
+use crate::frontend::authorization::{Authorization, AuthorizationType};
 use crate::jsonrpc::{JsonRpcParams, JsonRpcResultData};
 use crate::rpcs::request::RequestErrorHandler;
 use anyhow::{anyhow, Context};
@@ -17,17 +18,18 @@ use futures::StreamExt;
 use latency::{EwmaLatency, PeakEwmaLatency, RollingQuantileLatency};
 use migration::sea_orm::DatabaseConnection;
 use nanorand::Rng;
-use redis_rate_limiter::{RedisPool, RedisRateLimitResult, RedisRateLimiter};
+use redis_rate_limiter::{RedisCircuitBreaker, RedisPool, RedisRateLimitResult, RedisRateLimiter};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
 use serde_json::json;
 use std::cmp::Reverse;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::sync::atomic::{self, AtomicU32, AtomicU64, AtomicUsize};
+use std::sync::atomic::{self, AtomicBool, AtomicU32, AtomicU64, AtomicUsize};
 use std::{cmp::Ordering, sync::Arc};
 use tokio::sync::{mpsc, watch, RwLock as AsyncRwLock};
 use tokio::time::{interval, sleep, sleep_until, Duration, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, trace, warn, Level};
 use url::Url;
 
@@ -42,6 +44,8 @@ pub struct Web3Rpc {
     pub(super) http_provider: Option<EthersHttpProvider>,
     /// the websocket url is only used for subscriptions
     pub(super) ws_url: Option<Url>,
+    /// sent when the websocket (re)connects. never logged or serialized
+    pub(super) bearer_token: Option<String>,
     /// the websocket provider is only used for subscriptions
     pub(super) ws_provider: ArcSwapOption<EthersWsProvider>,
     /// keep track of hard limits
@@ -50,6 +54,8 @@ pub struct Web3Rpc {
     /// rate limits are stored in a central redis so that multiple proxies can share their rate limits
     /// We do not use the deferred rate limiter because going over limits would cause errors
     pub(super) hard_limit: Option<RedisRateLimiter>,
+    /// a local cap on requests per second. checked before the shared hard_limit
+    pub(super) max_requests_per_second: Option<TokenBucket>,
     /// used for ensuring enough requests are available before advancing the head block
     pub(super) soft_limit: u32,
     /// use web3 queries to find the block data limit for archive/pruned nodes
@@ -60,6 +66,28 @@ pub struct Web3Rpc {
     pub(super) block_data_limit: AtomicU64,
     /// head_block is only inside an Option so that the "Default" derive works. it will always be set.
     pub(super) head_block: Option<watch::Sender<Option<Web3ProxyBlock>>>,
+    /// unix time in milliseconds of the last change to head_block. 0 if it has never been set
+    pub(super) head_block_updated_at: AtomicU64,
+    /// true while eth_syncing returns anything other than false. syncing rpcs are taken out of rotation
+    pub(super) syncing: AtomicBool,
+    /// true while an admin has taken this rpc out of rotation. internal requests still go through so the head keeps updating
+    pub(super) admin_disabled: AtomicBool,
+    /// how errors from user requests are handled. Save stores reverts in the database
+    pub(super) revert_handler: RequestErrorHandler,
+    /// reverts are only saved for these methods
+    pub(super) revert_methods: Vec<String>,
+    /// use an eth_call to find out if state overrides are supported
+    pub(super) automatic_state_overrides: bool,
+    /// true if eth_call's third param (the state override set) works on this rpc
+    pub(super) state_overrides: AtomicBool,
+    /// use a trace_call to find out if `trace_*` methods are served
+    pub(super) automatic_trace_methods: bool,
+    /// true if `trace_*` methods work on this rpc
+    pub(super) trace_methods: AtomicBool,
+    /// use a debug_traceCall to find out if `debug_trace*` methods are served
+    pub(super) automatic_debug_trace_methods: bool,
+    /// true if `debug_trace*` methods work on this rpc
+    pub(super) debug_trace_methods: AtomicBool,
     /// Track head block latency.
     pub(super) head_delay: AsyncRwLock<EwmaLatency>,
     /// Track peak request latency
@@ -71,6 +99,8 @@ pub struct Web3Rpc {
     pub(super) internal_requests: AtomicUsize,
     /// Track total external requests served
     pub(super) external_requests: AtomicUsize,
+    /// Track responses that could not be parsed. truncated or otherwise malformed JSON
+    pub(super) malformed_responses: AtomicUsize,
     /// If the head block is too old, it is ignored.
     pub(super) max_head_block_age: Duration,
     /// Track time used by external requests served
@@ -80,6 +110,8 @@ pub struct Web3Rpc {
     pub(super) active_requests: AtomicUsize,
     /// disconnect_watch is only inside an Option so that the "Default" derive works. it will always be set.
     pub(super) disconnect_watch: Option<watch::Sender<bool>>,
+    /// cancelled when the app shuts down. the subscriptions and health checks exit without reconnecting
+    pub(super) shutdown_token: CancellationToken,
     /// created_at is only inside an Option so that the "Default" derive works. it will always be set.
     pub(super) created_at: Option<Instant>,
 }
@@ -96,11 +128,13 @@ impl Web3Rpc {
         // optional because this is only used for http providers. websocket providers don't use it
         http_client: Option<reqwest::Client>,
         redis_pool: Option<RedisPool>,
+        redis_breaker: RedisCircuitBreaker,
         block_interval: Duration,
         block_map: BlocksByHashCache,
         block_and_rpc_sender: Option<mpsc::UnboundedSender<BlockAndRpc>>,
         max_head_block_age: Duration,
         tx_id_sender: Option<mpsc::UnboundedSender<(TxHash, Arc<Self>)>>,
+        shutdown_token: CancellationToken,
     ) -> anyhow::Result<(Arc<Web3Rpc>, Web3ProxyJoinHandle<()>)> {
         let created_at = Instant::now();
 
@@ -114,7 +148,8 @@ impl Web3Rpc {
                     hard_limit,
                     60.0,
                     redis_pool,
-                );
+                )
+                .with_circuit_breaker(redis_breaker);
 
                 Some(rrl)
             }
@@ -126,6 +161,8 @@ impl Web3Rpc {
             }
         };
 
+        let max_requests_per_second = config.max_requests_per_second.map(TokenBucket::new);
+
         let tx_id_sender = if config.subscribe_txs {
             tx_id_sender
         } else {
@@ -138,6 +175,18 @@ impl Web3Rpc {
         let automatic_block_limit = (block_data_limit.load(atomic::Ordering::Acquire) == 0)
             && block_and_rpc_sender.is_some();
 
+        let state_overrides: AtomicBool = config.state_overrides.unwrap_or_default().into();
+        let automatic_state_overrides =
+            config.state_overrides.is_none() && block_and_rpc_sender.is_some();
+
+        // until they are checked, trace methods are assumed to work
+        let trace_methods: AtomicBool = config.trace_methods.unwrap_or(true).into();
+        let debug_trace_methods: AtomicBool = config.debug_trace_methods.unwrap_or(true).into();
+        let automatic_trace_methods =
+            config.trace_methods.is_none() && block_and_rpc_sender.is_some();
+        let automatic_debug_trace_methods =
+            config.debug_trace_methods.is_none() && block_and_rpc_sender.is_some();
+
         // have a sender for tracking hard limit anywhere. we use this in case we
         // and track on servers that have a configured hard limit
         let (hard_limit_until, _) = watch::channel(Instant::now());
@@ -167,7 +216,12 @@ impl Web3Rpc {
         let http_provider = if let Some(http_url) = config.http_url {
             let http_url = http_url.parse::<Url>()?;
 
-            Some(connect_http(http_url, http_client, block_interval)?)
+            Some(connect_http(
+                http_url,
+                config.bearer_token.as_deref(),
+                http_client,
+                block_interval,
+            )?)
 
             // TODO: check the provider is on the right chain
         } else {
@@ -186,23 +240,34 @@ impl Web3Rpc {
 
         let new_rpc = Self {
             automatic_block_limit,
+            automatic_state_overrides,
+            automatic_trace_methods,
+            automatic_debug_trace_methods,
             backup,
+            bearer_token: config.bearer_token,
             block_data_limit,
             block_interval,
             created_at: Some(created_at),
             db_conn,
+            debug_trace_methods,
             display_name: config.display_name,
             hard_limit,
             hard_limit_until: Some(hard_limit_until),
             head_block: Some(head_block),
             http_provider,
             max_head_block_age,
+            max_requests_per_second,
             name,
             peak_latency: Some(peak_latency),
             median_latency: Some(median_request_latency),
+            revert_handler: config.revert_handler.unwrap_or_default(),
+            revert_methods: config.revert_methods.unwrap_or_default(),
             soft_limit: config.soft_limit,
+            state_overrides,
+            trace_methods,
             ws_url,
             disconnect_watch: Some(disconnect_watch),
+            shutdown_token,
             ..Default::default()
         };
 
@@ -306,7 +371,9 @@ impl Web3Rpc {
             return Ok(None);
         }
 
-        // TODO: check eth_syncing. if it is not false, return Ok(None)
+        if self.syncing() {
+            return Ok(None);
+        }
 
         let mut limit = None;
 
@@ -382,6 +449,116 @@ impl Web3Rpc {
         Ok(limit)
     }
 
+    /// Send an eth_call that overrides the code at an empty address to return 42.
+    /// Some backends reject the third param and some silently ignore it, so the result has to be checked.
+    async fn check_state_overrides(self: &Arc<Self>) {
+        if !self.automatic_state_overrides {
+            return;
+        }
+
+        let result: Result<Bytes, _> = self
+            .internal_request(
+                "eth_call",
+                &json!((
+                    { "to": "0xdead00000000000000000000000000000000beef" },
+                    "latest",
+                    {
+                        "0xdead00000000000000000000000000000000beef": {
+                            // PUSH1 42 PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+                            "code": "0x602a60005260206000f3"
+                        }
+                    },
+                )),
+                // errors here are expected, so keep the level low
+                Some(Level::TRACE.into()),
+                Some(2),
+                Some(Duration::from_secs(5)),
+            )
+            .await;
+
+        let supported = matches!(result, Ok(x) if x.len() == 32 && x[31] == 42);
+
+        self.state_overrides
+            .store(supported, atomic::Ordering::Release);
+
+        info!("state overrides on {}: {}", self, supported);
+    }
+
+    /// An rpc that never connects. Anything the config leaves out is assumed to work.
+    /// `dry_run` uses these to ask the real routing code where requests would go.
+    pub fn offline(name: String, config: &Web3RpcConfig, head_block: Web3ProxyBlock) -> Self {
+        let (head_block, _) = watch::channel(Some(head_block));
+
+        Self {
+            backup: config.backup,
+            block_data_limit: config.block_data_limit.unwrap_or(u64::MAX).into(),
+            debug_trace_methods: config.debug_trace_methods.unwrap_or(true).into(),
+            display_name: config.display_name.clone(),
+            head_block: Some(head_block),
+            name,
+            soft_limit: config.soft_limit,
+            state_overrides: config.state_overrides.unwrap_or(true).into(),
+            trace_methods: config.trace_methods.unwrap_or(true).into(),
+            ..Default::default()
+        }
+    }
+
+    /// true if eth_call requests with a state override set can be sent to this rpc
+    pub fn state_overrides(&self) -> bool {
+        self.state_overrides.load(atomic::Ordering::Acquire)
+    }
+
+    /// Call something cheap in each trace namespace. Any result means the namespace is served.
+    async fn check_trace_methods(self: &Arc<Self>) {
+        let call = json!({ "to": "0xdead00000000000000000000000000000000beef" });
+
+        if self.automatic_trace_methods {
+            let result: Result<serde_json::Value, _> = self
+                .internal_request(
+                    "trace_call",
+                    &json!((&call, ["trace"], "latest")),
+                    // errors here are expected, so keep the level low
+                    Some(Level::TRACE.into()),
+                    Some(2),
+                    Some(Duration::from_secs(5)),
+                )
+                .await;
+
+            self.trace_methods
+                .store(result.is_ok(), atomic::Ordering::Release);
+
+            info!("trace methods on {}: {}", self, result.is_ok());
+        }
+
+        if self.automatic_debug_trace_methods {
+            let result: Result<serde_json::Value, _> = self
+                .internal_request(
+                    "debug_traceCall",
+                    &json!((&call, "latest")),
+                    Some(Level::TRACE.into()),
+                    Some(2),
+                    Some(Duration::from_secs(5)),
+                )
+                .await;
+
+            self.debug_trace_methods
+                .store(result.is_ok(), atomic::Ordering::Release);
+
+            info!("debug trace methods on {}: {}", self, result.is_ok());
+        }
+    }
+
+    /// false if this rpc is known to not serve the method
+    pub fn serves_method(&self, method: &str) -> bool {
+        if method.starts_with("trace_") {
+            self.trace_methods.load(atomic::Ordering::Acquire)
+        } else if method.starts_with("debug_trace") {
+            self.debug_trace_methods.load(atomic::Ordering::Acquire)
+        } else {
+            true
+        }
+    }
+
     /// TODO: this might be too simple. different nodes can prune differently. its possible we will have a block range
     pub fn block_data_limit(&self) -> U64 {
         self.block_data_limit.load(atomic::Ordering::Acquire).into()
@@ -457,6 +634,10 @@ impl Web3Rpc {
             .await
             .context(format!("unable to check_block_data_limit of {}", self))?;
 
+        self.check_state_overrides().await;
+
+        self.check_trace_methods().await;
+
         info!("successfully connected to {}", self);
 
         Ok(())
@@ -487,11 +668,27 @@ impl Web3Rpc {
 
                         // send an empty block to take this server out of rotation
                         head_block_sender.send_replace(None);
+                        self.head_block_updated();
 
                         // TODO: clear self.block_data_limit?
 
                         None
                     }
+                    Some(ref new_head_block)
+                        if self.might_be_syncing(new_head_block) && self.check_syncing().await =>
+                    {
+                        // a syncing rpc can still have a head block, but it is not safe to serve requests from it
+                        if head_block_sender.borrow().is_none() {
+                            return Ok(());
+                        }
+
+                        debug!("clearing head block on {} while it is syncing", self);
+
+                        head_block_sender.send_replace(None);
+                        self.head_block_updated();
+
+                        None
+                    }
                     Some(new_head_block) => {
                         let new_hash = *new_head_block.hash();
 
@@ -501,7 +698,12 @@ impl Web3Rpc {
                             .await;
 
                         // we are synced! yey!
-                        head_block_sender.send_replace(Some(new_head_block.clone()));
+                        let old_head_block =
+                            head_block_sender.send_replace(Some(new_head_block.clone()));
+
+                        if old_head_block.as_ref() != Some(&new_head_block) {
+                            self.head_block_updated();
+                        }
 
                         if self.block_data_limit() == U64::zero() {
                             if let Err(err) = self.check_block_data_limit().await {
@@ -520,7 +722,9 @@ impl Web3Rpc {
                 warn!(?err, "unable to get block from {}", self);
 
                 // send an empty block to take this server out of rotation
-                head_block_sender.send_replace(None);
+                if head_block_sender.send_replace(None).is_some() {
+                    self.head_block_updated();
+                }
 
                 // TODO: clear self.block_data_limit?
 
@@ -536,8 +740,69 @@ impl Web3Rpc {
         Ok(())
     }
 
+    fn head_block_updated(&self) {
+        self.head_block_updated_at.store(
+            chrono::Utc::now().timestamp_millis() as u64,
+            atomic::Ordering::Relaxed,
+        );
+    }
+
+    /// unix time in milliseconds of the last change to this rpc's head block. None if it has never had one
+    pub fn head_block_updated_at(&self) -> Option<u64> {
+        match self.head_block_updated_at.load(atomic::Ordering::Relaxed) {
+            0 => None,
+            x => Some(x),
+        }
+    }
+
+    /// true if the last `eth_syncing` check said this rpc is still syncing
+    pub fn syncing(&self) -> bool {
+        self.syncing.load(atomic::Ordering::Acquire)
+    }
+
+    /// true if an admin has taken this rpc out of rotation
+    pub fn admin_disabled(&self) -> bool {
+        self.admin_disabled.load(atomic::Ordering::Acquire)
+    }
+
+    /// A synced rpc's head block is recent, so `eth_syncing` is only sent when the head is too old or the rpc was syncing last time
+    fn might_be_syncing(&self, head_block: &Web3ProxyBlock) -> bool {
+        self.syncing() || head_block.age() > self.max_head_block_age
+    }
+
+    /// Query `eth_syncing` and save the result. Anything other than `false` means the rpc is still syncing.
+    /// If the query fails, the previous state is kept.
+    async fn check_syncing(self: &Arc<Self>) -> bool {
+        let syncing = match self
+            .internal_request::<_, serde_json::Value>(
+                "eth_syncing",
+                &[(); 0],
+                Some(Level::DEBUG.into()),
+                Some(2),
+                Some(Duration::from_secs(5)),
+            )
+            .await
+        {
+            Ok(x) => !matches!(x, serde_json::Value::Bool(false)),
+            Err(err) => {
+                debug!(?err, "eth_syncing failed on {}", self);
+                return self.syncing();
+            }
+        };
+
+        let was_syncing = self.syncing.swap(syncing, atomic::Ordering::AcqRel);
+
+        if syncing && !was_syncing {
+            warn!("{} is syncing", self);
+        } else if !syncing && was_syncing {
+            info!("{} finished syncing", self);
+        }
+
+        syncing
+    }
+
     fn should_disconnect(&self) -> bool {
-        *self.disconnect_watch.as_ref().unwrap().borrow()
+        self.shutdown_token.is_cancelled() || *self.disconnect_watch.as_ref().unwrap().borrow()
     }
 
     async fn healthcheck(
@@ -631,7 +896,10 @@ impl Web3Rpc {
             }
 
             // TODO: exponential backoff with jitter
-            sleep(Duration::from_secs(30)).await;
+            tokio::select! {
+                _ = sleep(Duration::from_secs(30)) => {}
+                _ = self.shutdown_token.cancelled() => break,
+            }
         }
 
         Ok(())
@@ -661,7 +929,7 @@ impl Web3Rpc {
         if let Some(url) = self.ws_url.clone() {
             trace!("starting websocket provider on {}", self);
 
-            let x = connect_ws(url, usize::MAX).await?;
+            let x = connect_ws(url, self.bearer_token.as_deref(), usize::MAX).await?;
 
             let x = Arc::new(x);
 
@@ -740,7 +1008,10 @@ impl Web3Rpc {
                     // TODO: should we count the requests done inside this health check
                     old_total_requests = new_total_requests;
 
-                    sleep(Duration::from_secs(health_sleep_seconds)).await;
+                    tokio::select! {
+                        _ = sleep(Duration::from_secs(health_sleep_seconds)) => {}
+                        _ = rpc.shutdown_token.cancelled() => break,
+                    }
                 }
 
                 debug!("healthcheck loop on {} exited", rpc);
@@ -790,9 +1061,16 @@ impl Web3Rpc {
             futures.push(flatten_handle(tokio::spawn(f)));
         }
 
-        // try_join on the futures
-        if let Err(err) = try_join_all(futures).await {
-            warn!(?err, "subscription erred");
+        // try_join on the futures. on shutdown, stop waiting and tell them all to stop
+        tokio::select! {
+            x = try_join_all(futures) => {
+                if let Err(err) = x {
+                    warn!(?err, "subscription erred");
+                }
+            }
+            _ = self.shutdown_token.cancelled() => {
+                debug!("shutting down subscriptions on {}", self);
+            }
         }
 
         debug!("subscriptions on {} exited", self);
@@ -1025,6 +1303,28 @@ impl Web3Rpc {
     ) -> Web3ProxyResult<OpenRequestResult> {
         // TODO: if websocket is reconnecting, return an error?
 
+        // internal requests are still allowed so that we can keep checking the syncing status
+        if self.syncing()
+            && matches!(
+                authorization.authorization_type,
+                AuthorizationType::Frontend
+            )
+        {
+            trace!("{} is syncing", self);
+            return Ok(OpenRequestResult::NotReady);
+        }
+
+        // disabled rpcs drain gracefully. requests that already have a handle are left to finish
+        if self.admin_disabled()
+            && matches!(
+                authorization.authorization_type,
+                AuthorizationType::Frontend
+            )
+        {
+            trace!("{} is disabled by an admin", self);
+            return Ok(OpenRequestResult::NotReady);
+        }
+
         // check cached rate limits
         if let Some(hard_limit_until) = self.hard_limit_until.as_ref() {
             let hard_limit_ready = *hard_limit_until.borrow();
@@ -1034,6 +1334,14 @@ impl Web3Rpc {
             }
         }
 
+        // check the local rate limit. this is not saved in hard_limit_until because it refills every second
+        if let Some(bucket) = self.max_requests_per_second.as_ref() {
+            if let Err(retry_at) = bucket.try_acquire() {
+                trace!("{} is at its max requests per second", self);
+                return Ok(OpenRequestResult::RetryAt(retry_at));
+            }
+        }
+
         // check shared rate limits
         if let Some(ratelimiter) = self.hard_limit.as_ref() {
             // TODO: how should we know if we should set expire or not?
@@ -1187,7 +1495,7 @@ impl Serialize for Web3Rpc {
         S: Serializer,
     {
         // 14 if we bring head_delay back
-        let mut state = serializer.serialize_struct("Web3Rpc", 13)?;
+        let mut state = serializer.serialize_struct("Web3Rpc", 19)?;
 
         // the url is excluded because it likely includes private information. just show the name that we use in keys
         state.serialize_field("name", &self.name)?;
@@ -1207,6 +1515,16 @@ impl Serialize for Web3Rpc {
 
         state.serialize_field("tier", &self.tier)?;
 
+        state.serialize_field("syncing", &self.syncing())?;
+
+        state.serialize_field("admin_disabled", &self.admin_disabled())?;
+
+        state.serialize_field("state_overrides", &self.state_overrides())?;
+
+        state.serialize_field("trace_methods", &self.serves_method("trace_"))?;
+
+        state.serialize_field("debug_trace_methods", &self.serves_method("debug_trace"))?;
+
         state.serialize_field("soft_limit", &self.soft_limit)?;
 
         // TODO: maybe this is too much data. serialize less?
@@ -1227,6 +1545,11 @@ impl Serialize for Web3Rpc {
             &self.internal_requests.load(atomic::Ordering::Relaxed),
         )?;
 
+        state.serialize_field(
+            "malformed_responses",
+            &self.malformed_responses.load(atomic::Ordering::Relaxed),
+        )?;
+
         state.serialize_field(
             "active_requests",
             &self.active_requests.load(atomic::Ordering::Relaxed),
@@ -1377,6 +1700,71 @@ mod tests {
         assert!(!x.has_block_data(&(head_block.number() + 1000)));
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_syncing_node_not_ready() {
+        let (tx, _rx) = watch::channel(None);
+
+        let x = Arc::new(Web3Rpc {
+            name: "name".to_string(),
+            soft_limit: 1_000,
+            head_block: Some(tx),
+            syncing: true.into(),
+            ..Default::default()
+        });
+
+        let mut authorization = Authorization::internal(None).unwrap();
+        authorization.authorization_type = AuthorizationType::Frontend;
+        let authorization = Arc::new(authorization);
+
+        let x = x.try_request_handle(&authorization, None).await.unwrap();
+
+        assert!(matches!(x, OpenRequestResult::NotReady));
+    }
+
+    #[test]
+    fn test_might_be_syncing() {
+        let now = chrono::Utc::now().timestamp();
+
+        let block = |timestamp: i64| {
+            Web3ProxyBlock::try_new(Arc::new(Block {
+                hash: Some(H256::random()),
+                number: Some(1_000_000.into()),
+                timestamp: timestamp.into(),
+                ..Default::default()
+            }))
+            .unwrap()
+        };
+
+        let x = Web3Rpc {
+            name: "name".to_string(),
+            max_head_block_age: Duration::from_secs(60),
+            ..Default::default()
+        };
+
+        assert!(!x.might_be_syncing(&block(now)));
+        assert!(x.might_be_syncing(&block(now - 3600)));
+
+        // keep checking until eth_syncing says it is done
+        x.syncing.store(true, atomic::Ordering::Release);
+
+        assert!(x.might_be_syncing(&block(now)));
+    }
+
+    #[test]
+    fn test_serves_method() {
+        let x = Web3Rpc {
+            name: "name".to_string(),
+            trace_methods: false.into(),
+            debug_trace_methods: true.into(),
+            ..Default::default()
+        };
+
+        assert!(!x.serves_method("trace_block"));
+        assert!(!x.serves_method("trace_filter"));
+        assert!(x.serves_method("debug_traceBlockByHash"));
+        assert!(x.serves_method("eth_call"));
+    }
+
     /*
     // TODO: think about how to bring the concept of a "lagged" node back
     #[test]
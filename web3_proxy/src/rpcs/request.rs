@@ -1,22 +1,31 @@
 use super::one::Web3Rpc;
+use super::revert_log_buffer::try_queue_revert_log;
 use crate::errors::{Web3ProxyErrorContext, Web3ProxyResult};
 use crate::frontend::authorization::{Authorization, AuthorizationType};
 use crate::jsonrpc::{JsonRpcParams, JsonRpcResultData};
+use crate::redact::{redact_call_data, redact_params};
+use crate::webhooks::WebhookEvent;
 use anyhow::Context;
 use chrono::Utc;
 use derive_more::From;
 use entities::revert_log;
 use entities::sea_orm_active_enums::Method;
-use ethers::providers::ProviderError;
+use ethers::abi::{self, ParamType};
+use ethers::providers::{JsonRpcError, ProviderError, RpcError};
 use ethers::types::{Address, Bytes};
 use migration::sea_orm::{self, ActiveEnum, ActiveModelTrait};
 use nanorand::Rng;
+use serde::Deserialize;
 use serde_json::json;
-use std::sync::atomic;
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicU64};
 use std::sync::Arc;
 use tokio::time::{Duration, Instant};
 use tracing::{debug, error, info, trace, warn, Level};
 
+/// Responses from any rpc that could not be parsed. truncated or otherwise malformed JSON
+pub static MALFORMED_RESPONSES: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Debug, From)]
 pub enum OpenRequestResult {
     Handle(OpenRequestHandle),
@@ -36,7 +45,9 @@ pub struct OpenRequestHandle {
 }
 
 /// Depending on the context, RPC errors require different handling.
-#[derive(Copy, Clone, Debug, Default)]
+/// In config, this is "save" or any `tracing::Level` ("trace", "debug", "info", "warn", "error").
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
 pub enum RequestErrorHandler {
     /// Log at the trace level. Use when errors are expected.
     #[default]
@@ -57,12 +68,26 @@ pub enum RequestErrorHandler {
 #[derive(serde::Deserialize, serde::Serialize)]
 struct EthCallParams((EthCallFirstParams, Option<serde_json::Value>));
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
 struct EthCallFirstParams {
     to: Option<Address>,
     data: Option<Bytes>,
 }
 
+impl TryFrom<String> for RequestErrorHandler {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.eq_ignore_ascii_case("save") {
+            return Ok(Self::Save);
+        }
+
+        Level::from_str(&value)
+            .map(Into::into)
+            .map_err(|_| format!("unknown revert handler: {}", value))
+    }
+}
+
 impl From<Level> for RequestErrorHandler {
     fn from(level: Level) -> Self {
         match level {
@@ -81,6 +106,7 @@ impl Authorization {
         self: Arc<Self>,
         method: Method,
         params: EthCallFirstParams,
+        revert: RevertDetails,
     ) -> Web3ProxyResult<()> {
         let rpc_key_id = match self.checks.rpc_secret_key_id {
             Some(rpc_key_id) => rpc_key_id.into(),
@@ -90,37 +116,67 @@ impl Authorization {
             }
         };
 
-        let db_conn = self.db_conn.as_ref().context("no database connection")?;
-
         // TODO: should the database set the timestamp?
         // we intentionally use "now" and not the time the request started
         // why? because we aggregate stats and setting one in the past could cause confusion
         let timestamp = Utc::now();
 
-        let to = params.to.unwrap_or_else(Address::zero).as_bytes().to_vec();
+        let to = params.to.unwrap_or_else(Address::zero);
 
-        let call_data = params.data.map(|x| x.to_string());
+        let call_data = redact_call_data(&method.to_value(), params.data);
 
         let rl = revert_log::ActiveModel {
             rpc_key_id: sea_orm::Set(rpc_key_id),
             method: sea_orm::Set(method),
-            to: sea_orm::Set(to),
+            to: sea_orm::Set(to.as_bytes().to_vec()),
             call_data: sea_orm::Set(call_data),
+            reason: sea_orm::Set(revert.reason),
+            revert_data: sea_orm::Set(revert.data.map(|x| x.to_string())),
             timestamp: sea_orm::Set(timestamp),
             ..Default::default()
         };
 
-        let rl = rl
-            .save(db_conn)
-            .await
-            .web3_context("Failed saving new revert log")?;
+        // most reverts are batched. without a running buffer, save this one directly
+        if let Err(rl) = try_queue_revert_log(rl) {
+            let db_conn = self.db_conn.as_ref().context("no database connection")?;
 
-        // TODO: what log level and format?
-        trace!(revert_log=?rl);
+            let rl = rl
+                .save(db_conn)
+                .await
+                .web3_context("Failed saving new revert log")?;
+
+            // TODO: what log level and format?
+            trace!(revert_log=?rl);
+        }
 
         // TODO: return something useful
         Ok(())
     }
+
+    /// Send a RPC call that returned "execution reverted" to the user's webhook.
+    /// This does not depend on `log_revert_chance`. `RevertWebhooks` limits how often each key sends one
+    async fn send_revert_webhook(
+        self: Arc<Self>,
+        method: Method,
+        params: EthCallFirstParams,
+        reason: Option<String>,
+    ) {
+        let (revert_webhooks, rpc_key_id) =
+            match (self.revert_webhooks.as_ref(), self.checks.rpc_secret_key_id) {
+                (Some(revert_webhooks), Some(rpc_key_id)) => (revert_webhooks, rpc_key_id.into()),
+                _ => return,
+            };
+
+        let event = WebhookEvent::Revert {
+            rpc_key_id,
+            method: method.to_value(),
+            to: params.to.unwrap_or_else(Address::zero),
+            call_data: redact_call_data(&method.to_value(), params.data),
+            reason,
+        };
+
+        revert_webhooks.notify(self.checks.user_id, rpc_key_id, event);
+    }
 }
 
 impl Drop for OpenRequestHandle {
@@ -143,7 +199,11 @@ impl OpenRequestHandle {
         rpc.active_requests
             .fetch_add(1, std::sync::atomic::Ordering::AcqRel);
 
-        let error_handler = error_handler.unwrap_or_default();
+        // Save marks a user request. the rpc decides how those are actually handled
+        let error_handler = match error_handler {
+            Some(RequestErrorHandler::Save) => rpc.revert_handler,
+            x => x.unwrap_or_default(),
+        };
 
         Self {
             authorization,
@@ -207,13 +267,19 @@ impl OpenRequestHandle {
         // we do NOT want to measure errors, so we intentionally do not record this latency now.
         let latency = start.elapsed();
 
+        // reqwest errors include the url. rpc urls often have keys in them, and these errors get logged and returned
+        let response = response.map_err(|err| match err {
+            ProviderError::HTTPError(err) => ProviderError::HTTPError(err.without_url()),
+            err => err,
+        });
+
         // we used to fetch_sub the active_request count here, but sometimes the handle is dropped without request being called!
 
         trace!(
             "response from {} for {} {:?}: {:?}",
             self.rpc,
             method,
-            params,
+            redact_params(method, params),
             response,
         );
 
@@ -222,7 +288,7 @@ impl OpenRequestHandle {
             // TODO: do something special for eth_sendRawTransaction too
             let error_handler = if let RequestErrorHandler::Save = self.error_handler {
                 // TODO: should all these be Trace or Debug or a mix?
-                if !["eth_call", "eth_estimateGas"].contains(&method) {
+                if !self.rpc.revert_methods.iter().any(|x| x == method) {
                     // trace!(%method, "skipping save on revert");
                     RequestErrorHandler::TraceLevel
                 } else if self.authorization.db_conn.is_some() {
@@ -257,12 +323,15 @@ impl OpenRequestHandle {
             enum ResponseTypes {
                 Revert,
                 RateLimit,
+                Malformed,
                 Error,
             }
 
             // check for "execution reverted" here
             // TODO: move this info a function on ResponseErrorType
-            let response_type = if let ProviderError::JsonRpcClientError(err) = err {
+            let response_type = if is_malformed_response(err) {
+                ResponseTypes::Malformed
+            } else if let ProviderError::JsonRpcClientError(err) = err {
                 // JsonRpc and Application errors get rolled into the JsonRpcClientError
                 let msg = err.as_error_response().map(|x| x.message.clone());
 
@@ -284,6 +353,32 @@ impl OpenRequestHandle {
                 ResponseTypes::Error
             };
 
+            // webhooks are decided separately from the sampled revert logs
+            let send_revert_webhook = matches!(response_type, ResponseTypes::Revert)
+                && matches!(self.error_handler, RequestErrorHandler::Save)
+                && self.authorization.checks.webhook_reverts
+                && self.authorization.revert_webhooks.is_some()
+                && self.rpc.revert_methods.iter().any(|x| x == method);
+
+            let revert =
+                if send_revert_webhook || matches!(error_handler, RequestErrorHandler::Save) {
+                    parse_revert(method, params, err)
+                } else {
+                    None
+                };
+
+            if send_revert_webhook {
+                if let Some((method, params, revert)) = revert.as_ref() {
+                    let f = self.authorization.clone().send_revert_webhook(
+                        method.clone(),
+                        params.clone(),
+                        revert.reason.clone(),
+                    );
+
+                    tokio::spawn(f);
+                }
+            }
+
             if matches!(response_type, ResponseTypes::RateLimit) {
                 if let Some(hard_limit_until) = self.rpc.hard_limit_until.as_ref() {
                     // TODO: how long should we actually wait? different providers have different times
@@ -297,11 +392,25 @@ impl OpenRequestHandle {
                         warn!(?retry_at, "rate limited on {}!", self.rpc);
                     }
 
+                    hard_limit_until.send_replace(retry_at);
+                }
+            } else if matches!(response_type, ResponseTypes::Malformed) {
+                // the caller skips this rpc and tries another. back off briefly so that other requests skip it too
+                self.rpc
+                    .malformed_responses
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+                MALFORMED_RESPONSES.fetch_add(1, atomic::Ordering::Relaxed);
+
+                if let Some(hard_limit_until) = self.rpc.hard_limit_until.as_ref() {
+                    let retry_at = Instant::now() + Duration::from_secs(1);
+
+                    warn!(?retry_at, %method, ?err, "malformed response from {}!", self.rpc);
+
                     hard_limit_until.send_replace(retry_at);
                 }
             }
 
-            // TODO: think more about the method and param logs. those can be sensitive information
+            // params can be sensitive information. `redact_params` hides them depending on the config
             // we do **NOT** use self.error_handler here because it might have been modified
             match error_handler {
                 RequestErrorHandler::DebugLevel => {
@@ -310,7 +419,7 @@ impl OpenRequestHandle {
                         trace!(
                             rpc=%self.rpc,
                             %method,
-                            ?params,
+                            params=?redact_params(method, params),
                             ?err,
                             "revert",
                         );
@@ -318,7 +427,7 @@ impl OpenRequestHandle {
                         debug!(
                             rpc=%self.rpc,
                             %method,
-                            ?params,
+                            params=?redact_params(method, params),
                             ?err,
                             "bad response",
                         );
@@ -328,7 +437,7 @@ impl OpenRequestHandle {
                     info!(
                         rpc=%self.rpc,
                         %method,
-                        ?params,
+                        params=?redact_params(method, params),
                         ?err,
                         "bad response",
                     );
@@ -337,7 +446,7 @@ impl OpenRequestHandle {
                     trace!(
                         rpc=%self.rpc,
                         %method,
-                        ?params,
+                        params=?redact_params(method, params),
                         ?err,
                         "bad response",
                     );
@@ -347,7 +456,7 @@ impl OpenRequestHandle {
                     error!(
                         rpc=%self.rpc,
                         %method,
-                        ?params,
+                        params=?redact_params(method, params),
                         ?err,
                         "bad response",
                     );
@@ -357,7 +466,7 @@ impl OpenRequestHandle {
                     warn!(
                         rpc=%self.rpc,
                         %method,
-                        ?params,
+                        params=?redact_params(method, params),
                         ?err,
                         "bad response",
                     );
@@ -366,30 +475,19 @@ impl OpenRequestHandle {
                     trace!(
                         rpc=%self.rpc,
                         %method,
-                        ?params,
+                        params=?redact_params(method, params),
                         ?err,
                         "bad response",
                     );
 
-                    // TODO: do not unwrap! (doesn't matter much since we check method as a string above)
-                    let method: Method = Method::try_from_value(&method.to_string()).unwrap();
-
-                    // TODO: i don't think this prsing is correct
-                    match serde_json::from_value::<EthCallParams>(json!(params)) {
-                        Ok(params) => {
-                            // spawn saving to the database so we don't slow down the request
-                            let f = self.authorization.clone().save_revert(method, params.0 .0);
-
-                            tokio::spawn(f);
-                        }
-                        Err(err) => {
-                            warn!(
-                                %method,
-                                ?params,
-                                ?err,
-                                "failed parsing eth_call params. unable to save revert",
-                            );
-                        }
+                    if let Some((method, params, revert)) = revert {
+                        // spawn saving to the database so we don't slow down the request
+                        let f = self
+                            .authorization
+                            .clone()
+                            .save_revert(method, params, revert);
+
+                        tokio::spawn(f);
                     }
                 }
             }
@@ -403,3 +501,195 @@ impl OpenRequestHandle {
         response
     }
 }
+
+/// The rpc sent something that isn't valid JSON or doesn't have the shape we expected.
+/// This is the rpc's fault, not the user's.
+fn is_malformed_response(err: &ProviderError) -> bool {
+    err.as_serde_error().is_some()
+}
+
+/// The call and the reason it reverted. None if the params are not an `eth_call` shape
+fn parse_revert<P: JsonRpcParams>(
+    method: &str,
+    params: &P,
+    err: &ProviderError,
+) -> Option<(Method, EthCallFirstParams, RevertDetails)> {
+    // revert_methods are checked against Method when the config loads. this is only in case that ever changes
+    let method: Method = Method::try_from_value(&method.to_string()).ok()?;
+
+    let revert = match err {
+        ProviderError::JsonRpcClientError(err) => err
+            .as_error_response()
+            .map(RevertDetails::from)
+            .unwrap_or_default(),
+        _ => RevertDetails::default(),
+    };
+
+    // TODO: i don't think this prsing is correct
+    match serde_json::from_value::<EthCallParams>(json!(params)) {
+        Ok(params) => Some((method, params.0 .0, revert)),
+        Err(err) => {
+            warn!(
+                ?method,
+                ?err,
+                "failed parsing eth_call params. unable to save revert",
+            );
+            None
+        }
+    }
+}
+
+/// Why a call reverted. Both are None if the rpc didn't say
+#[derive(Debug, Default)]
+struct RevertDetails {
+    reason: Option<String>,
+    /// the raw return data of the revert
+    data: Option<Bytes>,
+}
+
+impl From<&JsonRpcError> for RevertDetails {
+    fn from(err: &JsonRpcError) -> Self {
+        let data = err
+            .data
+            .as_ref()
+            .and_then(|x| x.as_str())
+            .and_then(|x| Bytes::from_str(x).ok())
+            .filter(|x| !x.is_empty());
+
+        // some rpcs only put the reason in the message
+        let reason = data.as_deref().and_then(decode_revert_data).or_else(|| {
+            err.message
+                .strip_prefix("execution reverted: ")
+                .map(ToString::to_string)
+        });
+
+        Self { reason, data }
+    }
+}
+
+/// Solidity's `Error(string)` and `Panic(uint256)`. Custom errors are only named by their selector since we don't have their abi
+fn decode_revert_data(data: &[u8]) -> Option<String> {
+    if data.len() < 4 {
+        return None;
+    }
+
+    let (selector, args) = data.split_at(4);
+
+    match selector {
+        [0x08, 0xc3, 0x79, 0xa0] => abi::decode(&[ParamType::String], args)
+            .ok()?
+            .pop()?
+            .into_string(),
+        [0x4e, 0x48, 0x7b, 0x71] => {
+            let code = abi::decode(&[ParamType::Uint(256)], args)
+                .ok()?
+                .pop()?
+                .into_uint()?;
+
+            Some(format!("panic code 0x{:x}", code))
+        }
+        _ => Some(format!("custom error {}", Bytes::from(selector.to_vec()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_malformed_response, RevertDetails};
+    use ethers::providers::{HttpClientError, JsonRpcError, ProviderError};
+    use serde_json::json;
+
+    #[test]
+    fn test_is_malformed_response() {
+        let truncated =
+            serde_json::from_str::<serde_json::Value>(r#"{"jsonrpc":"2.0","id":1,"res"#)
+                .unwrap_err();
+
+        assert!(is_malformed_response(&ProviderError::SerdeJson(truncated)));
+
+        let truncated =
+            serde_json::from_str::<serde_json::Value>(r#"{"jsonrpc":"2.0","id":1,"res"#)
+                .unwrap_err();
+
+        assert!(is_malformed_response(&ProviderError::JsonRpcClientError(
+            Box::new(HttpClientError::SerdeJson {
+                err: truncated,
+                text: r#"{"jsonrpc":"2.0","id":1,"res"#.to_string(),
+            })
+        )));
+
+        // a jsonrpc error is a valid response
+        let reverted = HttpClientError::JsonRpcError(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        });
+
+        assert!(!is_malformed_response(&ProviderError::JsonRpcClientError(
+            Box::new(reverted)
+        )));
+
+        assert!(!is_malformed_response(&ProviderError::CustomError(
+            "no provider configured!".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_revert_details() {
+        // Error("nope")
+        let data = concat!(
+            "0x08c379a0",
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "0000000000000000000000000000000000000000000000000000000000000004",
+            "6e6f706500000000000000000000000000000000000000000000000000000000",
+        );
+
+        let err = JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: Some(json!(data)),
+        };
+
+        let x = RevertDetails::from(&err);
+
+        assert_eq!(x.reason.as_deref(), Some("nope"));
+        assert_eq!(x.data.unwrap().to_string(), data);
+
+        // Panic(0x11) is an overflow
+        let err = JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: Some(json!(concat!(
+                "0x4e487b71",
+                "0000000000000000000000000000000000000000000000000000000000000011",
+            ))),
+        };
+
+        assert_eq!(
+            RevertDetails::from(&err).reason.as_deref(),
+            Some("panic code 0x11")
+        );
+
+        // custom errors can't be decoded without the contract's abi
+        let err = JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: Some(json!("0xdeadbeef0000")),
+        };
+
+        assert_eq!(
+            RevertDetails::from(&err).reason.as_deref(),
+            Some("custom error 0xdeadbeef")
+        );
+
+        let err = JsonRpcError {
+            code: 3,
+            message: "execution reverted: nope".to_string(),
+            data: None,
+        };
+
+        let x = RevertDetails::from(&err);
+
+        assert_eq!(x.reason.as_deref(), Some("nope"));
+        assert!(x.data.is_none());
+    }
+}
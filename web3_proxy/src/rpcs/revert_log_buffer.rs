@@ -0,0 +1,162 @@
+//! Batch revert log inserts so that a contract that reverts a lot can't hammer the database.
+use crate::app::Web3ProxyJoinHandle;
+use crate::errors::Web3ProxyResult;
+use arc_swap::ArcSwapOption;
+use entities::revert_log;
+use migration::sea_orm::{DatabaseConnection, EntityTrait};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, trace, warn};
+
+/// The sender for the running buffer. None if revert logs are saved without batching.
+/// This is global because reverts are saved from deep inside request handling where the app isn't available.
+pub static REVERT_LOG_SENDER: ArcSwapOption<mpsc::Sender<revert_log::ActiveModel>> =
+    ArcSwapOption::const_empty();
+
+/// Revert logs that were not saved because the buffer was full
+pub static REVERT_LOGS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// The channel holds this many batches before revert logs are dropped
+const CHANNEL_BATCHES: usize = 10;
+
+/// Queue a revert log to be saved by the buffer.
+/// Returns the revert log if there is no running buffer so that the caller can save it directly.
+pub fn try_queue_revert_log(rl: revert_log::ActiveModel) -> Result<(), revert_log::ActiveModel> {
+    let sender = REVERT_LOG_SENDER.load();
+
+    let sender = match sender.as_ref() {
+        Some(x) => x,
+        None => return Err(rl),
+    };
+
+    match sender.try_send(rl) {
+        Ok(()) => Ok(()),
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            // dropping is better than slowing down requests or the database
+            let dropped = REVERT_LOGS_DROPPED.fetch_add(1, Ordering::Relaxed) + 1;
+
+            if dropped.is_power_of_two() {
+                warn!(dropped, "revert log buffer is full. dropping revert logs");
+            }
+
+            Ok(())
+        }
+        Err(mpsc::error::TrySendError::Closed(rl)) => Err(rl),
+    }
+}
+
+pub struct RevertLogBuffer {
+    batch_size: usize,
+    buffer: Vec<revert_log::ActiveModel>,
+    db_conn: DatabaseConnection,
+    flush_interval: Duration,
+}
+
+impl RevertLogBuffer {
+    /// Start saving revert logs in batches of `batch_size` at least every `flush_seconds`.
+    /// Returns None if there is no database or batching is disabled.
+    pub fn try_spawn(
+        batch_size: usize,
+        db_conn: Option<DatabaseConnection>,
+        flush_seconds: u64,
+        shutdown_token: CancellationToken,
+    ) -> Option<Web3ProxyJoinHandle<()>> {
+        let db_conn = db_conn?;
+
+        if batch_size == 0 || flush_seconds == 0 {
+            return None;
+        }
+
+        let (sender, receiver) = mpsc::channel(batch_size * CHANNEL_BATCHES);
+
+        let sender = Arc::new(sender);
+
+        REVERT_LOG_SENDER.store(Some(sender.clone()));
+
+        let mut new = Self {
+            batch_size,
+            buffer: Vec::with_capacity(batch_size),
+            db_conn,
+            flush_interval: Duration::from_secs(flush_seconds),
+        };
+
+        let handle =
+            tokio::spawn(async move { new.save_loop(sender, receiver, shutdown_token).await });
+
+        Some(handle)
+    }
+
+    async fn save_loop(
+        &mut self,
+        sender: Arc<mpsc::Sender<revert_log::ActiveModel>>,
+        mut receiver: mpsc::Receiver<revert_log::ActiveModel>,
+        shutdown_token: CancellationToken,
+    ) -> Web3ProxyResult<()> {
+        let mut flush_interval = interval(self.flush_interval);
+        flush_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                rl = receiver.recv() => {
+                    match rl {
+                        Some(rl) => {
+                            self.buffer.push(rl);
+
+                            if self.buffer.len() >= self.batch_size {
+                                self.flush().await;
+                            }
+                        }
+                        None => {
+                            info!("revert log channel closed");
+                            break;
+                        }
+                    }
+                }
+                _ = flush_interval.tick() => {
+                    self.flush().await;
+                }
+                _ = shutdown_token.cancelled() => {
+                    info!("revert log buffer shutting down");
+                    break;
+                }
+            }
+        }
+
+        // stop accepting new revert logs and save whatever made it into the channel
+        // a newer buffer might have replaced this one. only clear the sender if it is still ours
+        REVERT_LOG_SENDER.compare_and_swap(&Some(sender), None);
+
+        receiver.close();
+
+        while let Some(rl) = receiver.recv().await {
+            self.buffer.push(rl);
+        }
+
+        self.flush().await;
+
+        Ok(())
+    }
+
+    /// Errors are logged and the batch is dropped. Revert logs are not important enough to retry.
+    async fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let batch = mem::replace(&mut self.buffer, Vec::with_capacity(self.batch_size));
+
+        let count = batch.len();
+
+        match revert_log::Entity::insert_many(batch)
+            .exec(&self.db_conn)
+            .await
+        {
+            Ok(_) => trace!(count, "saved revert logs"),
+            Err(err) => error!(?err, count, "failed saving revert logs"),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! A local token bucket for capping the requests per second sent to one backend rpc.
+//! Unlike `hard_limit`, this is not shared between proxies. Each proxy gets the full cap.
+
+use parking_lot::Mutex;
+use tokio::time::{Duration, Instant};
+
+struct TokenBucketState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+pub struct TokenBucket {
+    /// tokens added per second. this is also the size of the bucket, so at most one second of requests can burst
+    per_second: f64,
+    state: Mutex<TokenBucketState>,
+}
+
+impl TokenBucket {
+    /// The bucket starts full
+    pub fn new(per_second: u32) -> Self {
+        let per_second = per_second.max(1) as f64;
+
+        Self {
+            per_second,
+            state: Mutex::new(TokenBucketState {
+                tokens: per_second,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token. If the bucket is empty, return when the next token will be available
+    pub fn try_acquire(&self) -> Result<(), Instant> {
+        let now = Instant::now();
+
+        let mut state = self.state.lock();
+
+        let elapsed = now.saturating_duration_since(state.refilled_at);
+
+        state.tokens =
+            (state.tokens + elapsed.as_secs_f64() * self.per_second).min(self.per_second);
+        state.refilled_at = now;
+
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+
+            Ok(())
+        } else {
+            let wait = (1.0 - state.tokens) / self.per_second;
+
+            Err(now + Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenBucket;
+    use tokio::time::{Duration, Instant};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_bucket() {
+        let bucket = TokenBucket::new(10);
+
+        // a full second of requests can burst
+        for _ in 0..10 {
+            assert!(bucket.try_acquire().is_ok());
+        }
+
+        let retry_at = bucket.try_acquire().unwrap_err();
+        assert_eq!(retry_at, Instant::now() + Duration::from_millis(100));
+
+        tokio::time::advance(Duration::from_millis(100)).await;
+
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_err());
+
+        // the bucket never holds more than one second of tokens
+        tokio::time::advance(Duration::from_secs(60)).await;
+
+        for _ in 0..10 {
+            assert!(bucket.try_acquire().is_ok());
+        }
+        assert!(bucket.try_acquire().is_err());
+    }
+}
@@ -16,12 +16,34 @@ use hashbrown::HashMap;
 use migration::sea_orm::{
     ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Select,
 };
-use migration::{Condition, Expr, SimpleExpr};
+use migration::{Alias, Condition, Expr, SimpleExpr};
 use redis_rate_limiter::redis;
 use redis_rate_limiter::redis::AsyncCommands;
 use serde_json::json;
 use tracing::warn;
 
+/// The counters that the stats queries sum and what each sum is called in the results.
+/// `rpc_accounting` and `rpc_accounting_v2` both have these columns
+pub const TOTALS: [(&str, &str); 8] = [
+    ("frontend_requests", "total_frontend_requests"),
+    ("backend_requests", "total_backend_retries"),
+    ("cache_misses", "total_cache_misses"),
+    ("cache_hits", "total_cache_hits"),
+    ("sum_request_bytes", "total_request_bytes"),
+    ("sum_response_bytes", "total_response_bytes"),
+    ("error_response", "total_error_responses"),
+    ("sum_response_millis", "total_response_millis"),
+];
+
+/// Sum every counter in `TOTALS`. The caller adds the filters and groups
+pub fn select_totals<E: EntityTrait>() -> Select<E> {
+    TOTALS
+        .iter()
+        .fold(E::find().select_only(), |q, (column, alias)| {
+            q.column_as(Expr::col((E::default(), Alias::new(column))).sum(), *alias)
+        })
+}
+
 pub fn filter_query_window_seconds(
     query_window_seconds: u64,
     response: &mut HashMap<&str, serde_json::Value>,
@@ -67,7 +89,7 @@ pub async fn query_user_stats<'a>(
 
     // get the user id first. if it is 0, we should use a cache on the app
     let user_id =
-        get_user_id_from_params(&mut redis_conn, db_conn, db_replica, bearer, params).await?;
+        get_user_id_from_params(app, &mut redis_conn, db_conn, db_replica, bearer, params).await?;
     // get the query window seconds now so that we can pick a cache with a good TTL
     // TODO: for now though, just do one cache. its easier
     let query_window_seconds = get_query_window_seconds_from_params(params)?;
@@ -123,33 +145,7 @@ pub async fn query_user_stats<'a>(
 
     let mut response_body = HashMap::new();
 
-    let mut q = rpc_accounting::Entity::find()
-        .select_only()
-        .column_as(
-            rpc_accounting::Column::FrontendRequests.sum(),
-            "total_frontend_requests",
-        )
-        .column_as(
-            rpc_accounting::Column::BackendRequests.sum(),
-            "total_backend_retries",
-        )
-        .column_as(
-            rpc_accounting::Column::CacheMisses.sum(),
-            "total_cache_misses",
-        )
-        .column_as(rpc_accounting::Column::CacheHits.sum(), "total_cache_hits")
-        .column_as(
-            rpc_accounting::Column::SumResponseBytes.sum(),
-            "total_response_bytes",
-        )
-        .column_as(
-            rpc_accounting::Column::ErrorResponse.sum(),
-            "total_error_responses",
-        )
-        .column_as(
-            rpc_accounting::Column::SumResponseMillis.sum(),
-            "total_response_millis",
-        );
+    let mut q = select_totals::<rpc_accounting::Entity>();
 
     // TODO: make this and q mutable and clean up the code below. no need for more `let q`
     let mut condition = Condition::all();
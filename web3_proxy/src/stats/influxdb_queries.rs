@@ -180,6 +180,7 @@ pub async fn query_user_stats<'a>(
     // Or just unwrap or so
     let bucket = &app
         .config
+        .load()
         .influxdb_bucket
         .clone()
         .context("No influxdb bucket was provided")?;
@@ -202,19 +203,16 @@ pub async fn query_user_stats<'a>(
     trace!("Filters are: {:?}", filter_chain_id); // filter_field
     trace!("window seconds are: {:?}", query_window_seconds);
 
-    let group_keys = match stat_response_type {
-        StatType::Aggregated => {
-            r#"[
+    let mut group_keys = match stat_response_type {
+        StatType::Aggregated => vec![
             "_field",
             "_measurement",
             "archive_needed",
             "chain_id",
             "error_response",
             "rpc_secret_key_id",
-        ]"#
-        }
-        StatType::Detailed => {
-            r#"[
+        ],
+        StatType::Detailed => vec![
             "_field",
             "_measurement",
             "archive_needed",
@@ -223,10 +221,21 @@ pub async fn query_user_stats<'a>(
             "method",
             "rpc_secret_key_id",
             "user_error_response",
-        ]"#
-        }
+        ],
     };
 
+    // tags are set by the client with the `X-Web3-Proxy-Tag` header. only the opt-in measurement has them
+    let group_by_tag = params
+        .get("group_by_tag")
+        .map(|x| x == "true")
+        .unwrap_or_default();
+
+    if group_by_tag && user_id != 0 {
+        group_keys.push("tag");
+    }
+
+    let group_keys = f!(r#"{:?}"#, group_keys);
+
     let join_candidates = f!(
         r#"{:?}"#,
         vec![
@@ -493,6 +502,15 @@ pub async fn query_user_stats<'a>(
                             error!("method should always be a String!");
                         }
                     }
+                } else if key == "tag" {
+                    match value {
+                        influxdb2_structmap::value::Value::String(inner) => {
+                            out.insert("tag", serde_json::Value::String(inner));
+                        }
+                        _ => {
+                            error!("tag should always be a String!");
+                        }
+                    }
                 } else if key == "chain_id" {
                     match value {
                         influxdb2_structmap::value::Value::String(inner) => {
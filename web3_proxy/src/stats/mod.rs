@@ -4,6 +4,8 @@ mod stat_buffer;
 
 pub mod db_queries;
 pub mod influxdb_queries;
+pub mod slo;
+pub mod tier_metrics;
 
 use self::stat_buffer::BufferedRpcQueryStats;
 use crate::caches::{RpcSecretKeyCache, UserBalanceCache};
@@ -42,6 +44,9 @@ pub enum StatType {
 
 pub type BackendRequests = Mutex<Vec<Arc<Web3Rpc>>>;
 
+/// The one time bonus (in USD) a referee gets once they spend $100. `check_referrals` caps bonuses to this
+pub const REFEREE_BONUS: u64 = 10;
+
 #[derive(Copy, Clone, Debug)]
 pub struct FlushedStats {
     pub relational: usize,
@@ -92,6 +97,8 @@ pub struct RpcQueryKey {
     rpc_secret_key_id: Option<NonZeroU64>,
     /// None if the public url was used.
     rpc_key_user_id: Option<NonZeroU64>,
+    /// the client's `X-Web3-Proxy-Tag` header. None if it wasn't set.
+    tag: Option<String>,
 }
 
 /// round the unix epoch time to the start of a period
@@ -129,6 +136,7 @@ impl RpcQueryStats {
             rpc_key_user_id: self.authorization.checks.user_id.try_into().ok(),
             origin,
             user_error_response,
+            tag: self.authorization.tag.clone(),
         }
     }
 
@@ -141,6 +149,8 @@ impl RpcQueryStats {
         let origin = None;
         // everyone gets grouped together
         let rpc_secret_key_id = None;
+        // tags only mean something within one user's keys
+        let tag = None;
 
         RpcQueryKey {
             response_timestamp: self.response_timestamp,
@@ -151,6 +161,7 @@ impl RpcQueryStats {
             rpc_key_user_id: self.authorization.checks.user_id.try_into().ok(),
             user_error_response: self.user_error_response,
             origin,
+            tag,
         }
     }
 
@@ -174,6 +185,7 @@ impl RpcQueryStats {
             rpc_key_user_id: self.authorization.checks.user_id.try_into().ok(),
             user_error_response: self.user_error_response,
             origin,
+            tag: self.authorization.tag.clone(),
         };
 
         Some(key)
@@ -260,6 +272,7 @@ impl BufferedRpcQueryStats {
             sum_response_bytes: sea_orm::Set(self.sum_response_bytes),
             sum_credits_used: sea_orm::Set(self.paid_credits_used),
             sum_incl_free_credits_used: sea_orm::Set(self.sum_credits_used),
+            tag: sea_orm::Set(key.tag.clone().unwrap_or_default()),
         };
 
         rpc_accounting_v2::Entity::insert(accounting_entry)
@@ -385,8 +398,7 @@ impl BufferedRpcQueryStats {
                     if referrer_balance.was_ever_premium() {
                         // spend $100
                         let bonus_for_user_threshold = Decimal::from(100);
-                        // get $10
-                        let bonus_for_user = Decimal::from(10);
+                        let bonus_for_user = Decimal::from(REFEREE_BONUS);
 
                         let referral_start_date = referral_entity.referral_start_date;
 
@@ -484,6 +496,10 @@ impl BufferedRpcQueryStats {
 
         builder = builder.tag("method", key.method);
 
+        if let Some(tag) = key.tag {
+            builder = builder.tag("tag", tag);
+        }
+
         builder = builder
             .tag("archive_needed", key.archive_needed.to_string())
             .tag("error_response", key.error_response.to_string())
@@ -569,7 +585,12 @@ impl RpcQueryStats {
             x => x,
         };
 
-        let cu = ComputeUnit::new(&metadata.method, metadata.chain_id, response_bytes);
+        let cu = ComputeUnit::new(
+            &metadata.method,
+            metadata.chain_id,
+            response_bytes,
+            metadata.configured_cu,
+        );
 
         let cache_hit = backend_rpcs_used.is_empty();
 
@@ -0,0 +1,229 @@
+//! Rolling success rate and latency for every request the proxy answers. This is the public "is it healthy right now" view.
+//! Like the tier metrics, these are only kept in memory and reset when the app restarts.
+
+use crate::frontend::authorization::{AuthorizationType, RequestMetadata};
+use hdrhistogram::Histogram;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use tokio::time::Instant;
+
+/// Requests are grouped into buckets this many seconds wide
+const BUCKET_SECONDS: u64 = 10;
+
+/// The longest window that is reported
+const MAX_WINDOW_SECONDS: u64 = 3_600;
+
+/// The windows in `SloMetrics::summary`
+const WINDOWS: [(&str, u64); 3] = [("1m", 60), ("5m", 300), ("1h", MAX_WINDOW_SECONDS)];
+
+/// Anything slower than this is recorded as this. A request that takes an hour has already failed its SLO
+const MAX_RESPONSE_MILLIS: u64 = 3_600_000;
+
+struct SloBucket {
+    /// seconds since `started_at` divided by `BUCKET_SECONDS`
+    period: u64,
+    requests: u64,
+    /// requests that hit an application error. this does not include reverts or invalid requests
+    errors: u64,
+    response_millis: Histogram<u32>,
+}
+
+impl SloBucket {
+    fn new(period: u64) -> Self {
+        Self {
+            period,
+            requests: 0,
+            errors: 0,
+            response_millis: new_histogram(),
+        }
+    }
+}
+
+fn new_histogram() -> Histogram<u32> {
+    Histogram::new_with_bounds(1, MAX_RESPONSE_MILLIS, 2).expect("histogram bounds are valid")
+}
+
+pub struct SloMetrics {
+    started_at: Instant,
+    /// oldest first. periods without requests are skipped
+    buckets: Mutex<VecDeque<SloBucket>>,
+}
+
+/// Success rate and latency over one window. Rates are None if there were no requests.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SloWindow {
+    pub window: &'static str,
+    pub requests: u64,
+    pub errors: u64,
+    pub success_rate: Option<f64>,
+    pub p50_millis: Option<u64>,
+    pub p99_millis: Option<u64>,
+}
+
+impl Default for SloMetrics {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            buckets: Default::default(),
+        }
+    }
+}
+
+impl SloMetrics {
+    fn current_period(&self) -> u64 {
+        self.started_at.elapsed().as_secs() / BUCKET_SECONDS
+    }
+
+    /// Count a finished request. Internal requests are skipped.
+    pub fn record(&self, metadata: &RequestMetadata) {
+        match metadata.authorization.as_ref() {
+            Some(authorization) => {
+                if matches!(
+                    authorization.authorization_type,
+                    AuthorizationType::Internal
+                ) {
+                    return;
+                }
+            }
+            None => return,
+        }
+
+        let error = metadata.error_response.load(Ordering::Acquire);
+
+        let response_millis = match metadata.response_millis.load(Ordering::Acquire) {
+            0 => metadata.start_instant.elapsed().as_millis() as u64,
+            x => x,
+        };
+
+        self.record_response(error, response_millis);
+    }
+
+    fn record_response(&self, error: bool, response_millis: u64) {
+        let period = self.current_period();
+
+        let mut buckets = self.buckets.lock();
+
+        if buckets.back().map(|x| x.period) != Some(period) {
+            buckets.push_back(SloBucket::new(period));
+
+            // nothing reads buckets older than the longest window
+            while buckets.front().map_or(false, |x| {
+                x.period + MAX_WINDOW_SECONDS / BUCKET_SECONDS <= period
+            }) {
+                buckets.pop_front();
+            }
+        }
+
+        let bucket = buckets.back_mut().expect("a bucket was just pushed");
+
+        bucket.requests += 1;
+        if error {
+            bucket.errors += 1;
+        }
+        bucket
+            .response_millis
+            .saturating_record(response_millis.clamp(1, MAX_RESPONSE_MILLIS));
+    }
+
+    /// The 1m, 5m, and 1h windows. Each window includes the current partial bucket.
+    pub fn summary(&self) -> Vec<SloWindow> {
+        let period = self.current_period();
+
+        let buckets = self.buckets.lock();
+
+        WINDOWS
+            .iter()
+            .map(|(window, window_seconds)| {
+                let window_buckets = window_seconds / BUCKET_SECONDS;
+
+                let mut requests = 0;
+                let mut errors = 0;
+                let mut response_millis = new_histogram();
+
+                for bucket in buckets
+                    .iter()
+                    .filter(|x| x.period + window_buckets > period)
+                {
+                    requests += bucket.requests;
+                    errors += bucket.errors;
+                    response_millis
+                        .add(&bucket.response_millis)
+                        .expect("histograms have the same bounds");
+                }
+
+                let (success_rate, p50_millis, p99_millis) = if requests == 0 {
+                    (None, None, None)
+                } else {
+                    (
+                        Some(1.0 - errors as f64 / requests as f64),
+                        Some(response_millis.value_at_quantile(0.50)),
+                        Some(response_millis.value_at_quantile(0.99)),
+                    )
+                };
+
+                SloWindow {
+                    window,
+                    requests,
+                    errors,
+                    success_rate,
+                    p50_millis,
+                    p99_millis,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SloMetrics;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slo_windows() {
+        let slo = SloMetrics::default();
+
+        let summary = slo.summary();
+        assert_eq!(summary.len(), 3);
+        assert!(summary.iter().all(|x| x.requests == 0));
+        assert!(summary.iter().all(|x| x.success_rate.is_none()));
+
+        // an error 10 minutes ago is only in the 1h window
+        for _ in 0..99 {
+            slo.record_response(false, 10);
+        }
+        slo.record_response(true, 1_000);
+
+        tokio::time::advance(Duration::from_secs(600)).await;
+
+        for _ in 0..100 {
+            slo.record_response(false, 20);
+        }
+
+        let summary = slo.summary();
+
+        assert_eq!(summary[0].window, "1m");
+        assert_eq!(summary[0].requests, 100);
+        assert_eq!(summary[0].errors, 0);
+        assert_eq!(summary[0].success_rate, Some(1.0));
+        assert_eq!(summary[0].p99_millis, Some(20));
+
+        assert_eq!(summary[1].requests, 100);
+
+        assert_eq!(summary[2].window, "1h");
+        assert_eq!(summary[2].requests, 200);
+        assert_eq!(summary[2].errors, 1);
+        assert_eq!(summary[2].success_rate, Some(0.995));
+
+        // everything ages out after an hour
+        tokio::time::advance(Duration::from_secs(3_600)).await;
+
+        slo.record_response(false, 5);
+
+        let summary = slo.summary();
+        assert_eq!(summary[2].requests, 1);
+        assert_eq!(slo.buckets.lock().len(), 1);
+    }
+}
@@ -3,6 +3,7 @@ use crate::app::Web3ProxyJoinHandle;
 use crate::caches::{RpcSecretKeyCache, UserBalanceCache};
 use crate::errors::Web3ProxyResult;
 use crate::stats::RpcQueryStats;
+use crate::webhooks::{send_webhook, WebhookEvent, WebhookNotification, WebhookSender};
 use derive_more::From;
 use futures::stream;
 use hashbrown::HashMap;
@@ -55,6 +56,8 @@ pub struct StatBuffer {
     timestamp_precision: TimestampPrecision,
     tsdb_save_interval_seconds: u32,
     user_balance_cache: UserBalanceCache,
+    webhook_low_balance: Option<Decimal>,
+    webhook_sender: Option<WebhookSender>,
 
     _flush_sender: mpsc::Sender<oneshot::Sender<FlushedStats>>,
 }
@@ -74,6 +77,8 @@ impl StatBuffer {
         tsdb_save_interval_seconds: u32,
         flush_sender: mpsc::Sender<oneshot::Sender<FlushedStats>>,
         flush_receiver: mpsc::Receiver<oneshot::Sender<FlushedStats>>,
+        webhook_sender: Option<WebhookSender>,
+        webhook_low_balance: Option<Decimal>,
     ) -> anyhow::Result<Option<SpawnedStatBuffer>> {
         if influxdb_bucket.is_none() {
             influxdb_client = None;
@@ -101,6 +106,8 @@ impl StatBuffer {
             timestamp_precision,
             tsdb_save_interval_seconds,
             user_balance_cache: user_balance_cache.unwrap(),
+            webhook_low_balance,
+            webhook_sender,
             _flush_sender: flush_sender,
         };
 
@@ -148,6 +155,8 @@ impl StatBuffer {
                                     // update the user's cached balance
                                     let mut user_balance = stat.authorization.checks.latest_balance.write().await;
 
+                                    let balance_before = user_balance.remaining();
+
                                     // TODO: move this to a helper function
                                     user_balance.total_frontend_requests += 1;
                                     user_balance.total_spent += stat.compute_unit_cost;
@@ -180,6 +189,15 @@ impl StatBuffer {
                                     }
 
                                     approximate_balance_remaining = user_balance.remaining();
+
+                                    // only the query that crosses the threshold sends an event
+                                    if let (Some(threshold), Some(webhook_sender)) = (self.webhook_low_balance, self.webhook_sender.as_ref()) {
+                                        if balance_before >= threshold && approximate_balance_remaining < threshold {
+                                            let event = WebhookEvent::LowBalance { balance: approximate_balance_remaining, threshold };
+
+                                            send_webhook(webhook_sender, WebhookNotification::new(user_id, event));
+                                        }
+                                    }
                                 }
 
                                 self.accounting_db_buffer.entry(stat.accounting_key(self.billing_period_seconds)).or_default().add(stat.clone(), approximate_balance_remaining).await;
@@ -0,0 +1,167 @@
+//! Request counts grouped by user tier. This shows how much load each tier drives.
+//! Unlike the other stats, these are only kept in memory and reset when the app restarts.
+
+use crate::frontend::authorization::{AuthorizationType, RequestMetadata};
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use tokio::time::Instant;
+
+/// Label used for requests without an rpc key
+const ANONYMOUS_TIER: &str = "anonymous";
+
+#[derive(Clone, Copy, Debug, Default)]
+struct TierCounts {
+    requests: u64,
+    /// requests that hit an application error. this does not include reverts or invalid requests
+    errors: u64,
+    /// sum of response times. divide by `requests` to get the average
+    response_millis: u64,
+}
+
+#[derive(Debug)]
+pub struct TierMetrics {
+    started_at: Instant,
+    tiers: Mutex<HashMap<String, TierCounts>>,
+}
+
+/// Aggregated metrics for one tier. Rates are averaged over the app's uptime.
+#[derive(Debug, Serialize)]
+pub struct TierSummary {
+    pub tier: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub requests_per_second: f64,
+    pub error_rate: f64,
+    pub avg_response_millis: f64,
+}
+
+impl Default for TierMetrics {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            tiers: Default::default(),
+        }
+    }
+}
+
+impl TierMetrics {
+    /// Count a finished request. Internal requests are skipped.
+    pub fn record(&self, metadata: &RequestMetadata) {
+        let tier = match metadata.authorization.as_ref() {
+            Some(authorization) => {
+                if matches!(
+                    authorization.authorization_type,
+                    AuthorizationType::Internal
+                ) {
+                    return;
+                }
+
+                authorization
+                    .checks
+                    .user_tier_title
+                    .as_deref()
+                    .unwrap_or(ANONYMOUS_TIER)
+            }
+            None => return,
+        };
+
+        let error = metadata.error_response.load(Ordering::Acquire);
+
+        let response_millis = match metadata.response_millis.load(Ordering::Acquire) {
+            0 => metadata.start_instant.elapsed().as_millis() as u64,
+            x => x,
+        };
+
+        let mut tiers = self.tiers.lock();
+
+        let counts = tiers.entry_ref(tier).or_default();
+
+        counts.requests += 1;
+        if error {
+            counts.errors += 1;
+        }
+        counts.response_millis += response_millis;
+    }
+
+    /// Summaries for every tier that has sent a request, sorted by tier
+    pub fn summary(&self) -> Vec<TierSummary> {
+        let uptime = self.started_at.elapsed().as_secs_f64().max(1.0);
+
+        let mut summary: Vec<_> = self
+            .tiers
+            .lock()
+            .iter()
+            .map(|(tier, counts)| {
+                // requests is never 0 because counts are only created when a request is recorded
+                let requests = counts.requests as f64;
+
+                TierSummary {
+                    tier: tier.clone(),
+                    requests: counts.requests,
+                    errors: counts.errors,
+                    requests_per_second: requests / uptime,
+                    error_rate: counts.errors as f64 / requests,
+                    avg_response_millis: counts.response_millis as f64 / requests,
+                }
+            })
+            .collect();
+
+        summary.sort_by(|a, b| a.tier.cmp(&b.tier));
+
+        summary
+    }
+
+    /// Counters in the prometheus text format. Use `rate()` for requests per second.
+    pub fn prometheus_text(&self) -> String {
+        let mut tiers: Vec<_> = self
+            .tiers
+            .lock()
+            .iter()
+            .map(|(tier, counts)| (tier.clone(), *counts))
+            .collect();
+
+        tiers.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut text = String::new();
+
+        write_counter(
+            &mut text,
+            "web3_proxy_tier_requests",
+            "requests grouped by user tier",
+            tiers.iter().map(|(tier, x)| (tier, x.requests)),
+        );
+        write_counter(
+            &mut text,
+            "web3_proxy_tier_errors",
+            "requests that errored grouped by user tier",
+            tiers.iter().map(|(tier, x)| (tier, x.errors)),
+        );
+        write_counter(
+            &mut text,
+            "web3_proxy_tier_response_millis",
+            "sum of response times grouped by user tier",
+            tiers.iter().map(|(tier, x)| (tier, x.response_millis)),
+        );
+
+        text
+    }
+}
+
+fn write_counter<'a>(
+    text: &mut String,
+    name: &str,
+    help: &str,
+    values: impl Iterator<Item = (&'a String, u64)>,
+) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} counter", name).unwrap();
+
+    for (tier, value) in values {
+        let tier = tier.replace('\\', "\\\\").replace('"', "\\\"");
+
+        writeln!(text, "{}{{tier=\"{}\"}} {}", name, tier, value).unwrap();
+    }
+}
@@ -23,6 +23,22 @@ pub struct ChangeUserTierSubCommand {
     /// the amount of concurret requests to allow from a single user
     #[argh(option)]
     max_concurrent_requests: Option<u32>,
+
+    /// cached responses older than this are refreshed for this tier. 0 always skips the cache
+    #[argh(option)]
+    cache_max_age_seconds: Option<u64>,
+
+    /// eth_estimateGas results are multiplied by this percent for this tier. 120 returns 1.2x
+    #[argh(option)]
+    gas_estimate_percent: Option<u16>,
+
+    /// http request bodies larger than this get a 413 for this tier
+    #[argh(option)]
+    max_request_bytes: Option<u32>,
+
+    /// responses larger than this are replaced with an error for this tier
+    #[argh(option)]
+    max_response_bytes: Option<u32>,
 }
 
 impl ChangeUserTierSubCommand {
@@ -59,6 +75,46 @@ impl ChangeUserTierSubCommand {
             }
         }
 
+        if let Some(cache_max_age_seconds) = self.cache_max_age_seconds {
+            if user_tier.cache_max_age_seconds == sea_orm::Set(Some(cache_max_age_seconds)) {
+                info!("cache_max_age_seconds already has this value");
+            } else {
+                user_tier.cache_max_age_seconds = sea_orm::Set(Some(cache_max_age_seconds));
+
+                info!("changed cache_max_age_seconds")
+            }
+        }
+
+        if let Some(gas_estimate_percent) = self.gas_estimate_percent {
+            if user_tier.gas_estimate_percent == sea_orm::Set(Some(gas_estimate_percent)) {
+                info!("gas_estimate_percent already has this value");
+            } else {
+                user_tier.gas_estimate_percent = sea_orm::Set(Some(gas_estimate_percent));
+
+                info!("changed gas_estimate_percent")
+            }
+        }
+
+        if let Some(max_request_bytes) = self.max_request_bytes {
+            if user_tier.max_request_bytes == sea_orm::Set(Some(max_request_bytes)) {
+                info!("max_request_bytes already has this value");
+            } else {
+                user_tier.max_request_bytes = sea_orm::Set(Some(max_request_bytes));
+
+                info!("changed max_request_bytes")
+            }
+        }
+
+        if let Some(max_response_bytes) = self.max_response_bytes {
+            if user_tier.max_response_bytes == sea_orm::Set(Some(max_response_bytes)) {
+                info!("max_response_bytes already has this value");
+            } else {
+                user_tier.max_response_bytes = sea_orm::Set(Some(max_response_bytes));
+
+                info!("changed max_response_bytes")
+            }
+        }
+
         let user_tier = user_tier.save(db_conn).await?;
 
         debug!("new user_tier: {:#?}", user_tier);
@@ -8,7 +8,6 @@ use migration::sea_orm::{
 };
 use serde_json::json;
 use tracing::{debug, info};
-use uuid::Uuid;
 
 /// change a user's tier.
 #[derive(FromArgs, PartialEq, Eq, Debug)]
@@ -27,8 +26,6 @@ impl ChangeUserTierByKeySubCommand {
     // TODO: don't expose the RpcSecretKeys at all. Better to take a user/key id. this is definitely most convenient
 
     pub async fn main(self, db_conn: &DatabaseConnection) -> anyhow::Result<()> {
-        let rpc_secret_key: Uuid = self.rpc_secret_key.into();
-
         let user_tier = user_tier::Entity::find()
             .filter(user_tier::Column::Title.eq(self.user_tier_title))
             .one(db_conn)
@@ -40,7 +37,7 @@ impl ChangeUserTierByKeySubCommand {
         // use the rpc secret key to get the user
         let user = user::Entity::find()
             .inner_join(rpc_key::Entity)
-            .filter(rpc_key::Column::SecretKey.eq(rpc_secret_key))
+            .filter(self.rpc_secret_key.db_condition()?)
             .one(db_conn)
             .await?
             .context("No user found with that key")?;
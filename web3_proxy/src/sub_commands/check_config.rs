@@ -80,6 +80,11 @@ impl CheckConfigSubCommand {
             }
         }
 
+        if let Err(err) = top_config.check_rpc_urls() {
+            num_errors += 1;
+            error!("{}", err);
+        }
+
         // TODO: print num warnings and have a flag to fail even on warnings
 
         if num_errors == 0 {
@@ -0,0 +1,156 @@
+use crate::referral_code::ReferralCode;
+use crate::stats::REFEREE_BONUS;
+use argh::FromArgs;
+use entities::{referee, referrer, user};
+use hashbrown::{HashMap, HashSet};
+use migration::sea_orm::{
+    self, prelude::Decimal, ActiveModelTrait, EntityTrait, IntoActiveModel, ModelTrait,
+    QuerySelect, TransactionTrait,
+};
+use tracing::{info, warn};
+
+/// Check the referee and referrer tables for inconsistencies.
+/// Reports referees with a missing referrer or user, self referrals, referees that got their one time bonus more than once, and colliding referral codes.
+#[derive(FromArgs, PartialEq, Debug, Eq)]
+#[argh(subcommand, name = "check_referrals")]
+pub struct CheckReferralsSubCommand {
+    /// fix what can be fixed. orphaned and self referrals are deleted, bonuses are capped, and colliding codes are regenerated.
+    #[argh(switch)]
+    fix: bool,
+}
+
+impl CheckReferralsSubCommand {
+    pub async fn main(self, db_conn: &sea_orm::DatabaseConnection) -> anyhow::Result<()> {
+        let txn = db_conn.begin().await?;
+
+        let user_ids: HashSet<u64> = user::Entity::find()
+            .select_only()
+            .column(user::Column::Id)
+            .into_tuple::<u64>()
+            .all(&txn)
+            .await?
+            .into_iter()
+            .collect();
+
+        let referrers: HashMap<i32, referrer::Model> = referrer::Entity::find()
+            .all(&txn)
+            .await?
+            .into_iter()
+            .map(|x| (x.id, x))
+            .collect();
+
+        let referees = referee::Entity::find().all(&txn).await?;
+
+        // anything more than the one time bonus was credited more than once
+        let max_bonus = Decimal::from(REFEREE_BONUS);
+
+        let mut orphaned = vec![];
+        let mut self_referred = vec![];
+        let mut double_credited = vec![];
+
+        for referee in referees {
+            let referrer = referrers.get(&referee.used_referral_code);
+
+            if referrer.is_none() || !user_ids.contains(&referee.user_id) {
+                warn!(
+                    user_id = referee.user_id,
+                    used_referral_code = referee.used_referral_code,
+                    "orphaned referee"
+                );
+
+                orphaned.push(referee.user_id);
+
+                if self.fix {
+                    referee.delete(&txn).await?;
+                }
+            } else if referrer.map(|x| x.user_id) == Some(referee.user_id) {
+                warn!(user_id = referee.user_id, "user referred themselves");
+
+                self_referred.push(referee.user_id);
+
+                if self.fix {
+                    referee.delete(&txn).await?;
+                }
+            } else if referee.one_time_bonus_applied_for_referee > max_bonus {
+                warn!(
+                    user_id = referee.user_id,
+                    bonus = %referee.one_time_bonus_applied_for_referee,
+                    "referee bonus was credited more than once"
+                );
+
+                double_credited.push(referee.user_id);
+
+                if self.fix {
+                    let mut referee = referee.into_active_model();
+
+                    referee.one_time_bonus_applied_for_referee = sea_orm::Set(max_bonus);
+
+                    referee.save(&txn).await?;
+                }
+            }
+        }
+
+        // referrers for users that no longer exist can't be fixed without losing their referees
+        let missing_referrer_users: Vec<_> = referrers
+            .values()
+            .filter(|x| !user_ids.contains(&x.user_id))
+            .map(|x| x.user_id)
+            .collect();
+
+        for user_id in missing_referrer_users.iter() {
+            warn!(user_id, "referrer's user does not exist");
+        }
+
+        // the database compares codes without case, so codes that only differ by case collide
+        let mut codes: HashMap<String, &referrer::Model> = HashMap::new();
+        let mut colliding = vec![];
+
+        let mut sorted_referrers: Vec<_> = referrers.values().collect();
+        sorted_referrers.sort_by_key(|x| x.id);
+
+        for referrer in sorted_referrers {
+            let code = referrer.referral_code.to_lowercase();
+
+            if let Some(existing) = codes.get(&code) {
+                // the oldest referrer keeps the code
+                warn!(
+                    user_id = referrer.user_id,
+                    other_user_id = existing.user_id,
+                    referral_code = %referrer.referral_code,
+                    "referral code collision"
+                );
+
+                colliding.push(referrer.user_id);
+
+                if self.fix {
+                    let mut referrer = referrer.clone().into_active_model();
+
+                    referrer.referral_code = sea_orm::Set(ReferralCode::default().to_string());
+
+                    referrer.save(&txn).await?;
+                }
+            } else {
+                codes.insert(code, referrer);
+            }
+        }
+
+        if self.fix {
+            txn.commit().await?;
+        } else {
+            txn.rollback().await?;
+        }
+
+        info!(
+            ?orphaned,
+            ?self_referred,
+            ?double_credited,
+            ?missing_referrer_users,
+            ?colliding,
+            fixed = self.fix,
+            "checked {} referrers",
+            referrers.len(),
+        );
+
+        Ok(())
+    }
+}
@@ -73,7 +73,8 @@ impl CreateUserSubCommand {
         // create a key for the new user
         let uk = rpc_key::ActiveModel {
             user_id: u.id,
-            secret_key: sea_orm::Set(rpc_secret_key.into()),
+            secret_key: sea_orm::Set(rpc_secret_key.hashed()?),
+            secret_key_hashed: sea_orm::Set(true),
             description: sea_orm::Set(self.description),
             ..Default::default()
         };
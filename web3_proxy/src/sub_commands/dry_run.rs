@@ -0,0 +1,617 @@
+use crate::block_number::{block_param_index, BlockNumber_to_U64};
+use crate::config::{TopConfig, Web3RpcConfig};
+use crate::method_route::{method_route, MethodRoute};
+use crate::rpcs::blockchain::Web3ProxyBlock;
+use crate::rpcs::consensus::RankedRpcs;
+use crate::rpcs::one::Web3Rpc;
+use anyhow::Context;
+use argh::FromArgs;
+use derive_more::Display;
+use ethers::types::{Block, BlockNumber, H256, U64};
+use hashbrown::HashSet;
+use prettytable::{row, Table};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+#[derive(FromArgs, PartialEq, Eq, Debug)]
+/// Show which backend rpcs requests would be routed to. No requests are sent.
+#[argh(subcommand, name = "dry_run")]
+pub struct DryRunSubCommand {
+    #[argh(positional)]
+    /// path to the configuration toml.
+    path: String,
+
+    #[argh(option)]
+    /// the head block to assume for the chain.
+    head_block: u64,
+
+    #[argh(option, short = 'm')]
+    /// a method to route. Add a block with "@" like "eth_getBalance@0x10" or "eth_call@latest". Can be given more than once.
+    method: Vec<String>,
+
+    #[argh(switch)]
+    /// route eth_call as if it had a state override set.
+    state_overrides: bool,
+}
+
+/// A method and the block that was given for it
+#[derive(Debug, PartialEq, Eq)]
+struct Sample {
+    method: String,
+    block: Option<BlockNumber>,
+}
+
+impl Sample {
+    /// "eth_getBalance", "eth_getBalance@latest", "eth_getBalance@0x10", or "eth_getBalance@16"
+    fn parse(x: &str) -> anyhow::Result<Self> {
+        let (method, block) = match x.split_once('@') {
+            None => (x, None),
+            Some((method, block)) => {
+                let block = if let Ok(num) = block.parse::<u64>() {
+                    BlockNumber::Number(num.into())
+                } else {
+                    serde_json::from_value(json!(block))
+                        .with_context(|| format!("invalid block for {}: {}", method, block))?
+                };
+
+                (method, Some(block))
+            }
+        };
+
+        Ok(Self {
+            method: method.to_string(),
+            block,
+        })
+    }
+
+    /// The block that a backend needs to have to serve this request. None if any synced backend will work.
+    /// Methods that take a block use the head block if one is not given.
+    fn needed_block(&self, head_block: &U64) -> Option<U64> {
+        if block_param_index(&self.method).is_none() && self.method != "eth_getLogs" {
+            return None;
+        }
+
+        let block = self.block.unwrap_or(BlockNumber::Latest);
+
+        let (block_num, _) = BlockNumber_to_U64(block, head_block);
+
+        Some(block_num)
+    }
+}
+
+/// Why a backend would or would not get a request
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq)]
+enum RpcDecision {
+    #[display(fmt = "disabled")]
+    Disabled,
+    #[display(fmt = "no consensus")]
+    NoConsensus,
+    #[display(fmt = "pruned. oldest block is {}", _0)]
+    MissingBlock(U64),
+    #[display(fmt = "no state overrides")]
+    NoStateOverrides,
+    #[display(fmt = "method not served")]
+    MethodNotServed,
+    #[display(fmt = "yes")]
+    Primary,
+    /// only used if the primary rpcs are lagging
+    #[display(fmt = "backup")]
+    Backup,
+}
+
+impl RpcDecision {
+    fn is_routed(&self) -> bool {
+        matches!(self, Self::Primary | Self::Backup)
+    }
+}
+
+/// A head block for the chain at this number
+fn fake_head_block(num: U64) -> Web3ProxyBlock {
+    let block = Block {
+        hash: Some(H256::random()),
+        number: Some(num),
+        timestamp: chrono::Utc::now().timestamp().into(),
+        ..Default::default()
+    };
+
+    Web3ProxyBlock::try_new(Arc::new(block)).expect("the block has a hash and a number")
+}
+
+/// Every enabled rpc with its soft limit. They are all synced to `head_block`
+fn offline_rpcs(
+    rpcs: &HashMap<String, Web3RpcConfig>,
+    head_block: &Web3ProxyBlock,
+) -> Vec<(Arc<Web3Rpc>, u32)> {
+    rpcs.iter()
+        .filter(|(_, config)| !config.disabled)
+        .map(|(name, config)| {
+            let rpc = Web3Rpc::offline(name.clone(), config, head_block.clone());
+
+            (Arc::new(rpc), config.soft_limit)
+        })
+        .collect()
+}
+
+/// Vote on the head block the way `ConsensusFinder::find_consensus_connections` does.
+/// Backup rpcs only vote if the primary rpcs are not enough.
+fn ranked_rpcs(
+    min_synced_rpcs: usize,
+    min_sum_soft_limit: u32,
+    rpcs: &[(Arc<Web3Rpc>, u32)],
+    head_block: &Web3ProxyBlock,
+) -> Option<RankedRpcs> {
+    let heads: hashbrown::HashMap<_, _> = rpcs
+        .iter()
+        .map(|(rpc, _)| (rpc.clone(), head_block.clone()))
+        .collect();
+
+    let votes = |include_backups: bool| {
+        let voters: Vec<_> = rpcs
+            .iter()
+            .filter(|(rpc, _)| include_backups || !rpc.backup)
+            .collect();
+
+        let sum_soft_limit = voters.iter().map(|(_, soft_limit)| soft_limit).sum();
+
+        let voters: HashSet<_> = voters.into_iter().map(|(rpc, _)| rpc).collect();
+
+        let mut votes = hashbrown::HashMap::new();
+
+        votes.insert(head_block.clone(), (voters, sum_soft_limit));
+
+        votes
+    };
+
+    RankedRpcs::from_votes(
+        min_synced_rpcs,
+        min_sum_soft_limit,
+        *head_block.number(),
+        votes(false),
+        heads.clone(),
+    )
+    .or_else(|| {
+        RankedRpcs::from_votes(
+            min_synced_rpcs,
+            min_sum_soft_limit,
+            *head_block.number(),
+            votes(true),
+            heads,
+        )
+    })
+}
+
+/// `RankedRpcs::rpc_will_work_now` decides. The other checks only explain why an rpc was left out
+fn rpc_decision(
+    ranked_rpcs: &RankedRpcs,
+    rpc: &Arc<Web3Rpc>,
+    method: &str,
+    state_override_request: bool,
+    needed_block: Option<&U64>,
+) -> RpcDecision {
+    if ranked_rpcs.rpc_will_work_now(
+        &[],
+        Some(method),
+        state_override_request,
+        needed_block,
+        needed_block,
+        rpc,
+    ) {
+        if rpc.backup {
+            RpcDecision::Backup
+        } else {
+            RpcDecision::Primary
+        }
+    } else if state_override_request && !rpc.state_overrides() {
+        RpcDecision::NoStateOverrides
+    } else if !rpc.serves_method(method) {
+        RpcDecision::MethodNotServed
+    } else {
+        RpcDecision::MissingBlock(
+            ranked_rpcs
+                .head_block
+                .number()
+                .saturating_sub(rpc.block_data_limit()),
+        )
+    }
+}
+
+/// Options left out of the config are checked at startup. The dry run assumes they work
+fn startup_notes(
+    config: &Web3RpcConfig,
+    method: &str,
+    head_block: &U64,
+    needed_block: Option<&U64>,
+    state_override_request: bool,
+) -> Vec<&'static str> {
+    let mut notes = vec![];
+
+    if config.block_data_limit.is_none() && needed_block.map_or(false, |x| x < head_block) {
+        notes.push("block_data_limit is checked at startup");
+    }
+
+    if config.state_overrides.is_none() && state_override_request {
+        notes.push("state overrides are checked at startup");
+    }
+
+    if (config.trace_methods.is_none() && method.starts_with("trace_"))
+        || (config.debug_trace_methods.is_none() && method.starts_with("debug_trace"))
+    {
+        notes.push("trace methods are checked at startup");
+    }
+
+    notes
+}
+
+/// Requests are only sent once enough servers can serve them.
+fn consensus_summary(
+    min_synced_rpcs: usize,
+    ranked_rpcs: Option<&RankedRpcs>,
+    num_routed: usize,
+) -> String {
+    match ranked_rpcs {
+        None => {
+            "NO ROUTE! the enabled rpcs are not enough for min_synced_rpcs and min_sum_soft_limit"
+                .to_string()
+        }
+        Some(_) if num_routed < min_synced_rpcs => format!(
+            "NO ROUTE! only {} rpcs can serve this. min_synced_rpcs={}",
+            num_routed, min_synced_rpcs
+        ),
+        Some(x) if x.backups_needed => format!("needs backups! {} rpcs synced", x.num_synced),
+        Some(x) => format!("{} primary rpcs synced", x.num_synced),
+    }
+}
+
+impl DryRunSubCommand {
+    pub async fn main(self) -> anyhow::Result<()> {
+        info!("Loading config @ {}", self.path);
+        let top_config: String = fs::read_to_string(&self.path)?;
+        let top_config: TopConfig = toml::from_str(&top_config)?;
+
+        let head_block = U64::from(self.head_block);
+
+        let fake_head = fake_head_block(head_block);
+
+        if self.method.is_empty() {
+            warn!("no methods given. use --method");
+        }
+
+        for sample in self.method.iter() {
+            let sample = Sample::parse(sample)?;
+
+            let route = method_route(&sample.method);
+
+            let rpcs: Option<&HashMap<String, Web3RpcConfig>> = match route {
+                MethodRoute::Local | MethodRoute::Unsupported => None,
+                MethodRoute::Balanced => Some(&top_config.balanced_rpcs),
+                MethodRoute::Private => Some(
+                    top_config
+                        .private_rpcs
+                        .as_ref()
+                        .unwrap_or(&top_config.balanced_rpcs),
+                ),
+                MethodRoute::Bundler4337 => top_config.bundler_4337_rpcs.as_ref(),
+            };
+
+            let rpcs = match rpcs {
+                Some(x) => x,
+                None => {
+                    println!("{}: {}\n", sample.method, route);
+                    continue;
+                }
+            };
+
+            let needed_block = sample.needed_block(&head_block);
+
+            let state_override_request = self.state_overrides && sample.method == "eth_call";
+
+            match needed_block {
+                Some(x) => println!("{}: {} at block {}", sample.method, route, x),
+                None => {
+                    if sample.block.is_some() {
+                        warn!(method=%sample.method, "method does not take a block. ignoring it");
+                    }
+                    println!("{}: {}", sample.method, route)
+                }
+            }
+
+            // private rpcs get every transaction. they don't need consensus
+            let (min_synced_rpcs, min_sum_soft_limit) =
+                if route == MethodRoute::Private && top_config.private_rpcs.is_some() {
+                    (1, 0)
+                } else {
+                    (
+                        top_config.app.min_synced_rpcs,
+                        top_config.app.min_sum_soft_limit,
+                    )
+                };
+
+            let rpcs_at_head = offline_rpcs(rpcs, &fake_head);
+
+            let ranked = ranked_rpcs(
+                min_synced_rpcs,
+                min_sum_soft_limit,
+                &rpcs_at_head,
+                &fake_head,
+            );
+
+            let mut names: Vec<_> = rpcs.keys().collect();
+            names.sort();
+
+            let mut num_routed = 0;
+
+            let mut table = Table::new();
+
+            table.add_row(row![
+                "name",
+                "routed",
+                "soft_limit",
+                "block_data_limit",
+                "notes"
+            ]);
+
+            for name in names {
+                let config = &rpcs[name];
+
+                let rpc = rpcs_at_head.iter().find(|(x, _)| &x.name == name);
+
+                let decision = match (rpc, ranked.as_ref()) {
+                    (None, _) => RpcDecision::Disabled,
+                    (Some(_), None) => RpcDecision::NoConsensus,
+                    (Some((rpc, _)), Some(ranked_rpcs)) => rpc_decision(
+                        ranked_rpcs,
+                        rpc,
+                        &sample.method,
+                        state_override_request,
+                        needed_block.as_ref(),
+                    ),
+                };
+
+                if decision.is_routed() {
+                    num_routed += 1;
+                }
+
+                let notes = startup_notes(
+                    config,
+                    &sample.method,
+                    &head_block,
+                    needed_block.as_ref(),
+                    state_override_request,
+                );
+
+                table.add_row(row![
+                    name,
+                    decision,
+                    config.soft_limit,
+                    config
+                        .block_data_limit
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "?".to_string()),
+                    notes.join(". "),
+                ]);
+            }
+
+            table.printstd();
+
+            println!(
+                "consensus: {}",
+                consensus_summary(min_synced_rpcs, ranked.as_ref(), num_routed)
+            );
+
+            println!();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_parse_sample() {
+        assert_eq!(
+            Sample::parse("eth_chainId").unwrap(),
+            Sample {
+                method: "eth_chainId".to_string(),
+                block: None
+            }
+        );
+        assert_eq!(
+            Sample::parse("eth_getBalance@latest").unwrap().block,
+            Some(BlockNumber::Latest)
+        );
+        assert_eq!(
+            Sample::parse("eth_getBalance@0x10").unwrap().block,
+            Some(BlockNumber::Number(16.into()))
+        );
+        assert_eq!(
+            Sample::parse("eth_getBalance@16").unwrap().block,
+            Some(BlockNumber::Number(16.into()))
+        );
+        assert!(Sample::parse("eth_getBalance@yesterday").is_err());
+    }
+
+    #[test]
+    fn test_needed_block() {
+        let head_block = U64::from(1_000);
+
+        assert_eq!(
+            Sample::parse("eth_chainId")
+                .unwrap()
+                .needed_block(&head_block),
+            None
+        );
+        assert_eq!(
+            Sample::parse("eth_getBalance")
+                .unwrap()
+                .needed_block(&head_block),
+            Some(head_block)
+        );
+        assert_eq!(
+            Sample::parse("eth_getBalance@earliest")
+                .unwrap()
+                .needed_block(&head_block),
+            Some(U64::zero())
+        );
+        assert_eq!(
+            Sample::parse("eth_getLogs@100")
+                .unwrap()
+                .needed_block(&head_block),
+            Some(100.into())
+        );
+    }
+
+    #[test]
+    fn test_rpc_decision() {
+        let head_block = U64::from(1_000);
+        let old_block = U64::from(10);
+
+        let configs = HashMap::from([
+            (
+                "full".to_string(),
+                Web3RpcConfig {
+                    block_data_limit: Some(64),
+                    soft_limit: 100,
+                    ..Default::default()
+                },
+            ),
+            (
+                "archive".to_string(),
+                Web3RpcConfig {
+                    block_data_limit: Some(u64::MAX),
+                    state_overrides: Some(false),
+                    trace_methods: Some(false),
+                    soft_limit: 100,
+                    ..Default::default()
+                },
+            ),
+            (
+                "backup".to_string(),
+                Web3RpcConfig {
+                    backup: true,
+                    soft_limit: 100,
+                    ..Default::default()
+                },
+            ),
+            (
+                "disabled".to_string(),
+                Web3RpcConfig {
+                    disabled: true,
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let fake_head = fake_head_block(head_block);
+
+        let rpcs = offline_rpcs(&configs, &fake_head);
+
+        assert_eq!(rpcs.len(), 3);
+
+        let rpc = |name: &str| rpcs.iter().find(|(x, _)| x.name == name).unwrap().0.clone();
+
+        let ranked = ranked_rpcs(2, 200, &rpcs, &fake_head).unwrap();
+
+        assert!(!ranked.backups_needed);
+
+        assert_eq!(
+            rpc_decision(
+                &ranked,
+                &rpc("full"),
+                "eth_getBalance",
+                false,
+                Some(&head_block)
+            ),
+            RpcDecision::Primary
+        );
+        assert_eq!(
+            rpc_decision(
+                &ranked,
+                &rpc("full"),
+                "eth_getBalance",
+                false,
+                Some(&old_block)
+            ),
+            RpcDecision::MissingBlock(936.into())
+        );
+        assert_eq!(
+            rpc_decision(
+                &ranked,
+                &rpc("archive"),
+                "eth_getBalance",
+                false,
+                Some(&old_block)
+            ),
+            RpcDecision::Primary
+        );
+        assert_eq!(
+            rpc_decision(&ranked, &rpc("archive"), "eth_call", true, None),
+            RpcDecision::NoStateOverrides
+        );
+        assert_eq!(
+            rpc_decision(&ranked, &rpc("archive"), "trace_block", false, None),
+            RpcDecision::MethodNotServed
+        );
+        assert_eq!(
+            rpc_decision(&ranked, &rpc("backup"), "eth_call", true, Some(&old_block)),
+            RpcDecision::Backup
+        );
+
+        // the primary rpcs are not enough on their own
+        let ranked = ranked_rpcs(3, 200, &rpcs, &fake_head).unwrap();
+
+        assert!(ranked.backups_needed);
+
+        assert!(ranked_rpcs(4, 200, &rpcs, &fake_head).is_none());
+
+        assert_eq!(
+            startup_notes(
+                &configs["backup"],
+                "eth_call",
+                &head_block,
+                Some(&old_block),
+                true
+            )
+            .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_example_toml() {
+        let path = env::current_dir().expect("path");
+
+        let parent = path.parent().expect("always a parent");
+
+        let config_path = parent.join("config").join("example.toml");
+
+        let config_path_str = config_path.to_str().expect("always a valid path");
+
+        let dry_run_command = DryRunSubCommand::from_args(
+            &["dry_run"],
+            &[
+                config_path_str,
+                "--head-block",
+                "1000",
+                "--method",
+                "eth_getBalance@earliest",
+                "--method",
+                "eth_sendRawTransaction",
+                "--method",
+                "eth_chainId",
+            ],
+        )
+        .expect("the command should have run");
+
+        dry_run_command
+            .main()
+            .await
+            .expect("the dry run should succeed");
+    }
+}
@@ -0,0 +1,298 @@
+//! Export `rpc_accounting_v2` (or the older `rpc_accounting`) as OpenMetrics with timestamps so that a TSDB can backfill dashboards.
+//!
+//! Keys are summed together with the same totals as the stats endpoints. Each series is a chain and error_response.
+//! Only `rpc_accounting` has methods, so only its series have a method label.
+//! Counters start at 0 at `--start-timestamp`, so use `increase` or `rate` on them instead of the raw values.
+use crate::stats::db_queries::select_totals;
+use anyhow::Context;
+use argh::FromArgs;
+use chrono::{TimeZone, Utc};
+use entities::{rpc_accounting, rpc_accounting_v2};
+use migration::sea_orm::{
+    prelude::{DateTimeUtc, Decimal},
+    Condition, DatabaseConnection, EntityTrait, FromQueryResult, QueryFilter, QueryOrder,
+    QuerySelect,
+};
+use migration::{Alias, Expr};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use tracing::info;
+
+/// write stats from rpc_accounting_v2 as OpenMetrics with timestamps. `promtool tsdb create-blocks-from openmetrics` can load it
+#[derive(FromArgs, PartialEq, Debug, Eq)]
+#[argh(subcommand, name = "export_stats")]
+pub struct ExportStatsSubCommand {
+    /// where to write the file
+    #[argh(positional)]
+    output: String,
+
+    /// unix epoch timestamp of earliest period
+    #[argh(option)]
+    start_timestamp: i64,
+
+    /// unix epoch timestamp of last period
+    #[argh(option)]
+    end_timestamp: i64,
+
+    /// the chain id to export. If none, export all.
+    #[argh(option)]
+    chain_id: Option<u64>,
+
+    /// read the old rpc_accounting table instead of rpc_accounting_v2. It is the only one with methods
+    #[argh(switch)]
+    v1: bool,
+}
+
+/// One period of one series. Summed over every key
+#[derive(Debug, FromQueryResult)]
+struct PeriodStats {
+    period_datetime: DateTimeUtc,
+    chain_id: u64,
+    /// always None for `rpc_accounting_v2`
+    method: Option<String>,
+    error_response: bool,
+    total_frontend_requests: Decimal,
+    total_backend_retries: Decimal,
+    total_cache_hits: Decimal,
+    total_cache_misses: Decimal,
+    total_request_bytes: Decimal,
+    total_response_bytes: Decimal,
+    total_response_millis: Decimal,
+}
+
+/// name and help of each counter. in the same order as `PeriodStats::values`
+const METRICS: [(&str, &str); 7] = [
+    ("web3_proxy_frontend_requests", "requests from clients"),
+    (
+        "web3_proxy_backend_requests",
+        "requests sent to backend rpcs",
+    ),
+    ("web3_proxy_cache_hits", "requests answered from the cache"),
+    (
+        "web3_proxy_cache_misses",
+        "requests not answered from the cache",
+    ),
+    ("web3_proxy_request_bytes", "bytes of requests"),
+    ("web3_proxy_response_bytes", "bytes of responses"),
+    (
+        "web3_proxy_response_millis",
+        "milliseconds spent responding",
+    ),
+];
+
+impl PeriodStats {
+    fn values(&self) -> [Decimal; 7] {
+        [
+            self.total_frontend_requests,
+            self.total_backend_retries,
+            self.total_cache_hits,
+            self.total_cache_misses,
+            self.total_request_bytes,
+            self.total_response_bytes,
+            self.total_response_millis,
+        ]
+    }
+}
+
+impl ExportStatsSubCommand {
+    pub async fn main(self, db_conn: &DatabaseConnection) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.start_timestamp <= self.end_timestamp,
+            "--start-timestamp must not be after --end-timestamp"
+        );
+
+        let start = Utc
+            .timestamp_opt(self.start_timestamp, 0)
+            .single()
+            .context("invalid start timestamp")?;
+        let end = Utc
+            .timestamp_opt(self.end_timestamp, 0)
+            .single()
+            .context("invalid end timestamp")?;
+
+        let rows = if self.v1 {
+            period_stats::<rpc_accounting::Entity>(db_conn, start, end, self.chain_id, true).await?
+        } else {
+            period_stats::<rpc_accounting_v2::Entity>(db_conn, start, end, self.chain_id, false)
+                .await?
+        };
+
+        let output = openmetrics(&rows);
+
+        std::fs::write(&self.output, output)?;
+
+        info!(periods = rows.len(), output = %self.output, "exported stats");
+
+        Ok(())
+    }
+}
+
+/// Sum every key's stats for each period, chain, and error_response. Also each method if `has_method`.
+/// Sorted by period
+async fn period_stats<E: EntityTrait>(
+    db_conn: &DatabaseConnection,
+    start: DateTimeUtc,
+    end: DateTimeUtc,
+    chain_id: Option<u64>,
+    has_method: bool,
+) -> anyhow::Result<Vec<PeriodStats>> {
+    let column = |x: &str| Expr::col((E::default(), Alias::new(x)));
+
+    let mut condition = Condition::all()
+        .add(column("period_datetime").gte(start))
+        .add(column("period_datetime").lte(end));
+
+    if let Some(chain_id) = chain_id {
+        condition = condition.add(column("chain_id").eq(chain_id));
+    }
+
+    let mut q = select_totals::<E>()
+        .column_as(column("period_datetime"), "period_datetime")
+        .column_as(column("chain_id"), "chain_id")
+        .column_as(column("error_response"), "error_response")
+        .filter(condition)
+        .group_by(column("period_datetime"))
+        .group_by(column("chain_id"))
+        .group_by(column("error_response"))
+        .order_by_asc(column("period_datetime"));
+
+    q = if has_method {
+        q.column_as(column("method"), "method")
+            .group_by(column("method"))
+    } else {
+        q.column_as(Expr::cust("NULL"), "method")
+    };
+
+    let rows = q.into_model::<PeriodStats>().all(db_conn).await?;
+
+    Ok(rows)
+}
+
+/// `rows` must be sorted by period. Every sample of a metric is written together and each series is in time order
+fn openmetrics(rows: &[PeriodStats]) -> String {
+    // (chain_id, method, error_response) -> (timestamp, running totals)
+    let mut series = BTreeMap::<(u64, Option<&str>, bool), Vec<(i64, [Decimal; 7])>>::new();
+
+    for row in rows {
+        let points = series
+            .entry((row.chain_id, row.method.as_deref(), row.error_response))
+            .or_default();
+
+        let mut totals = points.last().map(|x| x.1).unwrap_or_default();
+
+        for (total, x) in totals.iter_mut().zip(row.values()) {
+            *total += x;
+        }
+
+        points.push((row.period_datetime.timestamp(), totals));
+    }
+
+    let mut out = String::new();
+
+    for (i, (name, help)) in METRICS.iter().enumerate() {
+        writeln!(out, "# TYPE {} counter", name).unwrap();
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+
+        for ((chain_id, method, error_response), points) in series.iter() {
+            let method = method
+                .map(|x| format!(",method=\"{}\"", escape_label(x)))
+                .unwrap_or_default();
+
+            for (timestamp, totals) in points {
+                writeln!(
+                    out,
+                    "{}_total{{chain_id=\"{}\"{},error_response=\"{}\"}} {} {}",
+                    name, chain_id, method, error_response, totals[i], timestamp
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    out.push_str("# EOF\n");
+
+    out
+}
+
+fn escape_label(x: &str) -> String {
+    x.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_label, openmetrics, PeriodStats};
+    use chrono::{TimeZone, Utc};
+
+    fn period(timestamp: i64, method: Option<&str>, frontend_requests: u64) -> PeriodStats {
+        PeriodStats {
+            period_datetime: Utc.timestamp_opt(timestamp, 0).unwrap(),
+            chain_id: 1,
+            method: method.map(ToString::to_string),
+            error_response: false,
+            total_frontend_requests: frontend_requests.into(),
+            total_backend_retries: 0.into(),
+            total_cache_hits: 0.into(),
+            total_cache_misses: 0.into(),
+            total_request_bytes: 0.into(),
+            total_response_bytes: 0.into(),
+            total_response_millis: 0.into(),
+        }
+    }
+
+    #[test]
+    fn test_openmetrics() {
+        let rows = [
+            period(60, Some("eth_call"), 2),
+            period(60, Some("eth_getBalance"), 1),
+            period(120, Some("eth_call"), 3),
+        ];
+
+        let x = openmetrics(&rows);
+
+        let frontend: Vec<_> = x
+            .lines()
+            .filter(|x| x.starts_with("web3_proxy_frontend_requests_total"))
+            .collect();
+
+        // each series is together and counts up
+        assert_eq!(
+            frontend,
+            vec![
+                r#"web3_proxy_frontend_requests_total{chain_id="1",method="eth_call",error_response="false"} 2 60"#,
+                r#"web3_proxy_frontend_requests_total{chain_id="1",method="eth_call",error_response="false"} 5 120"#,
+                r#"web3_proxy_frontend_requests_total{chain_id="1",method="eth_getBalance",error_response="false"} 1 60"#,
+            ]
+        );
+
+        assert!(x.starts_with("# TYPE web3_proxy_frontend_requests counter\n"));
+        assert!(x.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_openmetrics_without_methods() {
+        let rows = [period(60, None, 2), period(120, None, 3)];
+
+        let x = openmetrics(&rows);
+
+        let frontend: Vec<_> = x
+            .lines()
+            .filter(|x| x.starts_with("web3_proxy_frontend_requests_total"))
+            .collect();
+
+        // rpc_accounting_v2 has no method label
+        assert_eq!(
+            frontend,
+            vec![
+                r#"web3_proxy_frontend_requests_total{chain_id="1",error_response="false"} 2 60"#,
+                r#"web3_proxy_frontend_requests_total{chain_id="1",error_response="false"} 5 120"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+}
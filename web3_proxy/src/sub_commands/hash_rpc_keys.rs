@@ -0,0 +1,49 @@
+use crate::frontend::authorization::{RpcSecretKey, RPC_KEY_HASH_SECRET};
+use anyhow::Context;
+use argh::FromArgs;
+use entities::rpc_key;
+use migration::sea_orm::{
+    self, ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter,
+    TransactionTrait,
+};
+use tracing::info;
+
+/// Replace any raw rpc keys in the database with their hashes.
+/// This cannot be undone! Make sure `rpc_key_hash_secret` is set in the config and backed up first.
+#[derive(FromArgs, PartialEq, Debug, Eq)]
+#[argh(subcommand, name = "hash_rpc_keys")]
+pub struct HashRpcKeysSubCommand {}
+
+impl HashRpcKeysSubCommand {
+    pub async fn main(self, db_conn: &sea_orm::DatabaseConnection) -> anyhow::Result<()> {
+        RPC_KEY_HASH_SECRET.get().context(
+            "rpc_key_hash_secret was not loaded. is --config or --rpc-key-hash-secret set?",
+        )?;
+
+        let txn = db_conn.begin().await?;
+
+        let raw_keys = rpc_key::Entity::find()
+            .filter(rpc_key::Column::SecretKeyHashed.eq(false))
+            .all(&txn)
+            .await?;
+
+        let count = raw_keys.len();
+
+        for raw_key in raw_keys {
+            let hashed_secret_key = RpcSecretKey::from(raw_key.secret_key).hashed()?;
+
+            let mut raw_key = raw_key.into_active_model();
+
+            raw_key.secret_key = sea_orm::Set(hashed_secret_key);
+            raw_key.secret_key_hashed = sea_orm::Set(true);
+
+            raw_key.save(&txn).await?;
+        }
+
+        txn.commit().await?;
+
+        info!("hashed {} rpc keys", count);
+
+        Ok(())
+    }
+}
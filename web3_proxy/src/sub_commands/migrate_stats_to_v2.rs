@@ -88,6 +88,8 @@ impl MigrateStatsToV2SubCommand {
             1,
             flush_sender,
             flush_receiver,
+            None,
+            None,
         )
         .context("Error spawning stat buffer")?
         .context("No stat buffer spawned. Maybe missing influx or db credentials?")?;
@@ -129,8 +131,9 @@ impl MigrateStatsToV2SubCommand {
                             .context("Could not find rpc_key_obj for the given rpc_key_id")?;
 
                         authorization.checks.user_id = rpc_key_obj.user_id;
-                        authorization.checks.rpc_secret_key =
-                            Some(RpcSecretKey::Uuid(rpc_key_obj.secret_key));
+                        // hashed keys only have their hash in the database. it can't be used as a key, and the stats only need the id
+                        authorization.checks.rpc_secret_key = (!rpc_key_obj.secret_key_hashed)
+                            .then(|| RpcSecretKey::Uuid(rpc_key_obj.secret_key));
                         authorization.checks.rpc_secret_key_id =
                             NonZeroU64::try_from(rpc_key_id).ok();
                     }
@@ -184,6 +187,8 @@ impl MigrateStatsToV2SubCommand {
                         authorization: Some(authorization.clone()),
                         backend_requests: Mutex::new(backend_rpcs),
                         chain_id,
+                        // old stats were priced with the built in costs
+                        configured_cu: None,
                         error_response: x.error_response.into(),
                         // debug data is in kafka, not mysql or influx
                         kafka_debug_logger: None,
@@ -204,6 +209,9 @@ impl MigrateStatsToV2SubCommand {
                         // This is overwritten later on
                         start_instant: Instant::now(),
                         stat_sender: Some(stat_sender.clone()),
+                        // old stats are not live traffic
+                        slo_metrics: None,
+                        tier_metrics: None,
                         request_ulid,
                         user_error_response: false.into(),
                         usd_per_cu: top_config.app.usd_per_cu.unwrap_or_default(),
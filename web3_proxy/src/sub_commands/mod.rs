@@ -4,17 +4,24 @@ mod change_user_tier;
 mod change_user_tier_by_address;
 mod change_user_tier_by_key;
 mod check_config;
+mod check_referrals;
 mod count_users;
 mod create_key;
 mod create_user;
 mod drop_migration_lock;
+mod dry_run;
+mod export_stats;
+mod hash_rpc_keys;
 mod migrate_stats_to_v2;
 mod pagerduty;
 mod popularity_contest;
 mod proxyd;
+mod replay_recording;
+mod replay_request;
 mod rpc_accounting;
 mod search_kafka;
 mod sentryd;
+mod stale_keys;
 mod transfer_key;
 mod user_export;
 mod user_import;
@@ -25,17 +32,24 @@ pub use self::change_user_tier::ChangeUserTierSubCommand;
 pub use self::change_user_tier_by_address::ChangeUserTierByAddressSubCommand;
 pub use self::change_user_tier_by_key::ChangeUserTierByKeySubCommand;
 pub use self::check_config::CheckConfigSubCommand;
+pub use self::check_referrals::CheckReferralsSubCommand;
 pub use self::count_users::CountUsersSubCommand;
 pub use self::create_key::CreateKeySubCommand;
 pub use self::create_user::CreateUserSubCommand;
 pub use self::drop_migration_lock::DropMigrationLockSubCommand;
+pub use self::dry_run::DryRunSubCommand;
+pub use self::export_stats::ExportStatsSubCommand;
+pub use self::hash_rpc_keys::HashRpcKeysSubCommand;
 pub use self::migrate_stats_to_v2::MigrateStatsToV2SubCommand;
 pub use self::pagerduty::PagerdutySubCommand;
 pub use self::popularity_contest::PopularityContestSubCommand;
 pub use self::proxyd::ProxydSubCommand;
+pub use self::replay_recording::ReplayRecordingSubCommand;
+pub use self::replay_request::ReplayRequestSubCommand;
 pub use self::rpc_accounting::RpcAccountingSubCommand;
 pub use self::search_kafka::SearchKafkaSubCommand;
 pub use self::sentryd::SentrydSubCommand;
+pub use self::stale_keys::StaleKeysSubCommand;
 pub use self::transfer_key::TransferKeySubCommand;
 pub use self::user_export::UserExportSubCommand;
 pub use self::user_import::UserImportSubCommand;
@@ -1,8 +1,10 @@
 use argh::FromArgs;
 use ethers::types::U64;
+use hashbrown::HashMap;
 use ordered_float::OrderedFloat;
 use prettytable::{row, Table};
 use std::{cmp::Reverse, str::FromStr};
+use tokio::time::{sleep, Duration, Instant};
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// show what nodes are used most often
@@ -12,6 +14,15 @@ pub struct PopularityContestSubCommand {
     /// the web3-proxy url
     /// TODO: query multiple and add them together
     rpc: String,
+    #[argh(option, default = "1")]
+    /// how many times to read the status. with more than 1, rpcs are ranked by the external requests between the first and last read
+    samples: u32,
+    #[argh(option, default = "60")]
+    /// seconds between reads
+    interval: u64,
+    #[argh(option)]
+    /// seconds for requests to count half as much. older requests count less so that the ranking follows current usage. needs at least 2 samples
+    decay_half_life: Option<f64>,
 }
 
 #[derive(Debug)]
@@ -24,6 +35,8 @@ struct BackendRpcData<'a> {
     active_requests: u64,
     internal_requests: u64,
     external_requests: u64,
+    /// external requests during the samples. older requests are decayed. all-time external requests if there was only 1 sample
+    recent_requests: f64,
     head_delay_ms: f64,
     peak_latency_ms: f64,
     weighted_latency_ms: f64,
@@ -31,35 +44,64 @@ struct BackendRpcData<'a> {
 
 impl PopularityContestSubCommand {
     pub async fn main(self) -> anyhow::Result<()> {
-        let x: serde_json::Value = reqwest::get(format!("{}/status", self.rpc))
-            .await?
-            .json()
-            .await?;
-
-        let conns = x
-            .as_object()
-            .unwrap()
-            .get("balanced_rpcs")
-            .unwrap()
-            .as_object()
-            .unwrap()
-            .get("conns")
-            .unwrap()
-            .as_array()
-            .unwrap();
+        anyhow::ensure!(self.samples > 0, "--samples must be at least 1");
+        anyhow::ensure!(
+            self.decay_half_life.is_none() || self.samples > 1,
+            "--decay-half-life needs at least 2 samples"
+        );
+        anyhow::ensure!(
+            self.decay_half_life.map_or(true, |x| x > 0.0),
+            "--decay-half-life must be positive"
+        );
+
+        let mut samples = vec![];
+
+        for i in 0..self.samples {
+            if i > 0 {
+                sleep(Duration::from_secs(self.interval)).await;
+            }
+
+            let x: serde_json::Value = reqwest::get(format!("{}/status", self.rpc))
+                .await?
+                .json()
+                .await?;
+
+            samples.push((Instant::now(), x));
+        }
+
+        let (newest_at, x) = samples.last().expect("there is always at least 1 sample");
+
+        // (seconds before the newest sample, external requests) for each rpc. oldest first
+        let mut history = HashMap::<&str, Vec<(f64, u64)>>::new();
+
+        for (at, sample) in samples.iter() {
+            let age = newest_at.duration_since(*at).as_secs_f64();
+
+            for conn in balanced_conns(sample) {
+                let conn = conn.as_object().unwrap();
+
+                let external_requests = conn
+                    .get("external_requests")
+                    .and_then(|x| x.as_u64())
+                    .unwrap_or_default();
+
+                history
+                    .entry(conn_name(conn))
+                    .or_default()
+                    .push((age, external_requests));
+            }
+        }
+
+        let conns = balanced_conns(x);
 
         let mut highest_block = 0;
         let mut rpc_data = vec![];
-        let mut total_external_requests = 0;
+        let mut total_recent_requests = 0.0;
 
         for conn in conns {
             let conn = conn.as_object().unwrap();
 
-            let name = conn
-                .get("display_name")
-                .unwrap_or_else(|| conn.get("name").unwrap())
-                .as_str()
-                .unwrap_or("unknown");
+            let name = conn_name(conn);
 
             let tier = conn.get("tier").unwrap().as_u64().unwrap();
 
@@ -111,6 +153,12 @@ impl PopularityContestSubCommand {
                 .and_then(|x| x.as_f64())
                 .unwrap_or_default();
 
+            let recent_requests = if self.samples == 1 {
+                external_requests as f64
+            } else {
+                recent_requests(&history[name], self.decay_half_life)
+            };
+
             let x = BackendRpcData {
                 name,
                 tier,
@@ -119,20 +167,21 @@ impl PopularityContestSubCommand {
                 active_requests,
                 internal_requests,
                 external_requests,
+                recent_requests,
                 head_block,
                 head_delay_ms,
                 peak_latency_ms,
                 weighted_latency_ms,
             };
 
-            total_external_requests += x.external_requests;
+            total_recent_requests += x.recent_requests;
 
             rpc_data.push(x);
         }
 
         rpc_data.sort_by_key(|x| {
             (
-                Reverse(x.external_requests),
+                Reverse(OrderedFloat(x.recent_requests)),
                 OrderedFloat(x.weighted_latency_ms),
             )
         });
@@ -142,6 +191,7 @@ impl PopularityContestSubCommand {
         table.add_row(row![
             "name",
             "external %",
+            "recent",
             "external",
             "internal",
             "active",
@@ -154,10 +204,11 @@ impl PopularityContestSubCommand {
         ]);
 
         for rpc in rpc_data.into_iter() {
-            let external_request_pct = if total_external_requests == 0 {
+            // internal requests like health checks are not part of the contest
+            let external_request_pct = if total_recent_requests == 0.0 {
                 0.0
             } else {
-                (rpc.external_requests as f32) / (total_external_requests as f32) * 100.0
+                rpc.recent_requests / total_recent_requests * 100.0
             };
 
             let block_data_limit = if rpc.block_data_limit == u64::MAX {
@@ -177,6 +228,7 @@ impl PopularityContestSubCommand {
             table.add_row(row![
                 rpc.name,
                 format!("{:.3}", external_request_pct),
+                format!("{:.1}", rpc.recent_requests),
                 rpc.external_requests,
                 rpc.internal_requests,
                 rpc.active_requests,
@@ -194,3 +246,63 @@ impl PopularityContestSubCommand {
         Ok(())
     }
 }
+
+fn balanced_conns(status: &serde_json::Value) -> &Vec<serde_json::Value> {
+    status
+        .as_object()
+        .unwrap()
+        .get("balanced_rpcs")
+        .unwrap()
+        .as_object()
+        .unwrap()
+        .get("conns")
+        .unwrap()
+        .as_array()
+        .unwrap()
+}
+
+fn conn_name(conn: &serde_json::Map<String, serde_json::Value>) -> &str {
+    conn.get("display_name")
+        .unwrap_or_else(|| conn.get("name").unwrap())
+        .as_str()
+        .unwrap_or("unknown")
+}
+
+/// The requests between each pair of samples, weighted by how long ago the later sample was.
+/// `samples` are (seconds before the newest sample, request count) with the oldest first.
+/// Counts that go down (like after a restart) are treated as 0 new requests. No half life weights every sample the same
+fn recent_requests(samples: &[(f64, u64)], decay_half_life: Option<f64>) -> f64 {
+    samples
+        .windows(2)
+        .map(|x| {
+            let (_, older) = x[0];
+            let (age, newer) = x[1];
+
+            let weight = decay_half_life.map_or(1.0, |half_life| 0.5f64.powf(age / half_life));
+
+            newer.saturating_sub(older) as f64 * weight
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::recent_requests;
+
+    #[test]
+    fn test_recent_requests() {
+        let samples = [(120.0, 100), (60.0, 110), (0.0, 130)];
+
+        // 10 + 20
+        assert_eq!(recent_requests(&samples, None), 30.0);
+
+        // the first 10 were a half life ago
+        assert_eq!(recent_requests(&samples, Some(60.0)), 25.0);
+
+        // an rpc that restarted
+        assert_eq!(recent_requests(&[(60.0, 100), (0.0, 5)], None), 0.0);
+
+        // an rpc that only showed up in the newest sample
+        assert_eq!(recent_requests(&[(0.0, 5)], None), 0.0);
+    }
+}
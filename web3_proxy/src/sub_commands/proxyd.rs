@@ -8,12 +8,13 @@ use futures::StreamExt;
 use num::Zero;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicU16;
-use std::sync::Arc;
+use std::sync::{mpsc as std_mpsc, Arc};
 use std::time::Duration;
 use std::{fs, thread};
 use tokio::select;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::{broadcast, mpsc, oneshot};
-use tokio::time::{sleep_until, Instant};
+use tokio::time::{sleep_until, timeout, Instant};
 use tracing::{error, info, trace, warn};
 
 /// start the main proxy daemon
@@ -74,6 +75,9 @@ impl ProxydSubCommand {
             top_config.app.usd_per_cu = Some(default_usd_per_cu(top_config.app.chain_id));
         }
 
+        // fail now instead of with a confusing connection error later
+        top_config.check_rpc_urls()?;
+
         // tokio has code for catching ctrl+c so we use that to shut down in most cases
         // frontend_shutdown_sender is currently only used in tests, but we might make a /shutdown endpoint or something
         // we do not need this receiver. new receivers are made by `shutdown_sender.subscribe()`
@@ -100,12 +104,31 @@ impl ProxydSubCommand {
 
         let mut head_block_receiver = spawned_app.app.head_block_receiver();
 
+        // the frontend takes ownership of the app. keep a handle for shutting down the backend rpcs
+        let shutdown_token = spawned_app.app.shutdown_token.clone();
+        let app = spawned_app.app.clone();
+
         // start thread for watching config
         if let Some(top_config_path) = top_config_path {
             let config_sender = spawned_app.new_top_config;
             {
                 let mut current_config = config_sender.borrow().clone();
 
+                // SIGHUP checks the config right away instead of waiting for the next poll
+                let (reload_sender, reload_receiver) = std_mpsc::channel();
+
+                let mut hangup = signal(SignalKind::hangup())?;
+
+                tokio::spawn(async move {
+                    while hangup.recv().await.is_some() {
+                        info!("SIGHUP received. reloading config");
+
+                        if reload_sender.send(()).is_err() {
+                            break;
+                        }
+                    }
+                });
+
                 thread::spawn(move || loop {
                     match fs::read_to_string(&top_config_path) {
                         Ok(new_top_config) => match toml::from_str::<TopConfig>(&new_top_config) {
@@ -121,8 +144,13 @@ impl ProxydSubCommand {
 
                                     // TODO: print the differences
                                     // TODO: first run seems to always see differences. why?
-                                    info!("config @ {:?} changed", top_config_path);
-                                    config_sender.send(new_top_config.clone()).unwrap();
+                                    if let Err(err) = new_top_config.check_rpc_urls() {
+                                        // current_config is still replaced so that this is only logged once per change
+                                        error!(?err, "config @ {:?} changed but has invalid rpc urls. ignoring it", top_config_path);
+                                    } else {
+                                        info!("config @ {:?} changed", top_config_path);
+                                        config_sender.send(new_top_config.clone()).unwrap();
+                                    }
                                     current_config = new_top_config;
                                 }
                             }
@@ -137,7 +165,7 @@ impl ProxydSubCommand {
                         }
                     }
 
-                    thread::sleep(Duration::from_secs(10));
+                    let _ = reload_receiver.recv_timeout(Duration::from_secs(10));
                 });
             }
         }
@@ -172,6 +200,35 @@ impl ProxydSubCommand {
             }
         }
 
+        // a head block only needs one rpc. wait for more so that the first requests don't all go to the fastest one to connect
+        let (warmup_synced_rpcs, warmup_timeout) = {
+            let config = app.config.load();
+
+            (
+                config.warmup_synced_rpcs,
+                Duration::from_secs(config.warmup_timeout_seconds),
+            )
+        };
+
+        if warmup_synced_rpcs > 0 {
+            info!(%warmup_synced_rpcs, "warming up");
+
+            if app
+                .balanced_rpcs
+                .wait_for_synced_rpcs(warmup_synced_rpcs, warmup_timeout)
+                .await
+            {
+                info!("warmed up");
+            } else {
+                warn!(
+                    num_synced = app.balanced_rpcs.num_synced_rpcs(),
+                    %warmup_synced_rpcs,
+                    ?warmup_timeout,
+                    "warmup timed out. starting anyway"
+                );
+            }
+        }
+
         // start the frontend port
         let frontend_handle = tokio::spawn(frontend::serve(
             spawned_app.app,
@@ -263,6 +320,9 @@ impl ProxydSubCommand {
             warn!(?err, "backend sender");
         };
 
+        // no more user requests are coming in. stop the backend rpc connections, health checks, and revert log buffer
+        shutdown_token.cancel();
+
         info!(
             "waiting on {} important background tasks",
             spawned_app.background_handles.len()
@@ -286,6 +346,16 @@ impl ProxydSubCommand {
             }
         }
 
+        // wait for the rpcs so that their connection errors don't spam the logs while exiting
+        if timeout(Duration::from_secs(10), app.wait_for_rpcs_to_exit())
+            .await
+            .is_err()
+        {
+            warn!("rpcs did not exit in time");
+        } else {
+            info!("rpcs exited");
+        }
+
         if background_errors.is_zero() && !exited_with_err {
             info!("finished");
             Ok(())
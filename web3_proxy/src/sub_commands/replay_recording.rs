@@ -0,0 +1,199 @@
+use crate::request_recorder::RecordedRequest;
+use anyhow::Context;
+use argh::FromArgs;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use hdrhistogram::Histogram;
+use serde_json::json;
+use std::time::Duration;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+use tracing::{info, warn};
+
+/// Send requests saved by `record_requests_file` to a proxy at a steady rate and show how it held up
+#[derive(FromArgs, PartialEq, Debug, Eq)]
+#[argh(subcommand, name = "replay_recording")]
+pub struct ReplayRecordingSubCommand {
+    #[argh(positional)]
+    /// the file written by `record_requests_file`
+    path: String,
+    #[argh(positional)]
+    /// the rpc url to send the requests to. include an rpc key to test with a user's limits
+    rpc: String,
+    #[argh(option, default = "10")]
+    /// requests per second
+    rate: u32,
+    #[argh(option)]
+    /// stop after this many requests. defaults to one pass through the file
+    max_requests: Option<u64>,
+    #[argh(switch)]
+    /// start over at the beginning of the file instead of stopping at the end
+    repeat: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Success,
+    JsonRpcError,
+    Failed,
+}
+
+impl ReplayRecordingSubCommand {
+    pub async fn main(self) -> anyhow::Result<()> {
+        if self.rate == 0 {
+            return Err(anyhow::anyhow!("--rate must be at least 1"));
+        }
+
+        let recording = tokio::fs::read_to_string(&self.path)
+            .await
+            .context("reading recording")?;
+
+        let requests = parse_recording(&recording);
+
+        if requests.is_empty() {
+            return Err(anyhow::anyhow!("no requests in {}", self.path));
+        }
+
+        let max_requests = if self.repeat {
+            self.max_requests.unwrap_or(u64::MAX)
+        } else {
+            let len = requests.len() as u64;
+
+            self.max_requests.map_or(len, |x| x.min(len))
+        };
+
+        info!(
+            recorded = requests.len(),
+            max_requests,
+            rate = self.rate,
+            "replaying"
+        );
+
+        let client = reqwest::Client::new();
+
+        let mut rate_interval = interval(Duration::from_secs_f64(1.0 / self.rate as f64));
+        rate_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut requests = requests.into_iter().cycle();
+
+        let mut pending = FuturesUnordered::new();
+        let mut sent = 0u64;
+
+        let mut histogram = Histogram::<u64>::new(3)?;
+        let mut successes = 0u64;
+        let mut jsonrpc_errors = 0u64;
+        let mut failures = 0u64;
+
+        let start = Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = rate_interval.tick(), if sent < max_requests => {
+                    let request = requests.next().expect("cycle never ends");
+
+                    sent += 1;
+
+                    pending.push(tokio::spawn(send_request(client.clone(), self.rpc.clone(), sent, request)));
+                }
+                Some(x) = pending.next() => {
+                    let (latency, outcome) = x?;
+
+                    histogram.saturating_record(latency.as_millis() as u64);
+
+                    match outcome {
+                        Outcome::Success => successes += 1,
+                        Outcome::JsonRpcError => jsonrpc_errors += 1,
+                        Outcome::Failed => failures += 1,
+                    }
+                }
+                else => break,
+            }
+        }
+
+        let elapsed = start.elapsed();
+
+        info!(
+            sent,
+            successes,
+            jsonrpc_errors,
+            failures,
+            ?elapsed,
+            per_second = sent as f64 / elapsed.as_secs_f64(),
+            "done"
+        );
+
+        info!(
+            p50_ms = histogram.value_at_quantile(0.50),
+            p90_ms = histogram.value_at_quantile(0.90),
+            p99_ms = histogram.value_at_quantile(0.99),
+            max_ms = histogram.max(),
+            "latency"
+        );
+
+        Ok(())
+    }
+}
+
+/// Lines that are not recorded requests are skipped
+fn parse_recording(recording: &str) -> Vec<RecordedRequest> {
+    recording
+        .lines()
+        .enumerate()
+        .filter(|(_, x)| !x.trim().is_empty())
+        .filter_map(|(i, x)| match serde_json::from_str(x) {
+            Ok(x) => Some(x),
+            Err(err) => {
+                warn!(?err, line = i + 1, "skipping invalid line");
+                None
+            }
+        })
+        .collect()
+}
+
+async fn send_request(
+    client: reqwest::Client,
+    rpc: String,
+    id: u64,
+    request: RecordedRequest,
+) -> (Duration, Outcome) {
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": request.method,
+        "params": request.params,
+    });
+
+    let start = Instant::now();
+
+    let response = client.post(rpc).json(&body).send().await;
+
+    let outcome = match response {
+        Ok(x) if x.status().is_success() => match x.json::<serde_json::Value>().await {
+            Ok(x) if x.get("error").is_some() => Outcome::JsonRpcError,
+            Ok(_) => Outcome::Success,
+            Err(_) => Outcome::Failed,
+        },
+        _ => Outcome::Failed,
+    };
+
+    (start.elapsed(), outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_recording;
+
+    #[test]
+    fn test_parse_recording() {
+        let recording = r#"{"method":"eth_blockNumber","params":[]}
+
+not json
+{"method":"eth_chainId","params":null}
+"#;
+
+        let x = parse_recording(recording);
+
+        assert_eq!(x.len(), 2);
+        assert_eq!(x[0].method, "eth_blockNumber");
+        assert_eq!(x[1].method, "eth_chainId");
+    }
+}
@@ -0,0 +1,187 @@
+use crate::app::Web3ProxyApp;
+use crate::config::TopConfig;
+use crate::frontend::authorization::Authorization;
+use crate::jsonrpc::{JsonRpcId, JsonRpcRequest, JsonRpcRequestEnum};
+use anyhow::Context;
+use argh::FromArgs;
+use futures::StreamExt;
+use rdkafka::message::Headers;
+use rdkafka::{
+    consumer::{Consumer, StreamConsumer},
+    ClientConfig, Message,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::timeout;
+use tracing::{info, trace};
+use ulid::Ulid;
+
+/// Send a request through the proxy's routing to the live backends and show which backend answered.
+/// Either give a method and params or the request_ulid of a request logged to kafka.
+#[derive(FromArgs, PartialEq, Debug, Eq)]
+#[argh(subcommand, name = "replay_request")]
+pub struct ReplayRequestSubCommand {
+    #[argh(positional)]
+    /// the json-rpc method to call
+    method: Option<String>,
+    #[argh(positional)]
+    /// the params as a json array. defaults to []
+    params: Option<String>,
+    #[argh(option)]
+    /// replay the request with this ulid from kafka (logged by debug mode) instead
+    request_ulid: Option<Ulid>,
+    #[argh(option, default = "\"web3_proxy:rpc\".to_string()")]
+    /// the kafka topic to search for `request_ulid`
+    topic: String,
+    #[argh(option)]
+    /// optional kafka group id
+    group_id: Option<String>,
+}
+
+/// `JsonRpcRequest` as rmp_serde wrote it to kafka. The id isn't needed.
+#[derive(Deserialize)]
+struct KafkaRequest {
+    #[allow(dead_code)]
+    jsonrpc: String,
+    #[allow(dead_code)]
+    id: serde_json::Value,
+    method: String,
+    params: serde_json::Value,
+}
+
+impl ReplayRequestSubCommand {
+    pub async fn main(self, mut top_config: TopConfig, num_workers: usize) -> anyhow::Result<()> {
+        let (method, params) = match (self.request_ulid, self.method) {
+            (Some(request_ulid), None) => {
+                find_kafka_request(&top_config, &self.topic, self.group_id, request_ulid).await?
+            }
+            (None, Some(method)) => {
+                let params = match self.params {
+                    Some(params) => serde_json::from_str(&params).context("parsing params")?,
+                    None => serde_json::Value::Array(vec![]),
+                };
+
+                (method, params)
+            }
+            _ => return Err(anyhow::anyhow!("give either a method or --request-ulid")),
+        };
+
+        let request = JsonRpcRequest::new(JsonRpcId::Number(1), method, params)?;
+
+        info!(?request, "replaying");
+
+        // replays should not save stats, bill anyone, or log to kafka
+        top_config.app.db_url = None;
+        top_config.app.db_replica_url = None;
+        top_config.app.influxdb_host = None;
+        top_config.app.kafka_urls = None;
+
+        let (shutdown_sender, _) = broadcast::channel(1);
+        let (flush_stat_buffer_sender, flush_stat_buffer_receiver) = mpsc::channel(1);
+
+        let spawned_app = Web3ProxyApp::spawn(
+            Arc::new(0.into()),
+            Arc::new(0.into()),
+            top_config,
+            num_workers,
+            shutdown_sender.clone(),
+            flush_stat_buffer_sender,
+            flush_stat_buffer_receiver,
+        )
+        .await?;
+
+        let app = spawned_app.app;
+
+        // the same routing as a user's request needs a head block
+        let mut head_block_receiver = app.head_block_receiver();
+        timeout(Duration::from_secs(35), async {
+            while head_block_receiver.borrow_and_update().is_none() {
+                head_block_receiver.changed().await?;
+            }
+            anyhow::Ok(())
+        })
+        .await
+        .context("no head block")??;
+
+        let authorization = Arc::new(Authorization::internal(None)?);
+
+        let config = app.config.load_full();
+
+        let (status_code, response, rpcs) = app
+            .proxy_web3_rpc(&config, authorization, JsonRpcRequestEnum::Single(request))
+            .await?;
+
+        let rpcs: Vec<_> = rpcs.iter().map(|x| x.name.as_str()).collect();
+
+        // an empty list of rpcs means the response came from the cache
+        info!(%status_code, ?rpcs, "response: {:#}", serde_json::to_value(&response)?);
+
+        let _ = shutdown_sender.send(());
+
+        Ok(())
+    }
+}
+
+/// Search kafka for the request that was logged with `request_ulid`
+async fn find_kafka_request(
+    top_config: &TopConfig,
+    topic: &str,
+    group_id: Option<String>,
+    request_ulid: Ulid,
+) -> anyhow::Result<(String, serde_json::Value)> {
+    let kafka_brokers = top_config
+        .app
+        .kafka_urls
+        .as_ref()
+        .context("top_config.app.kafka_urls is required")?;
+
+    let mut consumer = ClientConfig::new();
+
+    consumer
+        .set("bootstrap.servers", kafka_brokers)
+        .set("enable.partition.eof", "false")
+        .set("security.protocol", &top_config.app.kafka_protocol)
+        .set("session.timeout.ms", "6000")
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest");
+
+    if let Some(group_id) = group_id {
+        consumer.set("group.id", &group_id);
+    }
+
+    let consumer: StreamConsumer = consumer
+        .create()
+        .context("kafka consumer creation failed")?;
+
+    consumer
+        .subscribe(&[topic])
+        .context("subscribing to kafka topic")?;
+
+    let wanted_request_ulid = request_ulid.to_string();
+
+    let mut stream = consumer.stream();
+
+    while let Some(msg) = stream.next().await {
+        let msg = msg?;
+
+        let matches = msg.headers().map_or(false, |headers| {
+            headers.iter().any(|header| {
+                header.key == "request_ulid" && header.value == Some(wanted_request_ulid.as_bytes())
+            })
+        });
+
+        if !matches {
+            continue;
+        }
+
+        // the response is logged with the same headers. only the request has a method
+        match msg.payload().map(rmp_serde::from_slice::<KafkaRequest>) {
+            Some(Ok(x)) => return Ok((x.method, x.params)),
+            _ => trace!(offset = msg.offset(), "skipping response"),
+        }
+    }
+
+    Err(anyhow::anyhow!("request {} not found", request_ulid))
+}
@@ -1,16 +1,19 @@
-use crate::{config::TopConfig, frontend::authorization::RpcSecretKey, relational_db::get_db};
+use crate::{
+    config::TopConfig,
+    frontend::authorization::RpcSecretKey,
+    relational_db::{get_db, DbPoolOptions},
+};
 use anyhow::Context;
 use argh::FromArgs;
 use entities::rpc_key;
 use futures::TryStreamExt;
-use migration::sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use migration::sea_orm::{EntityTrait, QueryFilter};
 use rdkafka::{
     consumer::{Consumer, StreamConsumer},
     ClientConfig, Message,
 };
 use std::num::NonZeroU64;
 use tracing::info;
-use uuid::Uuid;
 
 /// Second subcommand.
 #[derive(FromArgs, PartialEq, Debug, Eq)]
@@ -35,12 +38,10 @@ impl SearchKafkaSubCommand {
         let mut rpc_key_id = self.rpc_key_id.map(|x| x.get());
 
         if let Some(rpc_key) = self.rpc_key {
-            let db_conn = get_db(top_config.app.db_url.unwrap(), 1, 1).await?;
-
-            let rpc_key: Uuid = rpc_key.into();
+            let db_conn = get_db(top_config.app.db_url.unwrap(), DbPoolOptions::default()).await?;
 
             let x = rpc_key::Entity::find()
-                .filter(rpc_key::Column::SecretKey.eq(rpc_key))
+                .filter(rpc_key.db_condition()?)
                 .one(&db_conn)
                 .await?
                 .context("key not found")?;
@@ -10,7 +10,7 @@ use tracing::{debug, warn};
 use super::{SentrydErrorBuilder, SentrydResult};
 
 #[derive(Debug, Deserialize, Serialize)]
-struct JsonRpcResponse<V> {
+pub(super) struct JsonRpcResponse<V> {
     // pub jsonrpc: String,
     // pub id: Box<RawValue>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -1,5 +1,6 @@
 mod compare;
 mod simple;
+mod stalled;
 
 use crate::{config::TopConfig, pagerduty::pagerduty_alert};
 use anyhow::Context;
@@ -35,6 +36,10 @@ pub struct SentrydSubCommand {
     /// warning threshold for seconds between the rpc and best other_rpc's head blocks
     max_lag: i64,
 
+    #[argh(option, default = "20")]
+    /// alert if a web3-proxy's head block has not changed in this many of its chain's average block times
+    max_stall_blocks: u32,
+
     #[argh(option)]
     /// other (HTTP only) rpcs to compare the main rpc to
     other_rpc: Vec<String>,
@@ -213,6 +218,41 @@ impl SentrydSubCommand {
             handles.push(tokio::spawn(loop_f));
         }
 
+        // check that every web3-proxy's head block is still changing. each proxy might be on a different chain
+        // the comparison below can't catch this if every proxy is stuck on the same block
+        {
+            // TODO: what timeout?
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()?;
+
+            let proxies =
+                std::iter::once((primary_proxy.clone(), "main stalled head", Level::ERROR)).chain(
+                    other_proxy
+                        .iter()
+                        .map(|x| (x.clone(), "other stalled head", Level::WARN)),
+                );
+
+            for (rpc, class, level) in proxies {
+                let client = client.clone();
+                let error_sender = error_sender.clone();
+                let last_head = stalled::SharedLastHead::default();
+                let max_stall_blocks = self.max_stall_blocks;
+
+                let loop_f = a_loop(class, seconds, level, error_sender, move |error_builder| {
+                    stalled::main(
+                        error_builder,
+                        client.clone(),
+                        rpc.clone(),
+                        last_head.clone(),
+                        max_stall_blocks,
+                    )
+                });
+
+                handles.push(tokio::spawn(loop_f));
+            }
+        }
+
         // compare the main web3-proxy head block to all web3-proxies and rpcs
         {
             let max_age = self.max_age;
@@ -0,0 +1,233 @@
+//! Catch a proxy whose head block stopped changing. Each proxy is checked against its own chain's block time.
+use super::compare::JsonRpcResponse;
+use super::{SentrydError, SentrydErrorBuilder, SentrydResult};
+use crate::config::average_block_interval;
+use anyhow::{anyhow, Context};
+use ethers::types::U64;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// The head a proxy last reported and when it changed to it
+#[derive(Debug)]
+pub struct LastHead {
+    chain_id: u64,
+    num: U64,
+    since: Instant,
+    /// how long this chain may go without a new head
+    max_stall: Duration,
+}
+
+pub type SharedLastHead = Arc<Mutex<Option<LastHead>>>;
+
+pub async fn main(
+    error_builder: SentrydErrorBuilder,
+    client: reqwest::Client,
+    rpc: String,
+    last_head: SharedLastHead,
+    max_stall_blocks: u32,
+) -> SentrydResult {
+    let chain_id: U64 = query(&client, &rpc, "eth_chainId")
+        .await
+        .map_err(|x| error_builder.build(x))?;
+    let num: U64 = query(&client, &rpc, "eth_blockNumber")
+        .await
+        .map_err(|x| error_builder.build(x))?;
+
+    let mut last_head = last_head.lock().await;
+
+    let stalled = update_head(
+        &mut last_head,
+        chain_id.as_u64(),
+        num,
+        Instant::now(),
+        max_stall_blocks,
+    );
+
+    let last_head = last_head.as_ref().expect("update_head always sets it");
+
+    if let Some(head_age) = stalled {
+        return Err(SentrydError {
+            class: error_builder.class.clone(),
+            level: error_builder.level,
+            summary: format!(
+                "head on chain {} has been {} for {}s",
+                last_head.chain_id,
+                last_head.num,
+                head_age.as_secs()
+            ),
+            extra: Some(json!({
+                "rpc": rpc,
+                "chain_id": last_head.chain_id,
+                "head_num": last_head.num,
+                "head_age_seconds": head_age.as_secs(),
+                "max_stall_seconds": last_head.max_stall.as_secs_f32(),
+            })),
+        });
+    }
+
+    debug!(chain_id = last_head.chain_id, num = %last_head.num, %rpc, "head is moving");
+
+    Ok(())
+}
+
+/// Returns how long the head has been the same if that is longer than the chain allows
+fn update_head(
+    last_head: &mut Option<LastHead>,
+    chain_id: u64,
+    num: U64,
+    now: Instant,
+    max_stall_blocks: u32,
+) -> Option<Duration> {
+    match last_head {
+        // a proxy restarting onto a different chain starts over
+        Some(x) if x.chain_id == chain_id && x.num == num => {
+            let head_age = now.saturating_duration_since(x.since);
+
+            (head_age > x.max_stall).then_some(head_age)
+        }
+        Some(x) if x.chain_id == chain_id => {
+            x.num = num;
+            x.since = now;
+
+            None
+        }
+        _ => {
+            *last_head = Some(LastHead {
+                chain_id,
+                num,
+                since: now,
+                max_stall: average_block_interval(chain_id) * max_stall_blocks,
+            });
+
+            None
+        }
+    }
+}
+
+async fn query<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    rpc: &str,
+    method: &str,
+) -> anyhow::Result<T> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": "1",
+        "method": method,
+        "params": [],
+    });
+
+    let response = client
+        .post(rpc)
+        .json(&request)
+        .send()
+        .await
+        .context(format!("error querying {} from {}", method, rpc))?;
+
+    anyhow::ensure!(
+        response.status().is_success(),
+        "bad response from {}: {}",
+        rpc,
+        response.status(),
+    );
+
+    let response: JsonRpcResponse<T> = response
+        .json()
+        .await
+        .context(format!("failed parsing {} from {}", method, rpc))?;
+
+    if let Some(result) = response.result {
+        Ok(result)
+    } else if let Some(err) = response.error {
+        Err(anyhow!(
+            "jsonrpc error during {} from {}: {:#}",
+            method,
+            rpc,
+            json!(err),
+        ))
+    } else {
+        Err(anyhow!("empty result during {} from {}", method, rpc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::update_head;
+    use ethers::types::U64;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[test]
+    fn test_update_head() {
+        let start = Instant::now();
+
+        let mut last_head = None;
+
+        // 12 second blocks on mainnet
+        assert_eq!(update_head(&mut last_head, 1, U64::from(1), start, 5), None);
+        assert_eq!(
+            update_head(
+                &mut last_head,
+                1,
+                U64::from(1),
+                start + Duration::from_secs(60),
+                5
+            ),
+            None
+        );
+        assert_eq!(
+            update_head(
+                &mut last_head,
+                1,
+                U64::from(1),
+                start + Duration::from_secs(61),
+                5
+            ),
+            Some(Duration::from_secs(61))
+        );
+
+        // a new head resets the age
+        assert_eq!(
+            update_head(
+                &mut last_head,
+                1,
+                U64::from(2),
+                start + Duration::from_secs(62),
+                5
+            ),
+            None
+        );
+        assert_eq!(
+            update_head(
+                &mut last_head,
+                1,
+                U64::from(2),
+                start + Duration::from_secs(100),
+                5
+            ),
+            None
+        );
+
+        // 2 second blocks on polygon
+        let mut last_head = None;
+
+        assert_eq!(
+            update_head(&mut last_head, 137, U64::from(1), start, 5),
+            None
+        );
+        assert_eq!(
+            update_head(
+                &mut last_head,
+                137,
+                U64::from(1),
+                start + Duration::from_secs(11),
+                5
+            ),
+            Some(Duration::from_secs(11))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use argh::FromArgs;
+use chrono::{Duration, Utc};
+use entities::{rpc_key, user};
+use ethers::utils::hex;
+use migration::sea_orm::{self, ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder};
+use prettytable::{row, Table};
+use tracing::info;
+
+#[derive(FromArgs, PartialEq, Debug, Eq)]
+/// List active rpc keys that have not been used recently
+#[argh(subcommand, name = "stale_keys")]
+pub struct StaleKeysSubCommand {
+    /// keys not used in this many days are stale. keys that were never used are always stale
+    #[argh(option, default = "90")]
+    days: i64,
+}
+
+impl StaleKeysSubCommand {
+    pub async fn main(self, db: &sea_orm::DatabaseConnection) -> anyhow::Result<()> {
+        let cutoff = Utc::now() - Duration::days(self.days);
+
+        let keys = rpc_key::Entity::find()
+            .filter(rpc_key::Column::Active.eq(true))
+            .filter(
+                Condition::any()
+                    .add(rpc_key::Column::LastUsedAt.is_null())
+                    .add(rpc_key::Column::LastUsedAt.lt(cutoff)),
+            )
+            .order_by_asc(rpc_key::Column::LastUsedAt)
+            .find_also_related(user::Entity)
+            .all(db)
+            .await?;
+
+        info!(
+            stale = keys.len(),
+            days = self.days,
+            "active keys not used recently"
+        );
+
+        let mut table = Table::new();
+
+        table.add_row(row![
+            "key_id",
+            "user_id",
+            "address",
+            "description",
+            "last_used_at"
+        ]);
+
+        for (key, user) in keys {
+            let address = user
+                .map(|x| format!("0x{}", hex::encode(x.address)))
+                .unwrap_or_default();
+
+            let last_used_at = key
+                .last_used_at
+                .map(|x| x.to_rfc3339())
+                .unwrap_or_else(|| "never".to_string());
+
+            table.add_row(row![
+                key.id,
+                key.user_id,
+                address,
+                key.description.unwrap_or_default(),
+                last_used_at,
+            ]);
+        }
+
+        table.printstd();
+
+        Ok(())
+    }
+}
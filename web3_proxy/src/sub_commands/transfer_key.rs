@@ -7,7 +7,6 @@ use migration::sea_orm::{
     self, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel,
     QueryFilter,
 };
-use sea_orm::prelude::Uuid;
 use tracing::{debug, info};
 
 /// change a key's owner.
@@ -25,12 +24,10 @@ pub struct TransferKeySubCommand {
 
 impl TransferKeySubCommand {
     pub async fn main(self, db_conn: &DatabaseConnection) -> anyhow::Result<()> {
-        let rpc_secret_key: Uuid = self.rpc_secret_key.into();
-
         let new_address: Address = self.new_address.parse()?;
 
         let uk = rpc_key::Entity::find()
-            .filter(rpc_key::Column::SecretKey.eq(rpc_secret_key))
+            .filter(self.rpc_secret_key.db_condition()?)
             .one(db_conn)
             .await?
             .context("No key found")?;
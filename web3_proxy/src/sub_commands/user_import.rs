@@ -1,3 +1,4 @@
+use crate::frontend::authorization::RpcSecretKey;
 use anyhow::Context;
 use argh::FromArgs;
 use entities::{rpc_key, user};
@@ -173,22 +174,40 @@ impl UserImportSubCommand {
                 .get(&import_rk.user_id)
                 .context("user mapping required")?;
 
-            if let Some(existing_rk) = rpc_key::Entity::find()
-                .filter(rpc_key::Column::SecretKey.eq(import_rk.secret_key))
-                .one(db_conn)
-                .await?
-            {
+            // exports from before hashing have raw keys. look them up and save them the same way new keys are
+            let existing_rk = if import_rk.secret_key_hashed {
+                rpc_key::Entity::find()
+                    .filter(rpc_key::Column::SecretKey.eq(import_rk.secret_key))
+                    .filter(rpc_key::Column::SecretKeyHashed.eq(true))
+                    .one(db_conn)
+                    .await?
+            } else {
+                rpc_key::Entity::find()
+                    .filter(RpcSecretKey::from(import_rk.secret_key).db_condition()?)
+                    .one(db_conn)
+                    .await?
+            };
+
+            if let Some(existing_rk) = existing_rk {
                 // make sure it belongs to the mapped user
                 anyhow::ensure!(existing_rk.user_id == mapped_id, "unexpected user id");
 
                 // the key exists under the expected user. we are good to continue
             } else {
                 // user address is not known to the local database. no existing_rk
+                let raw_secret_key = (!import_rk.secret_key_hashed)
+                    .then(|| RpcSecretKey::from(import_rk.secret_key));
+
                 let mut new_rk = import_rk.into_active_model();
 
                 new_rk.id = NotSet;
                 new_rk.user_id = Set(mapped_id);
 
+                if let Some(raw_secret_key) = raw_secret_key {
+                    new_rk.secret_key = Set(raw_secret_key.hashed()?);
+                    new_rk.secret_key_hashed = Set(true);
+                }
+
                 new_rk.save(db_conn).await?;
 
                 count += 1;
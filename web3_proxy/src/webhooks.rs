@@ -0,0 +1,586 @@
+//! Send signed POSTs to a user's webhook when something happens to their account.
+//!
+//! Deliveries are signed the same way as requests to keys with an HMAC secret: `X-W3P-TIMESTAMP` and `X-W3P-SIGNATURE` over `{timestamp}.{body}`.
+//! Failed deliveries are retried with backoff. Events are not saved, so deliveries that are still retrying are lost on restart.
+//!
+//! Users choose the url, so it is only ever allowed to point at public addresses. It is checked when it is set and again before every delivery.
+use crate::app::{Web3ProxyJoinHandle, APP_USER_AGENT};
+use crate::errors::{Web3ProxyError, Web3ProxyResult};
+use crate::frontend::request_signature::{HmacSecret, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::quota::QuotaWindow;
+use chrono::{DateTime, Utc};
+use entities::user_webhook;
+use ethers::types::Address;
+use hashbrown::HashMap;
+use http::header::CONTENT_TYPE;
+use migration::sea_orm::prelude::Decimal;
+use migration::sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::lookup_host;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, trace, warn};
+use url::{Host, Url};
+
+/// Deliveries that fail this many times are dropped
+pub const DELIVERY_TRIES: u32 = 5;
+
+/// How long the user's server has to respond to each delivery
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events waiting for delivery. More than this are dropped instead of piling up in memory
+const CHANNEL_SIZE: usize = 10_000;
+
+/// Deliveries in progress at once. Each one can spend a while retrying a slow server
+const MAX_CONCURRENT_DELIVERIES: usize = 100;
+
+/// Events that were not delivered because the queue was full
+pub static WEBHOOKS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// the balance dropped below `webhook_low_balance`
+    LowBalance {
+        balance: Decimal,
+        threshold: Decimal,
+    },
+    /// a key used `webhook_quota_percent` of its quota for the current window
+    QuotaNearLimit {
+        rpc_key_id: u64,
+        used: u64,
+        max_requests: u64,
+        window: QuotaWindow,
+        reset_at: i64,
+    },
+    /// a key was turned off. rotated keys include the id of the key that replaced them
+    KeyDeactivated {
+        rpc_key_id: u64,
+        replaced_by_key_id: Option<u64>,
+    },
+    /// a login from an ip that has not logged in to the account recently
+    UnusualLogin { ip: IpAddr },
+    /// a revert from a key with `webhook_reverts`. at most one per key every `webhook_revert_seconds`
+    Revert {
+        rpc_key_id: u64,
+        method: String,
+        to: Address,
+        /// redacted the same way as the revert log
+        call_data: Option<String>,
+        /// None if the rpc did not say why
+        reason: Option<String>,
+    },
+}
+
+/// The JSON body of a delivery
+#[derive(Clone, Debug, Serialize)]
+pub struct WebhookNotification {
+    pub user_id: u64,
+    #[serde(flatten)]
+    pub event: WebhookEvent,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookNotification {
+    pub fn new(user_id: u64, event: WebhookEvent) -> Self {
+        Self {
+            user_id,
+            event,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+pub type WebhookSender = mpsc::Sender<WebhookNotification>;
+
+/// Queue an event without waiting. Events are dropped if the queue is full
+pub fn send_webhook(sender: &WebhookSender, notification: WebhookNotification) {
+    match sender.try_send(notification) {
+        Ok(()) => {}
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            let dropped = WEBHOOKS_DROPPED.fetch_add(1, Ordering::Relaxed) + 1;
+
+            if dropped.is_power_of_two() {
+                warn!(dropped, "webhook queue is full. dropping events");
+            }
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => {
+            warn!("webhook delivery is not running");
+        }
+    }
+}
+
+/// The addresses that a webhook url points to. Errors unless every one of them is on the public internet
+pub async fn resolve_webhook_url(url: &Url) -> Web3ProxyResult<Vec<SocketAddr>> {
+    // deliveries include account details, so they are not sent in the clear
+    if url.scheme() != "https" {
+        return Err(Web3ProxyError::BadRequest(
+            "webhook urls must use https".into(),
+        ));
+    }
+
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<SocketAddr> = match url.host() {
+        Some(Host::Ipv4(x)) => vec![SocketAddr::new(x.into(), port)],
+        Some(Host::Ipv6(x)) => vec![SocketAddr::new(x.into(), port)],
+        Some(Host::Domain(x)) => lookup_host((x, port))
+            .await
+            .map_err(|_| Web3ProxyError::BadRequest("webhook host could not be resolved".into()))?
+            .collect(),
+        None => {
+            return Err(Web3ProxyError::BadRequest(
+                "webhook urls must have a host".into(),
+            ))
+        }
+    };
+
+    if addrs.is_empty() {
+        return Err(Web3ProxyError::BadRequest(
+            "webhook host could not be resolved".into(),
+        ));
+    }
+
+    if !addrs.iter().all(|x| ip_is_global(x.ip())) {
+        return Err(Web3ProxyError::BadRequest(
+            "webhook urls must point at public addresses".into(),
+        ));
+    }
+
+    Ok(addrs)
+}
+
+/// `IpAddr::is_global` is not stable yet. Loopback, private, link-local (including cloud metadata), and reserved ranges are not global
+fn ip_is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(x) => ipv4_is_global(x),
+        IpAddr::V6(x) => {
+            // addresses that embed an ipv4 address are only as global as that address
+            if let Some(x) = x.to_ipv4_mapped() {
+                return ipv4_is_global(x);
+            }
+
+            let segments = x.segments();
+
+            // NAT64
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let o = x.octets();
+
+                return ipv4_is_global(Ipv4Addr::new(o[12], o[13], o[14], o[15]));
+            }
+
+            !(x.is_unspecified()
+                || x.is_loopback()
+                || x.is_multicast()
+                // unique local
+                || (segments[0] & 0xfe00) == 0xfc00
+                // link local
+                || (segments[0] & 0xffc0) == 0xfe80
+                // documentation
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8)
+                // ipv4-compatible and other addresses in ::/96
+                || segments[..6] == [0; 6])
+        }
+    }
+}
+
+fn ipv4_is_global(x: Ipv4Addr) -> bool {
+    let [a, b, ..] = x.octets();
+
+    !(x.is_unspecified()
+        || x.is_private()
+        || x.is_loopback()
+        || x.is_link_local()
+        || x.is_broadcast()
+        || x.is_documentation()
+        || x.is_multicast()
+        // "this network"
+        || a == 0
+        // shared address space (carrier-grade NAT)
+        || (a == 100 && (b & 0xc0) == 64)
+        // protocol assignments
+        || (a == 192 && b == 0 && x.octets()[2] == 0)
+        // benchmarking
+        || (a == 198 && (b & 0xfe) == 18)
+        // reserved
+        || a >= 240)
+}
+
+/// Revert events that were not sent because the key sent one recently
+pub static REVERT_WEBHOOKS_SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// A contract that always reverts would otherwise send a webhook for every call
+#[derive(Debug)]
+pub struct RevertWebhooks {
+    sender: WebhookSender,
+    min_interval: Duration,
+    /// when each rpc_key_id last sent a revert event.
+    /// nothing is evicted early, so a burst of other keys can't reset a key's limit
+    last_sent: Mutex<HashMap<u64, Instant>>,
+}
+
+impl RevertWebhooks {
+    pub fn new(sender: WebhookSender, min_interval: Duration) -> Self {
+        Self {
+            sender,
+            min_interval,
+            last_sent: Default::default(),
+        }
+    }
+
+    /// Returns false if the key already sent a revert event within `min_interval`
+    pub fn notify(&self, user_id: u64, rpc_key_id: u64, event: WebhookEvent) -> bool {
+        let now = Instant::now();
+
+        {
+            let mut last_sent = self.last_sent.lock();
+
+            if let Some(x) = last_sent.get(&rpc_key_id) {
+                if now.duration_since(*x) < self.min_interval {
+                    REVERT_WEBHOOKS_SKIPPED.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+            }
+
+            last_sent.insert(rpc_key_id, now);
+
+            // only entries that are past `min_interval` are removed. they no longer limit anything
+            if last_sent.len().is_power_of_two() {
+                last_sent.retain(|_, x| now.duration_since(*x) < self.min_interval);
+            }
+        }
+
+        send_webhook(&self.sender, WebhookNotification::new(user_id, event));
+
+        true
+    }
+}
+
+pub struct WebhookDelivery {
+    db_conn: DatabaseConnection,
+}
+
+impl WebhookDelivery {
+    /// Returns None if there is no database to load webhooks from
+    pub fn try_spawn(
+        db_conn: Option<DatabaseConnection>,
+        shutdown_token: CancellationToken,
+    ) -> Option<(WebhookSender, Web3ProxyJoinHandle<()>)> {
+        let db_conn = db_conn?;
+
+        let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+
+        let x = Self { db_conn };
+
+        let handle = tokio::spawn(async move { x.delivery_loop(receiver, shutdown_token).await });
+
+        Some((sender, handle))
+    }
+
+    async fn delivery_loop(
+        self,
+        mut receiver: mpsc::Receiver<WebhookNotification>,
+        shutdown_token: CancellationToken,
+    ) -> Web3ProxyResult<()> {
+        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES));
+
+        loop {
+            // waiting for a permit leaves events in the channel. once it is full, new events are dropped
+            let permit = tokio::select! {
+                x = semaphore.clone().acquire_owned() => x.expect("the semaphore is never closed"),
+                _ = shutdown_token.cancelled() => {
+                    info!("webhook delivery shutting down");
+                    break;
+                }
+            };
+
+            tokio::select! {
+                x = receiver.recv() => {
+                    match x {
+                        Some(notification) => {
+                            // each delivery retries on its own so that one slow server doesn't hold up everyone else
+                            let db_conn = self.db_conn.clone();
+
+                            tokio::spawn(async move {
+                                deliver(db_conn, notification).await;
+
+                                drop(permit);
+                            });
+                        }
+                        None => break,
+                    }
+                }
+                _ = shutdown_token.cancelled() => {
+                    info!("webhook delivery shutting down");
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn deliver(db_conn: DatabaseConnection, notification: WebhookNotification) {
+    let webhook = match user_webhook::Entity::find()
+        .filter(user_webhook::Column::UserId.eq(notification.user_id))
+        .one(&db_conn)
+        .await
+    {
+        Ok(Some(x)) => x,
+        Ok(None) => {
+            trace!(user_id = notification.user_id, "no webhook");
+            return;
+        }
+        Err(err) => {
+            warn!(
+                ?err,
+                user_id = notification.user_id,
+                "failed loading webhook"
+            );
+            return;
+        }
+    };
+
+    let body = match serde_json::to_vec(&notification) {
+        Ok(x) => x,
+        Err(err) => {
+            warn!(?err, ?notification, "failed serializing webhook");
+            return;
+        }
+    };
+
+    let http_client = match webhook_client(&webhook.url).await {
+        Ok(x) => x,
+        Err(err) => {
+            warn!(
+                ?err,
+                user_id = notification.user_id,
+                "webhook url is not allowed"
+            );
+            return;
+        }
+    };
+
+    let secret = HmacSecret::from(webhook.secret);
+
+    for attempt in 0..DELIVERY_TRIES {
+        if attempt > 0 {
+            sleep(retry_delay(attempt)).await;
+        }
+
+        // sign every attempt so that retries are not rejected as too old
+        let timestamp = Utc::now().timestamp();
+
+        let response = http_client
+            .post(&webhook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(SIGNATURE_HEADER, secret.sign(timestamp, &body))
+            .body(body.clone())
+            .send()
+            .await;
+
+        match response {
+            Ok(x) if x.status().is_success() => {
+                trace!(user_id = notification.user_id, attempt, "delivered webhook");
+                return;
+            }
+            Ok(x) => {
+                debug!(user_id = notification.user_id, attempt, status = %x.status(), "webhook rejected");
+            }
+            Err(err) => {
+                debug!(
+                    user_id = notification.user_id,
+                    attempt,
+                    ?err,
+                    "webhook failed"
+                );
+            }
+        }
+    }
+
+    warn!(
+        user_id = notification.user_id,
+        event = ?notification.event,
+        "webhook delivery gave up"
+    );
+}
+
+/// A client that can only connect to the addresses that were just checked.
+/// Pinning them keeps the host from resolving to a private address after the check. Redirects could go anywhere, so they are not followed
+async fn webhook_client(url: &str) -> Web3ProxyResult<reqwest::Client> {
+    let url: Url = url
+        .parse()
+        .map_err(|_| Web3ProxyError::BadRequest("invalid webhook url".into()))?;
+
+    let addrs = resolve_webhook_url(&url).await?;
+
+    let mut builder = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(DELIVERY_TIMEOUT)
+        .user_agent(APP_USER_AGENT);
+
+    if let Some(Host::Domain(host)) = url.host() {
+        builder = builder.resolve_to_addrs(host, &addrs);
+    }
+
+    builder
+        .build()
+        .map_err(|err| Web3ProxyError::Anyhow(err.into()))
+}
+
+/// 1, 2, 4, 8... seconds
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.saturating_sub(1).min(6))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ip_is_global, resolve_webhook_url, retry_delay, RevertWebhooks, WebhookEvent,
+        WebhookNotification,
+    };
+    use ethers::types::Address;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_notification_json() {
+        let notification = WebhookNotification::new(
+            7,
+            WebhookEvent::KeyDeactivated {
+                rpc_key_id: 3,
+                replaced_by_key_id: Some(4),
+            },
+        );
+
+        let x = serde_json::to_value(&notification).unwrap();
+
+        assert_eq!(x["event"], json!("key_deactivated"));
+        assert_eq!(x["user_id"], json!(7));
+        assert_eq!(x["rpc_key_id"], json!(3));
+        assert_eq!(x["replaced_by_key_id"], json!(4));
+    }
+
+    #[tokio::test]
+    async fn test_revert_webhooks_are_limited_per_key() {
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        let x = RevertWebhooks::new(sender, Duration::from_secs(60));
+
+        let event = |rpc_key_id| WebhookEvent::Revert {
+            rpc_key_id,
+            method: "eth_call".to_string(),
+            to: Address::zero(),
+            call_data: None,
+            reason: Some("nope".to_string()),
+        };
+
+        assert!(x.notify(7, 3, event(3)));
+        assert!(!x.notify(7, 3, event(3)));
+        // other keys have their own limit
+        assert!(x.notify(7, 4, event(4)));
+
+        let first = serde_json::to_value(receiver.recv().await.unwrap()).unwrap();
+
+        assert_eq!(first["event"], json!("revert"));
+        assert_eq!(first["rpc_key_id"], json!(3));
+        assert_eq!(first["reason"], json!("nope"));
+
+        assert_eq!(
+            serde_json::to_value(receiver.recv().await.unwrap()).unwrap()["rpc_key_id"],
+            json!(4)
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_revert_webhook_limits_are_not_evicted() {
+        let (sender, _receiver) = mpsc::channel(1);
+
+        let x = RevertWebhooks::new(sender, Duration::from_secs(60));
+
+        let event = || WebhookEvent::Revert {
+            rpc_key_id: 1,
+            method: "eth_call".to_string(),
+            to: Address::zero(),
+            call_data: None,
+            reason: None,
+        };
+
+        assert!(x.notify(7, 1, event()));
+
+        // lots of other keys must not reset the first key's limit
+        for rpc_key_id in 2..20_000 {
+            x.notify(7, rpc_key_id, event());
+        }
+
+        assert!(!x.notify(7, 1, event()));
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+
+        assert!(x.notify(7, 1, event()));
+    }
+
+    #[test]
+    fn test_ip_is_global() {
+        for x in ["8.8.8.8", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(ip_is_global(x.parse().unwrap()), "{}", x);
+        }
+
+        for x in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!ip_is_global(x.parse().unwrap()), "{}", x);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_webhook_url() {
+        assert!(
+            resolve_webhook_url(&"https://8.8.8.8/hook".parse().unwrap())
+                .await
+                .is_ok()
+        );
+
+        for x in [
+            "http://8.8.8.8/hook",
+            "https://127.0.0.1/hook",
+            "https://[::1]/hook",
+            "https://169.254.169.254/latest/meta-data",
+        ] {
+            assert!(
+                resolve_webhook_url(&x.parse().unwrap()).await.is_err(),
+                "{}",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Duration::from_secs(1));
+        assert_eq!(retry_delay(2), Duration::from_secs(2));
+        assert_eq!(retry_delay(4), Duration::from_secs(8));
+        assert_eq!(retry_delay(100), Duration::from_secs(64));
+    }
+}
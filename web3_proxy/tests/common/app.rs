@@ -31,7 +31,7 @@ use tokio::{
 use tracing::{info, trace, warn};
 use web3_proxy::{
     config::{AppConfig, TopConfig, Web3RpcConfig},
-    relational_db::get_migrated_db,
+    relational_db::{get_migrated_db, DbPoolOptions},
     stats::FlushedStats,
     sub_commands::ProxydSubCommand,
 };
@@ -68,6 +68,15 @@ pub struct TestApp {
 
 impl TestApp {
     pub async fn spawn(chain_id: u64, setup_db: bool) -> Self {
+        Self::spawn_with_config(chain_id, setup_db, json!({})).await
+    }
+
+    /// `extra_config` is merged over the default test AppConfig
+    pub async fn spawn_with_config(
+        chain_id: u64,
+        setup_db: bool,
+        extra_config: serde_json::Value,
+    ) -> Self {
         info!(?chain_id);
 
         let num_workers = 2;
@@ -221,7 +230,7 @@ impl TestApp {
                     panic!("db took too long to start");
                 }
 
-                match get_migrated_db(db_url.clone(), 1, 1).await {
+                match get_migrated_db(db_url.clone(), DbPoolOptions::default()).await {
                     Ok(x) => {
                         // it worked! yey!
                         db_data.conn = Some(x);
@@ -247,7 +256,7 @@ impl TestApp {
         // make a test TopConfig
         // TODO: test influx
         // TODO: test redis
-        let app_config: AppConfig = serde_json::from_value(json!({
+        let mut app_config = json!({
             "chain_id": chain_id,
             "db_url": db_url,
             "default_user_max_requests_per_period": Some(6_000_000),
@@ -259,8 +268,14 @@ impl TestApp {
             "min_synced_rpcs": 1,
             "public_requests_per_period": Some(1_000_000),
             "response_cache_max_bytes": 10_u64.pow(7),
-        }))
-        .unwrap();
+            "rpc_key_hash_secret": db_url.as_ref().map(|_| "test_secret"),
+        });
+
+        if let serde_json::Value::Object(extra_config) = extra_config {
+            app_config.as_object_mut().unwrap().extend(extra_config);
+        }
+
+        let app_config: AppConfig = serde_json::from_value(app_config).unwrap();
 
         let top_config = TopConfig {
             app: app_config,
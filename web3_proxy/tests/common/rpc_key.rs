@@ -27,7 +27,8 @@ pub struct RpcKey {
     pub log_revert_chance: f64,
     pub private_txs: bool,
     pub role: String,
-    pub secret_key: Ulid,
+    /// hashed keys are only shown once. the helper fills this in from the login response
+    pub secret_key: Option<Ulid>,
     pub user_id: u64,
 }
 
@@ -57,7 +58,17 @@ pub async fn user_get_first_rpc_key(
     let rpc_key: RpcKeyResponse = serde_json::from_value(rpc_key_response).unwrap();
     info!(?rpc_key);
 
-    rpc_key.user_rpc_keys.into_iter().next().unwrap().1
+    let mut rpc_key = rpc_key.user_rpc_keys.into_iter().next().unwrap().1;
+
+    // the raw key is only in the response from when the key was created
+    if rpc_key.secret_key.is_none() {
+        rpc_key.secret_key = login_response
+            .rpc_keys
+            .get(&rpc_key.id)
+            .map(|x| Ulid::from(x.secret_key));
+    }
+
+    rpc_key
 }
 
 #[allow(unused)]
@@ -76,6 +87,7 @@ pub async fn user_get_provider(
 
     connect_http(
         rpc_url.parse().unwrap(),
+        None,
         Some(r.clone()),
         Duration::from_secs(1),
     )
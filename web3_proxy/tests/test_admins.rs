@@ -39,7 +39,9 @@ async fn test_admin_grant_credits() {
     let admin_login_response = create_user_as_admin(&x, &r, &admin_wallet).await;
     info!(?admin_login_response);
 
-    set_user_tier(&x, user_login_response.user.clone(), "Premium").await.unwrap();
+    set_user_tier(&x, user_login_response.user.clone(), "Premium")
+        .await
+        .unwrap();
 
     let increase_balance_response = admin_increase_balance(
         &x,
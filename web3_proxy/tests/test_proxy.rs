@@ -1,13 +1,17 @@
 mod common;
 
 use crate::common::TestApp;
-use ethers::prelude::U256;
+use ethers::prelude::{Middleware, Provider, Ws, U256};
+use futures::{SinkExt, StreamExt};
 use http::StatusCode;
+use serde_json::json;
 use std::time::Duration;
 use tokio::{
     task::yield_now,
-    time::{sleep, Instant},
+    time::{sleep, timeout, Instant},
 };
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message;
 use web3_proxy::rpcs::blockchain::ArcBlock;
 
 #[cfg_attr(not(feature = "tests-needing-docker"), ignore)]
@@ -97,3 +101,92 @@ async fn it_starts_and_stops() {
     // most tests won't need to wait, but we should wait here to be sure all the shutdown logic works properly
     x.wait().await;
 }
+
+#[test_log::test(tokio::test)]
+async fn it_proxies_requests_over_websockets() {
+    let x = TestApp::spawn(31337, false).await;
+
+    let ws_url = x.proxy_provider.url().as_str().replacen("http", "ws", 1);
+
+    let ws_provider = Provider::<Ws>::connect(ws_url).await.unwrap();
+
+    // a subscription and plain requests share the same websocket
+    let _blocks = ws_provider.subscribe_blocks().await.unwrap();
+
+    let (chain_id, anvil_chain_id) =
+        tokio::join!(ws_provider.get_chainid(), x.anvil_provider.get_chainid());
+
+    assert_eq!(chain_id.unwrap(), anvil_chain_id.unwrap());
+
+    let block_number = ws_provider.get_block_number().await.unwrap();
+    let anvil_block_number = x.anvil_provider.get_block_number().await.unwrap();
+
+    assert_eq!(block_number, anvil_block_number);
+}
+
+#[test_log::test(tokio::test)]
+async fn it_closes_websockets_that_send_oversized_messages() {
+    let x = TestApp::spawn_with_config(31337, false, json!({ "ws_max_message_bytes": 1024 })).await;
+
+    let ws_url = x.proxy_provider.url().as_str().replacen("http", "ws", 1);
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_url).await.unwrap();
+
+    socket.send(Message::Text("a".repeat(2048))).await.unwrap();
+
+    let close_frame = timeout(Duration::from_secs(5), async {
+        while let Some(msg) = socket.next().await {
+            if let Message::Close(close_frame) = msg.unwrap() {
+                return close_frame;
+            }
+        }
+        None
+    })
+    .await
+    .unwrap()
+    .expect("the proxy should say why it closed the socket");
+
+    assert_eq!(close_frame.code, CloseCode::Size);
+}
+
+#[test_log::test(tokio::test)]
+async fn it_checks_public_allowed_methods_before_subscribing() {
+    let x = TestApp::spawn_with_config(
+        31337,
+        false,
+        json!({ "public_allowed_methods": ["eth_blockNumber"] }),
+    )
+    .await;
+
+    let ws_url = x.proxy_provider.url().as_str().replacen("http", "ws", 1);
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_url).await.unwrap();
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_subscribe",
+        "params": ["newHeads"],
+    });
+
+    socket
+        .send(Message::Text(request.to_string()))
+        .await
+        .unwrap();
+
+    let response = timeout(Duration::from_secs(5), socket.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+        .into_text()
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+
+    assert_eq!(response["id"], 1);
+    assert!(response["result"].is_null());
+    assert_eq!(
+        response["error"]["message"],
+        "the method eth_subscribe requires an api key"
+    );
+}
@@ -11,12 +11,18 @@ use crate::common::referral::{
 use crate::common::rpc_key::{user_get_first_rpc_key, RpcKey};
 use crate::common::user_balance::user_get_balance;
 use crate::common::TestApp;
-use ethers::prelude::{Http, Provider};
+use entities::user_tier;
+use ethers::prelude::{Http, Middleware, Provider};
 use ethers::{signers::Signer, types::Signature};
+use futures::{SinkExt, StreamExt};
+use http::StatusCode;
 use migration::sea_orm::prelude::Decimal;
+use migration::sea_orm::{self, ActiveModelTrait};
 use serde::Deserialize;
+use serde_json::json;
 use std::str::FromStr;
 use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, info, trace};
 use ulid::Ulid;
 use web3_proxy::frontend::users::authentication::PostLogin;
@@ -105,6 +111,10 @@ async fn test_admin_balance_increase() {
     let admin_login_response = create_user_as_admin(&x, &r, &admin_wallet).await;
     let user_login_response = create_user(&x, &r, &user_wallet, None).await;
 
+    // new users start without a balance
+    assert_eq!(user_login_response.available_balance, Decimal::from(0));
+    assert_eq!(user_login_response.total_deposits, Decimal::from(0));
+
     set_user_tier(&x, user_login_response.user.clone(), "Premium")
         .await
         .unwrap();
@@ -162,7 +172,11 @@ async fn test_user_balance_decreases() {
 
     // Get the rpc keys for this user
     let rpc_keys: RpcKey = user_get_first_rpc_key(&x, &r, &user_login_response).await;
-    let proxy_endpoint = format!("{}rpc/{}", x.proxy_provider.url(), rpc_keys.secret_key);
+    let proxy_endpoint = format!(
+        "{}rpc/{}",
+        x.proxy_provider.url(),
+        rpc_keys.secret_key.unwrap()
+    );
     let proxy_provider = Provider::<Http>::try_from(proxy_endpoint).unwrap();
 
     // Make some requests while in the free tier, so we can test bookkeeping here
@@ -337,7 +351,11 @@ async fn test_referral_bonus_non_concurrent() {
     let rpc_keys: RpcKey = user_get_first_rpc_key(&x, &r, &user_login_response).await;
     info!("Rpc key is: {:?}", rpc_keys);
 
-    let proxy_endpoint = format!("{}rpc/{}", x.proxy_provider.url(), rpc_keys.secret_key);
+    let proxy_endpoint = format!(
+        "{}rpc/{}",
+        x.proxy_provider.url(),
+        rpc_keys.secret_key.unwrap()
+    );
     let proxy_provider = Provider::<Http>::try_from(proxy_endpoint).unwrap();
 
     for _ in 1..=20_000 {
@@ -493,7 +511,7 @@ async fn test_referral_bonus_concurrent_referrer_only() {
     let mut handles = Vec::with_capacity(number_requests);
     for _ in 1..number_requests {
         let url = x.proxy_provider.url().clone();
-        let secret_key = rpc_keys.secret_key;
+        let secret_key = rpc_keys.secret_key.unwrap();
 
         handles.push(tokio::spawn(async move {
             let proxy_endpoint = format!("{}rpc/{}", url, secret_key);
@@ -666,7 +684,7 @@ async fn test_referral_bonus_concurrent_referrer_and_user() {
 
     for _ in 1..number_requests {
         let url = x.proxy_provider.url().clone();
-        let user_secret_key = user_rpc_keys.secret_key;
+        let user_secret_key = user_rpc_keys.secret_key.unwrap();
         handles.push(tokio::spawn(async move {
             let proxy_endpoint = format!("{}rpc/{}", url, user_secret_key);
             let proxy_provider = Provider::<Http>::try_from(proxy_endpoint).unwrap();
@@ -677,7 +695,7 @@ async fn test_referral_bonus_concurrent_referrer_and_user() {
                 .unwrap()
         }));
         let url = x.proxy_provider.url().clone();
-        let referrer_secret_key = referre_rpc_keys.secret_key;
+        let referrer_secret_key = referre_rpc_keys.secret_key.unwrap();
         handles.push(tokio::spawn(async move {
             let proxy_endpoint = format!("{}rpc/{}", url, referrer_secret_key);
             let proxy_provider = Provider::<Http>::try_from(proxy_endpoint).unwrap();
@@ -741,3 +759,165 @@ async fn test_referral_bonus_concurrent_referrer_and_user() {
         referrer_balance_post
     );
 }
+
+#[cfg_attr(not(feature = "tests-needing-docker"), ignore)]
+#[test_log::test(tokio::test)]
+async fn test_frontend_keys_cannot_subscribe_to_pending_transactions() {
+    let x = TestApp::spawn(31337, true).await;
+
+    let r = reqwest::Client::builder()
+        .timeout(Duration::from_secs(20))
+        .build()
+        .unwrap();
+
+    let user_wallet = x.wallet(0);
+    let user_login_response = create_user(&x, &r, &user_wallet, None).await;
+
+    let rpc_key: RpcKey = user_get_first_rpc_key(&x, &r, &user_login_response).await;
+
+    let update_key_url = format!("{}user/keys", x.proxy_provider.url());
+    let update_key_response = r
+        .put(update_key_url)
+        .bearer_auth(user_login_response.bearer_token)
+        .json(&json!({ "key_id": rpc_key.id, "scope": "frontend" }))
+        .send()
+        .await
+        .unwrap();
+    info!(?update_key_response);
+    assert!(update_key_response.status().is_success());
+
+    let ws_url = format!(
+        "{}rpc/{}",
+        x.proxy_provider.url().as_str().replacen("http", "ws", 1),
+        rpc_key.secret_key.unwrap()
+    );
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_url).await.unwrap();
+
+    let subscribe = |id: u64, subscribe_to: &'static str| {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "eth_subscribe",
+            "params": [subscribe_to],
+        });
+
+        Message::Text(request.to_string())
+    };
+
+    socket
+        .send(subscribe(1, "newPendingTransactions"))
+        .await
+        .unwrap();
+
+    let response = socket.next().await.unwrap().unwrap().into_text().unwrap();
+    let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+    info!(?response);
+
+    assert_eq!(response["id"], 1);
+    assert!(response["result"].is_null());
+    assert_eq!(
+        response["error"]["message"],
+        "newPendingTransactions subscriptions are not allowed for frontend keys"
+    );
+
+    socket.send(subscribe(2, "newHeads")).await.unwrap();
+
+    let response = socket.next().await.unwrap().unwrap().into_text().unwrap();
+    let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+    info!(?response);
+
+    assert_eq!(response["id"], 2);
+    assert!(response["error"].is_null());
+    assert!(response["result"].is_string());
+}
+
+#[cfg_attr(not(feature = "tests-needing-docker"), ignore)]
+#[test_log::test(tokio::test)]
+async fn test_tier_size_limits() {
+    let x = TestApp::spawn(31337, true).await;
+
+    let r = reqwest::Client::builder()
+        .timeout(Duration::from_secs(20))
+        .build()
+        .unwrap();
+
+    let user_wallet = x.wallet(0);
+    let user_login_response = create_user(&x, &r, &user_wallet, None).await;
+
+    // a new tier so that nothing has cached it yet
+    user_tier::ActiveModel {
+        title: sea_orm::Set("Small".to_string()),
+        max_request_bytes: sea_orm::Set(Some(1024)),
+        max_response_bytes: sea_orm::Set(Some(64)),
+        ..Default::default()
+    }
+    .insert(x.db_conn())
+    .await
+    .unwrap();
+
+    set_user_tier(&x, user_login_response.user.clone(), "Small")
+        .await
+        .unwrap();
+
+    let rpc_key: RpcKey = user_get_first_rpc_key(&x, &r, &user_login_response).await;
+
+    let proxy_endpoint = format!(
+        "{}rpc/{}",
+        x.proxy_provider.url(),
+        rpc_key.secret_key.unwrap()
+    );
+
+    let oversized_request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_blockNumber",
+        "params": ["a".repeat(2048)],
+    });
+
+    // http bodies over the tier's limit get a 413
+    let response = r
+        .post(&proxy_endpoint)
+        .json(&oversized_request)
+        .send()
+        .await
+        .unwrap();
+    info!(?response);
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // websocket messages get an error response. the socket stays open
+    let ws_url = proxy_endpoint.replacen("http", "ws", 1);
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_url).await.unwrap();
+
+    socket
+        .send(Message::Text(oversized_request.to_string()))
+        .await
+        .unwrap();
+
+    let response = socket.next().await.unwrap().unwrap().into_text().unwrap();
+    let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+    info!(?response);
+
+    assert_eq!(response["id"], 1);
+    assert_eq!(
+        response["error"]["message"],
+        "the request is larger than 1024 bytes. larger requests require a higher tier"
+    );
+
+    // small requests with small responses are fine
+    let proxy_provider = Provider::<Http>::try_from(proxy_endpoint).unwrap();
+
+    proxy_provider.get_block_number().await.unwrap();
+
+    // a full block is larger than the tier's max_response_bytes
+    let err = proxy_provider
+        .request::<_, Option<ArcBlock>>("eth_getBlockByNumber", ("latest", true))
+        .await
+        .unwrap_err();
+    info!(?err);
+
+    assert!(err
+        .to_string()
+        .contains("the response to eth_getBlockByNumber is larger than 64 bytes"));
+}